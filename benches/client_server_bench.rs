@@ -17,7 +17,7 @@ fn bench_client_creation(c: &mut Criterion) {
     c.bench_function("client_creation", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
             .iter(|| async {
-                let tls_config = tls::create_client_config().unwrap();
+                let tls_config = tls::create_insecure_client_config().unwrap();
                 let tls_connector = TlsConnector::from(tls_config);
                 let padding = PaddingFactory::default();
                 let server_name = ServerName::try_from("localhost".to_string()).unwrap();
@@ -41,7 +41,7 @@ fn bench_client_with_pool_config(c: &mut Criterion) {
     c.bench_function("client_with_pool_config", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
             .iter(|| async {
-                let tls_config = tls::create_client_config().unwrap();
+                let tls_config = tls::create_insecure_client_config().unwrap();
                 let tls_connector = TlsConnector::from(tls_config);
                 let padding = PaddingFactory::default();
                 let pool_config = SessionPoolConfig {
@@ -67,7 +67,7 @@ fn bench_client_with_pool_config(c: &mut Criterion) {
 fn bench_tls_connector_creation(c: &mut Criterion) {
     c.bench_function("tls_connector_creation", |b| {
         b.iter(|| {
-            let tls_config = tls::create_client_config().unwrap();
+            let tls_config = tls::create_insecure_client_config().unwrap();
             let tls_connector = TlsConnector::from(tls_config);
             black_box(Arc::new(tls_connector));
         })
@@ -76,7 +76,7 @@ fn bench_tls_connector_creation(c: &mut Criterion) {
 
 fn bench_tls_connector_reuse(c: &mut Criterion) {
     c.bench_function("tls_connector_reuse", |b| {
-        let tls_config = tls::create_client_config().unwrap();
+        let tls_config = tls::create_insecure_client_config().unwrap();
         let tls_connector = Arc::new(TlsConnector::from(tls_config));
 
         b.iter(|| {
@@ -115,7 +115,7 @@ fn bench_client_server_setup_components(c: &mut Criterion) {
         b.iter(|| {
             // Create all necessary components for client/server setup
             let server_tls_config = tls::create_server_config().unwrap();
-            let client_tls_config = tls::create_client_config().unwrap();
+            let client_tls_config = tls::create_insecure_client_config().unwrap();
             let padding = PaddingFactory::default();
 
             black_box((server_tls_config, client_tls_config, padding));
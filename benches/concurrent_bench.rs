@@ -70,6 +70,22 @@ async fn create_test_session() -> Session {
     )
 }
 
+/// Session over an in-memory pipe whose far end is drained, so every
+/// write and flush does real work
+fn create_piped_session() -> Arc<Session> {
+    let (near, mut far) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let _ = tokio::io::copy(&mut far, &mut tokio::io::sink()).await;
+    });
+    let (reader, writer) = tokio::io::split(near);
+    Arc::new(Session::new_client(
+        reader,
+        writer,
+        PaddingFactory::default(),
+        None,
+    ))
+}
+
 fn bench_concurrent_session_creation(c: &mut Criterion) {
     let mut group = c.benchmark_group("concurrent_session_creation");
 
@@ -172,6 +188,40 @@ fn bench_concurrent_stream_data_send(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_concurrent_frame_writes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("concurrent_frame_writes");
+
+    for writer_count in [1, 10, 50].iter() {
+        group.bench_with_input(
+            BenchmarkId::new("write_data_frame", format!("{}writers_1KiB", writer_count)),
+            writer_count,
+            |b, &writer_count| {
+                b.to_async(tokio::runtime::Runtime::new().unwrap())
+                    .iter(|| async move {
+                        let session = create_piped_session();
+                        let handles: Vec<_> = (0..writer_count)
+                            .map(|i| {
+                                let session = Arc::clone(&session);
+                                tokio::spawn(async move {
+                                    let data = Bytes::from(vec![0u8; 1024]);
+                                    for _ in 0..50 {
+                                        let _ = session.write_data_frame(i + 1, data.clone()).await;
+                                    }
+                                })
+                            })
+                            .collect();
+                        for handle in handles {
+                            let _ = handle.await;
+                        }
+                        black_box(session);
+                    })
+            },
+        );
+    }
+
+    group.finish();
+}
+
 fn bench_concurrent_multi_session_multi_stream(c: &mut Criterion) {
     let mut group = c.benchmark_group("concurrent_multi_session_multi_stream");
 
@@ -215,6 +265,7 @@ criterion_group!(
     bench_concurrent_session_creation,
     bench_concurrent_stream_creation,
     bench_concurrent_stream_data_send,
+    bench_concurrent_frame_writes,
     bench_concurrent_multi_session_multi_stream
 );
 criterion_main!(benches);
@@ -7,7 +7,7 @@
 use anyhow::Result;
 use anytls_rs::client::Client;
 use anytls_rs::padding::PaddingFactory;
-use anytls_rs::server::Server;
+use anytls_rs::server::{OutboundAcl, Server};
 use anytls_rs::session::Session;
 use anytls_rs::util::tls;
 use bytes::Bytes;
@@ -131,12 +131,10 @@ async fn udp_over_tcp_roundtrip(iterations: usize, payload_size: usize) -> Resul
 
     let server_config = tls::create_server_config()?;
     let tls_acceptor = Arc::new(TlsAcceptor::from(server_config));
-    let server = Arc::new(Server::new(
-        password,
-        tls_acceptor,
-        PaddingFactory::default(),
-        None,
-    ));
+    let server = Arc::new(
+        Server::new(password, tls_acceptor, PaddingFactory::default(), None)
+            .with_outbound_acl(OutboundAcl::allow_all()),
+    );
 
     let server_clone = server.clone();
     let server_addr_for_task = server_addr.clone();
@@ -168,7 +166,7 @@ async fn udp_over_tcp_roundtrip(iterations: usize, payload_size: usize) -> Resul
         }
     });
 
-    let client_config = tls::create_client_config()?;
+    let client_config = tls::create_insecure_client_config()?;
     let tls_connector = Arc::new(TlsConnector::from(client_config));
     let server_name = ServerName::try_from("localhost".to_string()).unwrap();
     let client = Arc::new(Client::new(
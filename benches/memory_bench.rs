@@ -2,11 +2,18 @@
 //!
 //! Run with: cargo bench --bench memory_bench
 //!
-//! These benchmarks measure memory allocation and copy performance
+//! These benchmarks measure memory allocation and copy performance, and
+//! check that session buffering stays bounded when a reader falls behind
 
+use anytls_rs::padding::PaddingFactory;
+use anytls_rs::session::{FlowControlConfig, ReceiveOverflow, Session};
 use bytes::Bytes;
 use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
 use std::hint::black_box;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 
 fn bench_bytes_allocation(c: &mut Criterion) {
     let mut group = c.benchmark_group("bytes_allocation");
@@ -157,12 +164,135 @@ fn bench_memory_reuse_patterns(c: &mut Criterion) {
     });
 }
 
+const SLOW_READER_FLOW_CONTROL: FlowControlConfig = FlowControlConfig {
+    stream_receive_buffer: 64 * 1024,
+    session_receive_buffer: 256 * 1024,
+    session_send_buffer: 256 * 1024,
+    overflow: ReceiveOverflow::Pause,
+};
+
+/// Push `total` bytes from a server stream to a client stream whose reader
+/// is much slower than the writer, returning the peak bytes buffered by both
+/// sessions
+async fn slow_reader_transfer(total: usize) -> usize {
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let (client_read, client_write) = tokio::io::split(client_io);
+    let (server_read, server_write) = tokio::io::split(server_io);
+    let padding = PaddingFactory::default();
+
+    let mut client = Session::new_client(client_read, client_write, padding.clone(), None);
+    client.set_flow_control(SLOW_READER_FLOW_CONTROL);
+    let client = Arc::new(client);
+    let mut server = Session::new_server(server_read, server_write, padding);
+    server.set_flow_control(SLOW_READER_FLOW_CONTROL);
+    let (stream_tx, mut stream_rx) = mpsc::unbounded_channel();
+    server.set_stream_callback(stream_tx);
+    let server = Arc::new(server);
+
+    for session in [&client, &server] {
+        let recv = Arc::clone(session);
+        tokio::spawn(async move { recv.recv_loop().await });
+        let send = Arc::clone(session);
+        tokio::spawn(async move { send.process_stream_data().await });
+    }
+
+    let peak = Arc::new(AtomicUsize::new(0));
+    let sample = {
+        let (client, server, peak) = (Arc::clone(&client), Arc::clone(&server), Arc::clone(&peak));
+        move || {
+            let buffered = client.receive_buffered() + server.send_buffered();
+            peak.fetch_max(buffered, Ordering::Relaxed);
+        }
+    };
+
+    let (client_stream, _synack) = client.open_stream().await.unwrap();
+    let server_stream = stream_rx.recv().await.unwrap();
+
+    // Fast origin: write as quickly as the send budget allows
+    let writer_sample = sample.clone();
+    let writer = tokio::spawn(async move {
+        let chunk = Bytes::from(vec![0u8; 16 * 1024]);
+        let mut sent = 0;
+        while sent < total {
+            server_stream.write_data(chunk.clone()).await.unwrap();
+            sent += chunk.len();
+            writer_sample();
+        }
+    });
+
+    // Slow client: small reads with a pause after every 64 KiB
+    let mut reader = client_stream.reader().lock().await;
+    let mut buf = vec![0u8; 4096];
+    let mut received = 0;
+    while received < total {
+        let n = reader.read(&mut buf).await.unwrap();
+        assert!(n > 0, "stream ended early");
+        received += n;
+        if received % (64 * 1024) < n {
+            tokio::time::sleep(Duration::from_micros(200)).await;
+        }
+        sample();
+    }
+    drop(reader);
+
+    writer.await.unwrap();
+    let _ = client.close().await;
+    let _ = server.close().await;
+    peak.load(Ordering::Relaxed)
+}
+
+fn bench_slow_reader_memory(c: &mut Criterion) {
+    let mut group = c.benchmark_group("slow_reader_memory");
+    group.sample_size(10);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    // Stream window + shared receive budget + send budget, plus one frame of slack each
+    let bound = SLOW_READER_FLOW_CONTROL.stream_receive_buffer
+        + SLOW_READER_FLOW_CONTROL.session_receive_buffer
+        + SLOW_READER_FLOW_CONTROL.session_send_buffer
+        + 2 * 65535;
+
+    for total in [1usize << 20, 4 << 20, 16 << 20] {
+        let peak = Arc::new(AtomicUsize::new(0));
+        group.bench_with_input(BenchmarkId::new("transfer", total), &total, |b, &total| {
+            let peak = Arc::clone(&peak);
+            b.to_async(&runtime).iter_custom(move |iters| {
+                let peak = Arc::clone(&peak);
+                async move {
+                    let start = Instant::now();
+                    for _ in 0..iters {
+                        let run_peak = slow_reader_transfer(total).await;
+                        peak.fetch_max(run_peak, Ordering::Relaxed);
+                    }
+                    start.elapsed()
+                }
+            })
+        });
+
+        // Buffered memory must not grow with the amount transferred
+        let peak = peak.load(Ordering::Relaxed);
+        println!(
+            "slow_reader_memory/{}: peak buffered {} bytes (bound {})",
+            total, peak, bound
+        );
+        assert!(
+            peak <= bound,
+            "buffering grew past the flow control budget: {} > {}",
+            peak,
+            bound
+        );
+    }
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_bytes_allocation,
     bench_bytes_clone_vs_copy,
     bench_bytes_slice,
     bench_vec_vs_bytes_allocation,
-    bench_memory_reuse_patterns
+    bench_memory_reuse_patterns,
+    bench_slow_reader_memory
 );
 criterion_main!(benches);
@@ -75,6 +75,25 @@ async fn create_test_stream() -> (Arc<anytls_rs::session::Stream>, Arc<Session>)
     (stream, session)
 }
 
+/// Stream of a session over an in-memory pipe whose far end is drained, with
+/// `process_stream_data` running
+fn create_piped_stream_session() -> Arc<Session> {
+    let (near, mut far) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let _ = tokio::io::copy(&mut far, &mut tokio::io::sink()).await;
+    });
+    let (reader, writer) = tokio::io::split(near);
+    let session = Arc::new(Session::new_client(
+        reader,
+        writer,
+        PaddingFactory::default(),
+        None,
+    ));
+    let process = Arc::clone(&session);
+    tokio::spawn(async move { process.process_stream_data().await });
+    session
+}
+
 fn bench_stream_write(c: &mut Criterion) {
     let mut group = c.benchmark_group("stream_write");
 
@@ -93,6 +112,44 @@ fn bench_stream_write(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_stream_write_through_session(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stream_write_through_session");
+
+    for stream_count in [1, 10, 50].iter() {
+        group.bench_with_input(
+            BenchmarkId::new("write_data", format!("{}streams_1KiB", stream_count)),
+            stream_count,
+            |b, &stream_count| {
+                b.to_async(tokio::runtime::Runtime::new().unwrap())
+                    .iter(|| async move {
+                        let session = create_piped_stream_session();
+                        let mut handles = Vec::new();
+                        for _ in 0..stream_count {
+                            let (stream, _synack_rx) = session.open_stream().await.unwrap();
+                            handles.push(tokio::spawn(async move {
+                                let data = Bytes::from(vec![0u8; 1024]);
+                                for _ in 0..50 {
+                                    let _ = stream.write_data(data.clone()).await;
+                                }
+                                stream
+                            }));
+                        }
+                        for handle in handles {
+                            let _ = handle.await;
+                        }
+                        // Wait until everything has reached the connection
+                        while session.send_buffered() > 0 {
+                            tokio::task::yield_now().await;
+                        }
+                        black_box(session);
+                    })
+            },
+        );
+    }
+
+    group.finish();
+}
+
 fn bench_stream_read(c: &mut Criterion) {
     let mut group = c.benchmark_group("stream_read");
 
@@ -187,6 +244,7 @@ fn bench_stream_concurrent_read_write(c: &mut Criterion) {
 criterion_group!(
     benches,
     bench_stream_write,
+    bench_stream_write_through_session,
     bench_stream_read,
     bench_streamreader_read,
     bench_stream_concurrent_read_write
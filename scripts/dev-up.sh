@@ -68,7 +68,8 @@ echo "[dev-up] Starting anytls-client SOCKS5 proxy on ${CLIENT_ADDR}"
 CLIENT_CMD=(cargo run --release --bin anytls-client -- \
   -l "${CLIENT_ADDR}" \
   -s "${SERVER_ADDR}" \
-  -p "${PASSWORD}")
+  -p "${PASSWORD}" \
+  --insecure)
 
 if [[ -n "${HTTP_ADDR}" ]]; then
   echo "[dev-up] HTTP proxy enabled on ${HTTP_ADDR}"
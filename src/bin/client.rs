@@ -1,15 +1,24 @@
 //! AnyTLS Client binary
 
 use anyhow::{Context, Result, anyhow};
-use anytls_rs::client::{Client, SessionPoolConfig, start_http_proxy_server, start_socks5_server};
+use anytls_rs::client::{
+    Client, LoadBalanceStrategy, Router, SessionPoolConfig, UpstreamEndpoint,
+    start_http_proxy_server_with_shutdown, start_socks5_server_with_shutdown,
+};
 use anytls_rs::padding::PaddingFactory;
-use anytls_rs::util::create_client_config;
+use anytls_rs::util::{
+    CertVerification, ClientFileConfig, ShutdownHandle, TimeoutPolicy, UpstreamConfig,
+    create_client_config_with_verification, spawn_metrics_server,
+};
 use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
+#[cfg(unix)]
+use tokio::signal::unix::{SignalKind, signal};
 use tokio_rustls::TlsConnector;
 use tokio_rustls::rustls::pki_types::ServerName;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const APP_NAME: &str = "anytls-client";
@@ -18,58 +27,157 @@ const APP_NAME: &str = "anytls-client";
 async fn main() -> Result<()> {
     // Parse command line arguments first to get log level
     let mut args = std::env::args().skip(1);
-    let mut listen_addr = "127.0.0.1:1080".to_string();
-    let mut http_listen_addr: Option<String> = None;
-    let mut server_addr = "127.0.0.1:8443".to_string();
-    let mut sni = None;
-    let mut password = None;
-    let mut idle_check_interval: Option<u64> = None;
-    let mut idle_timeout: Option<u64> = None;
-    let mut min_idle_sessions: Option<usize> = None;
-    let mut log_level = "info".to_string();
+    let mut config_path: Option<String> = None;
+    let mut cli = ClientFileConfig::default();
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
+            "-c" | "--config" => {
+                config_path = Some(args.next().context("Expected config file after --config")?);
+            }
             "-l" | "--listen" => {
-                listen_addr = args.next().context("Expected listen address after -l")?;
+                cli.listen = Some(args.next().context("Expected listen address after -l")?);
             }
             "-s" | "--server" => {
-                server_addr = args.next().context("Expected server address after -s")?;
+                cli.server = Some(args.next().context("Expected server address after -s")?);
             }
             "--sni" => {
-                sni = Some(args.next().context("Expected SNI after --sni")?);
+                cli.sni = Some(args.next().context("Expected SNI after --sni")?);
             }
             "-p" | "--password" => {
-                password = Some(args.next().context("Expected password after -p")?);
+                cli.password = Some(args.next().context("Expected password after -p")?);
+            }
+            "--padding-scheme" => {
+                cli.padding_scheme = Some(PathBuf::from(
+                    args.next()
+                        .context("Expected padding scheme file after --padding-scheme")?,
+                ));
+            }
+            "--cover-traffic" => {
+                cli.cover_traffic = Some(true);
+            }
+            "--cover-traffic-idle" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --cover-traffic-idle")?;
+                cli.cover_traffic_idle_after = Some(parse_u64(&value, "--cover-traffic-idle")?);
+            }
+            "--cover-traffic-interval" => {
+                cli.cover_traffic_interval_ms = Some(
+                    args.next()
+                        .context("Expected MIN-MAX milliseconds after --cover-traffic-interval")?,
+                );
+            }
+            "--cover-traffic-sizes" => {
+                cli.cover_traffic_sizes = Some(
+                    args.next()
+                        .context("Expected sizes after --cover-traffic-sizes")?,
+                );
+            }
+            "--cover-traffic-rate" => {
+                let value = args
+                    .next()
+                    .context("Expected bytes per second after --cover-traffic-rate")?;
+                cli.cover_traffic_max_rate = Some(parse_limit(&value, "--cover-traffic-rate")?);
+            }
+            "--strategy" => {
+                cli.strategy = Some(args.next().context("Expected strategy after --strategy")?);
+            }
+            "--ca" => {
+                cli.ca = Some(PathBuf::from(
+                    args.next().context("Expected CA file after --ca")?,
+                ));
+            }
+            "--pin" => {
+                cli.pin = Some(args.next().context("Expected fingerprint after --pin")?);
+            }
+            "--pin-spki" => {
+                cli.pin_spki = Some(
+                    args.next()
+                        .context("Expected fingerprint after --pin-spki")?,
+                );
+            }
+            "--insecure" => {
+                cli.insecure = Some(true);
             }
             "-H" | "--http-listen" => {
-                http_listen_addr = Some(
+                cli.http_listen = Some(
                     args.next()
                         .context("Expected listen address after --http-listen")?,
                 );
             }
+            "--rules" => {
+                cli.rules_file = Some(PathBuf::from(
+                    args.next()
+                        .context("Expected routing rules file after --rules")?,
+                ));
+            }
             "-I" | "--idle-session-check-interval" => {
                 let value = args
                     .next()
                     .context("Expected seconds after --idle-session-check-interval")?;
-                idle_check_interval = Some(parse_u64(&value, "--idle-session-check-interval")?);
+                cli.idle_session_check_interval =
+                    Some(parse_u64(&value, "--idle-session-check-interval")?);
             }
             "-T" | "--idle-session-timeout" => {
                 let value = args
                     .next()
                     .context("Expected seconds after --idle-session-timeout")?;
-                idle_timeout = Some(parse_u64(&value, "--idle-session-timeout")?);
+                cli.idle_session_timeout = Some(parse_u64(&value, "--idle-session-timeout")?);
             }
             "-M" | "--min-idle-session" => {
                 let value = args
                     .next()
                     .context("Expected value after --min-idle-session")?;
-                min_idle_sessions = Some(parse_usize(&value, "--min-idle-session")?);
+                cli.min_idle_session = Some(parse_usize(&value, "--min-idle-session")?);
             }
-            "-L" | "--log-level" => {
-                log_level = args
+            "--drain-timeout" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --drain-timeout")?;
+                cli.drain_timeout = Some(parse_u64(&value, "--drain-timeout")?);
+            }
+            "--synack-timeout" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --synack-timeout")?;
+                cli.synack_timeout = Some(parse_u64(&value, "--synack-timeout")?);
+            }
+            "--connect-timeout" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --connect-timeout")?;
+                cli.connect_timeout = Some(parse_u64(&value, "--connect-timeout")?);
+            }
+            "--dns-timeout" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --dns-timeout")?;
+                cli.dns_timeout = Some(parse_u64(&value, "--dns-timeout")?);
+            }
+            "--stream-idle-timeout" => {
+                let value = args
                     .next()
-                    .context("Expected log level after --log-level")?;
+                    .context("Expected seconds after --stream-idle-timeout")?;
+                cli.stream_idle_timeout = Some(parse_u64(&value, "--stream-idle-timeout")?);
+            }
+            "--max-stream-lifetime" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --max-stream-lifetime")?;
+                cli.max_stream_lifetime = Some(parse_u64(&value, "--max-stream-lifetime")?);
+            }
+            "--metrics-listen" => {
+                cli.metrics_listen = Some(
+                    args.next()
+                        .context("Expected listen address after --metrics-listen")?,
+                );
+            }
+            "-L" | "--log-level" => {
+                cli.log_level = Some(
+                    args.next()
+                        .context("Expected log level after --log-level")?,
+                );
             }
             "-V" | "--version" => {
                 println!("{APP_NAME} {VERSION}");
@@ -78,12 +186,62 @@ async fn main() -> Result<()> {
             "-h" | "--help" => {
                 println!("Usage: anytls-client [OPTIONS]");
                 println!("Options:");
+                println!("  -c, --config FILE         TOML/YAML config file (flags override it)");
                 println!(
                     "  -l, --listen ADDRESS      SOCKS5 listen address (default: 127.0.0.1:1080)"
                 );
                 println!("  -s, --server ADDRESS     Server address (default: 127.0.0.1:8443)");
                 println!("  --sni SNI                 TLS SNI (optional)");
                 println!("  -H, --http-listen ADDRESS  HTTP proxy listen address (optional)");
+                println!("  --padding-scheme FILE     Path to padding scheme file");
+                println!("  --cover-traffic           Send cover traffic on idle sessions");
+                println!(
+                    "  --cover-traffic-idle SECS  Idle time before cover traffic starts (default: 5)"
+                );
+                println!(
+                    "  --cover-traffic-interval MS[-MS]  Gap between cover frames (default: 1000-8000)"
+                );
+                println!(
+                    "  --cover-traffic-sizes SIZES  Cover frame sizes, e.g. 64-1200 (default)"
+                );
+                println!(
+                    "  --cover-traffic-rate BYTES  Cover bytes per second cap (default: 4096)"
+                );
+                println!(
+                    "  --strategy NAME           Upstream selection: failover|round-robin|least-active"
+                );
+                println!(
+                    "  --ca FILE                 Verify the server against CA certificates in FILE"
+                );
+                println!(
+                    "  --pin SHA256              Accept only the server certificate with this SHA256"
+                );
+                println!(
+                    "  --pin-spki SHA256         Accept only a server key (SPKI) with this SHA256"
+                );
+                println!(
+                    "  --insecure                Skip certificate verification (vulnerable to MITM)"
+                );
+                println!(
+                    "  --rules FILE              Routing rules: direct/proxy/block per destination"
+                );
+                println!("  --metrics-listen ADDRESS  Serve Prometheus metrics on ADDRESS/metrics");
+                println!(
+                    "  --drain-timeout SECS      Time open connections get on shutdown (default: 30)"
+                );
+                println!(
+                    "  --synack-timeout SECS     Wait for the server to connect (default: 30)"
+                );
+                println!(
+                    "  --connect-timeout SECS    Connect timeout for direct routes (default: 15)"
+                );
+                println!("  --dns-timeout SECS        DNS timeout for direct routes (default: 10)");
+                println!(
+                    "  --stream-idle-timeout SECS  Close streams idle this long (default: off)"
+                );
+                println!(
+                    "  --max-stream-lifetime SECS  Close streams open this long (default: off)"
+                );
                 println!(
                     "  -I, --idle-session-check-interval SECS  Idle session check interval (default: 30)"
                 );
@@ -99,6 +257,12 @@ async fn main() -> Result<()> {
                 println!("  -V, --version             Show version information");
                 println!("  -p, --password PASSWORD  Server password (required)");
                 println!("  -h, --help                Show this help message");
+                #[cfg(unix)]
+                {
+                    println!();
+                    println!("Signal Handling:");
+                    println!("  SIGHUP                    Reload the routing rules file");
+                }
                 return Ok(());
             }
             _ => {
@@ -108,96 +272,290 @@ async fn main() -> Result<()> {
         }
     }
 
+    // Command line flags override values from the config file
+    let file_config = match config_path.as_deref() {
+        Some(path) => ClientFileConfig::load(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load config file: {}", e))?,
+        None => ClientFileConfig::default(),
+    };
+    let config = file_config.merge(cli);
+    config
+        .validate()
+        .map_err(|e| anyhow::anyhow!("Invalid configuration: {}", e))?;
+
+    let log_level = config.log_level.as_deref().unwrap_or("info");
+    let listen_addr = config
+        .listen
+        .clone()
+        .unwrap_or_else(|| "127.0.0.1:1080".to_string());
+    let http_listen_addr = config.http_listen.clone();
+    let upstreams = config.upstreams.clone().unwrap_or_else(|| {
+        vec![UpstreamConfig {
+            server: config
+                .server
+                .clone()
+                .unwrap_or_else(|| "127.0.0.1:8443".to_string()),
+            ..Default::default()
+        }]
+    });
+    let strategy = match config.strategy.as_deref() {
+        Some(value) => value
+            .parse::<LoadBalanceStrategy>()
+            .map_err(|e| anyhow::anyhow!("Invalid strategy: {}", e))?,
+        None => LoadBalanceStrategy::default(),
+    };
+
     // Initialize tracing with configured log level
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&log_level)),
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(log_level)),
         )
         .init();
 
-    let password = password.context("Password is required (use -p or --password)")?;
-
-    // Determine effective SNI / server name
-    let effective_sni = match sni.as_deref() {
-        Some(value) if !value.trim().is_empty() => value.trim().to_string(),
-        _ => derive_sni_from_server_addr(&server_addr),
-    };
-    let server_name = build_server_name(&effective_sni)
-        .with_context(|| format!("Invalid SNI or server hostname '{}'", effective_sni))?;
-
     // Create TLS config
-    let client_config = create_client_config().context("Failed to create TLS client config")?;
+    let verification = config
+        .cert_verification()
+        .map_err(|e| anyhow::anyhow!("Invalid certificate verification settings: {}", e))?;
+    if matches!(verification, CertVerification::Insecure) {
+        warn!("TLS certificate verification disabled (--insecure)");
+    }
+    let client_config = create_client_config_with_verification(&verification)
+        .context("Failed to create TLS client config")?;
 
     // Create TLS connector
-    let tls_connector = TlsConnector::from(client_config);
+    let tls_connector = Arc::new(TlsConnector::from(client_config));
+
+    info!("{APP_NAME} v{VERSION}");
+
+    // One endpoint per upstream; top-level sni/password/padding are defaults
+    let mut endpoints = Vec::with_capacity(upstreams.len());
+    for upstream in &upstreams {
+        let password = upstream
+            .password
+            .as_ref()
+            .or(config.password.as_ref())
+            .with_context(|| {
+                format!(
+                    "Password is required for {} (use -p/--password or --config)",
+                    upstream.server
+                )
+            })?;
 
-    // Create padding factory & session pool config
-    let padding = PaddingFactory::default();
+        // Determine effective SNI / server name
+        let effective_sni = match upstream.sni.as_deref().or(config.sni.as_deref()) {
+            Some(value) if !value.trim().is_empty() => value.trim().to_string(),
+            _ => derive_sni_from_server_addr(&upstream.server),
+        };
+        let server_name = build_server_name(&effective_sni)
+            .with_context(|| format!("Invalid SNI or server hostname '{}'", effective_sni))?;
+
+        let padding = match upstream
+            .padding_scheme
+            .as_ref()
+            .or(config.padding_scheme.as_ref())
+        {
+            Some(path) => load_padding_scheme(path)?,
+            None => PaddingFactory::default(),
+        };
+
+        info!(
+            "Upstream {} (TLS SNI host: {})",
+            upstream.server, effective_sni
+        );
+        endpoints.push(UpstreamEndpoint::new(
+            password,
+            upstream.server.clone(),
+            server_name,
+            Arc::clone(&tls_connector),
+            padding,
+        ));
+    }
+    let server_desc = upstreams
+        .iter()
+        .map(|upstream| upstream.server.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if upstreams.len() > 1 {
+        info!("Upstream selection strategy: {}", strategy);
+    }
+
+    // Create session pool config
     let mut pool_config = SessionPoolConfig::default();
-    if let Some(secs) = idle_check_interval {
+    if let Some(secs) = config.idle_session_check_interval {
         pool_config.check_interval = Duration::from_secs(secs);
     }
-    if let Some(secs) = idle_timeout {
+    if let Some(secs) = config.idle_session_timeout {
         pool_config.idle_timeout = Duration::from_secs(secs);
     }
-    if let Some(count) = min_idle_sessions {
+    if let Some(count) = config.min_idle_session {
         pool_config.min_idle_sessions = count;
     }
 
-    info!("{APP_NAME} v{VERSION}");
-    info!("TLS SNI host: {}", effective_sni);
     if let Some(http_addr) = http_listen_addr.as_ref() {
         info!(
             "SOCKS5 {} + HTTP {} => {}",
-            listen_addr, http_addr, server_addr
+            listen_addr, http_addr, server_desc
         );
     } else {
-        info!("SOCKS5 {} => {}", listen_addr, server_addr);
+        info!("SOCKS5 {} => {}", listen_addr, server_desc);
     }
 
+    let router = match config.rules_file.as_ref() {
+        Some(path) => {
+            let router = Router::from_file(path)
+                .map_err(|e| anyhow::anyhow!("Failed to load routing rules: {}", e))?;
+            info!(
+                "{} routing rule(s) loaded (default: {})",
+                router.len(),
+                router.default_action()
+            );
+            router
+        }
+        None => Router::default(),
+    };
+
     // Create client
-    let client = Arc::new(Client::with_pool_config(
-        &password,
-        server_addr,
-        server_name,
-        Arc::new(tls_connector),
-        padding,
-        pool_config,
-    ));
+    let timeouts = config.timeouts();
+    if timeouts != TimeoutPolicy::default() {
+        info!("Timeouts: {}", timeouts);
+    }
+    let mut client = Client::with_endpoints(endpoints, pool_config)
+        .map_err(|e| anyhow::anyhow!("Failed to create client: {}", e))?
+        .with_strategy(strategy)
+        .with_router(router)
+        .with_timeouts(timeouts);
+    if let Some(cover) = config.cover_traffic_config()? {
+        info!(
+            "Sending cover traffic on sessions idle for {:?} (every {:?}-{:?}, up to {} B/s)",
+            cover.idle_after, cover.min_interval, cover.max_interval, cover.max_bytes_per_sec
+        );
+        client = client.with_cover_traffic(cover);
+    }
+    let client = Arc::new(client);
+
+    // Reload routing rules on SIGHUP (Unix only)
+    #[cfg(unix)]
+    if let Some(path) = config.rules_file.clone() {
+        let router_ref = client.get_router_ref();
+        tokio::spawn(async move {
+            let mut sighup = match signal(SignalKind::hangup()) {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("Failed to setup SIGHUP handler: {}", e);
+                    return;
+                }
+            };
+            loop {
+                sighup.recv().await;
+                info!("SIGHUP received, reloading routing rules...");
+                match Router::from_file(&path) {
+                    Ok(router) => {
+                        info!("Routing rules reloaded ({} rule(s))", router.len());
+                        *router_ref.write().unwrap() = Arc::new(router);
+                    }
+                    Err(e) => {
+                        error!("Routing rules reload failed, keeping current: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    if let Some(addr) = config.metrics_listen.as_deref() {
+        spawn_metrics_server(addr)
+            .await
+            .context("Failed to start metrics endpoint")?;
+    }
 
     info!("Client ready");
 
+    // Stop accepting and drain connections on Ctrl-C / SIGTERM
+    let mut shutdown = ShutdownHandle::new();
+    if let Some(secs) = config.drain_timeout {
+        shutdown = shutdown.with_drain_timeout(Duration::from_secs(secs));
+    }
+    {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            info!(
+                "Shutdown signal received, draining (timeout {}s)",
+                shutdown.drain_timeout().as_secs()
+            );
+            shutdown.shutdown();
+        });
+    }
+
     // Start proxy servers
     if let Some(http_addr) = http_listen_addr {
         let socks_addr = listen_addr.clone();
         let socks_client = Arc::clone(&client);
         let http_client = Arc::clone(&client);
+        let socks_shutdown = shutdown.clone();
+        let http_shutdown = shutdown.clone();
 
         let socks_task = tokio::spawn(async move {
-            start_socks5_server(&socks_addr, socks_client)
+            start_socks5_server_with_shutdown(&socks_addr, socks_client, socks_shutdown)
                 .await
                 .context("SOCKS5 server error")
         });
         let http_task = tokio::spawn(async move {
-            start_http_proxy_server(&http_addr, http_client)
+            start_http_proxy_server_with_shutdown(&http_addr, http_client, http_shutdown)
                 .await
                 .context("HTTP proxy server error")
         });
 
-        tokio::select! {
-            res = socks_task => res.context("SOCKS5 task join error")??,
-            res = http_task => res.context("HTTP task join error")??,
-        }
+        // If one listener fails, stop the other one as well
+        let (socks_res, http_res) = tokio::join!(
+            async {
+                let res = socks_task.await.context("SOCKS5 task join error");
+                shutdown.shutdown();
+                res
+            },
+            async {
+                let res = http_task.await.context("HTTP task join error");
+                shutdown.shutdown();
+                res
+            }
+        );
+        socks_res??;
+        http_res??;
     } else {
-        start_socks5_server(&listen_addr, client)
+        start_socks5_server_with_shutdown(&listen_addr, Arc::clone(&client), shutdown)
             .await
             .context("SOCKS5 server error")?;
     }
 
+    client.stop_session_pool_cleanup().await;
+    info!("Client stopped");
+
     Ok(())
 }
 
+/// Wait for Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = match signal(SignalKind::terminate()) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Failed to setup SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
 fn parse_u64(value: &str, flag: &str) -> Result<u64> {
     let parsed = value
         .parse::<u64>()
@@ -208,12 +566,26 @@ fn parse_u64(value: &str, flag: &str) -> Result<u64> {
     Ok(parsed)
 }
 
+fn parse_limit<T: TryFrom<u64>>(value: &str, flag: &str) -> Result<T> {
+    T::try_from(parse_u64(value, flag)?)
+        .map_err(|_| anyhow::anyhow!("{} value is too large: {}", flag, value))
+}
+
 fn parse_usize(value: &str, flag: &str) -> Result<usize> {
     value
         .parse::<usize>()
         .map_err(|e| anyhow::anyhow!("{} expects a non-negative integer: {}", flag, e))
 }
 
+fn load_padding_scheme(path: &Path) -> Result<Arc<PaddingFactory>> {
+    let scheme_bytes = std::fs::read(path)
+        .with_context(|| format!("Failed to read padding scheme file: {}", path.display()))?;
+    let factory = PaddingFactory::new(&scheme_bytes)
+        .map_err(|e| anyhow::anyhow!("Failed to parse padding scheme: {}", e))?;
+    info!("Loaded padding scheme from: {}", path.display());
+    Ok(Arc::new(factory))
+}
+
 fn derive_sni_from_server_addr(addr: &str) -> String {
     let trimmed = addr.trim();
     if trimmed.starts_with('[')
@@ -0,0 +1,223 @@
+//! AnyTLS padding scheme tool
+//!
+//! Lints padding scheme files, prints their md5 and simulates the record
+//! sizes a scheme produces for a trace of packet payload sizes.
+
+use anyhow::{Context, Result, anyhow, bail};
+use anytls_rs::padding::{DEFAULT_PADDING_SCHEME, PaddingFactory, lint_scheme};
+use std::path::Path;
+use std::time::Duration;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const APP_NAME: &str = "anytls-padding";
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = args.first() else {
+        print_help();
+        bail!("Missing command");
+    };
+    let rest = &args[1..];
+
+    match command.as_str() {
+        "lint" => lint(rest),
+        "md5" => md5(rest),
+        "simulate" => simulate(rest),
+        "-V" | "--version" => {
+            println!("{APP_NAME} {VERSION}");
+            Ok(())
+        }
+        "-h" | "--help" => {
+            print_help();
+            Ok(())
+        }
+        _ => Err(anyhow!("Unknown command: {}", command)),
+    }
+}
+
+fn print_help() {
+    println!("Usage: {APP_NAME} COMMAND [ARGS]");
+    println!();
+    println!("Commands:");
+    println!("  lint SCHEME...             Check schemes, printing every problem with its line");
+    println!("  md5 SCHEME                 Print the padding-md5 clients advertise");
+    println!("  simulate SCHEME [OPTIONS]  Print the records each packet of a trace is sent as");
+    println!();
+    println!("SCHEME is a scheme file, '-' for stdin or 'default' for the built-in scheme.");
+    println!();
+    println!("Simulate options:");
+    println!("      --trace SIZES          Payload bytes per packet, e.g. 517,1200,40 (default:");
+    println!("                             1200 for each padded packet and one more)");
+    println!("      --trace-file FILE      Payload sizes, one per line ('#' starts a comment)");
+    println!("      --runs N               Repeat the simulation N times (default: 1)");
+    println!();
+    println!("Other:");
+    println!("  -V, --version             Show version information");
+    println!("  -h, --help                Show this help message");
+}
+
+/// Read a scheme argument
+fn read_scheme(arg: &str) -> Result<Vec<u8>> {
+    match arg {
+        "default" => Ok(DEFAULT_PADDING_SCHEME.as_bytes().to_vec()),
+        "-" => {
+            let mut scheme = Vec::new();
+            std::io::Read::read_to_end(&mut std::io::stdin(), &mut scheme)
+                .context("Failed to read padding scheme from stdin")?;
+            Ok(scheme)
+        }
+        path => std::fs::read(Path::new(path))
+            .with_context(|| format!("Failed to read padding scheme file: {}", path)),
+    }
+}
+
+fn load_scheme(arg: &str) -> Result<PaddingFactory> {
+    PaddingFactory::new(&read_scheme(arg)?)
+        .map_err(|e| anyhow!("Invalid padding scheme {}: {}", arg, e))
+}
+
+fn lint(args: &[String]) -> Result<()> {
+    if args.is_empty() {
+        bail!("Expected at least one scheme after lint");
+    }
+
+    let mut invalid = 0;
+    for arg in args {
+        let raw = read_scheme(arg)?;
+        let diagnostics = lint_scheme(&raw);
+        for diagnostic in &diagnostics {
+            println!("{}: {}", arg, diagnostic);
+        }
+        if diagnostics.iter().any(|d| d.is_error()) {
+            invalid += 1;
+        } else {
+            let factory = PaddingFactory::new(&raw).map_err(|e| anyhow!(e))?;
+            println!(
+                "{}: ok, stop={}, fill={}, md5 {}",
+                arg,
+                factory.stop(),
+                factory.fill(),
+                factory.md5()
+            );
+        }
+    }
+    if invalid > 0 {
+        bail!("{} of {} scheme(s) invalid", invalid, args.len());
+    }
+    Ok(())
+}
+
+fn md5(args: &[String]) -> Result<()> {
+    let [arg] = args else {
+        bail!("Expected exactly one scheme after md5");
+    };
+    println!("{}", load_scheme(arg)?.md5());
+    Ok(())
+}
+
+fn simulate(args: &[String]) -> Result<()> {
+    let Some((scheme_arg, options)) = args.split_first() else {
+        bail!("Expected a scheme after simulate");
+    };
+    let factory = load_scheme(scheme_arg)?;
+
+    let mut trace: Option<Vec<usize>> = None;
+    let mut runs = 1usize;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--trace" => {
+                let sizes = options.next().context("Expected sizes after --trace")?;
+                trace = Some(parse_trace(sizes.split(',').map(str::trim))?);
+            }
+            "--trace-file" => {
+                let path = options.next().context("Expected file after --trace-file")?;
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read trace file: {}", path))?;
+                let lines = text
+                    .lines()
+                    .map(|line| line.split('#').next().unwrap_or("").trim())
+                    .filter(|line| !line.is_empty());
+                trace = Some(parse_trace(lines)?);
+            }
+            "--runs" => {
+                let value = options.next().context("Expected count after --runs")?;
+                runs = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .with_context(|| format!("Invalid --runs value: {}", value))?;
+            }
+            _ => bail!("Unknown simulate option: {}", option),
+        }
+    }
+    let trace = trace.unwrap_or_else(|| vec![1200; factory.stop() as usize + 1]);
+
+    println!("scheme md5 {}, stop={}", factory.md5(), factory.stop());
+    for run in 1..=runs {
+        if runs > 1 {
+            println!();
+            println!("run {}", run);
+        }
+        println!(
+            "{:>4}  {:>8}  {:>8}  records (payload+waste)",
+            "pkt", "payload", "wire"
+        );
+        let (mut payload_total, mut wire_total) = (0usize, 0usize);
+        let mut delay_total = Duration::ZERO;
+        for (pkt, &payload) in trace.iter().enumerate() {
+            let records = factory.plan_records(pkt as u32, payload);
+            let wire: usize = records.iter().map(|r| r.len()).sum();
+            let shown: Vec<String> = records
+                .iter()
+                .map(|r| {
+                    let size = match (r.payload, r.padding) {
+                        (payload, 0) => payload.to_string(),
+                        (payload, padding) => format!("{}+{}", payload, padding),
+                    };
+                    if r.delay.is_zero() {
+                        size
+                    } else {
+                        format!("(wait {}ms) {}", r.delay.as_millis(), size)
+                    }
+                })
+                .collect();
+            delay_total += records.iter().map(|r| r.delay).sum::<Duration>();
+            println!(
+                "{:>4}  {:>8}  {:>8}  {}",
+                pkt,
+                payload,
+                wire,
+                shown.join(" | ")
+            );
+            payload_total += payload;
+            wire_total += wire;
+        }
+        let overhead = if payload_total == 0 {
+            0.0
+        } else {
+            (wire_total - payload_total) as f64 * 100.0 / payload_total as f64
+        };
+        println!(
+            "total payload {} bytes, wire {} bytes, padding overhead {:.1}%, delay {}ms",
+            payload_total,
+            wire_total,
+            overhead,
+            delay_total.as_millis()
+        );
+    }
+    Ok(())
+}
+
+fn parse_trace<'a>(sizes: impl Iterator<Item = &'a str>) -> Result<Vec<usize>> {
+    let trace = sizes
+        .map(|size| {
+            size.parse::<usize>()
+                .with_context(|| format!("Invalid payload size in trace: {}", size))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if trace.is_empty() {
+        bail!("Trace is empty");
+    }
+    Ok(trace)
+}
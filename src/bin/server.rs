@@ -2,11 +2,16 @@
 
 use anyhow::{Context, Result};
 use anytls_rs::padding::PaddingFactory;
-use anytls_rs::server::Server;
+#[cfg(unix)]
+use anytls_rs::server::spawn_admin_unix_server;
+use anytls_rs::server::{AdminApi, OutboundAcl, Server, spawn_admin_server};
+use anytls_rs::session::DownstreamPadding;
 use anytls_rs::util::{
-    CertReloader, CertReloaderConfig, StringMap, create_server_config, set_custom_dns_servers,
+    CertReloader, CertReloaderConfig, METRICS, ServerFileConfig, ShutdownHandle, StringMap,
+    TimeoutPolicy, certificate_fingerprint, create_server_config_with_cert, format_fingerprint,
+    generate_key_pair, set_custom_dns_servers, spawn_metrics_server,
 };
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 #[cfg(unix)]
@@ -21,81 +26,244 @@ const APP_NAME: &str = "anytls-server";
 async fn main() -> Result<()> {
     // Parse command line arguments first to get log level
     let mut args = std::env::args().skip(1);
-    let mut listen_addr = "0.0.0.0:8443".to_string();
-    let mut password = None;
-    let mut padding_scheme_file = None;
-    let mut cert_path = None;
-    let mut key_path = None;
-    let mut idle_session_check_interval: Option<u64> = None;
-    let mut idle_session_timeout: Option<u64> = None;
-    let mut min_idle_session: Option<usize> = None;
-    let mut log_level = "info".to_string();
-    let mut watch_cert = false;
-    let mut show_cert_info = false;
-    let mut expiry_warning_days: u64 = 30;
+    let mut config_path: Option<String> = None;
+    let mut cli = ServerFileConfig::default();
     let mut dns_servers: Vec<String> = Vec::new();
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
+            "-c" | "--config" => {
+                config_path = Some(args.next().context("Expected config file after --config")?);
+            }
             "-l" | "--listen" => {
-                listen_addr = args.next().context("Expected listen address after -l")?;
+                cli.listen = Some(args.next().context("Expected listen address after -l")?);
             }
             "-p" | "--password" => {
-                password = Some(args.next().context("Expected password after -p")?);
+                cli.password = Some(args.next().context("Expected password after -p")?);
+            }
+            "--users" => {
+                cli.users_file = Some(PathBuf::from(
+                    args.next()
+                        .context("Expected user table file after --users")?,
+                ));
+            }
+            "--fallback" => {
+                cli.fallback = Some(
+                    args.next()
+                        .context("Expected fallback address after --fallback")?,
+                );
+            }
+            "--acl" => {
+                cli.acl_file = Some(PathBuf::from(
+                    args.next().context("Expected ACL file after --acl")?,
+                ));
             }
             "--padding-scheme" => {
-                padding_scheme_file = Some(
+                cli.padding_scheme = Some(PathBuf::from(
                     args.next()
                         .context("Expected padding scheme file after --padding-scheme")?,
+                ));
+            }
+            "--downstream-padding" => {
+                cli.downstream_padding = Some(true);
+            }
+            "--downstream-padding-scheme" => {
+                cli.downstream_padding_scheme =
+                    Some(PathBuf::from(args.next().context(
+                        "Expected padding scheme file after --downstream-padding-scheme",
+                    )?));
+            }
+            "--cover-traffic" => {
+                cli.cover_traffic = Some(true);
+            }
+            "--cover-traffic-idle" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --cover-traffic-idle")?;
+                cli.cover_traffic_idle_after = Some(parse_u64(&value, "--cover-traffic-idle")?);
+            }
+            "--cover-traffic-interval" => {
+                cli.cover_traffic_interval_ms = Some(
+                    args.next()
+                        .context("Expected MIN-MAX milliseconds after --cover-traffic-interval")?,
+                );
+            }
+            "--cover-traffic-sizes" => {
+                cli.cover_traffic_sizes = Some(
+                    args.next()
+                        .context("Expected sizes after --cover-traffic-sizes")?,
                 );
             }
+            "--cover-traffic-rate" => {
+                let value = args
+                    .next()
+                    .context("Expected bytes per second after --cover-traffic-rate")?;
+                cli.cover_traffic_max_rate = Some(parse_limit(&value, "--cover-traffic-rate")?);
+            }
             "--cert" => {
-                cert_path = Some(
+                cli.cert = Some(PathBuf::from(
                     args.next()
                         .context("Expected certificate path after --cert")?,
-                );
+                ));
             }
             "--key" => {
-                key_path = Some(
+                cli.key = Some(PathBuf::from(
                     args.next()
                         .context("Expected private key path after --key")?,
-                );
+                ));
             }
             "-I" | "--idle-session-check-interval" => {
                 let value = args
                     .next()
                     .context("Expected seconds after --idle-session-check-interval")?;
-                idle_session_check_interval =
+                cli.idle_session_check_interval =
                     Some(parse_u64(&value, "--idle-session-check-interval")?);
             }
             "-T" | "--idle-session-timeout" => {
                 let value = args
                     .next()
                     .context("Expected seconds after --idle-session-timeout")?;
-                idle_session_timeout = Some(parse_u64(&value, "--idle-session-timeout")?);
+                cli.idle_session_timeout = Some(parse_u64(&value, "--idle-session-timeout")?);
             }
             "-M" | "--min-idle-session" => {
                 let value = args
                     .next()
                     .context("Expected value after --min-idle-session")?;
-                min_idle_session = Some(parse_usize(&value, "--min-idle-session")?);
+                cli.min_idle_session = Some(parse_usize(&value, "--min-idle-session")?);
             }
-            "-L" | "--log-level" => {
-                log_level = args
+            "--drain-timeout" => {
+                let value = args
                     .next()
-                    .context("Expected log level after --log-level")?;
+                    .context("Expected seconds after --drain-timeout")?;
+                cli.drain_timeout = Some(parse_u64(&value, "--drain-timeout")?);
+            }
+            "--metrics-listen" => {
+                cli.metrics_listen = Some(
+                    args.next()
+                        .context("Expected listen address after --metrics-listen")?,
+                );
+            }
+            "--admin-listen" => {
+                cli.admin_listen = Some(
+                    args.next()
+                        .context("Expected listen address after --admin-listen")?,
+                );
+            }
+            "--admin-token" => {
+                cli.admin_token = Some(args.next().context("Expected token after --admin-token")?);
+            }
+            "--admin-token-file" => {
+                cli.admin_token_file = Some(PathBuf::from(
+                    args.next()
+                        .context("Expected token file after --admin-token-file")?,
+                ));
+            }
+            "-L" | "--log-level" => {
+                cli.log_level = Some(
+                    args.next()
+                        .context("Expected log level after --log-level")?,
+                );
             }
             "--watch-cert" => {
-                watch_cert = true;
+                cli.watch_cert = Some(true);
             }
             "--show-cert-info" => {
-                show_cert_info = true;
+                cli.show_cert_info = Some(true);
             }
             "--expiry-warning-days" => {
                 let value = args
                     .next()
                     .context("Expected days after --expiry-warning-days")?;
-                expiry_warning_days = parse_u64(&value, "--expiry-warning-days")?;
+                cli.expiry_warning_days = Some(parse_u64(&value, "--expiry-warning-days")?);
+            }
+            "--max-sessions" => {
+                let value = args.next().context("Expected count after --max-sessions")?;
+                cli.max_sessions = Some(parse_limit(&value, "--max-sessions")?);
+            }
+            "--max-connections-per-ip" => {
+                let value = args
+                    .next()
+                    .context("Expected count after --max-connections-per-ip")?;
+                cli.max_connections_per_ip = Some(parse_limit(&value, "--max-connections-per-ip")?);
+            }
+            "--max-streams-per-session" => {
+                let value = args
+                    .next()
+                    .context("Expected count after --max-streams-per-session")?;
+                cli.max_streams_per_session =
+                    Some(parse_limit(&value, "--max-streams-per-session")?);
+            }
+            "--max-syn-per-sec" => {
+                let value = args
+                    .next()
+                    .context("Expected count after --max-syn-per-sec")?;
+                cli.max_syn_per_sec = Some(parse_limit(&value, "--max-syn-per-sec")?);
+            }
+            "--tls-handshake-timeout" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --tls-handshake-timeout")?;
+                cli.tls_handshake_timeout = Some(parse_u64(&value, "--tls-handshake-timeout")?);
+            }
+            "--auth-timeout" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --auth-timeout")?;
+                cli.auth_timeout = Some(parse_u64(&value, "--auth-timeout")?);
+            }
+            "--first-frame-timeout" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --first-frame-timeout")?;
+                cli.first_frame_timeout = Some(parse_u64(&value, "--first-frame-timeout")?);
+            }
+            "--connect-timeout" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --connect-timeout")?;
+                cli.connect_timeout = Some(parse_u64(&value, "--connect-timeout")?);
+            }
+            "--dns-timeout" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --dns-timeout")?;
+                cli.dns_timeout = Some(parse_u64(&value, "--dns-timeout")?);
+            }
+            "--stream-idle-timeout" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --stream-idle-timeout")?;
+                cli.stream_idle_timeout = Some(parse_u64(&value, "--stream-idle-timeout")?);
+            }
+            "--max-stream-lifetime" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --max-stream-lifetime")?;
+                cli.max_stream_lifetime = Some(parse_u64(&value, "--max-stream-lifetime")?);
+            }
+            "--auth-ban-failures" => {
+                let value = args
+                    .next()
+                    .context("Expected count after --auth-ban-failures")?;
+                cli.auth_ban_failures = Some(parse_limit(&value, "--auth-ban-failures")?);
+            }
+            "--auth-ban-window" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --auth-ban-window")?;
+                cli.auth_ban_window = Some(parse_u64(&value, "--auth-ban-window")?);
+            }
+            "--auth-ban-duration" => {
+                let value = args
+                    .next()
+                    .context("Expected seconds after --auth-ban-duration")?;
+                cli.auth_ban_duration = Some(parse_u64(&value, "--auth-ban-duration")?);
+            }
+            "--auth-ban-file" => {
+                cli.auth_ban_file = Some(PathBuf::from(
+                    args.next()
+                        .context("Expected ban file after --auth-ban-file")?,
+                ));
             }
             "--dns" => {
                 let value = args.next().context("Expected DNS server after --dns")?;
@@ -109,11 +277,90 @@ async fn main() -> Result<()> {
                 println!("Usage: anytls-server [OPTIONS]");
                 println!();
                 println!("Options:");
+                println!("  -c, --config FILE         TOML/YAML config file (flags override it)");
                 println!("  -l, --listen ADDRESS      Listen address (default: 0.0.0.0:8443)");
-                println!("  -p, --password PASSWORD    Server password (required)");
+                println!("  -p, --password PASSWORD    Server password (required unless --users)");
+                println!(
+                    "      --users FILE           User table file, one 'name=password' per line"
+                );
+                println!(
+                    "      --fallback ADDRESS     Forward unauthenticated connections to ADDRESS"
+                );
+                println!(
+                    "      --acl FILE             Outbound access rules (default: block private addresses)"
+                );
                 println!("      --cert FILE            Path to PEM encoded TLS certificate");
                 println!("      --key  FILE            Path to PEM encoded TLS private key");
-                println!("      --padding-scheme FILE  Path to padding scheme file");
+                println!(
+                    "      --padding-scheme FILE  Path to padding scheme file (reloaded on SIGHUP)"
+                );
+                println!("      --downstream-padding   Also pad writes to clients that support it");
+                println!(
+                    "      --downstream-padding-scheme FILE  Separate scheme for writes to clients"
+                );
+                println!("      --cover-traffic        Send cover traffic on idle sessions");
+                println!(
+                    "      --cover-traffic-idle SECS  Idle time before cover traffic starts (default: 5)"
+                );
+                println!(
+                    "      --cover-traffic-interval MS[-MS]  Gap between cover frames (default: 1000-8000)"
+                );
+                println!(
+                    "      --cover-traffic-sizes SIZES  Cover frame sizes, e.g. 64-1200 (default)"
+                );
+                println!(
+                    "      --cover-traffic-rate BYTES  Cover bytes per second cap (default: 4096)"
+                );
+                println!(
+                    "      --metrics-listen ADDRESS  Serve Prometheus metrics on ADDRESS/metrics"
+                );
+                println!(
+                    "      --admin-listen ADDRESS Serve the admin API on a loopback ADDRESS or unix:PATH"
+                );
+                println!("      --admin-token TOKEN    Bearer token required by the admin API");
+                println!("      --admin-token-file FILE  Read the admin API token from FILE");
+                println!(
+                    "      --drain-timeout SECS   Time active streams get on shutdown (default: 30)"
+                );
+                println!("      --max-sessions COUNT   Concurrent sessions (default: unlimited)");
+                println!(
+                    "      --max-connections-per-ip COUNT   Concurrent connections per source IP"
+                );
+                println!("      --max-streams-per-session COUNT  Open streams per session");
+                println!(
+                    "      --max-syn-per-sec COUNT          New streams per session per second"
+                );
+                println!(
+                    "      --tls-handshake-timeout SECS     TLS handshake deadline (default: 10)"
+                );
+                println!(
+                    "      --auth-timeout SECS              Authentication deadline (default: 10)"
+                );
+                println!(
+                    "      --first-frame-timeout SECS       First frame deadline (default: 30)"
+                );
+                println!(
+                    "      --connect-timeout SECS           Outbound connect deadline (default: 15)"
+                );
+                println!(
+                    "      --dns-timeout SECS               Outbound DNS deadline (default: 10)"
+                );
+                println!(
+                    "      --stream-idle-timeout SECS       Close streams idle this long (default: off)"
+                );
+                println!(
+                    "      --max-stream-lifetime SECS       Close streams open this long (default: off)"
+                );
+                println!(
+                    "      --auth-ban-failures COUNT        Ban a source after COUNT failed logins (default: 5)"
+                );
+                println!(
+                    "      --auth-ban-window SECS           Period the failures count over (default: 600)"
+                );
+                println!("      --auth-ban-duration SECS         Ban length (default: 3600)");
+                println!(
+                    "      --auth-ban-file FILE             Keep the ban list in FILE across restarts"
+                );
                 println!(
                     "  -I, --idle-session-check-interval SECS  Hint for clients (default: 30)"
                 );
@@ -140,7 +387,9 @@ async fn main() -> Result<()> {
                 {
                     println!();
                     println!("Signal Handling:");
-                    println!("      SIGHUP                Manually reload TLS certificates");
+                    println!(
+                        "      SIGHUP                Reload TLS certificates, user table, ACL and padding scheme"
+                    );
                 }
                 println!();
                 println!("Other:");
@@ -155,24 +404,60 @@ async fn main() -> Result<()> {
         }
     }
 
+    if !dns_servers.is_empty() {
+        cli.dns = Some(dns_servers);
+    }
+
+    // Command line flags override values from the config file
+    let file_config = match config_path.as_deref() {
+        Some(path) => ServerFileConfig::load(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load config file: {}", e))?,
+        None => ServerFileConfig::default(),
+    };
+    let config = file_config.merge(cli);
+    config
+        .validate()
+        .map_err(|e| anyhow::anyhow!("Invalid configuration: {}", e))?;
+
+    let log_level = config.log_level.as_deref().unwrap_or("info");
+    let listen_addr = config
+        .listen
+        .clone()
+        .unwrap_or_else(|| "0.0.0.0:8443".to_string());
+    let watch_cert = config.watch_cert.unwrap_or(false);
+    let expiry_warning_days = config.expiry_warning_days.unwrap_or(30);
+    let dns_servers = config.dns.clone().unwrap_or_default();
+
     // Initialize tracing with configured log level
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&log_level)),
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(log_level)),
         )
         .init();
 
-    let password = password.context("Password is required (use -p or --password)")?;
+    if config.password.is_none() && config.users_file.is_none() && config.users.is_none() {
+        anyhow::bail!("Password is required (use -p/--password, --users or --config)");
+    }
+    let users = config
+        .user_table()
+        .map_err(|e| anyhow::anyhow!("Failed to load users: {}", e))?;
+    info!("[Server] {} user(s) configured", users.len());
+
+    // Without an ACL file private and loopback destinations are blocked
+    let acl = match config.acl_file.as_ref() {
+        Some(path) => {
+            let acl = OutboundAcl::from_file(path)
+                .map_err(|e| anyhow::anyhow!("Failed to load ACL: {}", e))?;
+            info!("[Server] {} outbound ACL rule(s) loaded", acl.len());
+            acl
+        }
+        None => OutboundAcl::default(),
+    };
 
     // Load padding scheme if provided
-    let padding = if let Some(file_path) = padding_scheme_file {
-        let scheme_bytes = std::fs::read(&file_path)
-            .with_context(|| format!("Failed to read padding scheme file: {}", file_path))?;
-        let factory = PaddingFactory::new(&scheme_bytes)
-            .map_err(|e| anyhow::anyhow!("Failed to parse padding scheme: {}", e))?;
-        info!("[Server] Loaded padding scheme from: {}", file_path);
-        Arc::new(factory)
+    let padding = if let Some(file_path) = config.padding_scheme.as_ref() {
+        load_padding_scheme(file_path)?
     } else {
         PaddingFactory::default()
     };
@@ -187,25 +472,30 @@ async fn main() -> Result<()> {
     info!("{APP_NAME} v{VERSION}");
 
     // Create TLS acceptor with optional certificate reloading
-    let (tls_acceptor_ref, cert_reloader) = match (cert_path.as_deref(), key_path.as_deref()) {
+    let (tls_acceptor_ref, cert_reloader) = match (config.cert.as_ref(), config.key.as_ref()) {
         (Some(cert), Some(key)) => {
-            info!("[Server] Loading TLS certificate from {}", cert);
+            info!("[Server] Loading TLS certificate from {}", cert.display());
 
             // Create certificate reloader
-            let config = CertReloaderConfig {
-                cert_path: PathBuf::from(cert),
-                key_path: PathBuf::from(key),
+            let reloader_config = CertReloaderConfig {
+                cert_path: cert.clone(),
+                key_path: key.clone(),
                 watch_enabled: watch_cert,
                 debounce_ms: 500,
                 check_expiry: true,
                 expiry_warning_days,
             };
 
-            let reloader = CertReloader::new(config)
-                .with_context(|| format!("Failed to load certificate/key: {cert}, {key}"))?;
+            let reloader = CertReloader::new(reloader_config).with_context(|| {
+                format!(
+                    "Failed to load certificate/key: {}, {}",
+                    cert.display(),
+                    key.display()
+                )
+            })?;
 
             // Show certificate info if requested
-            if show_cert_info {
+            if config.show_cert_info.unwrap_or(false) {
                 reloader.show_cert_info();
             }
 
@@ -214,8 +504,14 @@ async fn main() -> Result<()> {
         }
         (None, None) => {
             info!("[Server] No certificate provided, generating self-signed certificate");
-            let tls_config =
-                create_server_config().context("Failed to create TLS server config")?;
+            let (cert, key) =
+                generate_key_pair().context("Failed to generate self-signed certificate")?;
+            info!(
+                "[Server] Self-signed certificate SHA256: {} (use with client --pin)",
+                format_fingerprint(&certificate_fingerprint(&cert))
+            );
+            let tls_config = create_server_config_with_cert(cert, key)
+                .context("Failed to create TLS server config")?;
             let tls_acceptor = Arc::new(TlsAcceptor::from(tls_config));
             (Arc::new(std::sync::RwLock::new(tls_acceptor)), None)
         }
@@ -227,13 +523,13 @@ async fn main() -> Result<()> {
     info!("Listening on {}", listen_addr);
 
     let mut server_settings_map = StringMap::new();
-    if let Some(interval) = idle_session_check_interval {
+    if let Some(interval) = config.idle_session_check_interval {
         server_settings_map.insert("idle_session_check_interval", interval.to_string());
     }
-    if let Some(timeout) = idle_session_timeout {
+    if let Some(timeout) = config.idle_session_timeout {
         server_settings_map.insert("idle_session_timeout", timeout.to_string());
     }
-    if let Some(min_idle) = min_idle_session {
+    if let Some(min_idle) = config.min_idle_session {
         server_settings_map.insert("min_idle_session", min_idle.to_string());
     }
     let server_settings = if server_settings_map.is_empty() {
@@ -243,8 +539,97 @@ async fn main() -> Result<()> {
     };
 
     // Create and start server
-    let server =
-        Server::new_with_reloadable_tls(&password, tls_acceptor_ref, padding, server_settings);
+    let users_ref = Arc::new(std::sync::RwLock::new(Arc::new(users)));
+    let mut server = Server::new_with_users(
+        Arc::clone(&users_ref),
+        tls_acceptor_ref,
+        padding,
+        server_settings,
+    );
+    server = server.with_outbound_acl(acl);
+    if let Some(path) = config.downstream_padding_scheme.as_ref() {
+        info!("[Server] Padding writes to clients with a separate scheme");
+        server =
+            server.with_downstream_padding(DownstreamPadding::Scheme(load_padding_scheme(path)?));
+    } else if config.downstream_padding.unwrap_or(false) {
+        info!("[Server] Padding writes to clients");
+        server = server.with_downstream_padding(DownstreamPadding::SessionScheme);
+    }
+    if let Some(cover) = config.cover_traffic_config()? {
+        info!(
+            "[Server] Sending cover traffic on sessions idle for {:?} (every {:?}-{:?}, up to {} B/s)",
+            cover.idle_after, cover.min_interval, cover.max_interval, cover.max_bytes_per_sec
+        );
+        server = server.with_cover_traffic(cover);
+    }
+    let limits = config.limits();
+    if limits.is_limited() {
+        info!("[Server] Limits: {}", limits);
+    }
+    let timeouts = config.timeouts();
+    if timeouts != TimeoutPolicy::default() {
+        info!("[Server] Timeouts: {}", timeouts);
+    }
+    server = server
+        .with_limits(limits)
+        .with_handshake_timeouts(config.handshake_timeouts())
+        .with_timeouts(timeouts);
+    if let Some(bans) = config.auth_bans()? {
+        let ban_config = bans.config();
+        info!(
+            "[Server] Banning sources for {:?} after {} failed authentications within {:?} ({} restored)",
+            ban_config.ban_duration,
+            ban_config.max_failures,
+            ban_config.window,
+            bans.bans().len()
+        );
+        server = server.with_auth_bans(Arc::new(bans));
+    }
+    let acl_ref = server.get_acl_ref();
+    let padding_handle = server.padding_handle();
+    if let Some(addr) = config.fallback.clone() {
+        info!("[Server] Unauthenticated connections fall back to {}", addr);
+        server = server.with_fallback(addr);
+    }
+    let mut shutdown = ShutdownHandle::new();
+    if let Some(secs) = config.drain_timeout {
+        shutdown = shutdown.with_drain_timeout(Duration::from_secs(secs));
+    }
+    server = server.with_shutdown(shutdown.clone());
+
+    if let Some(ref reloader) = cert_reloader {
+        let reloader = Arc::clone(reloader);
+        METRICS.set_cert_reload_source(move || reloader.get_reload_count());
+    }
+    if let Some(addr) = config.metrics_listen.as_deref() {
+        spawn_metrics_server(addr)
+            .await
+            .context("Failed to start metrics endpoint")?;
+    }
+    if let Some(listen) = config.admin_listen.as_deref() {
+        let token = config
+            .admin_token()?
+            .context("--admin-listen requires --admin-token or --admin-token-file")?;
+        let mut api = AdminApi::new(&server, &token)?;
+        if let Some(ref reloader) = cert_reloader {
+            api = api.with_cert_reloader(Arc::clone(reloader));
+        }
+        match listen.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => {
+                spawn_admin_unix_server(path, api)
+                    .await
+                    .context("Failed to start admin API")?;
+            }
+            #[cfg(not(unix))]
+            Some(_) => anyhow::bail!("Unix socket admin listeners require a Unix platform"),
+            None => {
+                spawn_admin_server(listen, api)
+                    .await
+                    .context("Failed to start admin API")?;
+            }
+        }
+    }
 
     // Start certificate file watching if enabled
     if let Some(ref reloader) = cert_reloader
@@ -265,7 +650,13 @@ async fn main() -> Result<()> {
 
     // Setup SIGHUP signal handler for manual reload (Unix only)
     #[cfg(unix)]
-    if let Some(reloader) = cert_reloader.clone() {
+    if cert_reloader.is_some()
+        || config.users_file.is_some()
+        || config.acl_file.is_some()
+        || config.padding_scheme.is_some()
+    {
+        let reloader = cert_reloader.clone();
+        let user_config = config.clone();
         tokio::spawn(async move {
             let mut sighup = match signal(SignalKind::hangup()) {
                 Ok(s) => s,
@@ -275,21 +666,64 @@ async fn main() -> Result<()> {
                 }
             };
 
-            info!("[Server] SIGHUP handler ready (send SIGHUP to reload certificates)");
+            info!(
+                "[Server] SIGHUP handler ready (send SIGHUP to reload certificates/users/ACL/padding)"
+            );
 
             loop {
                 sighup.recv().await;
-                info!("[Server] SIGHUP received, reloading certificates...");
 
-                match reloader.reload() {
-                    Ok(_) => {
-                        info!("[Server] Certificate reload successful");
-                        if let Some(info) = reloader.get_cert_info() {
-                            info!("[Server] New certificate: {}", info.summary());
+                if let Some(reloader) = reloader.as_ref() {
+                    info!("[Server] SIGHUP received, reloading certificates...");
+                    match reloader.reload() {
+                        Ok(_) => {
+                            info!("[Server] Certificate reload successful");
+                            if let Some(info) = reloader.get_cert_info() {
+                                info!("[Server] New certificate: {}", info.summary());
+                            }
+                        }
+                        Err(e) => {
+                            error!("[Server] Certificate reload failed: {}", e);
                         }
                     }
-                    Err(e) => {
-                        error!("[Server] Certificate reload failed: {}", e);
+                }
+
+                if user_config.users_file.is_some() {
+                    info!("[Server] SIGHUP received, reloading user table...");
+                    match user_config.user_table() {
+                        Ok(table) => {
+                            info!("[Server] User table reloaded ({} user(s))", table.len());
+                            *users_ref.write().unwrap() = Arc::new(table);
+                        }
+                        Err(e) => {
+                            error!("[Server] User table reload failed, keeping current: {}", e);
+                        }
+                    }
+                }
+
+                if let Some(path) = user_config.acl_file.as_ref() {
+                    info!("[Server] SIGHUP received, reloading ACL...");
+                    match OutboundAcl::from_file(path) {
+                        Ok(acl) => {
+                            info!("[Server] ACL reloaded ({} rule(s))", acl.len());
+                            *acl_ref.write().unwrap() = Arc::new(acl);
+                        }
+                        Err(e) => {
+                            error!("[Server] ACL reload failed, keeping current: {}", e);
+                        }
+                    }
+                }
+
+                if let Some(path) = user_config.padding_scheme.as_ref() {
+                    info!("[Server] SIGHUP received, reloading padding scheme...");
+                    match load_padding_scheme(path) {
+                        Ok(padding) => padding_handle.update(padding),
+                        Err(e) => {
+                            error!(
+                                "[Server] Padding scheme reload failed, keeping current: {:#}",
+                                e
+                            );
+                        }
                     }
                 }
             }
@@ -303,6 +737,16 @@ async fn main() -> Result<()> {
         );
     }
 
+    // Stop accepting and drain sessions on Ctrl-C / SIGTERM
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!(
+            "[Server] Shutdown signal received, draining (timeout {}s)",
+            shutdown.drain_timeout().as_secs()
+        );
+        shutdown.shutdown();
+    });
+
     // Start listening
     server
         .listen(&listen_addr)
@@ -312,6 +756,29 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Wait for Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = match signal(SignalKind::terminate()) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Failed to setup SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
 fn parse_u64(value: &str, flag: &str) -> Result<u64> {
     let parsed = value
         .parse::<u64>()
@@ -322,6 +789,11 @@ fn parse_u64(value: &str, flag: &str) -> Result<u64> {
     Ok(parsed)
 }
 
+fn parse_limit<T: TryFrom<u64>>(value: &str, flag: &str) -> Result<T> {
+    T::try_from(parse_u64(value, flag)?)
+        .map_err(|_| anyhow::anyhow!("{} value is too large: {}", flag, value))
+}
+
 fn parse_usize(value: &str, flag: &str) -> Result<usize> {
     value
         .parse::<usize>()
@@ -336,3 +808,12 @@ fn parse_dns_entries(value: &str) -> Vec<String> {
         .map(|entry| entry.to_string())
         .collect()
 }
+
+fn load_padding_scheme(path: &Path) -> Result<Arc<PaddingFactory>> {
+    let scheme_bytes = std::fs::read(path)
+        .with_context(|| format!("Failed to read padding scheme file: {}", path.display()))?;
+    let factory = PaddingFactory::new(&scheme_bytes)
+        .map_err(|e| anyhow::anyhow!("Failed to parse padding scheme: {}", e))?;
+    info!("[Server] Loaded padding scheme from: {}", path.display());
+    Ok(Arc::new(factory))
+}
@@ -1,24 +1,33 @@
 //! AnyTLS Client implementation
 
-use crate::client::{SessionPool, SessionPoolConfig};
+use crate::client::upstream::{Upstream, candidate_order};
+use crate::client::{
+    DEFAULT_HEALTH_COOLDOWN, LoadBalanceStrategy, RouteAction, Router, SessionPoolConfig,
+    UpstreamEndpoint, UpstreamStatus,
+};
 use crate::padding::PaddingFactory;
-use crate::session::{Session, SessionHeartbeatConfig};
-use crate::util::{AnyTlsError, Result, configure_tcp_stream, hash_password, send_authentication};
+use crate::session::{CoverTrafficConfig, FlowControlConfig, Session, SessionHeartbeatConfig};
+use crate::util::{
+    AnyTlsError, METRICS, Result, TimeoutPolicy, configure_tcp_stream, send_authentication,
+};
 use std::net::{Ipv4Addr, Ipv6Addr};
-use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, RwLock};
 use tokio::net::TcpStream;
 use tokio::time::Duration;
 use tokio_rustls::rustls::pki_types::ServerName;
 
 /// Client manages connections to AnyTLS servers
 pub struct Client {
-    password_hash: [u8; 32],
-    server_addr: String,
-    server_name: ServerName<'static>,
-    tls_config: Arc<tokio_rustls::TlsConnector>,
-    padding: Arc<PaddingFactory>,
-    session_pool: Arc<SessionPool>,
+    upstreams: Vec<Arc<Upstream>>,
+    strategy: LoadBalanceStrategy,
+    next_upstream: AtomicUsize,
+    health_cooldown: Duration,
     pool_config: SessionPoolConfig,
+    cover_traffic: Option<CoverTrafficConfig>,
+    flow_control: FlowControlConfig,
+    router: Arc<RwLock<Arc<Router>>>,
+    timeouts: TimeoutPolicy,
 }
 
 impl Client {
@@ -49,20 +58,124 @@ impl Client {
         padding: Arc<PaddingFactory>,
         pool_config: crate::client::SessionPoolConfig,
     ) -> Self {
-        let password_hash = hash_password(password);
-        let session_pool = Arc::new(SessionPool::with_config(pool_config.clone()));
+        let endpoint =
+            UpstreamEndpoint::new(password, server_addr, server_name, tls_config, padding);
+        Self::from_endpoints(vec![endpoint], pool_config)
+    }
 
-        tracing::debug!("[Client] Creating new client for server: {}", server_addr);
+    /// Create a client balancing over several upstream servers
+    ///
+    /// Each endpoint gets its own session pool built from `pool_config`.
+    /// Endpoints are used in the given order unless
+    /// [`with_strategy`](Self::with_strategy) selects another strategy.
+    pub fn with_endpoints(
+        endpoints: Vec<UpstreamEndpoint>,
+        pool_config: SessionPoolConfig,
+    ) -> Result<Self> {
+        if endpoints.is_empty() {
+            return Err(AnyTlsError::Config(
+                "At least one upstream server is required".into(),
+            ));
+        }
+        Ok(Self::from_endpoints(endpoints, pool_config))
+    }
+
+    fn from_endpoints(endpoints: Vec<UpstreamEndpoint>, pool_config: SessionPoolConfig) -> Self {
+        let upstreams = endpoints
+            .into_iter()
+            .map(|endpoint| {
+                tracing::debug!(
+                    "[Client] Creating new client for server: {}",
+                    endpoint.server_addr()
+                );
+                Arc::new(Upstream::new(endpoint, pool_config.clone()))
+            })
+            .collect();
 
         Self {
-            password_hash,
-            server_addr,
-            server_name,
-            tls_config,
-            padding,
-            session_pool,
+            upstreams,
+            strategy: LoadBalanceStrategy::default(),
+            next_upstream: AtomicUsize::new(0),
+            health_cooldown: DEFAULT_HEALTH_COOLDOWN,
             pool_config,
+            cover_traffic: None,
+            flow_control: FlowControlConfig::default(),
+            router: Arc::new(RwLock::new(Arc::new(Router::default()))),
+            timeouts: TimeoutPolicy::default(),
+        }
+    }
+
+    /// Choose how new sessions are spread over upstream servers
+    pub fn with_strategy(mut self, strategy: LoadBalanceStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set how long an upstream stays marked down after a connect/TLS failure
+    pub fn with_health_cooldown(mut self, cooldown: Duration) -> Self {
+        self.health_cooldown = cooldown;
+        self
+    }
+
+    /// Send cover traffic on sessions while they are idle
+    ///
+    /// Servers drop the Waste frames, so this works with any server.
+    pub fn with_cover_traffic(mut self, config: CoverTrafficConfig) -> Self {
+        self.cover_traffic = Some(config);
+        self
+    }
+
+    /// Set the buffer limits of new sessions
+    ///
+    /// See [`FlowControlConfig::overflow`] for the choice between pausing a
+    /// session behind a slow stream and resetting that stream.
+    pub fn with_flow_control(mut self, config: FlowControlConfig) -> Self {
+        self.flow_control = config;
+        self
+    }
+
+    /// Set the SYNACK, direct connect and DNS timeouts and the idle and
+    /// lifetime limits of streams relayed by the SOCKS5 and HTTP proxies
+    pub fn with_timeouts(mut self, timeouts: TimeoutPolicy) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Timeouts in use
+    pub fn timeouts(&self) -> &TimeoutPolicy {
+        &self.timeouts
+    }
+
+    /// Load balancing strategy in use
+    pub fn strategy(&self) -> LoadBalanceStrategy {
+        self.strategy
+    }
+
+    /// Health and load of every upstream server, in configuration order
+    pub async fn upstream_status(&self) -> Vec<UpstreamStatus> {
+        let mut status = Vec::with_capacity(self.upstreams.len());
+        for upstream in &self.upstreams {
+            status.push(upstream.status().await);
         }
+        status
+    }
+
+    /// Route SOCKS5/HTTP requests with `router` instead of proxying everything
+    pub fn with_router(self, router: Router) -> Self {
+        *self.router.write().unwrap() = Arc::new(router);
+        self
+    }
+
+    /// Get router reference for hot-reloading
+    ///
+    /// New rules apply to requests accepted after the swap.
+    pub fn get_router_ref(&self) -> Arc<RwLock<Arc<Router>>> {
+        Arc::clone(&self.router)
+    }
+
+    /// Decide how the local proxies handle a request for `host:port`
+    pub fn route(&self, host: &str, port: u16) -> RouteAction {
+        self.router.read().unwrap().route(host, port)
     }
 
     /// Create a new stream by establishing or reusing a session
@@ -70,6 +183,17 @@ impl Client {
     pub async fn create_proxy_stream(
         &self,
         destination: (String, u16),
+    ) -> Result<(Arc<crate::session::Stream>, Arc<crate::session::Session>)> {
+        self.open_proxy_stream(destination, bytes::Bytes::new())
+            .await
+    }
+
+    /// Like [`Client::create_proxy_stream`], sending `initial` in the same frame
+    /// as the destination address so the server sees it before the SYNACK
+    pub(crate) async fn open_proxy_stream(
+        &self,
+        destination: (String, u16),
+        initial: bytes::Bytes,
     ) -> Result<(Arc<crate::session::Stream>, Arc<crate::session::Session>)> {
         tracing::debug!(
             "[Client] create_proxy_stream: {}:{}",
@@ -126,6 +250,7 @@ impl Client {
         // Write port (2 bytes, big-endian)
         tracing::trace!("[Client] Writing port: {}", port);
         addr_bytes.extend_from_slice(&port.to_be_bytes());
+        addr_bytes.extend_from_slice(&initial);
 
         // Use session's write_data_frame to send the address bytes
         // This avoids the need to unwrap Arc<Stream> which fails when multiple references exist
@@ -157,9 +282,9 @@ impl Client {
         );
 
         // Wait for SYNACK with timeout (30 seconds default)
-        const DEFAULT_SYNACK_TIMEOUT: Duration = Duration::from_secs(30);
+        let synack_timeout = self.timeouts.synack;
 
-        match tokio::time::timeout(DEFAULT_SYNACK_TIMEOUT, synack_rx).await {
+        match tokio::time::timeout(synack_timeout, synack_rx).await {
             Ok(Ok(Ok(()))) => {
                 tracing::debug!(
                     "[Client] SYNACK received for stream {} - stream ready",
@@ -169,58 +294,92 @@ impl Client {
             }
             Ok(Ok(Err(e))) => {
                 tracing::error!("[Client] SYNACK error for stream {}: {}", stream_id, e);
+                METRICS.synack_errors.inc();
                 let error_msg = e.to_string();
                 let error = AnyTlsError::Protocol(error_msg.clone());
                 stream.close_with_error(error).await;
+                session.release_stream(stream_id).await;
                 Err(AnyTlsError::Protocol(error_msg))
             }
             Ok(Err(_)) => {
                 tracing::error!("[Client] SYNACK channel closed for stream {}", stream_id);
+                METRICS.synack_errors.inc();
                 let error = AnyTlsError::Protocol("SYNACK channel closed".into());
                 stream.close_with_error(error).await;
+                session.release_stream(stream_id).await;
                 Err(AnyTlsError::Protocol("SYNACK channel closed".into()))
             }
             Err(_) => {
                 tracing::error!(
-                    "[Client] SYNACK timeout for stream {} after {}s",
+                    "[Client] SYNACK timeout for stream {} after {:?}",
                     stream_id,
-                    DEFAULT_SYNACK_TIMEOUT.as_secs()
+                    synack_timeout
                 );
-                let error_msg =
-                    format!("SYNACK timeout after {}s", DEFAULT_SYNACK_TIMEOUT.as_secs());
+                METRICS.synack_timeouts.inc();
+                let error_msg = format!("SYNACK timeout after {:?}", synack_timeout);
                 let error = AnyTlsError::Protocol(error_msg.clone());
                 stream.close_with_error(error).await;
+                session.release_stream(stream_id).await;
                 Err(AnyTlsError::Protocol(error_msg))
             }
         }
     }
 
     /// Create a new stream by establishing or reusing a session
+    ///
+    /// Upstreams are tried in the order chosen by the load balancing strategy;
+    /// a connect or TLS failure marks the upstream down and moves on to the
+    /// next one.
     pub async fn create_stream(&self) -> Result<Arc<Session>> {
-        // Try to get an idle session from pool
-        if let Some(session) = self.session_pool.get_idle_session().await {
-            tracing::debug!("[Client] Reusing idle session from pool");
-            return Ok(session);
+        let candidates = candidate_order(&self.upstreams, self.strategy, &self.next_upstream).await;
+        let mut last_error = None;
+
+        for upstream in candidates {
+            // Try to get an idle session from pool
+            if let Some(session) = upstream.pool.get_idle_session().await {
+                tracing::debug!(
+                    "[Client] Reusing idle session from pool of {}",
+                    upstream.endpoint.server_addr()
+                );
+                return Ok(session);
+            }
+
+            tracing::debug!("[Client] No idle session found, creating new session");
+            match self.create_new_session(&upstream).await {
+                Ok(session) => {
+                    upstream.mark_healthy();
+                    return Ok(session);
+                }
+                Err(e @ (AnyTlsError::Io(_) | AnyTlsError::Tls(_))) => {
+                    upstream.mark_failed(self.health_cooldown);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
         }
 
-        tracing::debug!("[Client] No idle session found, creating new session");
-        // Create new session
-        self.create_new_session().await
+        Err(last_error
+            .unwrap_or_else(|| AnyTlsError::Config("No upstream server configured".into())))
     }
 
-    /// Create a new session with the server
-    async fn create_new_session(&self) -> Result<Arc<Session>> {
-        tracing::debug!("[Client] Creating new session to {}", self.server_addr);
+    /// Create a new session with an upstream server
+    async fn create_new_session(&self, upstream: &Upstream) -> Result<Arc<Session>> {
+        let endpoint = &upstream.endpoint;
+        tracing::debug!("[Client] Creating new session to {}", endpoint.server_addr);
 
         // Establish TCP connection
         tracing::trace!(
             "[Client] Connecting TCP to {} (this may trigger DNS lookup)",
-            self.server_addr
+            endpoint.server_addr
         );
-        let tcp_stream = match TcpStream::connect(&self.server_addr).await {
+        let tcp_stream = match TcpStream::connect(&endpoint.server_addr).await {
             Ok(stream) => stream,
             Err(e) => {
-                tracing::error!("[Client] Failed to connect to {}: {}", self.server_addr, e);
+                tracing::error!(
+                    "[Client] Failed to connect to {}: {}",
+                    endpoint.server_addr,
+                    e
+                );
 
                 // Provide helpful error messages
                 let error_str = format!("{}", e);
@@ -228,26 +387,29 @@ impl Client {
                     || error_str.contains("DNS")
                     || error_str.contains("Try again")
                 {
-                    tracing::error!("[Client] DNS resolution failed for '{}'", self.server_addr);
+                    tracing::error!(
+                        "[Client] DNS resolution failed for '{}'",
+                        endpoint.server_addr
+                    );
                     tracing::error!("[Client] Troubleshooting steps:");
                     tracing::error!(
                         "[Client]   1. Check if server address is correct: {}",
-                        self.server_addr
+                        endpoint.server_addr
                     );
                     tracing::error!("[Client]   2. Try using IP address instead of hostname");
                     tracing::error!(
                         "[Client]   3. Test DNS: nslookup $(echo {} | cut -d: -f1)",
-                        self.server_addr
+                        endpoint.server_addr
                     );
                     tracing::error!(
                         "[Client]   4. Test TCP connection: nc -zv $(echo {} | cut -d: -f1) $(echo {} | cut -d: -f2)",
-                        self.server_addr,
-                        self.server_addr
+                        endpoint.server_addr,
+                        endpoint.server_addr
                     );
                 } else if error_str.contains("Connection refused") {
                     tracing::error!(
                         "[Client] Connection refused. Server may not be running or not listening on {}",
-                        self.server_addr
+                        endpoint.server_addr
                     );
                 } else if error_str.contains("Connection timed out") {
                     tracing::error!(
@@ -258,20 +420,20 @@ impl Client {
                 return Err(AnyTlsError::Io(e));
             }
         };
-        configure_tcp_stream(&tcp_stream, &self.server_addr);
+        configure_tcp_stream(&tcp_stream, &endpoint.server_addr);
 
         tracing::debug!(
             "[Client] TCP connection established to {}",
-            self.server_addr
+            endpoint.server_addr
         );
 
         // Perform TLS handshake
-        let server_name = self.server_name.clone();
+        let server_name = endpoint.server_name.clone();
         tracing::trace!(
             "[Client] Starting TLS handshake using SNI {:?}",
             server_name
         );
-        let tls_stream = self
+        let tls_stream = endpoint
             .tls_config
             .connect(server_name, tcp_stream)
             .await
@@ -285,7 +447,8 @@ impl Client {
         // Split TLS stream into reader and writer
         let (reader, mut writer) = tokio::io::split(tls_stream);
         tracing::trace!("[Client] Sending authentication");
-        send_authentication(&mut writer, &self.password_hash, &self.padding).await?;
+        let padding = endpoint.padding.read().await.clone();
+        send_authentication(&mut writer, &endpoint.password_hash, &padding).await?;
         tracing::debug!("[Client] Authentication sent successfully");
 
         // Create session with reader and writer
@@ -293,12 +456,12 @@ impl Client {
             interval: self.pool_config.check_interval,
             timeout: self.pool_config.idle_timeout,
         };
-        let session = Arc::new(Session::new_client(
-            reader,
-            writer,
-            self.padding.clone(),
-            Some(heartbeat_config),
-        ));
+        let mut session = Session::new_client(reader, writer, padding, Some(heartbeat_config));
+        // Scheme updates from the server carry over to later sessions
+        session.set_padding_ref(Arc::clone(&endpoint.padding));
+        session.set_cover_traffic(self.cover_traffic.clone());
+        session.set_flow_control(self.flow_control);
+        let session = Arc::new(session);
 
         // Set sequence number for pool ordering (use timestamp-based counter)
         static SEQ_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
@@ -312,7 +475,8 @@ impl Client {
         tracing::debug!("[Client] Client session started successfully");
 
         // Store in pool
-        self.session_pool.add_idle_session(session.clone()).await;
+        upstream.track_session(&session);
+        upstream.pool.add_idle_session(session.clone()).await;
         tracing::debug!("[Client] Session added to pool");
 
         Ok(session)
@@ -320,6 +484,8 @@ impl Client {
 
     /// Stop the background cleanup task in the session pool (primarily for tests)
     pub async fn stop_session_pool_cleanup(&self) {
-        self.session_pool.stop_cleanup_task().await;
+        for upstream in &self.upstreams {
+            upstream.pool.stop_cleanup_task().await;
+        }
     }
 }
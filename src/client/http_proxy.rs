@@ -3,27 +3,45 @@
 //! Supports CONNECT tunneling as well as forwarding HTTP requests
 //! via the AnyTLS stream pool.
 
-use crate::client::Client;
-use crate::util::{AnyTlsError, Result};
+use crate::client::{Client, RouteAction, connect_direct};
+use crate::util::{AnyTlsError, METRICS, RelayTimer, Result, ShutdownHandle};
 use bytes::Bytes;
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio_util::task::AbortOnDropHandle;
 
 const HEADER_TERMINATOR: &[u8] = b"\r\n\r\n";
 const MAX_HEADER_SIZE: usize = 64 * 1024;
 
 /// Start an HTTP proxy server that forwards traffic via AnyTLS.
 pub async fn start_http_proxy_server(listen_addr: &str, client: Arc<Client>) -> Result<()> {
+    start_http_proxy_server_with_shutdown(listen_addr, client, ShutdownHandle::new()).await
+}
+
+/// Start an HTTP proxy server that stops gracefully when `shutdown` is triggered.
+pub async fn start_http_proxy_server_with_shutdown(
+    listen_addr: &str,
+    client: Arc<Client>,
+    shutdown: ShutdownHandle,
+) -> Result<()> {
     let listener = TcpListener::bind(listen_addr).await?;
     tracing::info!("[HTTP] Listening on {}", listen_addr);
 
+    let mut connections = JoinSet::new();
     loop {
-        match listener.accept().await {
+        let accepted = tokio::select! {
+            biased;
+            _ = shutdown.triggered() => break,
+            Some(_) = connections.join_next() => continue,
+            accepted = listener.accept() => accepted,
+        };
+        match accepted {
             Ok((stream, addr)) => {
                 tracing::debug!("[HTTP] New connection from {}", addr);
                 let client_clone = Arc::clone(&client);
-                tokio::spawn(async move {
+                connections.spawn(async move {
                     if let Err(err) = handle_http_proxy_connection(stream, client_clone).await {
                         tracing::error!("[HTTP] Connection error: {}", err);
                     }
@@ -34,6 +52,14 @@ pub async fn start_http_proxy_server(listen_addr: &str, client: Arc<Client>) ->
             }
         }
     }
+
+    drop(listener);
+    tracing::info!(
+        "[HTTP] Shutdown requested, stopped accepting connections on {}",
+        listen_addr
+    );
+    shutdown.drain_tasks(&mut connections, "HTTP").await;
+    Ok(())
 }
 
 struct ParsedRequest {
@@ -66,6 +92,20 @@ async fn handle_http_proxy_connection(
         request.is_connect
     );
 
+    match client.route(&request.host, request.port) {
+        RouteAction::Block => {
+            tracing::info!(
+                "[HTTP] Blocked {}:{} by routing rules",
+                request.host,
+                request.port
+            );
+            send_http_error(&mut client_conn, 403, "Forbidden").await?;
+            return Ok(());
+        }
+        RouteAction::Direct => return relay_direct(client_conn, request, &client).await,
+        RouteAction::Proxy => {}
+    }
+
     let destination = (request.host.clone(), request.port);
     let (proxy_stream, session) = match client.create_proxy_stream(destination.clone()).await {
         Ok(res) => res,
@@ -91,8 +131,12 @@ async fn handle_http_proxy_connection(
 
     let (mut client_read, mut client_write) = tokio::io::split(client_conn);
     let proxy_stream_read = Arc::clone(&proxy_stream);
+    let proxy_stream_write = Arc::clone(&proxy_stream);
     let session_for_write = Arc::clone(&session);
     let stream_id = proxy_stream.id();
+    let timer = RelayTimer::new(client.timeouts());
+    let timer_for_read = timer.clone();
+    let timer_for_write = timer.clone();
 
     tracing::debug!(
         "[HTTP] Established tunnel for {}:{}, stream={}",
@@ -101,14 +145,18 @@ async fn handle_http_proxy_connection(
         stream_id
     );
 
-    let to_client = tokio::spawn(async move {
+    let to_client = AbortOnDropHandle::new(tokio::spawn(async move {
         let reader = proxy_stream_read.reader();
         let mut buf = vec![0u8; 8192];
         loop {
             let n = {
                 let mut guard = reader.lock().await;
                 match guard.read(&mut buf).await {
-                    Ok(0) => break,
+                    Ok(0) => {
+                        // The upstream finished sending; pass the EOF on
+                        let _ = client_write.shutdown().await;
+                        break;
+                    }
                     Ok(n) => n,
                     Err(e) => {
                         tracing::error!("[HTTP] Proxy stream read error: {}", e);
@@ -119,10 +167,11 @@ async fn handle_http_proxy_connection(
             if client_write.write_all(&buf[..n]).await.is_err() {
                 break;
             }
+            timer_for_read.touch();
         }
-    });
+    }));
 
-    let to_proxy = tokio::spawn(async move {
+    let to_proxy = AbortOnDropHandle::new(tokio::spawn(async move {
         let mut buf = vec![0u8; 8192];
         loop {
             let n = match client_read.read(&mut buf).await {
@@ -140,10 +189,31 @@ async fn handle_http_proxy_connection(
                 tracing::error!("[HTTP] Failed to send to proxy stream: {}", e);
                 break;
             }
+            timer_for_write.touch();
         }
-    });
-
-    let _ = tokio::join!(to_client, to_proxy);
+        // The client finished sending: half-close the stream
+        if let Err(e) = proxy_stream_write.shutdown_write() {
+            tracing::debug!("[HTTP] Failed to send FIN for stream {}: {}", stream_id, e);
+        }
+    }));
+
+    tokio::select! {
+        _ = async { tokio::join!(to_client, to_proxy) } => {}
+        expiry = timer.expired() => {
+            tracing::info!(
+                "[HTTP] Closing stream {} to {}:{}: {}",
+                stream_id,
+                request.host,
+                request.port,
+                expiry
+            );
+            METRICS.stream_timeouts.inc();
+            let _ = proxy_stream.shutdown_write();
+            proxy_stream
+                .close_with_error(AnyTlsError::Timeout(format!("stream {}", expiry)))
+                .await;
+        }
+    }
 
     tracing::debug!(
         "[HTTP] Connection to {}:{} closed (stream {})",
@@ -154,6 +224,60 @@ async fn handle_http_proxy_connection(
     Ok(())
 }
 
+/// Connect to the destination directly and relay data without AnyTLS
+async fn relay_direct(
+    mut client_conn: TcpStream,
+    request: ParsedRequest,
+    client: &Client,
+) -> Result<()> {
+    let mut outbound = match connect_direct(&request.host, request.port, client.timeouts()).await {
+        Ok(conn) => conn,
+        Err(err) => {
+            send_http_error(&mut client_conn, 502, "Bad Gateway").await?;
+            return Err(err);
+        }
+    };
+
+    if request.is_connect {
+        send_connect_success(&mut client_conn).await?;
+    } else {
+        outbound
+            .write_all(&build_forward_request(&request)?)
+            .await?;
+        outbound.write_all(&request.body).await?;
+    }
+
+    let timer = RelayTimer::new(client.timeouts());
+    match timer
+        .copy_bidirectional(&mut client_conn, &mut outbound)
+        .await
+    {
+        Ok((sent, received)) => tracing::debug!(
+            "[HTTP] Direct connection to {}:{} closed (sent {} bytes, received {} bytes)",
+            request.host,
+            request.port,
+            sent,
+            received
+        ),
+        Err(e @ AnyTlsError::Timeout(_)) => {
+            tracing::info!(
+                "[HTTP] Closing direct connection to {}:{}: {}",
+                request.host,
+                request.port,
+                e
+            );
+            METRICS.stream_timeouts.inc();
+        }
+        Err(e) => tracing::debug!(
+            "[HTTP] Direct connection to {}:{} ended: {}",
+            request.host,
+            request.port,
+            e
+        ),
+    }
+    Ok(())
+}
+
 async fn read_http_header(stream: &mut TcpStream) -> Result<(Vec<u8>, Vec<u8>)> {
     let mut buf = Vec::with_capacity(1024);
     let mut tmp = [0u8; 1024];
@@ -3,12 +3,16 @@
 #[allow(clippy::module_inception)]
 pub mod client;
 pub mod http_proxy;
+pub mod router;
 pub mod session_pool;
 pub mod socks5;
 pub mod udp_client;
+pub mod upstream;
 
 pub use client::*;
 pub use http_proxy::*;
+pub use router::*;
 pub use session_pool::*;
 pub use socks5::*;
 pub use udp_client::*;
+pub use upstream::*;
@@ -0,0 +1,330 @@
+//! Client-side routing rules
+//!
+//! The router decides for every SOCKS5/HTTP request whether to dial the
+//! destination directly, tunnel it through AnyTLS or reject it. Rules are
+//! evaluated in order and the first match wins; requests no rule matches use
+//! the default action (`proxy` unless a `default` line says otherwise).
+//!
+//! Rule file format, one rule per line:
+//!
+//! ```text
+//! # <direct|proxy|block> <matcher> <value>
+//! block  domain-keyword  doubleclick
+//! direct domain-suffix   lan
+//! direct domain          intranet.example.com
+//! direct ip-cidr         192.168.0.0/16
+//! proxy  port            443,8443
+//! default direct
+//! ```
+//!
+//! `ip-cidr` only matches destinations given as IP addresses: domain names
+//! are never resolved locally for routing, so DNS queries do not leak for
+//! proxied traffic. Domain matchers are case-insensitive.
+
+use crate::util::{
+    AnyTlsError, IpCidr, PortSet, Result, TimeoutPolicy, configure_tcp_stream,
+    domain_matches_suffix, normalize_domain, parse_domain_suffix, resolve_host_with_timeout,
+};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// How a request is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteAction {
+    /// Connect to the destination from the client machine
+    Direct,
+    /// Tunnel through the AnyTLS server
+    Proxy,
+    /// Refuse the request
+    Block,
+}
+
+impl FromStr for RouteAction {
+    type Err = AnyTlsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "direct" => Ok(RouteAction::Direct),
+            "proxy" => Ok(RouteAction::Proxy),
+            "block" | "reject" => Ok(RouteAction::Block),
+            other => Err(AnyTlsError::Config(format!(
+                "Unknown route action '{}' (expected direct, proxy or block)",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for RouteAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RouteAction::Direct => "direct",
+            RouteAction::Proxy => "proxy",
+            RouteAction::Block => "block",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RouteMatcher {
+    Domain(String),
+    DomainSuffix(String),
+    DomainKeyword(String),
+    IpCidr(IpCidr),
+    Port(PortSet),
+}
+
+impl RouteMatcher {
+    fn parse(kind: &str, value: &str) -> Result<Self> {
+        match kind.to_ascii_lowercase().as_str() {
+            "domain" => Ok(RouteMatcher::Domain(parse_domain_suffix(value)?)),
+            "domain-suffix" => Ok(RouteMatcher::DomainSuffix(parse_domain_suffix(value)?)),
+            "domain-keyword" => {
+                let keyword = normalize_domain(value);
+                if keyword.is_empty() {
+                    return Err(AnyTlsError::Config("Empty domain keyword".into()));
+                }
+                Ok(RouteMatcher::DomainKeyword(keyword))
+            }
+            "ip-cidr" => Ok(RouteMatcher::IpCidr(value.parse()?)),
+            "port" => Ok(RouteMatcher::Port(value.parse()?)),
+            other => Err(AnyTlsError::Config(format!(
+                "Unknown matcher '{}' (expected domain, domain-suffix, domain-keyword, ip-cidr or port)",
+                other
+            ))),
+        }
+    }
+
+    fn matches(&self, host: &str, ip: Option<IpAddr>, port: u16) -> bool {
+        match self {
+            RouteMatcher::IpCidr(cidr) => ip.is_some_and(|ip| cidr.contains(ip)),
+            RouteMatcher::Port(ports) => ports.contains(port),
+            _ if ip.is_some() => false,
+            RouteMatcher::Domain(domain) => normalize_domain(host) == *domain,
+            RouteMatcher::DomainSuffix(suffix) => domain_matches_suffix(host, suffix),
+            RouteMatcher::DomainKeyword(keyword) => normalize_domain(host).contains(keyword),
+        }
+    }
+}
+
+/// A single routing rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteRule {
+    action: RouteAction,
+    matcher: RouteMatcher,
+}
+
+impl RouteRule {
+    /// Action taken when this rule matches
+    pub fn action(&self) -> RouteAction {
+        self.action
+    }
+}
+
+impl FromStr for RouteRule {
+    type Err = AnyTlsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [action, kind, value] => Ok(Self {
+                action: action.parse()?,
+                matcher: RouteMatcher::parse(kind, value)?,
+            }),
+            _ => Err(AnyTlsError::Config(format!(
+                "Expected '<direct|proxy|block> <matcher> <value>', got '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Ordered routing rule set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Router {
+    rules: Vec<RouteRule>,
+    default_action: RouteAction,
+}
+
+impl Default for Router {
+    /// No rules: everything is proxied
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_action: RouteAction::Proxy,
+        }
+    }
+}
+
+impl Router {
+    /// Create a router that proxies everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse rules, one per line; `#` starts a comment
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut router = Self::default();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["default", action] => action.parse().map(|action| router.default_action = action),
+                _ => line.parse().map(|rule| router.rules.push(rule)),
+            };
+            parsed.map_err(|e| {
+                let message = match e {
+                    AnyTlsError::Config(message) => message,
+                    other => other.to_string(),
+                };
+                AnyTlsError::Config(format!("Routing rules line {}: {}", index + 1, message))
+            })?;
+        }
+        Ok(router)
+    }
+
+    /// Load rules from a file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            AnyTlsError::Config(format!(
+                "Failed to read routing rules {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Self::parse(&content)
+    }
+
+    /// Set the action for requests that no rule matches
+    pub fn with_default_action(mut self, action: RouteAction) -> Self {
+        self.default_action = action;
+        self
+    }
+
+    /// Append a rule after the existing ones
+    pub fn push(&mut self, rule: RouteRule) {
+        self.rules.push(rule);
+    }
+
+    /// Action for requests that no rule matches
+    pub fn default_action(&self) -> RouteAction {
+        self.default_action
+    }
+
+    /// Number of configured rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether no rules are configured
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Decide how to handle a request for `host:port`
+    ///
+    /// `host` is a domain name or an IP address as sent by the application.
+    pub fn route(&self, host: &str, port: u16) -> RouteAction {
+        let ip = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .ok();
+        self.rules
+            .iter()
+            .find(|rule| rule.matcher.matches(host, ip, port))
+            .map_or(self.default_action, |rule| rule.action)
+    }
+}
+
+/// Connect to `host:port` from the client machine for `direct` routes,
+/// within the DNS and connect timeouts of `timeouts`
+pub async fn connect_direct(host: &str, port: u16, timeouts: &TimeoutPolicy) -> Result<TcpStream> {
+    let target = format!("{}:{}", host, port);
+    let socket = match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port),
+        Err(_) => resolve_host_with_timeout(host, port, timeouts.dns).await?,
+    };
+    let stream = timeout(timeouts.connect, TcpStream::connect(socket))
+        .await
+        .map_err(|_| {
+            AnyTlsError::Protocol(format!(
+                "Connection timeout ({:?}) to {}",
+                timeouts.connect, target
+            ))
+        })?
+        .map_err(|e| AnyTlsError::Protocol(format!("Failed to connect to {}: {}", target, e)))?;
+    configure_tcp_stream(&stream, &target);
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_first_match_wins() {
+        let router = Router::parse(
+            "# comment\n\
+             block  domain-keyword doubleclick\n\
+             direct domain-suffix  lan\n\
+             direct domain         intranet.example.com\n\
+             direct ip-cidr        192.168.0.0/16\n\
+             proxy  port           443\n\
+             default direct\n",
+        )
+        .unwrap();
+        assert_eq!(router.len(), 5);
+        assert_eq!(router.default_action(), RouteAction::Direct);
+
+        assert_eq!(router.route("ad.doubleclick.net", 443), RouteAction::Block);
+        assert_eq!(router.route("NAS.lan", 443), RouteAction::Direct);
+        assert_eq!(
+            router.route("intranet.example.com", 443),
+            RouteAction::Direct
+        );
+        assert_eq!(
+            router.route("www.intranet.example.com", 443),
+            RouteAction::Proxy
+        );
+        assert_eq!(router.route("192.168.1.10", 443), RouteAction::Direct);
+        assert_eq!(router.route("8.8.8.8", 443), RouteAction::Proxy);
+        assert_eq!(router.route("example.org", 80), RouteAction::Direct);
+    }
+
+    #[test]
+    fn test_default_router_proxies_everything() {
+        let router = Router::default();
+        assert!(router.is_empty());
+        assert_eq!(router.route("example.com", 80), RouteAction::Proxy);
+        assert_eq!(router.route("127.0.0.1", 22), RouteAction::Proxy);
+    }
+
+    #[test]
+    fn test_ip_cidr_does_not_match_domains() {
+        let router = Router::parse("direct ip-cidr 0.0.0.0/0\ndirect ip-cidr ::/0").unwrap();
+        assert_eq!(router.route("localhost", 80), RouteAction::Proxy);
+        assert_eq!(router.route("[::1]", 80), RouteAction::Direct);
+    }
+
+    #[test]
+    fn test_parse_errors_report_line() {
+        for bad in [
+            "forward domain example.com",
+            "direct domain",
+            "direct host example.com",
+            "direct ip-cidr 10.0.0.0/40",
+            "proxy port 90-80",
+            "default maybe",
+        ] {
+            let err = Router::parse(&format!("proxy port 443\n{}", bad)).unwrap_err();
+            assert!(err.to_string().contains("line 2"), "{}: {}", bad, err);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 //! Session pool for connection reuse with configurable cleanup
 
 use crate::session::Session;
+use crate::util::{GaugeGuard, METRICS};
 use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -37,6 +38,8 @@ struct PooledSession {
     seq: u64,
     session: Arc<Session>,
     idle_since: Instant,
+    /// Counts the session in `pool_idle_sessions` while it sits in the pool
+    _idle: GaugeGuard,
 }
 
 /// SessionPool manages idle sessions for reuse with automatic cleanup
@@ -131,6 +134,7 @@ impl SessionPool {
             seq,
             session,
             idle_since: Instant::now(),
+            _idle: GaugeGuard::new(&METRICS.pool_idle_sessions),
         };
 
         let mut sessions = self.idle_sessions.write().await;
@@ -2,13 +2,29 @@
 //!
 //! Implements RFC 1928 SOCKS5 protocol to accept client connections
 //! and forward them through AnyTLS Stream
-
-use crate::client::Client;
-use crate::util::{AnyTlsError, Result};
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+//!
+//! Supports the CONNECT and UDP ASSOCIATE commands. A UDP association relays
+//! datagrams for each destination over its own UDP-over-TCP stream (or a
+//! local socket for `direct` routes) and ends when the control connection
+//! closes.
+
+use crate::client::udp_client::{encode_udp_packet, put_socks_addr, read_udp_packet};
+use crate::client::{Client, RouteAction, connect_direct};
+use crate::util::{
+    AnyTlsError, METRICS, RelayTimer, Result, ShutdownHandle, TimeoutPolicy,
+    resolve_host_with_cache,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::Duration;
+use tokio_util::task::AbortOnDropHandle;
 
 /// SOCKS5 version
 const SOCKS5_VERSION: u8 = 0x05;
@@ -18,11 +34,9 @@ const AUTH_NO_AUTHENTICATION: u8 = 0x00;
 const AUTH_NOT_ACCEPTABLE: u8 = 0xFF;
 
 /// SOCKS5 command types
-#[allow(dead_code)]
 const CMD_CONNECT: u8 = 0x01;
 #[allow(dead_code)]
 const CMD_BIND: u8 = 0x02;
-#[allow(dead_code)]
 const CMD_UDP_ASSOCIATE: u8 = 0x03;
 
 /// SOCKS5 address types
@@ -33,21 +47,31 @@ const ATYP_IPV6: u8 = 0x04;
 /// SOCKS5 reply codes
 const REPLY_SUCCEEDED: u8 = 0x00;
 const REPLY_GENERAL_FAILURE: u8 = 0x01;
-#[allow(dead_code)]
 const REPLY_CONNECTION_NOT_ALLOWED: u8 = 0x02;
 #[allow(dead_code)]
 const REPLY_NETWORK_UNREACHABLE: u8 = 0x03;
-#[allow(dead_code)]
 const REPLY_HOST_UNREACHABLE: u8 = 0x04;
 #[allow(dead_code)]
 const REPLY_CONNECTION_REFUSED: u8 = 0x05;
 #[allow(dead_code)]
 const REPLY_TTL_EXPIRED: u8 = 0x06;
-#[allow(dead_code)]
 const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
 #[allow(dead_code)]
 const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
 
+/// Largest datagram relayed for a UDP association
+const MAX_UDP_PACKET_SIZE: usize = 65535;
+
+/// Destinations a single UDP association may relay to at the same time
+const MAX_UDP_FLOWS: usize = 256;
+
+/// Datagrams queued per destination while its stream is being opened
+const UDP_FLOW_QUEUE: usize = 64;
+
+/// Idle time after which a UDP flow is closed when the client sets no
+/// stream idle timeout
+const UDP_FLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
 /// SOCKS5 address representation
 #[derive(Debug, Clone)]
 struct Socks5Addr {
@@ -57,17 +81,36 @@ struct Socks5Addr {
 
 /// Start SOCKS5 server that accepts connections and forwards them through Client
 pub async fn start_socks5_server(listen_addr: &str, client: Arc<Client>) -> Result<()> {
+    start_socks5_server_with_shutdown(listen_addr, client, ShutdownHandle::new()).await
+}
+
+/// Start SOCKS5 server that stops gracefully when `shutdown` is triggered
+///
+/// Open connections get the handle's drain timeout to finish before they
+/// are closed.
+pub async fn start_socks5_server_with_shutdown(
+    listen_addr: &str,
+    client: Arc<Client>,
+    shutdown: ShutdownHandle,
+) -> Result<()> {
     let listener = TcpListener::bind(listen_addr).await?;
 
     tracing::info!("[SOCKS5] Listening on {}", listen_addr);
 
+    let mut connections = JoinSet::new();
     loop {
-        match listener.accept().await {
+        let accepted = tokio::select! {
+            biased;
+            _ = shutdown.triggered() => break,
+            Some(_) = connections.join_next() => continue,
+            accepted = listener.accept() => accepted,
+        };
+        match accepted {
             Ok((stream, addr)) => {
                 tracing::debug!("[SOCKS5] New connection from {}", addr);
 
                 let client_clone = Arc::clone(&client);
-                tokio::spawn(async move {
+                connections.spawn(async move {
                     if let Err(e) = handle_socks5_connection(stream, client_clone).await {
                         tracing::error!("[SOCKS5] Connection error: {}", e);
                     }
@@ -78,6 +121,14 @@ pub async fn start_socks5_server(listen_addr: &str, client: Arc<Client>) -> Resu
             }
         }
     }
+
+    drop(listener);
+    tracing::info!(
+        "[SOCKS5] Shutdown requested, stopped accepting connections on {}",
+        listen_addr
+    );
+    shutdown.drain_tasks(&mut connections, "SOCKS5").await;
+    Ok(())
 }
 
 /// Handle a single SOCKS5 connection
@@ -92,14 +143,42 @@ async fn handle_socks5_connection(
 
     // Step 2: Read connection request
     tracing::debug!("[SOCKS5] Reading connection request");
-    let (dest_addr, _cmd) = read_connection_request(&mut client_conn).await?;
+    let (dest_addr, cmd) = read_connection_request(&mut client_conn).await?;
     tracing::debug!(
-        "[SOCKS5] Connection request: {}:{}",
+        "[SOCKS5] Connection request: cmd=0x{:02x} {}:{}",
+        cmd,
         dest_addr.addr,
         dest_addr.port
     );
+    match cmd {
+        CMD_CONNECT => {}
+        CMD_UDP_ASSOCIATE => return handle_udp_associate(client_conn, client, dest_addr).await,
+        _ => {
+            send_connection_reply(&mut client_conn, REPLY_COMMAND_NOT_SUPPORTED, dest_addr).await?;
+            return Err(AnyTlsError::Protocol(format!(
+                "Unsupported SOCKS5 command: 0x{:02x}",
+                cmd
+            )));
+        }
+    }
 
-    // Step 3: Create proxy connection through AnyTLS
+    // Step 3: Apply routing rules
+    match client.route(&dest_addr.addr, dest_addr.port) {
+        RouteAction::Block => {
+            tracing::info!(
+                "[SOCKS5] Blocked {}:{} by routing rules",
+                dest_addr.addr,
+                dest_addr.port
+            );
+            send_connection_reply(&mut client_conn, REPLY_CONNECTION_NOT_ALLOWED, dest_addr)
+                .await?;
+            return Ok(());
+        }
+        RouteAction::Direct => return relay_direct(client_conn, dest_addr, &client).await,
+        RouteAction::Proxy => {}
+    }
+
+    // Step 4: Create proxy connection through AnyTLS
     tracing::debug!(
         "[SOCKS5] Creating proxy stream to {}:{}",
         dest_addr.addr,
@@ -146,12 +225,12 @@ async fn handle_socks5_connection(
     };
     let stream_id = proxy_stream.id();
 
-    // Step 4: Send success reply
+    // Step 5: Send success reply
     tracing::debug!("[SOCKS5] Sending success reply to client");
     send_connection_reply(&mut client_conn, REPLY_SUCCEEDED, dest_addr.clone()).await?;
     tracing::debug!("[SOCKS5] Success reply sent");
 
-    // Step 5: Bidirectional data forwarding
+    // Step 6: Bidirectional data forwarding
     tracing::debug!(
         "[SOCKS5] Starting bidirectional data forwarding for stream {}",
         stream_id
@@ -165,11 +244,16 @@ async fn handle_socks5_connection(
     // ===== 新实现：不再需要 Arc<Mutex<>> 包装！=====
     // 直接克隆 Arc<Stream> 用于两个任务
     let proxy_stream_read = Arc::clone(&proxy_stream);
+    let proxy_stream_write = Arc::clone(&proxy_stream);
     let session_for_write: Arc<crate::session::Session> = Arc::clone(&session);
+    let timer = RelayTimer::new(client.timeouts());
+    let timer_for_read = timer.clone();
+    let timer_for_write = timer.clone();
 
     tracing::debug!("[SOCKS5] Spawning Task1 and Task2 for stream {}", stream_id);
 
-    let task1 = tokio::spawn(async move {
+    // Abort the forwarding tasks if this connection is closed by a shutdown
+    let task1 = AbortOnDropHandle::new(tokio::spawn(async move {
         tracing::debug!("[SOCKS5-Task1] Task started for stream {}", stream_id);
 
         // 获取 reader 的引用（无需锁整个 stream）
@@ -190,6 +274,8 @@ async fn handle_socks5_connection(
                             stream_id,
                             iteration
                         );
+                        // 远端已半关闭：将 EOF 传递给 SOCKS5 客户端
+                        let _ = client_write.shutdown().await;
                         break;
                     }
                     Ok(n) => {
@@ -219,6 +305,7 @@ async fn handle_socks5_connection(
                 );
                 break;
             }
+            timer_for_read.touch();
 
             tracing::trace!(
                 "[SOCKS5-Task1] Forwarded {} bytes to client (iteration={})",
@@ -232,9 +319,9 @@ async fn handle_socks5_connection(
             stream_id,
             iteration
         );
-    });
+    }));
 
-    let task2 = tokio::spawn(async move {
+    let task2 = AbortOnDropHandle::new(tokio::spawn(async move {
         tracing::debug!(
             "[SOCKS5-Task2] Task spawned, starting client->proxy forwarding for stream {}",
             stream_id
@@ -300,6 +387,7 @@ async fn handle_socks5_connection(
                 .await
             {
                 Ok(_) => {
+                    timer_for_write.touch();
                     tracing::trace!(
                         "[SOCKS5-Task2] Forwarded {} bytes to proxy stream {} (iteration {})",
                         n,
@@ -319,24 +407,50 @@ async fn handle_socks5_connection(
                 }
             }
         }
+        // 客户端不再发送数据：向服务端发送 Fin（半关闭）
+        if let Err(e) = proxy_stream_write.shutdown_write() {
+            tracing::debug!(
+                "[SOCKS5-Task2] Failed to send FIN for stream {}: {}",
+                stream_id,
+                e
+            );
+        }
         tracing::debug!(
             "[SOCKS5-Task2] Task2 (client->proxy) finished for stream {} after {} iterations",
             stream_id,
             iteration
         );
-    });
+    }));
 
     tracing::debug!(
         "[SOCKS5] Tasks spawned, waiting for completion (stream {})",
         stream_id
     );
-    let (result1, result2) = tokio::join!(task1, task2);
-    tracing::debug!("[SOCKS5] Both tasks completed for stream {}", stream_id);
-    if let Err(e) = result1 {
-        tracing::error!("[SOCKS5] Task1 error: {:?}", e);
-    }
-    if let Err(e) = result2 {
-        tracing::error!("[SOCKS5] Task2 error: {:?}", e);
+    tokio::select! {
+        (result1, result2) = async { tokio::join!(task1, task2) } => {
+            tracing::debug!("[SOCKS5] Both tasks completed for stream {}", stream_id);
+            if let Err(e) = result1 {
+                tracing::error!("[SOCKS5] Task1 error: {:?}", e);
+            }
+            if let Err(e) = result2 {
+                tracing::error!("[SOCKS5] Task2 error: {:?}", e);
+            }
+        }
+        expiry = timer.expired() => {
+            // Dropping the handles aborts both tasks and closes the client connection
+            tracing::info!(
+                "[SOCKS5] Closing stream {} to {}:{}: {}",
+                stream_id,
+                dest_addr.addr,
+                dest_addr.port,
+                expiry
+            );
+            METRICS.stream_timeouts.inc();
+            let _ = proxy_stream.shutdown_write();
+            proxy_stream
+                .close_with_error(AnyTlsError::Timeout(format!("stream {}", expiry)))
+                .await;
+        }
     }
 
     tracing::debug!(
@@ -347,6 +461,386 @@ async fn handle_socks5_connection(
     Ok(())
 }
 
+/// Connect to the destination directly and relay data without AnyTLS
+async fn relay_direct(
+    mut client_conn: tokio::net::TcpStream,
+    dest_addr: Socks5Addr,
+    client: &Client,
+) -> Result<()> {
+    tracing::debug!(
+        "[SOCKS5] Connecting directly to {}:{}",
+        dest_addr.addr,
+        dest_addr.port
+    );
+    let mut outbound =
+        match connect_direct(&dest_addr.addr, dest_addr.port, client.timeouts()).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                send_connection_reply(&mut client_conn, REPLY_HOST_UNREACHABLE, dest_addr).await?;
+                return Err(e);
+            }
+        };
+    send_connection_reply(&mut client_conn, REPLY_SUCCEEDED, dest_addr.clone()).await?;
+
+    let timer = RelayTimer::new(client.timeouts());
+    match timer
+        .copy_bidirectional(&mut client_conn, &mut outbound)
+        .await
+    {
+        Ok((sent, received)) => tracing::debug!(
+            "[SOCKS5] Direct connection to {}:{} closed (sent {} bytes, received {} bytes)",
+            dest_addr.addr,
+            dest_addr.port,
+            sent,
+            received
+        ),
+        Err(e @ AnyTlsError::Timeout(_)) => {
+            tracing::info!(
+                "[SOCKS5] Closing direct connection to {}:{}: {}",
+                dest_addr.addr,
+                dest_addr.port,
+                e
+            );
+            METRICS.stream_timeouts.inc();
+        }
+        Err(e) => tracing::debug!(
+            "[SOCKS5] Direct connection to {}:{} ended: {}",
+            dest_addr.addr,
+            dest_addr.port,
+            e
+        ),
+    }
+    Ok(())
+}
+
+/// Handle a UDP ASSOCIATE request
+///
+/// Binds a relay socket next to the control connection and relays datagrams
+/// from the requesting host until the control connection closes. `requested`
+/// carries the client's expected UDP source; a zero port accepts the first
+/// sender from the control connection's IP.
+async fn handle_udp_associate(
+    mut control: tokio::net::TcpStream,
+    client: Arc<Client>,
+    requested: Socks5Addr,
+) -> Result<()> {
+    let client_ip = control.peer_addr()?.ip().to_canonical();
+    let relay = match UdpSocket::bind(SocketAddr::new(control.local_addr()?.ip(), 0)).await {
+        Ok(socket) => Arc::new(socket),
+        Err(e) => {
+            send_connection_reply(&mut control, REPLY_GENERAL_FAILURE, requested).await?;
+            return Err(AnyTlsError::Io(e));
+        }
+    };
+    let relay_addr = relay.local_addr()?;
+    send_bound_reply(&mut control, REPLY_SUCCEEDED, relay_addr).await?;
+    tracing::debug!(
+        "[SOCKS5] UDP association for {} relaying on {}",
+        client_ip,
+        relay_addr
+    );
+
+    // The association lives as long as the control connection
+    let mut buf = [0u8; 64];
+    let control_closed = async {
+        loop {
+            match control.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+    };
+    tokio::select! {
+        result = relay_udp_association(client, relay, client_ip, requested.port) => {
+            result?;
+        }
+        _ = control_closed => {}
+    }
+
+    tracing::debug!("[SOCKS5] UDP association on {} closed", relay_addr);
+    Ok(())
+}
+
+/// Relay datagrams from the SOCKS5 client to per-destination flows
+///
+/// Dropping this future aborts every flow of the association.
+async fn relay_udp_association(
+    client: Arc<Client>,
+    relay: Arc<UdpSocket>,
+    client_ip: IpAddr,
+    expected_port: u16,
+) -> Result<()> {
+    let mut client_addr: Option<SocketAddr> = None;
+    let mut flows: HashMap<(String, u16), mpsc::Sender<Bytes>> = HashMap::new();
+    let mut flow_tasks = JoinSet::new();
+    let mut buf = vec![0u8; MAX_UDP_PACKET_SIZE];
+
+    loop {
+        let (len, src) = tokio::select! {
+            received = relay.recv_from(&mut buf) => received?,
+            Some(_) = flow_tasks.join_next() => {
+                // Forget flows that ended, e.g. after their idle timeout
+                flows.retain(|_, sender| !sender.is_closed());
+                continue;
+            }
+        };
+
+        // Only the host that requested the association may use it
+        if src.ip().to_canonical() != client_ip {
+            tracing::debug!("[SOCKS5] Dropping UDP datagram from foreign host {}", src);
+            continue;
+        }
+        match client_addr {
+            Some(addr) if addr != src => {
+                tracing::debug!(
+                    "[SOCKS5] Dropping UDP datagram from unexpected port {}",
+                    src
+                );
+                continue;
+            }
+            None if expected_port != 0 && src.port() != expected_port => {
+                tracing::debug!(
+                    "[SOCKS5] Dropping UDP datagram from unexpected port {}",
+                    src
+                );
+                continue;
+            }
+            None => client_addr = Some(src),
+            Some(_) => {}
+        }
+
+        let (dest, header_len) = match parse_udp_request(&buf[..len]) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                tracing::debug!("[SOCKS5] Dropping UDP datagram from {}: {}", src, e);
+                continue;
+            }
+        };
+        let payload = Bytes::copy_from_slice(&buf[header_len..len]);
+
+        let action = client.route(&dest.addr, dest.port);
+        if action == RouteAction::Block {
+            tracing::debug!(
+                "[SOCKS5] Blocked UDP datagram to {}:{} by routing rules",
+                dest.addr,
+                dest.port
+            );
+            continue;
+        }
+
+        let key = (dest.addr.clone(), dest.port);
+        if let Some(sender) = flows.get(&key)
+            && sender.is_closed()
+        {
+            flows.remove(&key);
+        }
+        if !flows.contains_key(&key) && flows.len() >= MAX_UDP_FLOWS {
+            flows.retain(|_, sender| !sender.is_closed());
+            if flows.len() >= MAX_UDP_FLOWS {
+                tracing::warn!(
+                    "[SOCKS5] UDP association has {} destinations, dropping datagram to {}:{}",
+                    MAX_UDP_FLOWS,
+                    dest.addr,
+                    dest.port
+                );
+                continue;
+            }
+        }
+        let sender = match flows.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let (tx, rx) = mpsc::channel(UDP_FLOW_QUEUE);
+                let client = Arc::clone(&client);
+                let relay = Arc::clone(&relay);
+                let reply_to = src;
+                flow_tasks.spawn(async move {
+                    let target = format!("{}:{}", dest.addr, dest.port);
+                    if let Err(e) = run_udp_flow(client, relay, reply_to, dest, action, rx).await {
+                        tracing::debug!("[SOCKS5] UDP flow to {} ended: {}", target, e);
+                    }
+                });
+                entry.insert(tx)
+            }
+        };
+        // UDP is lossy: drop rather than stall the whole association
+        if sender.try_send(payload).is_err() {
+            tracing::trace!("[SOCKS5] UDP flow queue full, dropping datagram");
+        }
+    }
+}
+
+/// Forward datagrams for one destination and relay replies to the client
+///
+/// The flow ends, closing its stream or socket, once it has been idle for the
+/// client's stream idle timeout ([`UDP_FLOW_IDLE_TIMEOUT`] if unset) or
+/// reaches the stream lifetime; the next datagram opens a new flow.
+async fn run_udp_flow(
+    client: Arc<Client>,
+    relay: Arc<UdpSocket>,
+    reply_to: SocketAddr,
+    dest: Socks5Addr,
+    action: RouteAction,
+    mut packets: mpsc::Receiver<Bytes>,
+) -> Result<()> {
+    // Replies carry the destination as the client addressed it
+    let mut header = BytesMut::from(&[0x00, 0x00, 0x00][..]); // RSV + FRAG
+    put_socks_addr(&mut header, &dest.addr, dest.port)?;
+    let reply = |payload: &[u8]| {
+        let mut datagram = BytesMut::with_capacity(header.len() + payload.len());
+        datagram.put_slice(&header);
+        datagram.put_slice(payload);
+        datagram.freeze()
+    };
+    let policy = client.timeouts();
+    let timer = RelayTimer::new(&TimeoutPolicy {
+        stream_idle: Some(policy.stream_idle.unwrap_or(UDP_FLOW_IDLE_TIMEOUT)),
+        ..*policy
+    });
+
+    if action == RouteAction::Direct {
+        let target = match dest.addr.parse::<IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, dest.port),
+            Err(_) => resolve_host_with_cache(&dest.addr, dest.port).await?,
+        };
+        let bind_addr: SocketAddr = if target.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        socket.connect(target).await?;
+
+        let forward = async {
+            while let Some(payload) = packets.recv().await {
+                timer.touch();
+                socket.send(&payload).await?;
+            }
+            Ok::<(), AnyTlsError>(())
+        };
+        let backward = async {
+            let mut buf = vec![0u8; MAX_UDP_PACKET_SIZE];
+            loop {
+                let n = socket.recv(&mut buf).await?;
+                timer.touch();
+                relay.send_to(&reply(&buf[..n]), reply_to).await?;
+            }
+        };
+        return tokio::select! {
+            result = forward => result,
+            result = backward => result,
+            expiry = timer.expired() => {
+                tracing::debug!(
+                    "[SOCKS5] Closing direct UDP flow to {}:{}: {}",
+                    dest.addr,
+                    dest.port,
+                    expiry
+                );
+                Ok(())
+            }
+        };
+    }
+
+    let stream = client.create_udp_stream(&dest.addr, dest.port).await?;
+    let forward = async {
+        while let Some(payload) = packets.recv().await {
+            timer.touch();
+            stream.write_data(encode_udp_packet(&payload)?).await?;
+        }
+        Ok::<(), AnyTlsError>(())
+    };
+    let backward = async {
+        let mut reader = stream.reader().lock().await;
+        loop {
+            let payload = read_udp_packet(&mut reader).await?;
+            if payload.is_empty() {
+                return Ok(());
+            }
+            timer.touch();
+            relay.send_to(&reply(&payload), reply_to).await?;
+        }
+    };
+    let result = tokio::select! {
+        result = forward => result,
+        result = backward => result,
+        expiry = timer.expired() => {
+            tracing::debug!(
+                "[SOCKS5] Closing UDP flow to {}:{} (stream {}): {}",
+                dest.addr,
+                dest.port,
+                stream.id(),
+                expiry
+            );
+            let _ = stream.shutdown_write();
+            stream
+                .close_with_error(AnyTlsError::Timeout(format!("UDP flow {}", expiry)))
+                .await;
+            return Ok(());
+        }
+    };
+    // Let the server release its socket for this flow
+    let _ = stream.shutdown_write();
+    result
+}
+
+/// Parse the SOCKS5 UDP request header
+///
+/// Format: [RSV (2) | FRAG (1) | ATYP (1) | DST.ADDR (variable) | DST.PORT (2) | DATA]
+/// Returns the destination and the header length. Fragmented datagrams are
+/// rejected, as RFC 1928 allows.
+fn parse_udp_request(datagram: &[u8]) -> Result<(Socks5Addr, usize)> {
+    let truncated = || AnyTlsError::Protocol("Truncated SOCKS5 UDP header".to_string());
+    if datagram.len() < 4 {
+        return Err(truncated());
+    }
+    if datagram[2] != 0 {
+        return Err(AnyTlsError::Protocol(format!(
+            "Fragmented SOCKS5 UDP datagram (frag={})",
+            datagram[2]
+        )));
+    }
+
+    let (addr, port_offset) = match datagram[3] {
+        ATYP_IPV4 => {
+            let ip: [u8; 4] = datagram
+                .get(4..8)
+                .ok_or_else(truncated)?
+                .try_into()
+                .unwrap();
+            (Ipv4Addr::from(ip).to_string(), 8)
+        }
+        ATYP_IPV6 => {
+            let ip: [u8; 16] = datagram
+                .get(4..20)
+                .ok_or_else(truncated)?
+                .try_into()
+                .unwrap();
+            (Ipv6Addr::from(ip).to_string(), 20)
+        }
+        ATYP_DOMAIN => {
+            let len = *datagram.get(4).ok_or_else(truncated)? as usize;
+            if len == 0 {
+                return Err(AnyTlsError::Protocol("Invalid domain length".to_string()));
+            }
+            let domain = datagram.get(5..5 + len).ok_or_else(truncated)?;
+            let domain = String::from_utf8(domain.to_vec())
+                .map_err(|e| AnyTlsError::Protocol(format!("Invalid domain name: {}", e)))?;
+            (domain, 5 + len)
+        }
+        atyp => {
+            return Err(AnyTlsError::Protocol(format!(
+                "Unsupported address type: 0x{:02x}",
+                atyp
+            )));
+        }
+    };
+
+    let port = datagram
+        .get(port_offset..port_offset + 2)
+        .ok_or_else(truncated)?;
+    let port = u16::from_be_bytes([port[0], port[1]]);
+    Ok((Socks5Addr { addr, port }, port_offset + 2))
+}
+
 /// Perform SOCKS5 authentication handshake
 async fn authenticate(conn: &mut tokio::net::TcpStream) -> Result<()> {
     // Read client greeting: [VER (1) | NMETHODS (1) | METHODS (NMETHODS)]
@@ -448,6 +942,21 @@ async fn read_connection_request(conn: &mut tokio::net::TcpStream) -> Result<(So
     Ok((Socks5Addr { addr, port }, cmd))
 }
 
+/// Send SOCKS5 reply carrying the bound address (used by UDP ASSOCIATE)
+async fn send_bound_reply(
+    conn: &mut tokio::net::TcpStream,
+    reply: u8,
+    bound: SocketAddr,
+) -> Result<()> {
+    let mut reply_buf = BytesMut::from(&[SOCKS5_VERSION, reply, 0x00][..]);
+    put_socks_addr(&mut reply_buf, &bound.ip().to_string(), bound.port())?;
+
+    conn.write_all(&reply_buf).await?;
+    conn.flush().await?;
+
+    Ok(())
+}
+
 /// Send SOCKS5 connection reply
 async fn send_connection_reply(
     conn: &mut tokio::net::TcpStream,
@@ -474,3 +983,37 @@ async fn send_connection_reply(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_udp_request() {
+        let mut datagram = vec![0x00, 0x00, 0x00, ATYP_IPV4, 10, 0, 0, 1, 0x00, 0x35];
+        datagram.extend_from_slice(b"query");
+        let (dest, header_len) = parse_udp_request(&datagram).unwrap();
+        assert_eq!((dest.addr.as_str(), dest.port), ("10.0.0.1", 53));
+        assert_eq!(&datagram[header_len..], b"query");
+
+        let mut datagram = vec![0x00, 0x00, 0x00, ATYP_DOMAIN, 11];
+        datagram.extend_from_slice(b"example.com");
+        datagram.extend_from_slice(&443u16.to_be_bytes());
+        let (dest, header_len) = parse_udp_request(&datagram).unwrap();
+        assert_eq!((dest.addr.as_str(), dest.port), ("example.com", 443));
+        assert_eq!(header_len, datagram.len());
+    }
+
+    #[test]
+    fn test_parse_udp_request_rejects_bad_headers() {
+        for bad in [
+            &[0x00, 0x00, 0x00][..],
+            &[0x00, 0x00, 0x01, ATYP_IPV4, 10, 0, 0, 1, 0x00, 0x35],
+            &[0x00, 0x00, 0x00, ATYP_IPV6, 0, 0, 0, 0],
+            &[0x00, 0x00, 0x00, ATYP_DOMAIN, 5, b'a', b'b'],
+            &[0x00, 0x00, 0x00, 0x05, 1, 2, 3, 4, 0, 1],
+        ] {
+            assert!(parse_udp_request(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -3,9 +3,10 @@
 //! Implements sing-box udp-over-tcp v2 protocol (Connect format)
 
 use crate::client::Client;
+use crate::session::Stream;
 use crate::util::{AnyTlsError, Result};
 use bytes::{BufMut, Bytes, BytesMut};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use tokio::net::UdpSocket;
 use tokio::sync::Mutex;
@@ -16,6 +17,29 @@ const MAX_UDP_PACKET_SIZE: usize = 65535;
 pub const UDP_OVER_TCP_MAGIC_ADDR: &str = "sp.v2.udp-over-tcp.arpa";
 
 impl Client {
+    /// Open a UDP over TCP stream to `host:port` (Connect format)
+    ///
+    /// `host` may be a domain name, which the server resolves. Every datagram
+    /// written to or read from the returned stream is length-prefixed (see
+    /// [`encode_udp_packet`] and [`read_udp_packet`]).
+    pub async fn create_udp_stream(&self, host: &str, port: u16) -> Result<Arc<Stream>> {
+        // The request travels with the address so the server can check the
+        // target before acknowledging the stream
+        let magic_destination = (UDP_OVER_TCP_MAGIC_ADDR.to_string(), 0);
+        let initial_request = encode_initial_request_to(host, port)?;
+        let (stream, _session) = self
+            .open_proxy_stream(magic_destination, initial_request)
+            .await?;
+
+        tracing::debug!(
+            "[UDP Client] Opened stream {} for UDP over TCP to {}:{}",
+            stream.id(),
+            host,
+            port
+        );
+        Ok(stream)
+    }
+
     /// Create a UDP over TCP proxy connection
     ///
     /// This creates a special stream to the magic address "sp.v2.udp-over-tcp.arpa"
@@ -38,31 +62,16 @@ impl Client {
             target_addr
         );
 
-        // Step 1: Create a stream to the magic address
+        // Step 1: Create a stream to the magic address, sending the initial
+        // request (target address) along with it
         let magic_destination = (UDP_OVER_TCP_MAGIC_ADDR.to_string(), 0);
-        let (stream, _session) = self.create_proxy_stream(magic_destination).await?;
-
-        tracing::debug!(
-            "[UDP Client] Created stream {} for UDP over TCP",
-            stream.id()
-        );
-
-        // Step 2: Send initial request (isConnect + target address)
         let initial_request = encode_initial_request(target_addr)?;
+        let (stream, _session) = self
+            .open_proxy_stream(magic_destination, initial_request)
+            .await?;
 
         tracing::debug!(
-            "[UDP Client] Sending initial request ({} bytes) for stream {}",
-            initial_request.len(),
-            stream.id()
-        );
-
-        // Send the initial request
-        stream
-            .send_data(initial_request)
-            .map_err(|e| AnyTlsError::Protocol(format!("Failed to send initial request: {}", e)))?;
-
-        tracing::debug!(
-            "[UDP Client] Initial request sent to stream {}",
+            "[UDP Client] Created stream {} for UDP over TCP",
             stream.id()
         );
 
@@ -102,26 +111,52 @@ impl Client {
 /// | u8 (=1)   | u8   | variable| u16be|
 /// ```
 fn encode_initial_request(target: SocketAddr) -> Result<Bytes> {
+    encode_initial_request_to(&target.ip().to_string(), target.port())
+}
+
+/// Encode initial request for a target given as host (IP or domain) and port
+fn encode_initial_request_to(host: &str, port: u16) -> Result<Bytes> {
     let mut buf = BytesMut::new();
 
     // isConnect = 1 (use Connect format)
     buf.put_u8(1);
 
     // Encode target address in SOCKS5 format
-    match target {
-        SocketAddr::V4(addr) => {
+    put_socks_addr(&mut buf, host, port)?;
+
+    Ok(buf.freeze())
+}
+
+/// Append `host:port` in SOCKS5 address format (ATYP + Address + Port)
+pub(crate) fn put_socks_addr(buf: &mut BytesMut, host: &str, port: u16) -> Result<()> {
+    match host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(IpAddr::V4(ip)) => {
             buf.put_u8(0x01); // IPv4
-            buf.put_slice(&addr.ip().octets());
-            buf.put_u16(addr.port());
+            buf.put_slice(&ip.octets());
         }
-        SocketAddr::V6(addr) => {
+        Ok(IpAddr::V6(ip)) => {
             buf.put_u8(0x04); // IPv6
-            buf.put_slice(&addr.ip().octets());
-            buf.put_u16(addr.port());
+            buf.put_slice(&ip.octets());
+        }
+        Err(_) => {
+            let domain = host.as_bytes();
+            if domain.is_empty() || domain.len() > 255 {
+                return Err(AnyTlsError::Protocol(format!(
+                    "Invalid domain name length: {}",
+                    domain.len()
+                )));
+            }
+            buf.put_u8(0x03); // Domain
+            buf.put_u8(domain.len() as u8);
+            buf.put_slice(domain);
         }
     }
-
-    Ok(buf.freeze())
+    buf.put_u16(port);
+    Ok(())
 }
 
 /// Main UDP proxy loop: bidirectional forwarding between local UDP and remote stream
@@ -187,9 +222,9 @@ async fn udp_to_stream(
         let packet = encode_udp_packet(&buf[..len])?;
 
         // Send to stream
-        stream.send_data(packet).map_err(|e| {
+        stream.write_data(packet).await.map_err(|e| {
             tracing::error!("[UDP Client] Failed to send to stream: {}", e);
-            AnyTlsError::Protocol("Channel send failed".into())
+            e
         })?;
     }
 }
@@ -263,7 +298,7 @@ async fn stream_to_udp(
 /// Read one UDP packet from stream
 ///
 /// Format: | Length (2 bytes BE) | Payload |
-async fn read_udp_packet(reader: &mut crate::session::StreamReader) -> Result<Vec<u8>> {
+pub(crate) async fn read_udp_packet(reader: &mut crate::session::StreamReader) -> Result<Vec<u8>> {
     // Read 2-byte length (Big-Endian)
     let mut len_buf = [0u8; 2];
     reader
@@ -297,7 +332,7 @@ async fn read_udp_packet(reader: &mut crate::session::StreamReader) -> Result<Ve
 /// Encode UDP packet (simple format)
 ///
 /// Format: | Length (2 bytes BE) | Payload |
-fn encode_udp_packet(payload: &[u8]) -> Result<Bytes> {
+pub(crate) fn encode_udp_packet(payload: &[u8]) -> Result<Bytes> {
     let mut buf = BytesMut::new();
 
     if payload.len() > MAX_UDP_PACKET_SIZE {
@@ -345,6 +380,19 @@ mod tests {
         assert_eq!(u16::from_be_bytes([encoded[18], encoded[19]]), 53); // Port
     }
 
+    #[test]
+    fn test_encode_initial_request_domain() {
+        let encoded = encode_initial_request_to("dns.example", 53).unwrap();
+
+        // isConnect (1) + ATYP (1) + Len (1) + Domain (11) + Port (2)
+        assert_eq!(encoded[0], 1);
+        assert_eq!(encoded[1], 0x03); // Domain
+        assert_eq!(encoded[2] as usize, "dns.example".len());
+        assert_eq!(&encoded[3..14], b"dns.example");
+        assert_eq!(u16::from_be_bytes([encoded[14], encoded[15]]), 53);
+        assert!(encode_initial_request_to(&"a".repeat(256), 53).is_err());
+    }
+
     #[test]
     fn test_encode_udp_packet() {
         let payload = b"Hello, UDP!";
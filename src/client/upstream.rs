@@ -0,0 +1,348 @@
+//! Upstream servers and load balancing for [`Client`](crate::client::Client)
+//!
+//! A client may be configured with several upstream endpoints, each with its
+//! own address, SNI, password and padding scheme, and each with its own
+//! [`SessionPool`]. New sessions go to an endpoint picked by the
+//! [`LoadBalanceStrategy`]. Endpoints whose TCP connect or TLS handshake
+//! fails are marked down for a cooldown period and are only tried again
+//! once it expires or when every endpoint is down.
+//!
+//! A padding scheme pushed by an endpoint's server replaces that endpoint's
+//! scheme for its live sessions and for every session opened later.
+
+use crate::client::{SessionPool, SessionPoolConfig};
+use crate::padding::PaddingFactory;
+use crate::session::Session;
+use crate::util::{AnyTlsError, Result, hash_password};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::time::{Duration, Instant};
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+/// Default time an endpoint stays marked down after a connect/TLS failure
+pub const DEFAULT_HEALTH_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// How new sessions are spread over upstream endpoints
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LoadBalanceStrategy {
+    /// Use the first healthy endpoint in configuration order
+    #[default]
+    Failover,
+    /// Rotate over healthy endpoints
+    RoundRobin,
+    /// Prefer the healthy endpoint with the fewest open streams
+    LeastActive,
+}
+
+impl FromStr for LoadBalanceStrategy {
+    type Err = AnyTlsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "failover" => Ok(LoadBalanceStrategy::Failover),
+            "round-robin" => Ok(LoadBalanceStrategy::RoundRobin),
+            "least-active" => Ok(LoadBalanceStrategy::LeastActive),
+            other => Err(AnyTlsError::Config(format!(
+                "Unknown load balancing strategy '{}' (expected failover, round-robin or least-active)",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for LoadBalanceStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LoadBalanceStrategy::Failover => "failover",
+            LoadBalanceStrategy::RoundRobin => "round-robin",
+            LoadBalanceStrategy::LeastActive => "least-active",
+        })
+    }
+}
+
+/// Connection settings for one upstream server
+#[derive(Clone)]
+pub struct UpstreamEndpoint {
+    pub(crate) server_addr: String,
+    pub(crate) server_name: ServerName<'static>,
+    pub(crate) tls_config: Arc<TlsConnector>,
+    pub(crate) password_hash: [u8; 32],
+    /// Shared with the endpoint's sessions, which swap in server updates
+    pub(crate) padding: Arc<tokio::sync::RwLock<Arc<PaddingFactory>>>,
+}
+
+impl UpstreamEndpoint {
+    /// Create an endpoint
+    pub fn new(
+        password: &str,
+        server_addr: String,
+        server_name: ServerName<'static>,
+        tls_config: Arc<TlsConnector>,
+        padding: Arc<PaddingFactory>,
+    ) -> Self {
+        Self {
+            server_addr,
+            server_name,
+            tls_config,
+            password_hash: hash_password(password),
+            padding: Arc::new(tokio::sync::RwLock::new(padding)),
+        }
+    }
+
+    /// Server address (`host:port`)
+    pub fn server_addr(&self) -> &str {
+        &self.server_addr
+    }
+}
+
+impl fmt::Debug for UpstreamEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UpstreamEndpoint")
+            .field("server_addr", &self.server_addr)
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Health snapshot of an upstream endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamStatus {
+    /// Server address
+    pub server_addr: String,
+    /// Whether the endpoint is currently considered healthy
+    pub healthy: bool,
+    /// Connect/TLS failures since the last successful session
+    pub consecutive_failures: u32,
+    /// Open streams over this endpoint's live sessions
+    pub active_streams: usize,
+    /// MD5 of the padding scheme currently used with this endpoint
+    pub padding_md5: String,
+}
+
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    down_until: Option<Instant>,
+}
+
+/// Runtime state of an endpoint: its pool, live sessions and health
+pub(crate) struct Upstream {
+    pub(crate) endpoint: UpstreamEndpoint,
+    pub(crate) pool: Arc<SessionPool>,
+    sessions: Mutex<Vec<Weak<Session>>>,
+    health: Mutex<Health>,
+}
+
+impl Upstream {
+    pub(crate) fn new(endpoint: UpstreamEndpoint, pool_config: SessionPoolConfig) -> Self {
+        Self {
+            endpoint,
+            pool: Arc::new(SessionPool::with_config(pool_config)),
+            sessions: Mutex::new(Vec::new()),
+            health: Mutex::new(Health::default()),
+        }
+    }
+
+    /// Remember a session created for this endpoint
+    pub(crate) fn track_session(&self, session: &Arc<Session>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|s| s.upgrade().is_some_and(|s| !s.is_closed()));
+        sessions.push(Arc::downgrade(session));
+    }
+
+    /// Open streams over all live sessions of this endpoint
+    pub(crate) async fn active_streams(&self) -> usize {
+        let sessions: Vec<Arc<Session>> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|s| !s.is_closed())
+            .collect();
+        let mut total = 0;
+        for session in sessions {
+            total += session.stream_count().await;
+        }
+        total
+    }
+
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.health
+            .lock()
+            .unwrap()
+            .down_until
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    fn down_until(&self) -> Option<Instant> {
+        self.health.lock().unwrap().down_until
+    }
+
+    pub(crate) fn mark_healthy(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.down_until.is_some() {
+            tracing::info!(
+                "[Client] Upstream {} is healthy again",
+                self.endpoint.server_addr
+            );
+        }
+        *health = Health::default();
+    }
+
+    pub(crate) fn mark_failed(&self, cooldown: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.consecutive_failures += 1;
+        health.down_until = Some(Instant::now() + cooldown);
+        tracing::warn!(
+            "[Client] Upstream {} marked down for {}s ({} consecutive failure(s))",
+            self.endpoint.server_addr,
+            cooldown.as_secs(),
+            health.consecutive_failures
+        );
+    }
+
+    pub(crate) async fn status(&self) -> UpstreamStatus {
+        let consecutive_failures = self.health.lock().unwrap().consecutive_failures;
+        UpstreamStatus {
+            server_addr: self.endpoint.server_addr.clone(),
+            healthy: self.is_healthy(),
+            consecutive_failures,
+            active_streams: self.active_streams().await,
+            padding_md5: self.endpoint.padding.read().await.md5().to_string(),
+        }
+    }
+}
+
+/// Order in which endpoints are tried for a new session
+///
+/// Healthy endpoints come first, in the order given by `strategy`; endpoints
+/// marked down follow, soonest recovery first, so a request still has a
+/// chance when every endpoint is down.
+pub(crate) async fn candidate_order(
+    upstreams: &[Arc<Upstream>],
+    strategy: LoadBalanceStrategy,
+    cursor: &AtomicUsize,
+) -> Vec<Arc<Upstream>> {
+    let (mut healthy, mut down): (Vec<_>, Vec<_>) = upstreams
+        .iter()
+        .cloned()
+        .partition(|upstream| upstream.is_healthy());
+
+    match strategy {
+        LoadBalanceStrategy::Failover => {}
+        LoadBalanceStrategy::RoundRobin => {
+            if !healthy.is_empty() {
+                let start = cursor.fetch_add(1, Ordering::Relaxed) % healthy.len();
+                healthy.rotate_left(start);
+            }
+        }
+        LoadBalanceStrategy::LeastActive => {
+            let mut loads = Vec::with_capacity(healthy.len());
+            for upstream in &healthy {
+                loads.push(upstream.active_streams().await);
+            }
+            let mut indexed: Vec<_> = loads.into_iter().zip(healthy).enumerate().collect();
+            // Stable on ties: configuration order decides
+            indexed.sort_by_key(|(index, (load, _))| (*load, *index));
+            healthy = indexed
+                .into_iter()
+                .map(|(_, (_, upstream))| upstream)
+                .collect();
+        }
+    }
+
+    down.sort_by_key(|upstream| upstream.down_until());
+    healthy.extend(down);
+    healthy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::create_insecure_client_config;
+
+    fn upstreams(count: usize) -> Vec<Arc<Upstream>> {
+        let tls = Arc::new(TlsConnector::from(create_insecure_client_config().unwrap()));
+        (0..count)
+            .map(|i| {
+                let endpoint = UpstreamEndpoint::new(
+                    "password",
+                    format!("127.0.0.1:{}", 9000 + i),
+                    ServerName::try_from("localhost").unwrap(),
+                    Arc::clone(&tls),
+                    PaddingFactory::default(),
+                );
+                Arc::new(Upstream::new(endpoint, SessionPoolConfig::default()))
+            })
+            .collect()
+    }
+
+    fn addrs(order: &[Arc<Upstream>]) -> Vec<&str> {
+        order.iter().map(|u| u.endpoint.server_addr()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_failover_skips_unhealthy_endpoints() {
+        let upstreams = upstreams(3);
+        let cursor = AtomicUsize::new(0);
+        upstreams[0].mark_failed(Duration::from_secs(60));
+
+        let order = candidate_order(&upstreams, LoadBalanceStrategy::Failover, &cursor).await;
+        assert_eq!(
+            addrs(&order),
+            ["127.0.0.1:9001", "127.0.0.1:9002", "127.0.0.1:9000"]
+        );
+
+        upstreams[0].mark_healthy();
+        let order = candidate_order(&upstreams, LoadBalanceStrategy::Failover, &cursor).await;
+        assert_eq!(addrs(&order)[0], "127.0.0.1:9000");
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_rotates() {
+        let upstreams = upstreams(3);
+        let cursor = AtomicUsize::new(0);
+        let mut firsts = Vec::new();
+        for _ in 0..4 {
+            let order = candidate_order(&upstreams, LoadBalanceStrategy::RoundRobin, &cursor).await;
+            assert_eq!(order.len(), 3);
+            firsts.push(order[0].endpoint.server_addr().to_string());
+        }
+        assert_eq!(
+            firsts,
+            [
+                "127.0.0.1:9000",
+                "127.0.0.1:9001",
+                "127.0.0.1:9002",
+                "127.0.0.1:9000"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cooldown_expires() {
+        let upstreams = upstreams(1);
+        upstreams[0].mark_failed(Duration::from_millis(20));
+        assert!(!upstreams[0].is_healthy());
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(upstreams[0].is_healthy());
+        assert_eq!(upstreams[0].status().await.consecutive_failures, 1);
+    }
+
+    #[test]
+    fn test_parse_strategy() {
+        assert_eq!(
+            "round_robin".parse::<LoadBalanceStrategy>().unwrap(),
+            LoadBalanceStrategy::RoundRobin
+        );
+        assert_eq!(
+            "Least-Active".parse::<LoadBalanceStrategy>().unwrap(),
+            LoadBalanceStrategy::LeastActive
+        );
+        assert!("random".parse::<LoadBalanceStrategy>().is_err());
+    }
+}
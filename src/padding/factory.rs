@@ -1,6 +1,8 @@
 use crate::padding::CHECK_MARK;
-use crate::util::StringMap;
+use crate::padding::scheme::{PaddingFill, ParsedScheme, SchemeDiagnostic, SizeSpec, parse_scheme};
+use crate::protocol::HEADER_OVERHEAD_SIZE;
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Default padding scheme
 pub const DEFAULT_PADDING_SCHEME: &str = r#"stop=8
@@ -13,12 +15,34 @@ pub const DEFAULT_PADDING_SCHEME: &str = r#"stop=8
 6=500-1000
 7=500-1000"#;
 
+/// One write of a padded packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaddedRecord {
+    /// Bytes of frame data taken from the packet
+    pub payload: usize,
+    /// Bytes of the Waste frame appended after the payload, header included
+    pub padding: usize,
+    /// Time to wait before the write
+    pub delay: Duration,
+}
+
+impl PaddedRecord {
+    /// Size of the write
+    pub fn len(&self) -> usize {
+        self.payload + self.padding
+    }
+
+    /// Whether the write carries no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 /// PaddingFactory generates padding sizes according to the scheme
 #[derive(Debug, Clone)]
 pub struct PaddingFactory {
-    scheme: StringMap,
+    scheme: ParsedScheme,
     raw_scheme: Vec<u8>,
-    stop: u32,
     md5: String,
 }
 
@@ -27,14 +51,19 @@ static DEFAULT_FACTORY: std::sync::OnceLock<Arc<PaddingFactory>> = std::sync::On
 
 impl PaddingFactory {
     /// Create a new PaddingFactory from raw scheme bytes
+    ///
+    /// Fails with every error [`lint_scheme`](crate::padding::lint_scheme)
+    /// reports; warnings are ignored.
     pub fn new(raw_scheme: &[u8]) -> Result<Self, String> {
-        let scheme = StringMap::from_bytes(raw_scheme);
-
-        let stop = scheme
-            .get("stop")
-            .ok_or_else(|| "missing 'stop' in padding scheme".to_string())?
-            .parse::<u32>()
-            .map_err(|_| "invalid 'stop' value".to_string())?;
+        let (scheme, diagnostics) = parse_scheme(raw_scheme);
+        let Some(scheme) = scheme else {
+            let errors: Vec<_> = diagnostics
+                .iter()
+                .filter(|d| d.is_error())
+                .map(SchemeDiagnostic::to_string)
+                .collect();
+            return Err(errors.join("; "));
+        };
 
         let md5_hash = md5::compute(raw_scheme);
         let md5 = format!("{:x}", md5_hash);
@@ -42,7 +71,6 @@ impl PaddingFactory {
         Ok(Self {
             scheme,
             raw_scheme: raw_scheme.to_vec(),
-            stop,
             md5,
         })
     }
@@ -64,6 +92,10 @@ impl PaddingFactory {
     }
 
     /// Update the default padding factory
+    ///
+    /// Only succeeds before [`default`](Self::default) is first used. Schemes
+    /// pushed by a server are kept per client instead (see
+    /// `Session::set_padding_ref`).
     pub fn update_default(raw_scheme: &[u8]) -> Result<(), String> {
         let factory = Arc::new(Self::new(raw_scheme)?);
         DEFAULT_FACTORY
@@ -73,7 +105,7 @@ impl PaddingFactory {
 
     /// Get the stop value
     pub fn stop(&self) -> u32 {
-        self.stop
+        self.scheme.stop
     }
 
     /// Get the MD5 hash of the scheme
@@ -86,45 +118,129 @@ impl PaddingFactory {
         &self.raw_scheme
     }
 
+    /// Content of the padding
+    pub fn fill(&self) -> PaddingFill {
+        self.scheme.fill
+    }
+
+    /// Fill `padding` with zeros or random bytes, as the scheme says
+    pub fn fill_padding(&self, padding: &mut [u8]) {
+        match self.scheme.fill {
+            PaddingFill::Zero => padding.fill(0),
+            PaddingFill::Random => rand::fill(padding),
+        }
+    }
+
     /// Generate record payload sizes for a given packet number
     /// Returns a vector of sizes, where CHECK_MARK (-1) indicates a check point
+    ///
+    /// Delays are left out; see [`plan_records`](Self::plan_records).
     pub fn generate_record_payload_sizes(&self, pkt: u32) -> Vec<i32> {
-        let key = pkt.to_string();
-        let Some(spec) = self.scheme.get(&key) else {
+        let Some(specs) = self.scheme.packets.get(&pkt) else {
             return Vec::new();
         };
 
-        let mut sizes = Vec::new();
-        let parts: Vec<&str> = spec.split(',').collect();
+        specs
+            .iter()
+            .filter_map(|spec| match spec {
+                SizeSpec::Check => Some(CHECK_MARK),
+                SizeSpec::Delay { .. } => None,
+                sizes => sizes.sample_size().map(|size| size as i32),
+            })
+            .collect()
+    }
 
-        for part in parts {
-            let part = part.trim();
-            if part == "c" {
-                sizes.push(CHECK_MARK);
-                continue;
-            }
+    /// Split packet `pkt` carrying `payload_len` bytes into padded writes
+    ///
+    /// Packets at or past `stop`, or without sizes, go out as one write.
+    pub fn plan_records(&self, pkt: u32, payload_len: usize) -> Vec<PaddedRecord> {
+        let specs = match self.scheme.packets.get(&pkt) {
+            Some(specs) if pkt < self.stop() => specs.as_slice(),
+            _ => &[],
+        };
+        let steps = specs.iter().map(|spec| match spec {
+            SizeSpec::Check => Step::Check,
+            SizeSpec::Delay { .. } => Step::Delay(spec.sample_delay().unwrap_or_default()),
+            sizes => Step::Size(sizes.sample_size().unwrap_or_default() as usize),
+        });
+        plan_steps(steps, payload_len)
+    }
+}
 
-            if let Some((min_str, max_str)) = part.split_once('-') {
-                let min_val = min_str.trim().parse::<i64>().unwrap_or(0);
-                let max_val = max_str.trim().parse::<i64>().unwrap_or(0);
+/// Entry of a packet's plan with the sizes drawn
+enum Step {
+    Size(usize),
+    Check,
+    Delay(Duration),
+}
 
-                if min_val <= 0 || max_val <= 0 {
-                    continue;
-                }
+/// Split `payload_len` bytes into writes of the given record sizes
+///
+/// A record larger than the remaining payload is filled up with a Waste
+/// frame; once the payload is written, a check mark ends the packet.
+/// Payload left after the last size goes out unpadded.
+pub fn plan_records(sizes: &[i32], payload_len: usize) -> Vec<PaddedRecord> {
+    let steps = sizes.iter().map(|&size| match size {
+        CHECK_MARK => Step::Check,
+        size => Step::Size(size.max(0) as usize),
+    });
+    plan_steps(steps, payload_len)
+}
 
-                let (min_val, max_val) = (min_val.min(max_val), min_val.max(max_val));
+fn plan_steps(steps: impl Iterator<Item = Step>, payload_len: usize) -> Vec<PaddedRecord> {
+    let mut records = Vec::new();
+    let mut remain = payload_len;
+    let mut delay = Duration::ZERO;
+    for step in steps {
+        let size = match step {
+            Step::Size(size) => size,
+            Step::Check if remain == 0 => break,
+            Step::Check => continue,
+            Step::Delay(wait) => {
+                delay += wait;
+                continue;
+            }
+        };
 
-                if min_val == max_val {
-                    sizes.push(min_val as i32);
+        let delay = std::mem::take(&mut delay);
+        if remain > size {
+            // All payload, the rest follows in later records
+            records.push(PaddedRecord {
+                payload: size,
+                padding: 0,
+                delay,
+            });
+            remain -= size;
+        } else if remain > 0 {
+            // The rest of the payload, padded up to the record size
+            let padding_len = size.saturating_sub(remain + HEADER_OVERHEAD_SIZE);
+            records.push(PaddedRecord {
+                payload: remain,
+                padding: if padding_len > 0 {
+                    HEADER_OVERHEAD_SIZE + padding_len
                 } else {
-                    let size = rand::random_range(min_val..=max_val);
-                    sizes.push(size as i32);
-                }
-            }
+                    0
+                },
+                delay,
+            });
+            remain = 0;
+        } else {
+            // All padding
+            records.push(PaddedRecord {
+                payload: 0,
+                padding: HEADER_OVERHEAD_SIZE + size,
+                delay,
+            });
         }
-
-        sizes
     }
+    if remain > 0 {
+        records.push(PaddedRecord {
+            payload: remain,
+            padding: 0,
+            delay,
+        });
+    }
+    records
 }
 
 #[cfg(test)]
@@ -165,6 +281,69 @@ mod tests {
         assert!(sizes[2] >= 500 && sizes[2] <= 1000);
     }
 
+    #[test]
+    fn test_plan_records() {
+        let record = |payload, padding| PaddedRecord {
+            payload,
+            padding,
+            delay: Duration::ZERO,
+        };
+
+        // Split, padded tail, then the check mark ends the packet
+        assert_eq!(
+            plan_records(&[400, 500, CHECK_MARK, 500], 600),
+            vec![record(400, 0), record(200, 300)]
+        );
+        // Nothing left: records are all padding up to the check mark
+        assert_eq!(
+            plan_records(&[30, 100, CHECK_MARK, 200], 20),
+            vec![record(20, 10), record(0, 107)]
+        );
+        // Too little room for a Waste frame header: no padding
+        assert_eq!(plan_records(&[30], 25), vec![record(25, 0)]);
+        // Payload beyond the sizes goes out unpadded
+        assert_eq!(
+            plan_records(&[100], 250),
+            vec![record(100, 0), record(150, 0)]
+        );
+
+        // Past stop the packet is one write
+        let factory = PaddingFactory::default();
+        assert_eq!(factory.plan_records(8, 5000), vec![record(5000, 0)]);
+        let total: usize = factory.plan_records(1, 50).iter().map(|r| r.len()).sum();
+        assert!((100..=400).contains(&total));
+    }
+
+    #[test]
+    fn test_plan_records_delays_and_fill() {
+        let factory =
+            PaddingFactory::new(b"stop=2\nfill=random\n1=d5-5,200-200,d3-3,d4-4").unwrap();
+        let records = factory.plan_records(1, 300);
+        assert_eq!(
+            records,
+            vec![
+                PaddedRecord {
+                    payload: 200,
+                    padding: 0,
+                    delay: Duration::from_millis(5),
+                },
+                PaddedRecord {
+                    payload: 100,
+                    padding: 0,
+                    delay: Duration::from_millis(7),
+                },
+            ]
+        );
+        // Delays are not record sizes
+        assert_eq!(factory.generate_record_payload_sizes(1), vec![200]);
+
+        let mut padding = [0u8; 256];
+        factory.fill_padding(&mut padding);
+        assert!(padding.iter().any(|&b| b != 0));
+        PaddingFactory::default().fill_padding(&mut padding);
+        assert!(padding.iter().all(|&b| b == 0));
+    }
+
     #[test]
     fn test_md5_hash() {
         let factory1 = PaddingFactory::default();
@@ -1,7 +1,11 @@
 /// Padding factory for traffic obfuscation
 pub mod factory;
+pub mod scheme;
 
 pub use factory::*;
+pub use scheme::{
+    MAX_DELAY_MS, PaddingFill, SchemeDiagnostic, Severity, SizeDistribution, lint_scheme,
+};
 
 /// Check mark in padding scheme, indicates should check if data remains
 pub const CHECK_MARK: i32 = -1;
@@ -0,0 +1,609 @@
+//! Padding scheme parsing and diagnostics
+//!
+//! A scheme is a list of `key=value` lines:
+//!
+//! ```text
+//! stop=8
+//! 0=30-30
+//! 2=400-500,c,500-1000
+//! ```
+//!
+//! `stop` is the number of packets to pad. Every other key is a packet
+//! number whose value lists record sizes: `min-max` ranges (1 to 65535
+//! bytes) and `c` check marks, which end the packet once its payload has
+//! been written.
+//!
+//! anytls-rs also understands these extensions, which leave schemes
+//! without them parsing as before:
+//!
+//! ```text
+//! fill=random
+//! 1=400-1000~
+//! 2=100-200*3|900-1200,c,500-1000
+//! 3=d5-20,500-1000
+//! ```
+//!
+//! - `fill=random` fills padding with random bytes instead of zeros
+//! - `min-max~` draws from a normal distribution centred in the range
+//!   (three standard deviations to either end) instead of a uniform one
+//! - `a|b` picks one of several ranges, weighted by an optional `*weight`
+//!   (default 1)
+//! - `dmin-max` waits `min` to `max` milliseconds before the next record
+//!
+//! Other implementations skip entries they do not understand, so a scheme
+//! using extensions degrades to fewer padded records there. The md5 is
+//! always taken over the raw scheme.
+
+use crate::protocol::MAX_FRAME_DATA_SIZE;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Longest `d` delay, in milliseconds
+pub const MAX_DELAY_MS: u32 = 10_000;
+
+/// How serious a [`SchemeDiagnostic`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The scheme is rejected
+    Error,
+    /// The scheme is accepted but probably not what was meant
+    Warning,
+}
+
+/// Problem found in a padding scheme
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemeDiagnostic {
+    /// 1-based line number, 0 for the scheme as a whole
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl SchemeDiagnostic {
+    fn error(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+
+    /// Whether the scheme is rejected because of this diagnostic
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for SchemeDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        if self.line == 0 {
+            write!(f, "{}: {}", severity, self.message)
+        } else {
+            write!(f, "line {}: {}: {}", self.line, severity, self.message)
+        }
+    }
+}
+
+/// Content of padding
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PaddingFill {
+    /// Zero bytes
+    #[default]
+    Zero,
+    /// Random bytes
+    Random,
+}
+
+impl fmt::Display for PaddingFill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Zero => f.write_str("zero"),
+            Self::Random => f.write_str("random"),
+        }
+    }
+}
+
+/// How a size is drawn from a range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Distribution {
+    Uniform,
+    Normal,
+}
+
+/// One alternative of a record size entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SizeRange {
+    pub(crate) min: u32,
+    pub(crate) max: u32,
+    pub(crate) weight: u32,
+    pub(crate) distribution: Distribution,
+}
+
+impl SizeRange {
+    fn sample(&self) -> u32 {
+        if self.min == self.max {
+            return self.min;
+        }
+        match self.distribution {
+            Distribution::Uniform => rand::random_range(self.min..=self.max),
+            Distribution::Normal => {
+                // Box-Muller transform; 1 - u keeps the logarithm finite
+                let u1 = 1.0 - rand::random::<f64>();
+                let u2 = rand::random::<f64>();
+                let z = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                let mean = (self.min as f64 + self.max as f64) / 2.0;
+                let sigma = (self.max - self.min) as f64 / 6.0;
+                (mean + z * sigma)
+                    .round()
+                    .clamp(self.min as f64, self.max as f64) as u32
+            }
+        }
+    }
+}
+
+/// Record size entry of a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SizeSpec {
+    /// Record size drawn from one of the weighted ranges
+    Sizes(Vec<SizeRange>),
+    Check,
+    /// Wait before the next record (milliseconds)
+    Delay {
+        min: u32,
+        max: u32,
+    },
+}
+
+/// Draw a size from one of the weighted `ranges`
+fn sample_ranges(ranges: &[SizeRange]) -> u32 {
+    let total: u32 = ranges.iter().map(|r| r.weight).sum();
+    let mut pick = if ranges.len() > 1 {
+        rand::random_range(0..total)
+    } else {
+        0
+    };
+    for range in ranges {
+        if pick < range.weight {
+            return range.sample();
+        }
+        pick -= range.weight;
+    }
+    ranges.last().map_or(0, SizeRange::sample)
+}
+
+/// Sizes written like one record size entry of a scheme
+///
+/// Parses `min-max` ranges with the `~` and `a|b*weight` extensions, e.g.
+/// `100-200*3|900-1200~`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeDistribution(Vec<SizeRange>);
+
+impl SizeDistribution {
+    /// Draw a size
+    pub fn sample(&self) -> u32 {
+        sample_ranges(&self.0)
+    }
+
+    /// Smallest and largest size that can be drawn
+    pub fn bounds(&self) -> (u32, u32) {
+        let min = self.0.iter().map(|r| r.min).min().unwrap_or(0);
+        let max = self.0.iter().map(|r| r.max).max().unwrap_or(0);
+        (min, max)
+    }
+}
+
+impl std::str::FromStr for SizeDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        s.split('|')
+            .map(|choice| parse_choice(s, choice.trim()))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+    }
+}
+
+impl SizeSpec {
+    /// Draw a record size; `None` for check marks and delays
+    pub(crate) fn sample_size(&self) -> Option<u32> {
+        match self {
+            Self::Sizes(ranges) => Some(sample_ranges(ranges)),
+            _ => None,
+        }
+    }
+
+    /// Draw a delay; `None` unless this is a delay
+    pub(crate) fn sample_delay(&self) -> Option<Duration> {
+        let Self::Delay { min, max } = *self else {
+            return None;
+        };
+        let ms = if min == max {
+            min
+        } else {
+            rand::random_range(min..=max)
+        };
+        Some(Duration::from_millis(ms as u64))
+    }
+}
+
+/// A scheme that parsed without errors
+#[derive(Debug, Clone)]
+pub(crate) struct ParsedScheme {
+    pub(crate) stop: u32,
+    pub(crate) fill: PaddingFill,
+    pub(crate) packets: HashMap<u32, Vec<SizeSpec>>,
+}
+
+/// Check a padding scheme, returning every problem found
+///
+/// The scheme is valid when none of the diagnostics is an error.
+pub fn lint_scheme(raw_scheme: &[u8]) -> Vec<SchemeDiagnostic> {
+    parse_scheme(raw_scheme).1
+}
+
+/// Parse `raw_scheme`; the scheme is `None` if any diagnostic is an error
+pub(crate) fn parse_scheme(raw_scheme: &[u8]) -> (Option<ParsedScheme>, Vec<SchemeDiagnostic>) {
+    let mut diagnostics = Vec::new();
+    let Ok(text) = std::str::from_utf8(raw_scheme) else {
+        diagnostics.push(SchemeDiagnostic::error(0, "scheme is not valid UTF-8"));
+        return (None, diagnostics);
+    };
+
+    let mut stop: Option<(usize, u32)> = None;
+    let mut fill: Option<(usize, PaddingFill)> = None;
+    let mut packets: HashMap<u32, (usize, Vec<SizeSpec>)> = HashMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            diagnostics.push(SchemeDiagnostic::error(
+                line_no,
+                format!("expected 'key=value', got '{}'", line.trim()),
+            ));
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+
+        if key == "stop" {
+            if let Some((first, _)) = stop {
+                diagnostics.push(SchemeDiagnostic::error(
+                    line_no,
+                    format!("duplicate 'stop' (first set on line {})", first),
+                ));
+                continue;
+            }
+            match value.parse::<u32>() {
+                Ok(value) => stop = Some((line_no, value)),
+                Err(_) => diagnostics.push(SchemeDiagnostic::error(
+                    line_no,
+                    format!("invalid 'stop' value '{}'", value),
+                )),
+            }
+            continue;
+        }
+        if key == "fill" {
+            if let Some((first, _)) = fill {
+                diagnostics.push(SchemeDiagnostic::error(
+                    line_no,
+                    format!("duplicate 'fill' (first set on line {})", first),
+                ));
+                continue;
+            }
+            match value {
+                "zero" => fill = Some((line_no, PaddingFill::Zero)),
+                "random" => fill = Some((line_no, PaddingFill::Random)),
+                _ => diagnostics.push(SchemeDiagnostic::error(
+                    line_no,
+                    format!("invalid 'fill' value '{}' (expected zero or random)", value),
+                )),
+            }
+            continue;
+        }
+
+        let Ok(pkt) = key.parse::<u32>() else {
+            diagnostics.push(SchemeDiagnostic::error(
+                line_no,
+                format!(
+                    "unknown key '{}' (expected 'stop', 'fill' or a packet number)",
+                    key
+                ),
+            ));
+            continue;
+        };
+        if let Some((first, _)) = packets.get(&pkt) {
+            diagnostics.push(SchemeDiagnostic::error(
+                line_no,
+                format!("duplicate packet {} (first set on line {})", pkt, first),
+            ));
+            continue;
+        }
+        let sizes = parse_sizes(line_no, value, &mut diagnostics);
+        packets.insert(pkt, (line_no, sizes));
+    }
+
+    let Some((_, stop)) = stop else {
+        diagnostics.push(SchemeDiagnostic::error(
+            0,
+            "missing 'stop' in padding scheme",
+        ));
+        return (None, diagnostics);
+    };
+    let mut unused: Vec<_> = packets
+        .iter()
+        .filter(|(pkt, _)| **pkt >= stop)
+        .map(|(pkt, (line_no, _))| (*line_no, *pkt))
+        .collect();
+    unused.sort_unstable();
+    for (line_no, pkt) in unused {
+        diagnostics.push(SchemeDiagnostic::warning(
+            line_no,
+            format!("packet {} is never padded (stop={})", pkt, stop),
+        ));
+    }
+
+    if diagnostics.iter().any(SchemeDiagnostic::is_error) {
+        return (None, diagnostics);
+    }
+    let packets = packets
+        .into_iter()
+        .map(|(pkt, (_, sizes))| (pkt, sizes))
+        .collect();
+    let fill = fill.map(|(_, fill)| fill).unwrap_or_default();
+    (
+        Some(ParsedScheme {
+            stop,
+            fill,
+            packets,
+        }),
+        diagnostics,
+    )
+}
+
+/// Parse the record sizes of one packet line, skipping invalid ones
+fn parse_sizes(
+    line_no: usize,
+    value: &str,
+    diagnostics: &mut Vec<SchemeDiagnostic>,
+) -> Vec<SizeSpec> {
+    if value.is_empty() {
+        diagnostics.push(SchemeDiagnostic::warning(
+            line_no,
+            "packet has no record sizes and is sent unpadded",
+        ));
+        return Vec::new();
+    }
+
+    let mut sizes = Vec::new();
+    for part in value.split(',').map(str::trim) {
+        let spec = if part == "c" {
+            Ok(SizeSpec::Check)
+        } else if let Some(delay) = part.strip_prefix('d') {
+            parse_delay(part, delay)
+        } else {
+            part.split('|')
+                .map(|choice| parse_choice(part, choice.trim()))
+                .collect::<Result<Vec<_>, _>>()
+                .map(SizeSpec::Sizes)
+        };
+        match spec {
+            Ok(spec) => sizes.push(spec),
+            Err(message) => diagnostics.push(SchemeDiagnostic::error(line_no, message)),
+        }
+    }
+    if sizes.last() == Some(&SizeSpec::Check) {
+        diagnostics.push(SchemeDiagnostic::warning(
+            line_no,
+            "trailing 'c' has no effect",
+        ));
+    }
+    sizes
+}
+
+/// Parse one `min-max[~][*weight]` alternative of entry `part`
+fn parse_choice(part: &str, choice: &str) -> Result<SizeRange, String> {
+    let (range, weight) = match choice.rsplit_once('*') {
+        Some((range, weight)) => {
+            let weight = match weight.trim().parse::<u16>() {
+                Ok(0) => return Err(format!("weight in '{}' must be positive", part)),
+                Ok(weight) => weight as u32,
+                Err(_) => {
+                    return Err(format!("invalid weight '{}' in '{}'", weight.trim(), part));
+                }
+            };
+            (range.trim(), weight)
+        }
+        None => (choice, 1),
+    };
+    let (range, distribution) = match range.strip_suffix('~') {
+        Some(range) => (range.trim(), Distribution::Normal),
+        None => (range, Distribution::Uniform),
+    };
+    let (min, max) = parse_range(part, range, 1, MAX_FRAME_DATA_SIZE as u32, "record size")?;
+    Ok(SizeRange {
+        min,
+        max,
+        weight,
+        distribution,
+    })
+}
+
+/// Parse the `min-max` of a `d` delay entry
+fn parse_delay(part: &str, range: &str) -> Result<SizeSpec, String> {
+    let (min, max) = parse_range(part, range, 0, MAX_DELAY_MS, "delay")?;
+    Ok(SizeSpec::Delay { min, max })
+}
+
+/// Parse a `min-max` range of entry `part` with bounds in `lowest..=highest`
+fn parse_range(
+    part: &str,
+    range: &str,
+    lowest: u32,
+    highest: u32,
+    what: &str,
+) -> Result<(u32, u32), String> {
+    let Some((min, max)) = range.split_once('-') else {
+        return Err(format!("expected 'min-max' or 'c', got '{}'", part));
+    };
+    let parse_bound = |bound: &str| {
+        let bound = bound.trim();
+        match bound.parse::<u32>() {
+            Ok(value) if value < lowest => Err(format!("{} in '{}' must be positive", what, part)),
+            Ok(value) if value > highest => Err(format!(
+                "{} {} in '{}' exceeds {}",
+                what, value, part, highest
+            )),
+            Ok(value) => Ok(value),
+            Err(_) => Err(format!("invalid {} '{}' in '{}'", what, bound, part)),
+        }
+    };
+    let (min, max) = (parse_bound(min)?, parse_bound(max)?);
+    if min > max {
+        return Err(format!("range '{}' starts above its end", part));
+    }
+    Ok((min, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::padding::DEFAULT_PADDING_SCHEME;
+
+    fn errors(raw: &str) -> Vec<String> {
+        lint_scheme(raw.as_bytes())
+            .into_iter()
+            .filter(SchemeDiagnostic::is_error)
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_default_scheme_is_clean() {
+        assert!(lint_scheme(DEFAULT_PADDING_SCHEME.as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        let raw = "stop=4\n0=30-30\n1=100-x00\n2 400-500\n3=0-10\nfoo=1\n1=5-5";
+        assert_eq!(
+            errors(raw),
+            vec![
+                "line 3: error: invalid record size 'x00' in '100-x00'",
+                "line 4: error: expected 'key=value', got '2 400-500'",
+                "line 5: error: record size in '0-10' must be positive",
+                "line 6: error: unknown key 'foo' (expected 'stop', 'fill' or a packet number)",
+                "line 7: error: duplicate packet 1 (first set on line 3)",
+            ]
+        );
+        assert_eq!(
+            errors("0=30-30\n1=500-400,70000-70000"),
+            vec![
+                "line 2: error: range '500-400' starts above its end",
+                "line 2: error: record size 70000 in '70000-70000' exceeds 65535",
+                "error: missing 'stop' in padding scheme",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_warnings_keep_scheme_valid() {
+        let (parsed, diagnostics) = parse_scheme(b"stop=2\n0=30-30,c\n\n1=\n5=10-20");
+        let parsed = parsed.expect("warnings only");
+        assert_eq!(parsed.stop, 2);
+        assert_eq!(parsed.packets[&1], Vec::new());
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "line 2: warning: trailing 'c' has no effect",
+                "line 4: warning: packet has no record sizes and is sent unpadded",
+                "line 5: warning: packet 5 is never padded (stop=2)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plain_ranges_parse_as_before() {
+        let (parsed, diagnostics) = parse_scheme(DEFAULT_PADDING_SCHEME.as_bytes());
+        let parsed = parsed.unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(parsed.fill, PaddingFill::Zero);
+        let uniform = |min, max| {
+            SizeSpec::Sizes(vec![SizeRange {
+                min,
+                max,
+                weight: 1,
+                distribution: Distribution::Uniform,
+            }])
+        };
+        assert_eq!(parsed.packets[&3], vec![uniform(9, 9), uniform(500, 1000)]);
+        assert_eq!(
+            parsed.packets[&2][..3],
+            [uniform(400, 500), SizeSpec::Check, uniform(500, 1000)]
+        );
+    }
+
+    #[test]
+    fn test_extensions() {
+        let raw = "stop=3\nfill=random\n0=d5-20,1000-2000~\n1=10-10*3|20-20\n2=d7-7";
+        let (parsed, diagnostics) = parse_scheme(raw.as_bytes());
+        let parsed = parsed.unwrap();
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(parsed.fill, PaddingFill::Random);
+
+        let [delay, normal] = &parsed.packets[&0][..] else {
+            panic!("two entries expected");
+        };
+        for _ in 0..1000 {
+            let wait = delay.sample_delay().unwrap();
+            assert!(wait >= Duration::from_millis(5) && wait <= Duration::from_millis(20));
+        }
+        let samples: Vec<u32> = (0..2000).map(|_| normal.sample_size().unwrap()).collect();
+        assert!(samples.iter().all(|size| (1000..=2000).contains(size)));
+        let mean = samples.iter().sum::<u32>() as f64 / samples.len() as f64;
+        assert!((1450.0..=1550.0).contains(&mean), "mean {}", mean);
+        // Within two standard deviations: about 95%, against 67% if uniform
+        let central = samples.iter().filter(|&&s| (1167..=1833).contains(&s));
+        assert!(central.count() > 1800);
+
+        let weighted = &parsed.packets[&1][0];
+        let tens = (0..4000)
+            .map(|_| weighted.sample_size().unwrap())
+            .filter(|&size| {
+                assert!(size == 10 || size == 20);
+                size == 10
+            })
+            .count();
+        assert!((2700..=3300).contains(&tens), "{} of 4000", tens);
+        assert_eq!(parsed.packets[&2], vec![SizeSpec::Delay { min: 7, max: 7 }]);
+    }
+
+    #[test]
+    fn test_extension_errors() {
+        assert_eq!(
+            errors("stop=2\nfill=maybe\n1=100-200*0,d5-x,d1-20000,300-400~*x"),
+            vec![
+                "line 2: error: invalid 'fill' value 'maybe' (expected zero or random)",
+                "line 3: error: weight in '100-200*0' must be positive",
+                "line 3: error: invalid delay 'x' in 'd5-x'",
+                "line 3: error: delay 20000 in 'd1-20000' exceeds 10000",
+                "line 3: error: invalid weight 'x' in '300-400~*x'",
+            ]
+        );
+    }
+}
@@ -1,4 +1,5 @@
-use crate::protocol::frame::{Command, Frame, HEADER_OVERHEAD_SIZE};
+use crate::protocol::frame::{Command, Frame, HEADER_OVERHEAD_SIZE, MAX_FRAME_DATA_SIZE};
+use crate::util::AnyTlsError;
 use bytes::{BufMut, Bytes, BytesMut};
 use std::io;
 use tokio_util::codec::{Decoder, Encoder};
@@ -93,10 +94,16 @@ impl Decoder for FrameCodec {
 }
 
 impl Encoder<Frame> for FrameCodec {
-    type Error = io::Error;
+    type Error = AnyTlsError;
 
     fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> Result<(), Self::Error> {
         let data_len = item.data.len();
+        if data_len > MAX_FRAME_DATA_SIZE {
+            return Err(AnyTlsError::InvalidFrame(format!(
+                "{:?} frame for stream {} carries {} bytes (max {})",
+                item.cmd, item.stream_id, data_len, MAX_FRAME_DATA_SIZE
+            )));
+        }
 
         // Reserve space: header + data
         dst.reserve(HEADER_OVERHEAD_SIZE + data_len);
@@ -165,4 +172,20 @@ mod tests {
         let result = codec.decode(&mut buf);
         assert!(result.unwrap().is_none()); // Should return None, not error
     }
+
+    #[test]
+    fn test_encode_rejects_oversized_frame() {
+        let mut codec = FrameCodec;
+        let mut buf = BytesMut::new();
+
+        let frame = Frame::data(1, Bytes::from(vec![0u8; MAX_FRAME_DATA_SIZE]));
+        codec.encode(frame, &mut buf).unwrap();
+        assert_eq!(buf.len(), HEADER_OVERHEAD_SIZE + MAX_FRAME_DATA_SIZE);
+
+        buf.clear();
+        let frame = Frame::data(1, Bytes::from(vec![0u8; MAX_FRAME_DATA_SIZE + 1]));
+        let result = codec.encode(frame, &mut buf);
+        assert!(matches!(result, Err(AnyTlsError::InvalidFrame(_))));
+        assert!(buf.is_empty());
+    }
 }
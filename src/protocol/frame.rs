@@ -3,6 +3,9 @@ use bytes::Bytes;
 /// Frame header size: 1 (cmd) + 4 (stream_id) + 2 (data_len) = 7 bytes
 pub const HEADER_OVERHEAD_SIZE: usize = 7;
 
+/// Largest payload a single frame can carry (the length field is a u16)
+pub const MAX_FRAME_DATA_SIZE: usize = u16::MAX as usize;
+
 /// Command types for AnyTLS protocol frames
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -0,0 +1,455 @@
+//! Outbound access control for proxied destinations
+//!
+//! Rules are evaluated in order before the server dials a destination and
+//! the first matching rule decides. Destinations that no rule matches are
+//! allowed, except private, loopback, link-local and other non-public
+//! addresses, which are denied unless a rule allows them (`allow private`).
+//! Only `private` and IP/CIDR rules can allow such addresses: `allow *` and
+//! domain allow rules skip them, so a name that resolves (or is rebound) to
+//! a private address stays blocked.
+//!
+//! Rule file format, one rule per line:
+//!
+//! ```text
+//! # <allow|deny> <target> [ports]
+//! deny  169.254.169.254          # cloud metadata endpoint
+//! allow 10.1.0.0/16  5432        # internal database
+//! allow private      53
+//! deny  example.org  0-1023
+//! deny  *            25,465,587  # no outbound mail
+//! ```
+//!
+//! `target` is `*`, `private`, an IP address or CIDR, or a domain suffix
+//! (`example.org` also matches `www.example.org`). `ports` is a port, a range
+//! `LOW-HIGH` or a comma separated list of both; without it all ports match.
+//! Domain rules only match destinations requested by name, while IP rules
+//! are checked against the resolved address. Names are checked once before
+//! they are resolved ([`OutboundAcl::check_domain`]) so denied names are
+//! never looked up, and again with the resolved address.
+
+use crate::util::{
+    AnyTlsError, IpCidr, PortSet, Result, domain_matches_suffix, parse_domain_suffix,
+};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// What a matching rule does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclAction {
+    Allow,
+    Deny,
+}
+
+/// Whether `ip` is loopback, private, link-local or otherwise not a public
+/// unicast address
+pub fn is_private_ip(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            v4.is_unspecified()
+                || v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || a == 0
+                // Shared address space (RFC 6598)
+                || (a == 100 && (64..128).contains(&b))
+                // Reserved (240.0.0.0/4)
+                || a >= 240
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            v6.is_unspecified()
+                || v6.is_loopback()
+                || v6.is_multicast()
+                // Unique local (fc00::/7)
+                || (first & 0xfe00) == 0xfc00
+                // Link-local (fe80::/10)
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AclTarget {
+    Any,
+    Private,
+    Cidr(IpCidr),
+    DomainSuffix(String),
+}
+
+impl AclTarget {
+    fn matches(&self, domain: Option<&str>, ip: IpAddr) -> bool {
+        match self {
+            AclTarget::Any => true,
+            AclTarget::Private => is_private_ip(ip),
+            AclTarget::Cidr(cidr) => cidr.contains(ip),
+            AclTarget::DomainSuffix(suffix) => {
+                domain.is_some_and(|domain| domain_matches_suffix(domain, suffix))
+            }
+        }
+    }
+}
+
+/// A single `allow`/`deny` rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclRule {
+    action: AclAction,
+    target: AclTarget,
+    ports: Option<PortSet>,
+    text: String,
+}
+
+impl AclRule {
+    /// Action taken when this rule matches
+    pub fn action(&self) -> AclAction {
+        self.action
+    }
+
+    fn matches(&self, domain: Option<&str>, ip: IpAddr, port: u16) -> bool {
+        // `*` and domain rules never allow private addresses; those need an
+        // explicit `private` or IP/CIDR rule
+        if self.action == AclAction::Allow
+            && matches!(self.target, AclTarget::Any | AclTarget::DomainSuffix(_))
+            && is_private_ip(ip)
+        {
+            return false;
+        }
+        self.ports_match(port) && self.target.matches(domain, ip)
+    }
+
+    fn ports_match(&self, port: u16) -> bool {
+        self.ports.as_ref().is_none_or(|ports| ports.contains(port))
+    }
+}
+
+impl FromStr for AclRule {
+    type Err = AnyTlsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let (action, target, ports) = match fields.as_slice() {
+            [action, target] => (*action, *target, None),
+            [action, target, ports] => (*action, *target, Some(*ports)),
+            _ => {
+                return Err(AnyTlsError::Config(format!(
+                    "Expected '<allow|deny> <target> [ports]', got '{}'",
+                    s
+                )));
+            }
+        };
+
+        let action = match action.to_ascii_lowercase().as_str() {
+            "allow" => AclAction::Allow,
+            "deny" => AclAction::Deny,
+            other => {
+                return Err(AnyTlsError::Config(format!(
+                    "Unknown ACL action '{}' (expected allow or deny)",
+                    other
+                )));
+            }
+        };
+
+        let target = match target {
+            "*" => AclTarget::Any,
+            t if t.eq_ignore_ascii_case("private") => AclTarget::Private,
+            t if t.parse::<IpAddr>().is_ok() || t.contains('/') => {
+                AclTarget::Cidr(t.parse::<IpCidr>()?)
+            }
+            t => AclTarget::DomainSuffix(parse_domain_suffix(t)?),
+        };
+
+        let ports = ports.map(str::parse).transpose()?;
+
+        Ok(Self {
+            action,
+            target,
+            ports,
+            text: fields.join(" "),
+        })
+    }
+}
+
+impl fmt::Display for AclRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Ordered outbound rule set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboundAcl {
+    rules: Vec<AclRule>,
+}
+
+impl Default for OutboundAcl {
+    /// No rules: public destinations are allowed, private ones denied
+    fn default() -> Self {
+        Self { rules: Vec::new() }
+    }
+}
+
+impl OutboundAcl {
+    /// Rule set that allows every destination, including private ones
+    pub fn allow_all() -> Self {
+        Self {
+            rules: vec![
+                AclRule {
+                    action: AclAction::Allow,
+                    target: AclTarget::Private,
+                    ports: None,
+                    text: "allow private".to_string(),
+                },
+                AclRule {
+                    action: AclAction::Allow,
+                    target: AclTarget::Any,
+                    ports: None,
+                    text: "allow *".to_string(),
+                },
+            ],
+        }
+    }
+
+    /// Parse rules, one per line; `#` starts a comment
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let rule = line.parse::<AclRule>().map_err(|e| {
+                AnyTlsError::Config(format!("ACL line {}: {}", index + 1, config_message(e)))
+            })?;
+            rules.push(rule);
+        }
+        Ok(Self { rules })
+    }
+
+    /// Load rules from a file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            AnyTlsError::Config(format!("Failed to read ACL file {}: {}", path.display(), e))
+        })?;
+        Self::parse(&content)
+    }
+
+    /// Append a rule after the existing ones
+    pub fn push(&mut self, rule: AclRule) {
+        self.rules.push(rule);
+    }
+
+    /// Number of configured rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether no rules are configured
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Decide whether the server may connect to `ip:port`
+    ///
+    /// `domain` is the requested host name when the client asked for a
+    /// domain; `ip` is the address it resolved to.
+    pub fn check(&self, domain: Option<&str>, ip: IpAddr, port: u16) -> Result<()> {
+        match self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(domain, ip, port))
+        {
+            Some(rule) if rule.action == AclAction::Allow => Ok(()),
+            Some(rule) => Err(blocked_by(rule)),
+            None if is_private_ip(ip) => Err(AnyTlsError::AccessDenied(
+                "private destinations are not allowed".to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Decide whether `domain:port` is denied before resolving `domain`
+    ///
+    /// Rejects the name if a matching deny rule comes before any rule that
+    /// depends on the address. `Ok` only means the name may be resolved; the
+    /// resolved address must still pass [`check`](Self::check).
+    pub fn check_domain(&self, domain: &str, port: u16) -> Result<()> {
+        for rule in self.rules.iter().filter(|rule| rule.ports_match(port)) {
+            match &rule.target {
+                AclTarget::Private | AclTarget::Cidr(_) => return Ok(()),
+                AclTarget::DomainSuffix(suffix) if !domain_matches_suffix(domain, suffix) => {}
+                AclTarget::Any | AclTarget::DomainSuffix(_) => {
+                    return match rule.action {
+                        AclAction::Allow => Ok(()),
+                        AclAction::Deny => Err(blocked_by(rule)),
+                    };
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn blocked_by(rule: &AclRule) -> AnyTlsError {
+    AnyTlsError::AccessDenied(format!("destination blocked by rule '{}'", rule))
+}
+
+fn config_message(error: AnyTlsError) -> String {
+    match error {
+        AnyTlsError::Config(message) => message,
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_default_blocks_private_destinations() {
+        let acl = OutboundAcl::default();
+        for addr in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(acl.check(None, ip(addr), 80).is_err(), "{} allowed", addr);
+        }
+        assert!(acl.check(None, ip("1.1.1.1"), 443).is_ok());
+        assert!(
+            acl.check(Some("example.com"), ip("2606:4700::1"), 443)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let acl = OutboundAcl::parse(
+            "# comment\n\
+             deny 169.254.169.254\n\
+             allow 10.1.0.0/16 5432\n\
+             allow private 53\n\
+             deny *.example.org 0-1023  # privileged ports\n\
+             deny * 25,465,587\n",
+        )
+        .unwrap();
+        assert_eq!(acl.len(), 5);
+
+        assert!(acl.check(None, ip("169.254.169.254"), 53).is_err());
+        assert!(acl.check(None, ip("10.1.9.9"), 5432).is_ok());
+        assert!(acl.check(None, ip("10.1.9.9"), 5433).is_err());
+        assert!(acl.check(None, ip("192.168.0.1"), 53).is_ok());
+
+        assert!(
+            acl.check(Some("www.example.org"), ip("1.2.3.4"), 443)
+                .is_err()
+        );
+        assert!(acl.check(Some("Example.ORG."), ip("1.2.3.4"), 80).is_err());
+        assert!(acl.check(Some("example.org"), ip("1.2.3.4"), 8080).is_ok());
+        assert!(acl.check(Some("notexample.org"), ip("1.2.3.4"), 80).is_ok());
+        assert!(acl.check(None, ip("1.2.3.4"), 80).is_ok());
+
+        let err = acl.check(None, ip("8.8.8.8"), 25).unwrap_err();
+        assert!(err.to_string().contains("deny * 25,465,587"), "{}", err);
+    }
+
+    #[test]
+    fn test_wildcard_allow_keeps_private_destinations_blocked() {
+        let acl = OutboundAcl::parse("allow * 80,443").unwrap();
+        assert!(acl.check(None, ip("1.1.1.1"), 443).is_ok());
+        for addr in ["127.0.0.1", "169.254.169.254", "10.0.0.1", "::1"] {
+            assert!(acl.check(None, ip(addr), 80).is_err(), "{} allowed", addr);
+        }
+
+        // A later explicit rule still allows them
+        let acl = OutboundAcl::parse(
+            "allow *
+allow 10.0.0.0/8",
+        )
+        .unwrap();
+        assert!(acl.check(None, ip("10.0.0.1"), 80).is_ok());
+        assert!(acl.check(None, ip("127.0.0.1"), 80).is_err());
+
+        let acl = OutboundAcl::allow_all();
+        assert!(acl.check(None, ip("127.0.0.1"), 80).is_ok());
+    }
+
+    #[test]
+    fn test_domain_allow_does_not_cover_private_address() {
+        let acl = OutboundAcl::parse("allow intranet.corp").unwrap();
+        assert!(
+            acl.check(Some("git.intranet.corp"), ip("1.2.3.4"), 22)
+                .is_ok()
+        );
+        // A name resolving (or rebound) to a private address stays blocked
+        assert!(
+            acl.check(Some("git.intranet.corp"), ip("10.0.0.5"), 22)
+                .is_err()
+        );
+        assert!(
+            acl.check(Some("intranet.corp"), ip("127.0.0.1"), 22)
+                .is_err()
+        );
+
+        let acl = OutboundAcl::parse(
+            "allow intranet.corp
+allow 10.0.0.0/24",
+        )
+        .unwrap();
+        assert!(
+            acl.check(Some("git.intranet.corp"), ip("10.0.0.5"), 22)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_check_domain_before_resolving() {
+        let acl = OutboundAcl::parse(
+            "deny blocked.test
+             allow example.org
+             deny * 25
+             allow 10.0.0.0/8
+             deny internal.test
+",
+        )
+        .unwrap();
+        assert!(acl.check_domain("www.blocked.test", 443).is_err());
+        assert!(acl.check_domain("example.org", 25).is_ok());
+        assert!(acl.check_domain("other.test", 25).is_err());
+        assert!(acl.check_domain("other.test", 80).is_ok());
+        // Decided by the address, which is not known yet
+        assert!(acl.check_domain("internal.test", 80).is_ok());
+        assert!(acl.check(Some("internal.test"), ip("1.2.3.4"), 80).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors_report_line() {
+        for bad in [
+            "permit *",
+            "allow",
+            "allow 10.0.0.0/33",
+            "deny * 80-20",
+            "deny * http",
+            "allow bad_host!",
+        ] {
+            let err = OutboundAcl::parse(&format!("allow *\n{}", bad)).unwrap_err();
+            assert!(err.to_string().contains("line 2"), "{}: {}", bad, err);
+        }
+    }
+}
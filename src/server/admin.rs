@@ -0,0 +1,481 @@
+//! Admin API for live introspection and control
+//!
+//! An optional HTTP/JSON listener for operators, bound to a loopback
+//! address or a Unix socket. Every request must carry
+//! `Authorization: Bearer <token>`; requests without a valid token get
+//! `401 Unauthorized`.
+//!
+//! | Route | Action |
+//! |-------|--------|
+//! | `GET /sessions` | Live sessions with their streams |
+//! | `POST /sessions/{id}/close` | Close a session and its streams |
+//! | `GET /cert` | Current TLS certificate |
+//! | `POST /cert/reload` | Reload the certificate and key from disk |
+//! | `GET /bans` | Sources banned after failed authentications |
+
+use crate::server::{AuthBans, Server};
+use crate::session::Session;
+use crate::util::{AnyTlsError, CertReloader, Result, read_request_head, write_response};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// A live session as reported by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    /// Session identifier
+    pub id: u64,
+    /// Client address
+    pub peer: Option<String>,
+    /// Authenticated user
+    pub user: Option<String>,
+    /// Seconds since the session was created
+    pub age_secs: u64,
+    /// Protocol version negotiated with the client
+    pub peer_version: u8,
+    /// Number of open streams
+    pub stream_count: usize,
+    /// Stream payload bytes received from the client
+    pub bytes_in: u64,
+    /// Stream payload bytes sent to the client
+    pub bytes_out: u64,
+    /// Open streams
+    pub streams: Vec<StreamSummary>,
+}
+
+/// An open stream of a [`SessionSummary`]
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamSummary {
+    /// Stream identifier within the session
+    pub id: u32,
+    /// `host:port` the stream is relayed to, once the client sent it
+    pub destination: Option<String>,
+}
+
+impl SessionSummary {
+    async fn new(session: &Session, peer: Option<SocketAddr>) -> Self {
+        let mut streams: Vec<_> = session
+            .streams()
+            .await
+            .iter()
+            .map(|stream| StreamSummary {
+                id: stream.id(),
+                destination: stream.destination().map(str::to_string),
+            })
+            .collect();
+        streams.sort_by_key(|stream| stream.id);
+        Self {
+            id: session.id(),
+            peer: peer.map(|peer| peer.to_string()),
+            user: session.user().map(ToString::to_string),
+            age_secs: session.age().as_secs(),
+            peer_version: session.peer_version(),
+            stream_count: streams.len(),
+            bytes_in: session.bytes_in(),
+            bytes_out: session.bytes_out(),
+            streams,
+        }
+    }
+}
+
+struct RegisteredSession {
+    session: Arc<Session>,
+    peer: Option<SocketAddr>,
+}
+
+/// Live sessions of a server, by session id
+#[derive(Clone, Default)]
+pub(crate) struct SessionRegistry {
+    sessions: Arc<Mutex<HashMap<u64, RegisteredSession>>>,
+}
+
+impl SessionRegistry {
+    /// Add `session`; it is removed when the returned guard is dropped
+    pub(crate) fn register(
+        &self,
+        session: Arc<Session>,
+        peer: Option<SocketAddr>,
+    ) -> SessionRegistration {
+        let id = session.id();
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id, RegisteredSession { session, peer });
+        SessionRegistration {
+            sessions: Arc::clone(&self.sessions),
+            id,
+        }
+    }
+
+    /// Summaries of all live sessions, ordered by id
+    pub(crate) async fn summaries(&self) -> Vec<SessionSummary> {
+        let mut entries: Vec<_> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| (Arc::clone(&entry.session), entry.peer))
+            .collect();
+        entries.sort_by_key(|(session, _)| session.id());
+        let mut summaries = Vec::with_capacity(entries.len());
+        for (session, peer) in entries {
+            summaries.push(SessionSummary::new(&session, peer).await);
+        }
+        summaries
+    }
+
+    /// Close session `id`; returns `false` if there is no such session
+    pub(crate) async fn close(&self, id: u64) -> Result<bool> {
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| Arc::clone(&entry.session));
+        match session {
+            Some(session) => session.close().await.map(|()| true),
+            None => Ok(false),
+        }
+    }
+}
+
+/// A registered session; dropping it removes the session from the registry
+pub(crate) struct SessionRegistration {
+    sessions: Arc<Mutex<HashMap<u64, RegisteredSession>>>,
+    id: u64,
+}
+
+impl Drop for SessionRegistration {
+    fn drop(&mut self) {
+        self.sessions.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Request handler of the admin API
+#[derive(Clone)]
+pub struct AdminApi {
+    sessions: SessionRegistry,
+    token_hash: [u8; 32],
+    cert_reloader: Option<Arc<CertReloader>>,
+    auth_bans: Option<Arc<AuthBans>>,
+}
+
+impl AdminApi {
+    /// Serve the sessions of `server` to requests carrying `token`
+    pub fn new(server: &Server, token: &str) -> Result<Self> {
+        if token.is_empty() {
+            return Err(AnyTlsError::Config("admin token cannot be empty".into()));
+        }
+        Ok(Self {
+            sessions: server.session_registry(),
+            token_hash: Sha256::digest(token.as_bytes()).into(),
+            cert_reloader: None,
+            auth_bans: server.auth_bans(),
+        })
+    }
+
+    /// Show and reload the certificate managed by `reloader`
+    pub fn with_cert_reloader(mut self, reloader: Arc<CertReloader>) -> Self {
+        self.cert_reloader = Some(reloader);
+        self
+    }
+
+    /// Whether the request head carries the bearer token
+    ///
+    /// Tokens are compared through their SHA-256 digests in constant time.
+    fn is_authorized(&self, head: &str) -> bool {
+        let token = head.lines().skip(1).find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if !name.trim().eq_ignore_ascii_case("authorization") {
+                return None;
+            }
+            let (scheme, token) = value.trim().split_once(' ')?;
+            scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+        });
+        let Some(token) = token else {
+            return false;
+        };
+        let digest: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        digest
+            .iter()
+            .zip(self.token_hash.iter())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+
+    /// Status line and JSON body for a request head
+    async fn respond(&self, head: &str) -> (&'static str, String) {
+        let request_line = head.lines().next().unwrap_or_default();
+        let mut parts = request_line.split(' ');
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        if !self.is_authorized(head) {
+            tracing::warn!(
+                "[Admin] Rejected {} {} without a valid token",
+                method,
+                target
+            );
+            return error_response("401 Unauthorized", "missing or invalid token");
+        }
+
+        let path = target.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            ("GET", ["sessions"]) => {
+                let sessions = self.sessions.summaries().await;
+                ok_response(json!({ "sessions": sessions }))
+            }
+            ("POST", ["sessions", id, "close"]) => {
+                let Ok(id) = id.parse::<u64>() else {
+                    return error_response("404 Not Found", "no such session");
+                };
+                match self.sessions.close(id).await {
+                    Ok(true) => {
+                        tracing::info!("[Admin] Closed session {}", id);
+                        ok_response(json!({ "closed": id }))
+                    }
+                    Ok(false) => error_response("404 Not Found", "no such session"),
+                    Err(e) => error_response("500 Internal Server Error", &e.to_string()),
+                }
+            }
+            ("GET", ["cert"]) => match &self.cert_reloader {
+                Some(reloader) => ok_response(cert_json(reloader)),
+                None => error_response("404 Not Found", "no certificate files configured"),
+            },
+            ("POST", ["cert", "reload"]) => match &self.cert_reloader {
+                Some(reloader) => match reloader.reload() {
+                    Ok(()) => {
+                        tracing::info!("[Admin] Certificate reloaded");
+                        ok_response(cert_json(reloader))
+                    }
+                    Err(e) => {
+                        tracing::error!("[Admin] Certificate reload failed: {}", e);
+                        error_response("500 Internal Server Error", &e.to_string())
+                    }
+                },
+                None => error_response("404 Not Found", "no certificate files configured"),
+            },
+            ("GET", ["bans"]) => match &self.auth_bans {
+                Some(bans) => ok_response(bans_json(bans)),
+                None => error_response("404 Not Found", "authentication bans are disabled"),
+            },
+            (
+                _,
+                ["sessions"] | ["sessions", _, "close"] | ["cert"] | ["cert", "reload"] | ["bans"],
+            ) => error_response("405 Method Not Allowed", "method not allowed"),
+            _ => error_response("404 Not Found", "not found"),
+        }
+    }
+}
+
+fn ok_response(body: serde_json::Value) -> (&'static str, String) {
+    ("200 OK", format!("{}\n", body))
+}
+
+fn error_response(status: &'static str, message: &str) -> (&'static str, String) {
+    (status, format!("{}\n", json!({ "error": message })))
+}
+
+fn cert_json(reloader: &CertReloader) -> serde_json::Value {
+    let certificate = reloader.get_cert_info().map(|info| {
+        json!({
+            "summary": info.summary(),
+            "subject": info.subject,
+            "issuer": info.issuer,
+            "serial_number": info.serial_number,
+            "san_names": info.san_names,
+            "not_after": info
+                .not_after
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            "days_until_expiry": info.days_until_expiry,
+            "self_signed": info.is_self_signed,
+        })
+    });
+    json!({
+        "certificate": certificate,
+        "reload_count": reloader.get_reload_count(),
+    })
+}
+
+fn bans_json(bans: &AuthBans) -> serde_json::Value {
+    let bans: Vec<_> = bans
+        .bans()
+        .iter()
+        .map(|ban| {
+            json!({
+                "source": ban.source.to_string(),
+                "until": ban
+                    .until
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+            })
+        })
+        .collect();
+    json!({ "bans": bans })
+}
+
+async fn handle_admin_request<S>(mut socket: S, api: &AdminApi) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let Some(head) = read_request_head(&mut socket).await? else {
+        return Ok(());
+    };
+    let head = String::from_utf8_lossy(&head);
+    let (status, body) = api.respond(&head).await;
+    write_response(&mut socket, status, "application/json", &body).await
+}
+
+/// Bind `addr` and serve the admin API in a background task
+///
+/// Only loopback addresses are accepted. Returns the bound address (useful
+/// with port 0) and the task handle.
+pub async fn spawn_admin_server(addr: &str, api: AdminApi) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(addr).await.map_err(|e| {
+        AnyTlsError::Config(format!("Failed to bind admin listener {}: {}", addr, e))
+    })?;
+    let local_addr = listener.local_addr()?;
+    if !local_addr.ip().is_loopback() {
+        return Err(AnyTlsError::Config(format!(
+            "admin listener {} is not a loopback address",
+            addr
+        )));
+    }
+    tracing::info!("[Admin] Serving admin API on http://{}", local_addr);
+
+    let api = Arc::new(api);
+    let handle = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, peer)) => {
+                    let api = Arc::clone(&api);
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_admin_request(socket, &api).await {
+                            tracing::debug!("[Admin] Request from {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!("[Admin] Failed to accept connection: {}", e);
+                }
+            }
+        }
+    });
+
+    Ok((local_addr, handle))
+}
+
+/// Serve the admin API on the Unix socket `path` in a background task
+///
+/// A stale socket file at `path` is replaced; the new socket is only
+/// accessible by its owner.
+#[cfg(unix)]
+pub async fn spawn_admin_unix_server(
+    path: impl AsRef<std::path::Path>,
+    api: AdminApi,
+) -> Result<JoinHandle<()>> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let path = path.as_ref();
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path).map_err(|e| {
+        AnyTlsError::Config(format!(
+            "Failed to bind admin socket {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    tracing::info!("[Admin] Serving admin API on unix:{}", path.display());
+
+    let api = Arc::new(api);
+    let handle = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    let api = Arc::clone(&api);
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_admin_request(socket, &api).await {
+                            tracing::debug!("[Admin] Request failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!("[Admin] Failed to accept connection: {}", e);
+                }
+            }
+        }
+    });
+
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api(token: &str) -> AdminApi {
+        AdminApi {
+            sessions: SessionRegistry::default(),
+            token_hash: Sha256::digest(token.as_bytes()).into(),
+            cert_reloader: None,
+            auth_bans: None,
+        }
+    }
+
+    fn request(method_and_path: &str, token: Option<&str>) -> String {
+        let auth = token.map_or_else(String::new, |token| {
+            format!("Authorization: Bearer {}\r\n", token)
+        });
+        format!(
+            "{} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+            method_and_path, auth
+        )
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes() {
+        let api = api("s3cret");
+
+        // Missing or wrong tokens are rejected before routing
+        for token in [None, Some("wrong"), Some("s3cret2")] {
+            let (status, _) = api.respond(&request("GET /sessions", token)).await;
+            assert_eq!(status, "401 Unauthorized");
+        }
+        let lowercase = "GET /sessions HTTP/1.1\r\nauthorization: bearer s3cret\r\n\r\n";
+        assert_eq!(api.respond(lowercase).await.0, "200 OK");
+
+        let (status, body) = api.respond(&request("GET /sessions", Some("s3cret"))).await;
+        assert_eq!(status, "200 OK");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body, json!({ "sessions": [] }));
+
+        let cases = [
+            ("POST /sessions/7/close", "404 Not Found"),
+            ("POST /sessions/abc/close", "404 Not Found"),
+            ("GET /sessions/7/close", "405 Method Not Allowed"),
+            ("DELETE /sessions", "405 Method Not Allowed"),
+            ("GET /cert", "404 Not Found"),
+            ("POST /cert/reload", "404 Not Found"),
+            ("GET /bans", "404 Not Found"),
+            ("POST /bans", "405 Method Not Allowed"),
+            ("GET /metrics", "404 Not Found"),
+        ];
+        for (method_and_path, expected) in cases {
+            let (status, body) = api.respond(&request(method_and_path, Some("s3cret"))).await;
+            assert_eq!(status, expected, "{method_and_path}");
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert!(body["error"].is_string(), "{method_and_path}: {body}");
+        }
+    }
+}
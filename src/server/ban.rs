@@ -0,0 +1,427 @@
+//! Authentication failure tracking and temporary bans
+//!
+//! Failed authentications are counted per source network (the client's
+//! address truncated to a configurable prefix). A source reaching
+//! [`BanConfig::max_failures`] within [`BanConfig::window`] is banned for
+//! [`BanConfig::ban_duration`]: the server then closes its connections
+//! before the TLS handshake, or hands them to the fallback backend without
+//! reading a password if one is configured.
+//!
+//! Bans can be persisted to a file so they survive restarts, one per line:
+//!
+//! ```text
+//! # <source> <expiry, unix seconds>
+//! 203.0.113.7/32 1767225600
+//! 2001:db8:1:2::/64 1767229200
+//! ```
+
+use crate::util::{AnyTlsError, IpCidr, Result};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Sources with recent failures kept before stale ones are pruned
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// When and for how long sources are banned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BanConfig {
+    /// Failed authentications that trigger a ban
+    pub max_failures: u32,
+    /// Period the failures have to fall within
+    pub window: Duration,
+    /// How long a ban lasts
+    pub ban_duration: Duration,
+    /// Prefix length grouping IPv4 clients into one source
+    pub ipv4_prefix: u8,
+    /// Prefix length grouping IPv6 clients into one source
+    pub ipv6_prefix: u8,
+}
+
+impl Default for BanConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            window: Duration::from_secs(600),
+            ban_duration: Duration::from_secs(3600),
+            ipv4_prefix: 32,
+            ipv6_prefix: 64,
+        }
+    }
+}
+
+/// A banned source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BanEntry {
+    /// Banned network
+    pub source: IpCidr,
+    /// When the ban ends
+    pub until: SystemTime,
+}
+
+#[derive(Default)]
+struct BanState {
+    failures: HashMap<IpCidr, VecDeque<Instant>>,
+    bans: HashMap<IpCidr, SystemTime>,
+    /// Bumped on every change of `bans` that is persisted
+    version: u64,
+}
+
+/// Copy of the ban list taken under the state lock, written after it is released
+struct BanSnapshot {
+    version: u64,
+    bans: HashMap<IpCidr, SystemTime>,
+}
+
+/// File the bans are persisted to
+struct BanFile {
+    path: PathBuf,
+    /// Version of the last snapshot written, so a stale one never
+    /// overwrites a newer list
+    written: Mutex<u64>,
+}
+
+impl BanFile {
+    fn write(&self, snapshot: &BanSnapshot) {
+        let mut written = self.written.lock().unwrap();
+        if *written >= snapshot.version {
+            return;
+        }
+        match write_bans(&self.path, &snapshot.bans) {
+            Ok(()) => *written = snapshot.version,
+            Err(e) => tracing::warn!("[Server] Failed to save ban file {:?}: {}", self.path, e),
+        }
+    }
+}
+
+/// Failed authentications and bans of a [`Server`](crate::server::Server)
+pub struct AuthBans {
+    config: BanConfig,
+    state: Mutex<BanState>,
+    file: Option<Arc<BanFile>>,
+}
+
+impl AuthBans {
+    /// Create an empty tracker
+    pub fn new(config: BanConfig) -> Result<Self> {
+        for (prefix, max) in [(config.ipv4_prefix, 32), (config.ipv6_prefix, 128)] {
+            if prefix > max {
+                return Err(AnyTlsError::Config(format!(
+                    "Invalid ban prefix length /{}",
+                    prefix
+                )));
+            }
+        }
+        if config.max_failures == 0 {
+            return Err(AnyTlsError::Config(
+                "Ban failure count must be greater than 0".into(),
+            ));
+        }
+        Ok(Self {
+            config,
+            state: Mutex::new(BanState::default()),
+            file: None,
+        })
+    }
+
+    /// Create a tracker persisting its bans to `path`
+    ///
+    /// Unexpired bans already in the file are restored; the file is
+    /// rewritten whenever the ban list changes.
+    pub fn with_file(config: BanConfig, path: impl Into<PathBuf>) -> Result<Self> {
+        let mut bans = Self::new(config)?;
+        let path = path.into();
+        if path.exists() {
+            let text = std::fs::read_to_string(&path).map_err(|e| {
+                AnyTlsError::Config(format!("Failed to read ban file {:?}: {}", path, e))
+            })?;
+            let now = SystemTime::now();
+            let state = bans.state.get_mut().unwrap();
+            for entry in parse_bans(&text)? {
+                if entry.until > now {
+                    state.bans.insert(entry.source, entry.until);
+                }
+            }
+        }
+        bans.file = Some(Arc::new(BanFile {
+            path,
+            written: Mutex::new(0),
+        }));
+        Ok(bans)
+    }
+
+    /// Settings in use
+    pub fn config(&self) -> &BanConfig {
+        &self.config
+    }
+
+    /// Source network `ip` is counted under
+    pub fn source_of(&self, ip: IpAddr) -> IpCidr {
+        let ip = ip.to_canonical();
+        let prefix = match ip {
+            IpAddr::V4(_) => self.config.ipv4_prefix,
+            IpAddr::V6(_) => self.config.ipv6_prefix,
+        };
+        IpCidr::new(ip, prefix).expect("prefix lengths are checked in new")
+    }
+
+    /// Whether connections from `ip` are currently banned
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let source = self.source_of(ip);
+        let mut state = self.state.lock().unwrap();
+        match state.bans.get(&source) {
+            Some(&until) if until > SystemTime::now() => true,
+            Some(_) => {
+                state.bans.remove(&source);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Count a failed authentication from `ip`
+    ///
+    /// Returns the new ban if this failure banned the source.
+    pub fn record_failure(&self, ip: IpAddr) -> Option<BanEntry> {
+        let source = self.source_of(ip);
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if state.failures.len() >= PRUNE_THRESHOLD {
+            let window = self.config.window;
+            state.failures.retain(|_, times| {
+                times
+                    .back()
+                    .is_some_and(|last| now.duration_since(*last) < window)
+            });
+        }
+
+        let times = state.failures.entry(source).or_default();
+        times.push_back(now);
+        while times
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= self.config.window)
+        {
+            times.pop_front();
+        }
+        if times.len() < self.config.max_failures as usize {
+            return None;
+        }
+
+        state.failures.remove(&source);
+        let until = SystemTime::now() + self.config.ban_duration;
+        state.bans.insert(source, until);
+        let snapshot = self.snapshot(&mut state);
+        drop(state);
+        self.persist(snapshot);
+        Some(BanEntry { source, until })
+    }
+
+    /// Current bans, soonest to expire first
+    pub fn bans(&self) -> Vec<BanEntry> {
+        let now = SystemTime::now();
+        let mut state = self.state.lock().unwrap();
+        state.bans.retain(|_, until| *until > now);
+        let mut bans: Vec<BanEntry> = state
+            .bans
+            .iter()
+            .map(|(&source, &until)| BanEntry { source, until })
+            .collect();
+        bans.sort_by_key(|entry| entry.until);
+        bans
+    }
+
+    /// Lift the ban of `source`, returning whether it was banned
+    pub fn unban(&self, source: &IpCidr) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.bans.remove(source).is_none() {
+            return false;
+        }
+        let snapshot = self.snapshot(&mut state);
+        drop(state);
+        self.persist(snapshot);
+        true
+    }
+
+    /// Write the current bans to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let bans = self.state.lock().unwrap().bans.clone();
+        write_bans(path, &bans)
+    }
+
+    /// Copy the ban list for [`persist`](Self::persist) if it goes to a file
+    fn snapshot(&self, state: &mut BanState) -> Option<BanSnapshot> {
+        self.file.as_ref()?;
+        state.version += 1;
+        Some(BanSnapshot {
+            version: state.version,
+            bans: state.bans.clone(),
+        })
+    }
+
+    /// Write `snapshot` to the ban file, on the blocking pool when called
+    /// from a runtime so the accept path never waits on the disk
+    fn persist(&self, snapshot: Option<BanSnapshot>) {
+        let (Some(file), Some(snapshot)) = (&self.file, snapshot) else {
+            return;
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let file = Arc::clone(file);
+                handle.spawn_blocking(move || file.write(&snapshot));
+            }
+            Err(_) => file.write(&snapshot),
+        }
+    }
+}
+
+fn parse_bans(text: &str) -> Result<Vec<BanEntry>> {
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = || AnyTlsError::Config(format!("Invalid ban entry on line {}", index + 1));
+        let mut fields = line.split_whitespace();
+        let (Some(source), Some(until), None) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+        let source: IpCidr = source.parse().map_err(|_| invalid())?;
+        let until: u64 = until.parse().map_err(|_| invalid())?;
+        entries.push(BanEntry {
+            source,
+            until: UNIX_EPOCH + Duration::from_secs(until),
+        });
+    }
+    Ok(entries)
+}
+
+fn write_bans(path: &Path, bans: &HashMap<IpCidr, SystemTime>) -> Result<()> {
+    let now = SystemTime::now();
+    let mut text = String::from("# <source> <expiry, unix seconds>\n");
+    for (source, until) in bans {
+        if *until <= now {
+            continue;
+        }
+        let secs = until
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let _ = writeln!(text, "{} {}", source, secs);
+    }
+    // Write a sibling file first so a crash never leaves a truncated list
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, text)?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BanConfig {
+        BanConfig {
+            max_failures: 3,
+            window: Duration::from_secs(60),
+            ban_duration: Duration::from_secs(60),
+            ipv4_prefix: 24,
+            ipv6_prefix: 64,
+        }
+    }
+
+    #[test]
+    fn test_failures_ban_source_network() {
+        let bans = AuthBans::new(config()).unwrap();
+        let ip: IpAddr = "192.0.2.10".parse().unwrap();
+        let neighbour: IpAddr = "192.0.2.99".parse().unwrap();
+        let other: IpAddr = "198.51.100.1".parse().unwrap();
+
+        assert!(bans.record_failure(ip).is_none());
+        assert!(bans.record_failure(other).is_none());
+        assert!(bans.record_failure(neighbour).is_none());
+        let ban = bans.record_failure(ip).unwrap();
+        assert_eq!(ban.source.to_string(), "192.0.2.0/24");
+
+        assert!(bans.is_banned(neighbour));
+        assert!(!bans.is_banned(other));
+        assert_eq!(bans.bans(), vec![ban]);
+
+        assert!(bans.unban(&ban.source));
+        assert!(!bans.is_banned(ip));
+        assert!(bans.bans().is_empty());
+    }
+
+    #[test]
+    fn test_failures_outside_window_expire() {
+        let bans = AuthBans::new(BanConfig {
+            window: Duration::from_millis(50),
+            ..config()
+        })
+        .unwrap();
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        assert!(bans.record_failure(ip).is_none());
+        assert!(bans.record_failure(ip).is_none());
+        std::thread::sleep(Duration::from_millis(80));
+        assert!(bans.record_failure(ip).is_none());
+        assert!(!bans.is_banned(ip));
+        assert_eq!(bans.source_of(ip).to_string(), "2001:db8::/64");
+    }
+
+    #[test]
+    fn test_bans_persist_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bans.txt");
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        let bans = AuthBans::with_file(config(), &path).unwrap();
+        for _ in 0..3 {
+            bans.record_failure(ip);
+        }
+        assert!(bans.is_banned(ip));
+        drop(bans);
+
+        let restored = AuthBans::with_file(config(), &path).unwrap();
+        assert!(restored.is_banned(ip));
+        assert_eq!(restored.bans().len(), 1);
+
+        // Expired and malformed entries
+        std::fs::write(&path, "203.0.113.0/24 1\n").unwrap();
+        let expired = AuthBans::with_file(config(), &path).unwrap();
+        assert!(!expired.is_banned(ip));
+        std::fs::write(&path, "203.0.113.0/24\n").unwrap();
+        assert!(AuthBans::with_file(config(), &path).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bans_persist_from_runtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bans.txt");
+        let bans = AuthBans::with_file(config(), &path).unwrap();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        for _ in 0..3 {
+            bans.record_failure(ip);
+        }
+        let source = bans.source_of(ip);
+        assert!(bans.unban(&source));
+        for _ in 0..3 {
+            bans.record_failure(ip);
+        }
+
+        // Written on the blocking pool; the newest list wins
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            let text = std::fs::read_to_string(&path).unwrap_or_default();
+            if parse_bans(&text).unwrap().len() == 1 {
+                break;
+            }
+            assert!(Instant::now() < deadline, "ban file not written");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
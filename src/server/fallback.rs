@@ -0,0 +1,61 @@
+//! Fallback forwarding for unauthenticated connections
+//!
+//! Dropping connections that fail authentication makes the server easy to
+//! fingerprint. With a fallback backend configured, the decrypted bytes that
+//! were already read are replayed to the backend (e.g. a local web server)
+//! and the rest of the TLS plaintext is spliced to it, so a probe sees an
+//! ordinary HTTPS site.
+
+use crate::util::{AnyTlsError, Result, configure_tcp_stream};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, copy_bidirectional};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, timeout};
+
+/// Timeout for connecting to the fallback backend
+const FALLBACK_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Forward a rejected connection to the fallback backend
+///
+/// `consumed` holds the bytes read from `client` during authentication; they
+/// are written to the backend before the connections are spliced together.
+pub async fn forward_to_fallback<S>(
+    mut client: S,
+    consumed: &[u8],
+    fallback_addr: &str,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut backend =
+        match timeout(FALLBACK_CONNECT_TIMEOUT, TcpStream::connect(fallback_addr)).await {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => {
+                return Err(AnyTlsError::Protocol(format!(
+                    "Failed to connect to fallback {}: {}",
+                    fallback_addr, e
+                )));
+            }
+            Err(_) => {
+                return Err(AnyTlsError::Protocol(format!(
+                    "Connection timeout ({}s) to fallback {}",
+                    FALLBACK_CONNECT_TIMEOUT.as_secs(),
+                    fallback_addr
+                )));
+            }
+        };
+    configure_tcp_stream(&backend, fallback_addr);
+
+    if !consumed.is_empty() {
+        backend.write_all(consumed).await?;
+    }
+
+    let (to_backend, to_client) = copy_bidirectional(&mut client, &mut backend).await?;
+    tracing::debug!(
+        "[Fallback] Connection to {} finished ({} bytes sent, {} bytes received)",
+        fallback_addr,
+        to_backend + consumed.len() as u64,
+        to_client
+    );
+
+    Ok(())
+}
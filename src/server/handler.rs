@@ -1,29 +1,39 @@
 //! Server connection handlers
 
 use crate::protocol::{Command, Frame};
+use crate::server::OutboundAcl;
 use crate::session::{Session, Stream};
-use crate::util::{AnyTlsError, Result, configure_tcp_stream, resolve_host_with_cache};
+use crate::util::{
+    AnyTlsError, METRICS, RelayTimer, Result, TimeoutPolicy, UserIdentity, configure_tcp_stream,
+    resolve_host_with_timeout,
+};
 use bytes::Bytes;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
-use tokio::time::{Duration, timeout};
+use tokio::time::timeout;
+use tokio_util::task::AbortOnDropHandle;
 
 /// Handler trait for processing new streams
 pub trait StreamHandler: Send + Sync {
-    /// Handle a new stream
+    /// Handle a new stream opened by the authenticated `user`
     fn handle_stream(
         &self,
         stream: Arc<Stream>,
         session: Arc<crate::session::Session>,
+        user: UserIdentity,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + '_>>;
 }
 
 /// Default stream handler that proxies TCP connections
+///
+/// Destinations are checked against an [`OutboundAcl`] before dialing; the
+/// default rule set blocks private and loopback addresses.
 pub struct TcpProxyHandler {
-    // Destination will be read from stream
+    acl: Arc<OutboundAcl>,
+    timeouts: TimeoutPolicy,
 }
 
 impl Default for TcpProxyHandler {
@@ -35,7 +45,22 @@ impl Default for TcpProxyHandler {
 impl TcpProxyHandler {
     /// Create a new TCP proxy handler
     pub fn new() -> Self {
-        Self {}
+        Self {
+            acl: Arc::new(OutboundAcl::default()),
+            timeouts: TimeoutPolicy::default(),
+        }
+    }
+
+    /// Use `acl` to decide which destinations may be dialed
+    pub fn with_acl(mut self, acl: Arc<OutboundAcl>) -> Self {
+        self.acl = acl;
+        self
+    }
+
+    /// Use the connect, DNS and stream relay limits of `timeouts`
+    pub fn with_timeouts(mut self, timeouts: TimeoutPolicy) -> Self {
+        self.timeouts = timeouts;
+        self
     }
 }
 
@@ -44,13 +69,15 @@ impl StreamHandler for TcpProxyHandler {
         &self,
         stream: Arc<Stream>,
         session: Arc<crate::session::Session>,
+        user: UserIdentity,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + '_>> {
         Box::pin(async move {
             let stream_id = stream.id();
             let peer_version = session.peer_version();
             tracing::debug!(
-                "[Proxy] Handling stream {} (peer_version={})",
+                "[Proxy] Handling stream {} for user {} (peer_version={})",
                 stream_id,
+                user,
                 peer_version
             );
 
@@ -64,26 +91,23 @@ impl StreamHandler for TcpProxyHandler {
             };
 
             tracing::info!(
+                user = %user,
                 "[Proxy] Destination: {}:{}",
                 destination.addr,
                 destination.port
             );
+            stream.set_destination(format!("{}:{}", destination.addr, destination.port));
 
             // Check if this is a UDP over TCP request
             if destination.addr.contains("udp-over-tcp.arpa") {
                 tracing::debug!("[Proxy] Detected UDP over TCP request");
-                if peer_version >= 2 {
-                    tracing::debug!(
-                        "[Proxy] Sending SYNACK for UDP stream {} (connection established)",
-                        stream_id
-                    );
-                    let synack_frame = Frame::control(Command::SynAck, stream_id);
-                    if let Err(e) = session.write_control_frame(synack_frame).await {
-                        tracing::error!("[Proxy] Failed to send SYNACK: {}", e);
-                        return Err(e);
-                    }
-                }
-                crate::server::udp_proxy::handle_udp_over_tcp(stream).await
+                crate::server::udp_proxy::handle_udp_over_tcp_with_acl(
+                    stream,
+                    &session,
+                    &self.acl,
+                    &self.timeouts,
+                )
+                .await
             } else {
                 // Regular TCP proxy
                 proxy_tcp_connection_with_synack_internal(
@@ -92,6 +116,8 @@ impl StreamHandler for TcpProxyHandler {
                     stream_id,
                     peer_version,
                     destination,
+                    &self.acl,
+                    &self.timeouts,
                 )
                 .await
             }
@@ -246,6 +272,8 @@ async fn proxy_tcp_connection_with_synack_internal(
     stream_id: u32,
     peer_version: u8,
     destination: SocksAddr,
+    acl: &OutboundAcl,
+    timeouts: &TimeoutPolicy,
 ) -> Result<()> {
     tracing::debug!(
         "[Proxy] proxy_tcp_connection_with_synack: Starting for stream {} (peer_version={})",
@@ -259,10 +287,19 @@ async fn proxy_tcp_connection_with_synack_internal(
     );
 
     let target_display = format!("{}:{}", destination.addr, destination.port);
+    let domain = (destination.addr.parse::<IpAddr>().is_err()).then_some(destination.addr.as_str());
+    // Reject denied names before they are looked up
+    if let Some(domain) = domain
+        && let Err(e) = acl.check_domain(domain, destination.port)
+    {
+        tracing::warn!("[Proxy] Rejected {}: {}", target_display, e);
+        report_refused(&session, stream_id, peer_version, &target_display, &e).await;
+        return Err(e);
+    }
     let target_socket = if let Ok(ip) = destination.addr.parse::<IpAddr>() {
         SocketAddr::new(ip, destination.port)
     } else {
-        resolve_host_with_cache(&destination.addr, destination.port)
+        resolve_host_with_timeout(&destination.addr, destination.port, timeouts.dns)
             .await
             .map_err(|err| {
                 tracing::error!(
@@ -274,10 +311,22 @@ async fn proxy_tcp_connection_with_synack_internal(
             })?
     };
 
+    // Check the resolved address as well, so names pointing at private
+    // addresses cannot bypass IP rules
+    if let Err(e) = acl.check(domain, target_socket.ip(), destination.port) {
+        tracing::warn!(
+            "[Proxy] Rejected {} ({}): {}",
+            target_display,
+            target_socket,
+            e
+        );
+        report_refused(&session, stream_id, peer_version, &target_display, &e).await;
+        return Err(e);
+    }
+
     // Create outbound TCP connection with timeout
-    // Default 15s timeout for DNS resolution + TCP handshake
     // This prevents hanging on slow/unreachable targets
-    let connect_timeout = Duration::from_secs(15);
+    let connect_timeout = timeouts.connect;
     let outbound = match timeout(connect_timeout, TcpStream::connect(target_socket)).await {
         Ok(Ok(conn)) => {
             configure_tcp_stream(&conn, &target_display);
@@ -286,6 +335,7 @@ async fn proxy_tcp_connection_with_synack_internal(
         }
         Ok(Err(e)) => {
             tracing::error!("[Proxy] Failed to connect to {}: {}", target_display, e);
+            METRICS.synack_errors.inc();
             // Send SYNACK with error if protocol version >= 2
             // Note: All streams should receive SYNACK in protocol v2+, including stream_id=1
             if peer_version >= 2 {
@@ -303,11 +353,11 @@ async fn proxy_tcp_connection_with_synack_internal(
         }
         Err(_) => {
             let error_msg = format!(
-                "Connection timeout ({}s) to {}",
-                connect_timeout.as_secs(),
-                target_display
+                "Connection timeout ({:?}) to {}",
+                connect_timeout, target_display
             );
             tracing::error!("[Proxy] {}", error_msg);
+            METRICS.synack_timeouts.inc();
             // Send SYNACK with timeout error
             if peer_version >= 2 {
                 let synack_frame =
@@ -341,7 +391,25 @@ async fn proxy_tcp_connection_with_synack_internal(
         "[Proxy] proxy_tcp_connection_with_synack: Calling proxy_tcp_connection_data_forwarding for stream {}",
         stream_id
     );
-    proxy_tcp_connection_data_forwarding(stream, outbound, destination).await
+    proxy_tcp_connection_data_forwarding(stream, outbound, destination, RelayTimer::new(timeouts))
+        .await
+}
+
+/// Tell a v2 client that the ACL refused its destination
+async fn report_refused(
+    session: &Session,
+    stream_id: u32,
+    peer_version: u8,
+    target_display: &str,
+    error: &AnyTlsError,
+) {
+    if peer_version >= 2 {
+        let error_msg = format!("Connection to {} refused: {}", target_display, error);
+        let synack_frame = Frame::with_data(Command::SynAck, stream_id, Bytes::from(error_msg));
+        if let Err(send_err) = session.write_control_frame(synack_frame).await {
+            tracing::error!("[Proxy] Failed to send SYNACK with error: {}", send_err);
+        }
+    }
 }
 
 /// Forward data between stream and outbound connection
@@ -352,6 +420,7 @@ async fn proxy_tcp_connection_data_forwarding(
     stream: Arc<Stream>,
     outbound: TcpStream,
     destination: SocksAddr,
+    timer: RelayTimer,
 ) -> Result<()> {
     let stream_id = stream.id();
     tracing::debug!(
@@ -377,7 +446,8 @@ async fn proxy_tcp_connection_data_forwarding(
         stream_id
     );
     let bytes_to_outbound_clone = Arc::clone(&bytes_to_outbound);
-    let task1 = tokio::spawn(async move {
+    let timer_for_read = timer.clone();
+    let task1 = AbortOnDropHandle::new(tokio::spawn(async move {
         tracing::debug!("[Proxy-Task1] Task started for stream {}", stream_id);
 
         // 获取 reader 的引用（无需锁整个 stream）
@@ -399,6 +469,10 @@ async fn proxy_tcp_connection_data_forwarding(
                             stream_id,
                             iteration
                         );
+                        // 客户端已半关闭：将 EOF 传递给 outbound，保留另一方向
+                        if let Err(e) = outbound_write.shutdown().await {
+                            tracing::debug!("[Proxy-Task1] Outbound shutdown error: {}", e);
+                        }
                         break;
                     }
                     Ok(n) => {
@@ -428,6 +502,7 @@ async fn proxy_tcp_connection_data_forwarding(
                 break;
             }
             bytes_to_outbound_clone.fetch_add(n as u64, Ordering::Relaxed);
+            timer_for_read.touch();
 
             tracing::trace!(
                 "[Proxy-Task1] Forwarded {} bytes to outbound (iteration={})",
@@ -441,7 +516,7 @@ async fn proxy_tcp_connection_data_forwarding(
             stream_id,
             iteration
         );
-    });
+    }));
 
     // Task 2: Outbound -> Stream（从 outbound 读取，写入 stream）
     tracing::debug!(
@@ -449,7 +524,8 @@ async fn proxy_tcp_connection_data_forwarding(
         stream_id
     );
     let bytes_to_client_clone = Arc::clone(&bytes_to_client);
-    let task2 = tokio::spawn(async move {
+    let timer_for_write = timer.clone();
+    let task2 = AbortOnDropHandle::new(tokio::spawn(async move {
         tracing::debug!("[Proxy-Task2] Task started for stream {}", stream_id);
         let mut buf = vec![0u8; 8192];
         let mut iteration = 0u64;
@@ -481,9 +557,12 @@ async fn proxy_tcp_connection_data_forwarding(
                 }
             };
 
-            // 写入 stream（使用 send_data，完全无锁！）
+            // 写入 stream（无锁；Session 发送队列满时等待，对 outbound 形成背压）
             use bytes::Bytes;
-            if let Err(e) = stream_for_write.send_data(Bytes::copy_from_slice(&buf[..n])) {
+            if let Err(e) = stream_for_write
+                .write_data(Bytes::copy_from_slice(&buf[..n]))
+                .await
+            {
                 tracing::error!(
                     "[Proxy-Task2] Stream write error (stream_id={}, iteration={}): {:?}",
                     stream_id,
@@ -493,6 +572,7 @@ async fn proxy_tcp_connection_data_forwarding(
                 break;
             }
             bytes_to_client_clone.fetch_add(n as u64, Ordering::Relaxed);
+            timer_for_write.touch();
 
             tracing::trace!(
                 "[Proxy-Task2] Wrote {} bytes to stream {} (iteration={})",
@@ -502,19 +582,45 @@ async fn proxy_tcp_connection_data_forwarding(
             );
         }
 
+        // outbound 不再有数据：向客户端发送 Fin（半关闭）
+        if let Err(e) = stream_for_write.shutdown_write() {
+            tracing::debug!(
+                "[Proxy-Task2] Failed to send FIN for stream {}: {}",
+                stream_id,
+                e
+            );
+        }
+
         tracing::debug!(
             "[Proxy-Task2] Task completed for stream {} after {} iterations",
             stream_id,
             iteration
         );
-    });
+    }));
 
     // 等待两个任务完成
     tracing::debug!(
         "[Proxy] Waiting for tasks to complete for stream {}",
         stream_id
     );
-    let _ = tokio::join!(task1, task2);
+    // 空闲超时或达到最长存活时间时中止两个任务并关闭 outbound
+    tokio::select! {
+        _ = async { tokio::join!(task1, task2) } => {}
+        expiry = timer.expired() => {
+            tracing::info!(
+                "[Proxy] Closing stream {} to {}:{}: {}",
+                stream_id,
+                destination.addr,
+                destination.port,
+                expiry
+            );
+            METRICS.stream_timeouts.inc();
+            let _ = stream.shutdown_write();
+            stream
+                .close_with_error(AnyTlsError::Timeout(format!("stream {}", expiry)))
+                .await;
+        }
+    }
     let outbound_bytes = bytes_to_outbound.load(Ordering::Relaxed);
     let client_bytes = bytes_to_client.load(Ordering::Relaxed);
 
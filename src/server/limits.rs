@@ -0,0 +1,207 @@
+//! Server resource limits
+//!
+//! Caps on concurrent connections, overall and per source IP, and on the
+//! streams each session may open. Connections over a limit are closed
+//! before the TLS handshake; streams over a limit are refused with a SYNACK
+//! error (protocol v2) or a Fin. Handshake timeouts keep peers that stall
+//! or trickle bytes from holding a connection slot indefinitely.
+
+use crate::session::StreamLimits;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Resource limits of a [`Server`](crate::server::Server); `None` means
+/// unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerLimits {
+    /// Concurrent sessions, counting connections still in the handshake
+    pub max_sessions: Option<usize>,
+    /// Concurrent connections from one source IP
+    pub max_connections_per_ip: Option<usize>,
+    /// Open streams of one session
+    pub max_streams_per_session: Option<usize>,
+    /// Streams one session may open per second
+    pub max_syn_per_sec: Option<u32>,
+}
+
+impl ServerLimits {
+    /// Whether any limit is set
+    pub fn is_limited(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Limits applied to each session
+    pub fn stream_limits(&self) -> StreamLimits {
+        StreamLimits {
+            max_streams: self.max_streams_per_session,
+            max_syn_per_sec: self.max_syn_per_sec,
+        }
+    }
+}
+
+impl fmt::Display for ServerLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn show(limit: Option<impl fmt::Display>) -> String {
+            limit.map_or_else(|| "unlimited".to_string(), |n| n.to_string())
+        }
+        write!(
+            f,
+            "sessions {}, connections per IP {}, streams per session {}, SYN/s per session {}",
+            show(self.max_sessions),
+            show(self.max_connections_per_ip),
+            show(self.max_streams_per_session),
+            show(self.max_syn_per_sec)
+        )
+    }
+}
+
+/// Deadlines for a new connection to become a working session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeTimeouts {
+    /// TLS handshake, from accepting the socket
+    pub tls_handshake: Duration,
+    /// Authentication preamble (password hash and padding), after TLS
+    pub authentication: Duration,
+    /// First complete frame, after authentication
+    pub first_frame: Duration,
+}
+
+impl Default for HandshakeTimeouts {
+    fn default() -> Self {
+        Self {
+            tls_handshake: Duration::from_secs(10),
+            authentication: Duration::from_secs(10),
+            first_frame: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Limit a connection was refused for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionLimit {
+    /// `max_sessions` reached
+    Sessions(usize),
+    /// `max_connections_per_ip` reached
+    PerIp(usize),
+}
+
+impl fmt::Display for ConnectionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sessions(max) => write!(f, "session limit of {} reached", max),
+            Self::PerIp(max) => write!(f, "limit of {} connections per IP reached", max),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ConnectionCounts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Open connections of a server, overall and per source IP
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionTracker {
+    counts: Arc<Mutex<ConnectionCounts>>,
+}
+
+impl ConnectionTracker {
+    /// Count a new connection from `ip`, unless that exceeds `limits`
+    pub(crate) fn try_acquire(
+        &self,
+        ip: IpAddr,
+        limits: &ServerLimits,
+    ) -> Result<ConnectionPermit, ConnectionLimit> {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(max) = limits.max_sessions
+            && counts.total >= max
+        {
+            return Err(ConnectionLimit::Sessions(max));
+        }
+        let from_ip = counts.per_ip.get(&ip).copied().unwrap_or(0);
+        if let Some(max) = limits.max_connections_per_ip
+            && from_ip >= max
+        {
+            return Err(ConnectionLimit::PerIp(max));
+        }
+        counts.total += 1;
+        counts.per_ip.insert(ip, from_ip + 1);
+        Ok(ConnectionPermit {
+            counts: Arc::clone(&self.counts),
+            ip,
+        })
+    }
+
+    /// Number of open connections
+    pub(crate) fn total(&self) -> usize {
+        self.counts.lock().unwrap().total
+    }
+}
+
+/// A counted connection; dropping it releases the count
+pub(crate) struct ConnectionPermit {
+    counts: Arc<Mutex<ConnectionCounts>>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.total -= 1;
+        if let Some(count) = counts.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_limits() {
+        let tracker = ConnectionTracker::default();
+        let limits = ServerLimits {
+            max_sessions: Some(3),
+            max_connections_per_ip: Some(2),
+            ..ServerLimits::default()
+        };
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+        let first = tracker.try_acquire(a, &limits).unwrap();
+        let _second = tracker.try_acquire(a, &limits).unwrap();
+        assert_eq!(
+            tracker.try_acquire(a, &limits).err(),
+            Some(ConnectionLimit::PerIp(2))
+        );
+        let _third = tracker.try_acquire(b, &limits).unwrap();
+        assert_eq!(
+            tracker.try_acquire(b, &limits).err(),
+            Some(ConnectionLimit::Sessions(3))
+        );
+        assert_eq!(tracker.total(), 3);
+
+        // Closing a connection frees its slot
+        drop(first);
+        assert_eq!(tracker.total(), 2);
+        assert!(tracker.try_acquire(a, &limits).is_ok());
+
+        // Without limits everything is accepted
+        let unlimited = ServerLimits::default();
+        assert!(!unlimited.is_limited());
+        let permits: Vec<_> = (0..10)
+            .map(|_| tracker.try_acquire(a, &unlimited).unwrap())
+            .collect();
+        assert_eq!(tracker.total(), 12);
+        drop(permits);
+        assert_eq!(tracker.total(), 2);
+    }
+}
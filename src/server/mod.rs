@@ -1,10 +1,22 @@
 //! Server implementation for AnyTLS protocol
 
+pub mod acl;
+pub mod admin;
+pub mod ban;
+pub mod fallback;
 pub mod handler;
+pub mod limits;
+pub mod padding;
 #[allow(clippy::module_inception)]
 pub mod server;
 pub mod udp_proxy;
 
+pub use acl::*;
+pub use admin::*;
+pub use ban::*;
+pub use fallback::*;
 pub use handler::*;
+pub use limits::*;
+pub use padding::*;
 pub use server::*;
 pub use udp_proxy::*;
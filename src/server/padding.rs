@@ -0,0 +1,40 @@
+//! Reloadable server padding scheme
+//!
+//! New sessions start with the current scheme. When it is replaced, every
+//! live session switches over and clients that advertised a different
+//! `padding-md5` are sent `UpdatePaddingScheme`, so traffic shape can be
+//! rotated without reconnecting.
+
+use crate::padding::PaddingFactory;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Handle to the padding scheme of a [`Server`](crate::server::Server)
+#[derive(Clone)]
+pub struct PaddingHandle {
+    tx: Arc<watch::Sender<Arc<PaddingFactory>>>,
+}
+
+impl PaddingHandle {
+    /// Create a handle holding `padding`
+    pub fn new(padding: Arc<PaddingFactory>) -> Self {
+        let (tx, _) = watch::channel(padding);
+        Self { tx: Arc::new(tx) }
+    }
+
+    /// Scheme given to new sessions
+    pub fn current(&self) -> Arc<PaddingFactory> {
+        self.tx.borrow().clone()
+    }
+
+    /// Replace the scheme for new and live sessions
+    pub fn update(&self, padding: Arc<PaddingFactory>) {
+        tracing::info!("[Server] Padding scheme switched to {}", padding.md5());
+        self.tx.send_replace(padding);
+    }
+
+    /// Receiver notified on every [`update`](Self::update)
+    pub(crate) fn subscribe(&self) -> watch::Receiver<Arc<PaddingFactory>> {
+        self.tx.subscribe()
+    }
+}
@@ -1,23 +1,51 @@
 //! AnyTLS Server implementation
 
 use crate::padding::PaddingFactory;
+use crate::protocol::{Command, Frame};
+use crate::server::acl::OutboundAcl;
+use crate::server::admin::{SessionRegistry, SessionSummary};
+use crate::server::ban::AuthBans;
+use crate::server::fallback::forward_to_fallback;
 use crate::server::handler::{StreamHandler, TcpProxyHandler};
-use crate::session::Session;
+use crate::server::limits::{ConnectionTracker, HandshakeTimeouts, ServerLimits};
+use crate::server::padding::PaddingHandle;
+use crate::session::{
+    CoverTrafficConfig, DownstreamPadding, FlowControlConfig, Session, Stream, StreamLimits,
+};
 use crate::util::{
-    AnyTlsError, Result, StringMap, authenticate_client, configure_tcp_stream, hash_password,
+    ActivityTracker, AnyTlsError, AuthResult, DEFAULT_USER_NAME, METRICS, Result, ShutdownHandle,
+    StringMap, TimeoutPolicy, UserTable, configure_tcp_stream, read_authentication,
 };
+use bytes::Bytes;
 use std::sync::{Arc, RwLock};
 use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
 use tokio_rustls::TlsAcceptor;
 use tracing::{Instrument, Span, field, info_span};
 
+/// Callback invoked for every new stream instead of the TCP proxy handler
+type StreamCallback = Arc<dyn Fn(Arc<Stream>) + Send + Sync + 'static>;
+
 /// Server manages AnyTLS server connections
 pub struct Server {
-    password_hash: [u8; 32],
+    users: Arc<RwLock<Arc<UserTable>>>,
     tls_config: Arc<RwLock<Arc<TlsAcceptor>>>,
-    padding: Arc<PaddingFactory>,
-    on_new_stream: Option<Arc<dyn Fn(Arc<crate::session::Stream>) + Send + Sync + 'static>>,
+    padding: PaddingHandle,
+    downstream_padding: DownstreamPadding,
+    cover_traffic: Option<CoverTrafficConfig>,
+    flow_control: FlowControlConfig,
+    on_new_stream: Option<StreamCallback>,
     server_settings: Option<StringMap>,
+    fallback: Option<Arc<str>>,
+    shutdown: ShutdownHandle,
+    acl: Arc<RwLock<Arc<OutboundAcl>>>,
+    limits: ServerLimits,
+    connections: ConnectionTracker,
+    handshake_timeouts: HandshakeTimeouts,
+    auth_bans: Option<Arc<AuthBans>>,
+    timeouts: TimeoutPolicy,
+    sessions: SessionRegistry,
 }
 
 impl Server {
@@ -28,15 +56,12 @@ impl Server {
         padding: Arc<PaddingFactory>,
         server_settings: Option<StringMap>,
     ) -> Self {
-        let password_hash = hash_password(password);
-
-        Self {
-            password_hash,
-            tls_config: Arc::new(RwLock::new(tls_config)),
+        Self::new_with_reloadable_tls(
+            password,
+            Arc::new(RwLock::new(tls_config)),
             padding,
-            on_new_stream: None,
             server_settings,
-        }
+        )
     }
 
     /// Create a new server with reloadable TLS config
@@ -46,57 +71,261 @@ impl Server {
         padding: Arc<PaddingFactory>,
         server_settings: Option<StringMap>,
     ) -> Self {
-        let password_hash = hash_password(password);
+        let users = UserTable::single(DEFAULT_USER_NAME, password);
+        Self::new_with_users(
+            Arc::new(RwLock::new(Arc::new(users))),
+            tls_config,
+            padding,
+            server_settings,
+        )
+    }
 
+    /// Create a new server authenticating against a (reloadable) user table
+    pub fn new_with_users(
+        users: Arc<RwLock<Arc<UserTable>>>,
+        tls_config: Arc<RwLock<Arc<TlsAcceptor>>>,
+        padding: Arc<PaddingFactory>,
+        server_settings: Option<StringMap>,
+    ) -> Self {
         Self {
-            password_hash,
+            users,
             tls_config,
-            padding,
+            padding: PaddingHandle::new(padding),
+            downstream_padding: DownstreamPadding::Disabled,
+            cover_traffic: None,
+            flow_control: FlowControlConfig::default(),
             on_new_stream: None,
             server_settings,
+            fallback: None,
+            shutdown: ShutdownHandle::new(),
+            acl: Arc::new(RwLock::new(Arc::new(OutboundAcl::default()))),
+            limits: ServerLimits::default(),
+            connections: ConnectionTracker::default(),
+            auth_bans: None,
+            timeouts: TimeoutPolicy::default(),
+            handshake_timeouts: HandshakeTimeouts::default(),
+            sessions: SessionRegistry::default(),
         }
     }
 
+    /// Get user table reference for hot-reloading
+    pub fn get_users_ref(&self) -> Arc<RwLock<Arc<UserTable>>> {
+        Arc::clone(&self.users)
+    }
+
+    /// Get the padding scheme handle for hot-reloading
+    ///
+    /// A new scheme is used by new sessions and pushed to connected clients.
+    pub fn padding_handle(&self) -> PaddingHandle {
+        self.padding.clone()
+    }
+
+    /// Pad the first packets sent to clients as well
+    ///
+    /// Only clients that advertise `downstream-padding=1` in their
+    /// `Settings` are padded, so older clients keep getting plain writes.
+    pub fn with_downstream_padding(mut self, padding: DownstreamPadding) -> Self {
+        self.downstream_padding = padding;
+        self
+    }
+
+    /// Send cover traffic to clients while their sessions are idle
+    ///
+    /// Clients drop the Waste frames, so this works with any client.
+    pub fn with_cover_traffic(mut self, config: CoverTrafficConfig) -> Self {
+        self.cover_traffic = Some(config);
+        self
+    }
+
+    /// Set the buffer limits of each session
+    ///
+    /// See [`FlowControlConfig::overflow`] for the choice between pausing a
+    /// session behind a slow stream and resetting that stream.
+    pub fn with_flow_control(mut self, config: FlowControlConfig) -> Self {
+        self.flow_control = config;
+        self
+    }
+
+    /// Restrict which destinations the default TCP proxy handler may dial
+    ///
+    /// Without this, private and loopback destinations are rejected and
+    /// everything else is allowed.
+    pub fn with_outbound_acl(self, acl: OutboundAcl) -> Self {
+        *self.acl.write().unwrap() = Arc::new(acl);
+        self
+    }
+
+    /// Get outbound ACL reference for hot-reloading
+    ///
+    /// New rules apply to streams opened after the swap.
+    pub fn get_acl_ref(&self) -> Arc<RwLock<Arc<OutboundAcl>>> {
+        Arc::clone(&self.acl)
+    }
+
+    /// Cap sessions, connections per source IP and streams per session
+    ///
+    /// Connections over a limit are closed before the TLS handshake and
+    /// streams over a limit are refused; each refusal is logged.
+    pub fn with_limits(mut self, limits: ServerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set how long a new connection may take for the TLS handshake, the
+    /// authentication preamble and its first frame
+    ///
+    /// Connections that miss a deadline are closed, logged and counted in
+    /// the `anytls_*_timeouts_total` metrics.
+    pub fn with_handshake_timeouts(mut self, timeouts: HandshakeTimeouts) -> Self {
+        self.handshake_timeouts = timeouts;
+        self
+    }
+
+    /// Set the outbound connect and DNS timeouts and the idle and lifetime
+    /// limits of relayed streams
+    ///
+    /// The SYNACK timeout only applies to clients.
+    pub fn with_timeouts(mut self, timeouts: TimeoutPolicy) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Ban sources after repeated authentication failures
+    ///
+    /// Connections from a banned source are closed before the TLS handshake,
+    /// or forwarded to the fallback backend without reading a password if
+    /// one is configured.
+    pub fn with_auth_bans(mut self, bans: Arc<AuthBans>) -> Self {
+        self.auth_bans = Some(bans);
+        self
+    }
+
+    /// Failure tracker and ban list, if bans are enabled
+    pub fn auth_bans(&self) -> Option<Arc<AuthBans>> {
+        self.auth_bans.clone()
+    }
+
+    /// Number of open connections, including those still in the handshake
+    pub fn connection_count(&self) -> usize {
+        self.connections.total()
+    }
+
+    /// Live sessions with their streams, ordered by session id
+    pub async fn sessions(&self) -> Vec<SessionSummary> {
+        self.sessions.summaries().await
+    }
+
+    /// Close session `id` and its streams
+    ///
+    /// Returns `false` if no such session is open.
+    pub async fn close_session(&self, id: u64) -> Result<bool> {
+        self.sessions.close(id).await
+    }
+
+    pub(crate) fn session_registry(&self) -> SessionRegistry {
+        self.sessions.clone()
+    }
+
     /// Set callback for new streams
     pub fn with_stream_handler<F>(mut self, callback: F) -> Self
     where
-        F: Fn(Arc<crate::session::Stream>) + Send + Sync + 'static,
+        F: Fn(Arc<Stream>) + Send + Sync + 'static,
     {
         self.on_new_stream = Some(Arc::new(callback));
         self
     }
 
+    /// Forward connections that fail authentication to a fallback backend
+    ///
+    /// Instead of dropping the connection, the server replays the bytes it
+    /// already read and splices the rest of the TLS plaintext to `addr`
+    /// (for example a local web server), so active probes see a normal site.
+    pub fn with_fallback(mut self, addr: impl Into<Arc<str>>) -> Self {
+        self.fallback = Some(addr.into());
+        self
+    }
+
+    /// Use `shutdown` to stop [`listen`](Self::listen) gracefully
+    ///
+    /// Once triggered, the server stops accepting connections, rejects new
+    /// streams, closes idle sessions and gives active streams up to the
+    /// handle's drain timeout before closing their sessions.
+    pub fn with_shutdown(mut self, shutdown: ShutdownHandle) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Handle that triggers a graceful shutdown of this server
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
     /// Start the server and listen for connections
+    ///
+    /// Returns once shutdown has been triggered and all sessions are closed.
     pub async fn listen(&self, addr: &str) -> Result<()> {
         let listener = TcpListener::bind(addr).await?;
 
         tracing::info!("[Server] Listening on {}", addr);
 
+        let mut connections = JoinSet::new();
         loop {
-            match listener.accept().await {
+            let accepted = tokio::select! {
+                biased;
+                _ = self.shutdown.triggered() => break,
+                Some(_) = connections.join_next() => continue,
+                accepted = listener.accept() => accepted,
+            };
+            match accepted {
                 Ok((stream, addr)) => {
+                    let banned = self
+                        .auth_bans
+                        .as_ref()
+                        .is_some_and(|bans| bans.is_banned(addr.ip()));
+                    if banned && self.fallback.is_none() {
+                        tracing::warn!("[Server] Closing connection from banned source {}", addr);
+                        METRICS.banned_connections.inc();
+                        drop(stream);
+                        continue;
+                    }
+                    let permit = match self.connections.try_acquire(addr.ip(), &self.limits) {
+                        Ok(permit) => permit,
+                        Err(limit) => {
+                            tracing::warn!("[Server] Closing connection from {}: {}", addr, limit);
+                            drop(stream);
+                            continue;
+                        }
+                    };
                     let tls_config = self.tls_config.read().unwrap().clone();
-                    let password_hash = self.password_hash;
-                    let padding = Arc::clone(&self.padding);
-                    let on_new_stream = self.on_new_stream.clone();
-                    let server_settings = self.server_settings.clone();
+                    let users = self.users.read().unwrap().clone();
+                    let context = ConnectionContext {
+                        padding: self.padding.clone(),
+                        downstream_padding: self.downstream_padding.clone(),
+                        cover_traffic: self.cover_traffic.clone(),
+                        flow_control: self.flow_control,
+                        stream_limits: self.limits.stream_limits(),
+                        handshake_timeouts: self.handshake_timeouts,
+                        timeouts: self.timeouts,
+                        on_new_stream: self.on_new_stream.clone(),
+                        server_settings: self.server_settings.clone(),
+                        fallback: self.fallback.clone(),
+                        auth_bans: self.auth_bans.clone(),
+                        banned,
+                        shutdown: self.shutdown.clone(),
+                        acl: Arc::clone(&self.acl),
+                        sessions: self.sessions.clone(),
+                    };
                     let span = info_span!(
                         "anytls.connection",
                         peer_addr = %addr,
                         session_id = field::Empty
                     );
 
-                    tokio::spawn(
+                    connections.spawn(
                         async move {
-                            if let Err(e) = handle_connection(
-                                stream,
-                                tls_config,
-                                password_hash,
-                                padding,
-                                on_new_stream,
-                                server_settings,
-                            )
-                            .await
+                            let _permit = permit;
+                            if let Err(e) =
+                                handle_connection(stream, tls_config, users, context).await
                             {
                                 tracing::error!("[Server] Connection error: {}", e);
                             }
@@ -109,35 +338,153 @@ impl Server {
                 }
             }
         }
+
+        drop(listener);
+        tracing::info!(
+            "[Server] Shutdown requested, stopped accepting connections on {}",
+            addr
+        );
+        self.shutdown.join_tasks(&mut connections, "Server").await;
+        tracing::info!("[Server] Shutdown complete");
+        Ok(())
     }
 }
 
+/// Let the streams of `session` finish, then close it
+///
+/// Streams handed to a custom stream handler cannot be tracked
+/// (`active_streams` is `None`), so such sessions are kept until they close
+/// on their own or the drain timeout expires.
+async fn drain_session(
+    session: &Session,
+    active_streams: Option<&ActivityTracker>,
+    shutdown: &ShutdownHandle,
+) {
+    let drained = match active_streams {
+        Some(tracker) => timeout(shutdown.drain_timeout(), async {
+            tokio::select! {
+                _ = tracker.wait_idle() => {}
+                _ = session.closed() => {}
+            }
+        })
+        .await
+        .is_ok(),
+        None => timeout(shutdown.drain_timeout(), session.closed())
+            .await
+            .is_ok(),
+    };
+    if session.is_closed() {
+        return;
+    }
+    if drained {
+        tracing::debug!("[Server] Closing idle session {}", session.id());
+    } else {
+        tracing::warn!(
+            "[Server] Drain timeout reached, closing session {} ({} active stream(s))",
+            session.id(),
+            active_streams.map_or(0, ActivityTracker::active)
+        );
+    }
+    if let Err(e) = session.close().await {
+        tracing::debug!("[Server] Failed to close session {}: {}", session.id(), e);
+    }
+}
+
+/// Refuse a stream opened while the server is shutting down
+async fn reject_stream(stream: &Stream, session: &Session) {
+    const REASON: &str = "server is shutting down";
+    tracing::debug!(
+        "[Server] Rejecting stream {} of session {}: {}",
+        stream.id(),
+        session.id(),
+        REASON
+    );
+    if session.peer_version() >= 2 {
+        let synack_frame = Frame::with_data(Command::SynAck, stream.id(), Bytes::from(REASON));
+        if let Err(e) = session.write_control_frame(synack_frame).await {
+            tracing::debug!("[Server] Failed to send SYNACK with error: {}", e);
+        }
+    }
+    stream
+        .close_with_error(AnyTlsError::Protocol(REASON.into()))
+        .await;
+    session.release_stream(stream.id()).await;
+}
+
+/// Server settings handed to each connection task
+struct ConnectionContext {
+    padding: PaddingHandle,
+    downstream_padding: DownstreamPadding,
+    cover_traffic: Option<CoverTrafficConfig>,
+    flow_control: FlowControlConfig,
+    stream_limits: StreamLimits,
+    handshake_timeouts: HandshakeTimeouts,
+    timeouts: TimeoutPolicy,
+    on_new_stream: Option<StreamCallback>,
+    server_settings: Option<StringMap>,
+    fallback: Option<Arc<str>>,
+    auth_bans: Option<Arc<AuthBans>>,
+    /// Source was banned when accepted; only set with a fallback
+    banned: bool,
+    shutdown: ShutdownHandle,
+    acl: Arc<RwLock<Arc<OutboundAcl>>>,
+    sessions: SessionRegistry,
+}
+
 /// Handle a single TCP connection
 async fn handle_connection(
     tcp_stream: tokio::net::TcpStream,
     tls_config: Arc<TlsAcceptor>,
-    password_hash: [u8; 32],
-    padding: Arc<PaddingFactory>,
-    on_new_stream: Option<Arc<dyn Fn(Arc<crate::session::Stream>) + Send + Sync + 'static>>,
-    server_settings: Option<StringMap>,
+    users: Arc<UserTable>,
+    context: ConnectionContext,
 ) -> Result<()> {
-    let peer_addr = tcp_stream
-        .peer_addr()
-        .map(|a| a.to_string())
-        .unwrap_or_else(|_| "unknown".to_string());
+    let ConnectionContext {
+        padding,
+        downstream_padding,
+        cover_traffic,
+        flow_control,
+        stream_limits,
+        handshake_timeouts,
+        timeouts,
+        on_new_stream,
+        server_settings,
+        fallback,
+        auth_bans,
+        banned,
+        shutdown,
+        acl,
+        sessions,
+    } = context;
+    let peer = tcp_stream.peer_addr().ok();
+    let peer_addr = peer.map_or_else(|| "unknown".to_string(), |a| a.to_string());
     configure_tcp_stream(&tcp_stream, &peer_addr);
     let handshake_span = info_span!(
         "anytls.handshake",
         peer_addr = %peer_addr,
         session_id = field::Empty,
+        user = field::Empty,
         tls_version = field::Empty,
         cipher_suite = field::Empty
     );
-    let _handshake_guard = handshake_span.enter();
+    let handshake_guard = handshake_span.enter();
     tracing::info!("[Server] New connection from {}", peer_addr);
     // Perform TLS handshake
     tracing::debug!("[Server] Starting TLS handshake");
-    let tls_stream = tls_config.accept(tcp_stream).await.map_err(|e| {
+    let accepted = timeout(
+        handshake_timeouts.tls_handshake,
+        tls_config.accept(tcp_stream),
+    )
+    .await
+    .map_err(|_| {
+        tracing::warn!(
+            "[Server] TLS handshake from {} timed out after {:?}",
+            peer_addr,
+            handshake_timeouts.tls_handshake
+        );
+        METRICS.tls_handshake_timeouts.inc();
+        AnyTlsError::Timeout("TLS handshake".into())
+    })?;
+    let mut tls_stream = accepted.map_err(|e| {
         tracing::error!("[Server] TLS handshake failed: {}", e);
         AnyTlsError::Tls(format!("TLS handshake failed: {}", e))
     })?;
@@ -153,24 +500,85 @@ async fn handle_connection(
         );
     }
 
+    // Banned sources go straight to the fallback, without a password check
+    if banned && let Some(fallback_addr) = fallback {
+        tracing::info!(
+            "[Server] Forwarding connection from banned source {} to fallback {}",
+            peer_addr,
+            fallback_addr
+        );
+        METRICS.banned_connections.inc();
+        return forward_to_fallback(tls_stream, &[], &fallback_addr).await;
+    }
+
     // Authenticate client
     tracing::debug!("[Server] Authenticating client");
-    let (mut reader, writer) = tokio::io::split(tls_stream);
-    authenticate_client(&mut reader, &password_hash, &padding).await?;
-    tracing::debug!("[Server] Client authenticated");
+    let authentication = timeout(
+        handshake_timeouts.authentication,
+        read_authentication(&mut tls_stream, &users),
+    )
+    .await
+    .map_err(|_| {
+        tracing::warn!(
+            "[Server] Authentication from {} timed out after {:?}",
+            peer_addr,
+            handshake_timeouts.authentication
+        );
+        METRICS.auth_timeouts.inc();
+        AnyTlsError::Timeout("authentication".into())
+    })?;
+    let user = match authentication? {
+        AuthResult::Accepted(user) => user,
+        AuthResult::Rejected(consumed) => {
+            tracing::warn!("[Server] Authentication failed from {}", peer_addr);
+            METRICS.auth_failures.inc();
+            if let (Some(bans), Some(peer)) = (&auth_bans, peer)
+                && let Some(ban) = bans.record_failure(peer.ip())
+            {
+                tracing::warn!(
+                    "[Server] Banning {} for {:?} after {} failed authentications",
+                    ban.source,
+                    bans.config().ban_duration,
+                    bans.config().max_failures
+                );
+            }
+            let Some(fallback_addr) = fallback else {
+                return Err(AnyTlsError::AuthenticationFailed);
+            };
+            tracing::info!(
+                "[Server] Forwarding unauthenticated connection from {} to fallback {}",
+                peer_addr,
+                fallback_addr
+            );
+            return forward_to_fallback(tls_stream, &consumed, &fallback_addr).await;
+        }
+    };
+    let (reader, writer) = tokio::io::split(tls_stream);
+    handshake_span.record("user", field::display(&user));
+    tracing::debug!("[Server] Client authenticated as {}", user);
 
     // Create callback channel for new streams
     let (stream_callback_tx, mut stream_callback_rx) =
-        tokio::sync::mpsc::unbounded_channel::<Arc<crate::session::Stream>>();
+        tokio::sync::mpsc::unbounded_channel::<Arc<Stream>>();
 
-    // Create server session
-    let mut session = Session::new_server(reader, writer, padding);
+    // Create server session; it follows later padding scheme updates
+    let mut padding_updates = padding.subscribe();
+    let current_padding = padding_updates.borrow_and_update().clone();
+    let mut session = Session::new_server(reader, writer, current_padding);
     session.set_server_settings(server_settings.clone());
+    session.set_downstream_padding(downstream_padding);
+    session.set_cover_traffic(cover_traffic);
+    session.set_flow_control(flow_control);
+    session.set_stream_limits(stream_limits);
+    session.set_first_frame_timeout(Some(handshake_timeouts.first_frame));
+    session.set_user(user.clone());
 
     // Set callback channel in session
     session.set_stream_callback(stream_callback_tx);
 
     let session = Arc::new(session);
+    let _registration = sessions.register(Arc::clone(&session), peer);
+    session.start_cover_traffic();
     let session_id = session.id();
     Span::current().record("session_id", session_id);
     handshake_span.record("session_id", field::display(session_id));
@@ -178,11 +586,13 @@ async fn handle_connection(
     tracing::info!(
         session_id = session_id,
         peer_addr = %peer_addr,
+        user = %user,
         "[Server] Session {} created",
         session_id
     );
 
     // Handle new streams in a task
+    let active_streams = on_new_stream.is_none().then(ActivityTracker::new);
     if let Some(callback) = on_new_stream {
         tracing::debug!("[Server] Using custom stream callback");
         tokio::spawn(async move {
@@ -209,16 +619,26 @@ async fn handle_connection(
         // Use default TCP proxy handler if no callback is provided
         tracing::debug!("[Server] Using default TCP proxy handler");
         let session_for_handler = Arc::clone(&session);
+        let active_streams = active_streams.clone().unwrap_or_default();
+        let shutdown = shutdown.clone();
         tokio::spawn(async move {
             while let Some(stream) = stream_callback_rx.recv().await {
                 tracing::debug!(
                     "[Server] Received stream {} for default handler",
                     stream.id()
                 );
+                if shutdown.is_shutdown() {
+                    reject_stream(&stream, &session_for_handler).await;
+                    continue;
+                }
+                let activity = active_streams.track();
                 let stream_clone = Arc::clone(&stream);
                 let session_clone = Arc::clone(&session_for_handler);
-                // Create a new handler instance for each stream (TcpProxyHandler is small and stateless)
-                let handler = TcpProxyHandler::new();
+                // Create a new handler instance for each stream with the current rule set
+                let handler = TcpProxyHandler::new()
+                    .with_acl(acl.read().unwrap().clone())
+                    .with_timeouts(timeouts);
+                let user = user.clone();
                 let stream_id = stream_clone.id();
                 let stream_span = info_span!(
                     "anytls.stream.proxy",
@@ -227,9 +647,17 @@ async fn handle_connection(
                 );
                 tokio::spawn(
                     async move {
-                        if let Err(e) = handler.handle_stream(stream_clone, session_clone).await {
+                        let _activity = activity;
+                        let session = Arc::clone(&session_clone);
+                        if let Err(e) = handler
+                            .handle_stream(stream_clone, session_clone, user)
+                            .await
+                        {
                             tracing::error!("[Proxy] Handler error: {}", e);
                         }
+                        // Failed and refused streams must not keep counting
+                        // against the per-session stream limit
+                        session.release_stream(stream_id).await;
                     }
                     .instrument(stream_span),
                 );
@@ -298,10 +726,28 @@ async fn handle_connection(
     );
 
     tracing::debug!("[Server] Connection handler setup complete");
+    drop(handshake_guard);
 
-    // Wait for connection to close
-    // The connection will be managed by the spawned tasks
-    // In a production implementation, we'd wait for the session to close
+    // Stay alive until the session closes so that a shutdown can drain it
+    loop {
+        tokio::select! {
+            _ = session.closed() => break,
+            _ = shutdown.triggered() => {
+                drain_session(&session, active_streams.as_ref(), &shutdown).await;
+                break;
+            }
+            Ok(()) = padding_updates.changed() => {
+                let new_padding = padding_updates.borrow_and_update().clone();
+                if let Err(e) = session.update_padding(new_padding).await {
+                    tracing::debug!(
+                        "[Server] Failed to push padding scheme to session {}: {}",
+                        session_id,
+                        e
+                    );
+                }
+            }
+        }
+    }
 
     Ok(())
 }
@@ -18,9 +18,14 @@
 //!
 //! Reference: <https://github.com/SagerNet/sing-box/blob/dev-next/docs/configuration/shared/udp-over-tcp.md>
 
-use crate::session::{Stream, StreamReader};
-use crate::util::{AnyTlsError, Result, resolve_host_with_cache};
+use crate::protocol::{Command, Frame};
+use crate::server::OutboundAcl;
+use crate::session::{Session, Stream, StreamReader};
+use crate::util::{
+    AnyTlsError, METRICS, RelayTimer, Result, TimeoutPolicy, resolve_host_with_cache,
+};
 use bytes::{BufMut, Bytes, BytesMut};
+use std::fmt;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -29,6 +34,28 @@ use tracing::{field, info_span};
 
 const MAX_UDP_PACKET_SIZE: usize = 65535;
 
+/// Destination named by the initial request, before it is resolved
+enum UdpRequest {
+    Addr(SocketAddr),
+    Domain(String, u16),
+}
+
+impl fmt::Display for UdpRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UdpRequest::Addr(addr) => write!(f, "{}", addr),
+            UdpRequest::Domain(domain, port) => write!(f, "{}:{}", domain, port),
+        }
+    }
+}
+
+/// Checked and resolved target of a UDP over TCP stream
+struct UdpTarget {
+    /// Requested domain name, if the client did not send an IP address
+    domain: Option<String>,
+    addr: SocketAddr,
+}
+
 /// Handle UDP over TCP stream
 ///
 /// Target address should be "sp.v2.udp-over-tcp.arpa"
@@ -42,6 +69,130 @@ const MAX_UDP_PACKET_SIZE: usize = 65535;
 ///
 /// Reference: <https://github.com/SagerNet/sing-box/blob/dev-next/docs/configuration/shared/udp-over-tcp.md>
 pub async fn handle_udp_over_tcp(stream: Arc<Stream>) -> Result<()> {
+    let request = read_target(&stream).await?;
+    let target = resolve_target(request, &OutboundAcl::default()).await?;
+    relay_udp(
+        stream,
+        target.addr,
+        RelayTimer::new(&TimeoutPolicy::default()),
+    )
+    .await
+}
+
+/// Like [`handle_udp_over_tcp`], checking the target against `acl` and
+/// reporting the outcome to the client with a SYNACK (protocol v2)
+///
+/// When the initial request arrives in the same frame as the stream address
+/// (as this crate's client sends it), the SYNACK is held back until the
+/// target is checked: a rejected target gets an error SYNACK, like a refused
+/// TCP destination, and a success SYNACK means the relay is ready.
+///
+/// Clients that wait for the SYNACK before sending the request get a success
+/// SYNACK for the stream right away, as no target has been named yet. Their
+/// target is checked when the request arrives; if it is rejected they get an
+/// error SYNACK afterwards, which resets the stream, and no datagram is sent.
+///
+/// The relay stops after the stream idle timeout or lifetime of `timeouts`.
+pub async fn handle_udp_over_tcp_with_acl(
+    stream: Arc<Stream>,
+    session: &Session,
+    acl: &OutboundAcl,
+    timeouts: &TimeoutPolicy,
+) -> Result<()> {
+    let stream_id = stream.id();
+    let report = session.peer_version() >= 2;
+    // Whatever followed the stream address in its frame is already buffered
+    let early_request = stream.reader().lock().await.buffer_len() > 0;
+    if report && !early_request {
+        send_synack(session, stream_id, None).await?;
+    }
+
+    let request = match read_target(&stream).await {
+        Ok(request) => request,
+        Err(e) => {
+            if report {
+                let _ = send_synack(session, stream_id, Some(e.to_string())).await;
+            }
+            return Err(e);
+        }
+    };
+    let target_display = request.to_string();
+    let target = match resolve_target(request, acl).await {
+        Ok(target) => target,
+        Err(e) => {
+            if report {
+                let error_msg = match e {
+                    AnyTlsError::AccessDenied(_) => {
+                        format!("Connection to {} refused: {}", target_display, e)
+                    }
+                    _ => format!("Failed to resolve {}: {}", target_display, e),
+                };
+                let _ = send_synack(session, stream_id, Some(error_msg)).await;
+            }
+            return Err(e);
+        }
+    };
+    if report && early_request {
+        send_synack(session, stream_id, None).await?;
+    }
+    relay_udp(stream, target.addr, RelayTimer::new(timeouts)).await
+}
+
+/// Send a SYNACK for `stream_id`, carrying `error` if the stream failed
+async fn send_synack(session: &Session, stream_id: u32, error: Option<String>) -> Result<()> {
+    let frame = match error {
+        Some(error) => Frame::with_data(Command::SynAck, stream_id, Bytes::from(error)),
+        None => Frame::control(Command::SynAck, stream_id),
+    };
+    session.write_control_frame(frame).await.inspect_err(|e| {
+        tracing::error!("[UDP] Failed to send SYNACK: {}", e);
+    })
+}
+
+/// Read the initial request of a UDP over TCP stream
+async fn read_target(stream: &Stream) -> Result<UdpRequest> {
+    let mut reader = stream.reader().lock().await;
+    read_initial_request(&mut reader).await.inspect_err(|e| {
+        tracing::error!("[UDP] Failed to read initial request: {}", e);
+    })
+}
+
+/// Check the requested destination against `acl` and resolve it
+///
+/// Names are checked before they are looked up, so denied names never reach
+/// the resolver, and the resolved address is checked again.
+async fn resolve_target(request: UdpRequest, acl: &OutboundAcl) -> Result<UdpTarget> {
+    let target = match request {
+        UdpRequest::Addr(addr) => UdpTarget { domain: None, addr },
+        UdpRequest::Domain(domain, port) => {
+            if let Err(e) = acl.check_domain(&domain, port) {
+                tracing::warn!("[UDP] Rejected target {}:{}: {}", domain, port, e);
+                return Err(e);
+            }
+            let addr = resolve_host_with_cache(&domain, port).await?;
+            UdpTarget {
+                domain: Some(domain),
+                addr,
+            }
+        }
+    };
+    if let Err(e) = acl.check(
+        target.domain.as_deref(),
+        target.addr.ip(),
+        target.addr.port(),
+    ) {
+        let target_display = match &target.domain {
+            Some(domain) => format!("{}:{} ({})", domain, target.addr.port(), target.addr),
+            None => target.addr.to_string(),
+        };
+        tracing::warn!("[UDP] Rejected target {}: {}", target_display, e);
+        return Err(e);
+    }
+    Ok(target)
+}
+
+/// Forward datagrams between `stream` and `target_addr`
+async fn relay_udp(stream: Arc<Stream>, target_addr: SocketAddr, timer: RelayTimer) -> Result<()> {
     let stream_id = stream.id();
     let udp_span = info_span!(
         "anytls.udp.proxy",
@@ -56,26 +207,10 @@ pub async fn handle_udp_over_tcp(stream: Arc<Stream>) -> Result<()> {
     let _udp_guard = udp_span.enter();
 
     tracing::debug!("[UDP] Starting UDP over TCP proxy for stream {}", stream_id);
-
-    let reader = stream.reader();
-    let mut reader_guard = reader.lock().await;
-
-    // Step 1: Read initial request (contains target address)
-    // Format: SOCKS5 address (ATYP + Address + Port)
-    let target_addr = match read_initial_request(&mut reader_guard).await {
-        Ok(addr) => addr,
-        Err(e) => {
-            tracing::error!("[UDP] Failed to read initial request: {}", e);
-            return Err(e);
-        }
-    };
     udp_span.record("target", field::display(target_addr));
-
     tracing::debug!("[UDP] Target UDP address: {}", target_addr);
 
-    drop(reader_guard);
-
-    // Step 2: Create UDP socket (bind to any available port)
+    // Create UDP socket (bind to any available port)
     let udp_socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| {
         tracing::error!("[UDP] Failed to create UDP socket: {}", e);
         AnyTlsError::Io(e)
@@ -99,6 +234,7 @@ pub async fn handle_udp_over_tcp(stream: Arc<Stream>) -> Result<()> {
             &stream,
             &udp_socket,
             &target_addr,
+            &timer,
             Arc::clone(&packets_stream_to_udp),
             Arc::clone(&bytes_stream_to_udp)
         ) => {
@@ -111,6 +247,7 @@ pub async fn handle_udp_over_tcp(stream: Arc<Stream>) -> Result<()> {
             &stream,
             &udp_socket,
             &target_addr,
+            &timer,
             Arc::clone(&packets_udp_to_stream),
             Arc::clone(&bytes_udp_to_stream)
         ) => {
@@ -119,6 +256,14 @@ pub async fn handle_udp_over_tcp(stream: Arc<Stream>) -> Result<()> {
                 return Err(e);
             }
         }
+        expiry = timer.expired() => {
+            tracing::info!("[UDP] Closing stream {} to {}: {}", stream_id, target_addr, expiry);
+            METRICS.stream_timeouts.inc();
+            let _ = stream.shutdown_write();
+            stream
+                .close_with_error(AnyTlsError::Timeout(format!("stream {}", expiry)))
+                .await;
+        }
     }
 
     let packets_out = packets_stream_to_udp.load(Ordering::Relaxed);
@@ -147,8 +292,8 @@ pub async fn handle_udp_over_tcp(stream: Arc<Stream>) -> Result<()> {
 /// | u8        | u8   | variable| u16be|
 /// ```
 ///
-/// Returns the target SocketAddr
-async fn read_initial_request(reader: &mut StreamReader) -> Result<SocketAddr> {
+/// Returns the target, with domain names resolved
+async fn read_initial_request(reader: &mut StreamReader) -> Result<UdpRequest> {
     // Read isConnect (1 byte)
     let mut is_connect_buf = [0u8; 1];
     reader
@@ -195,7 +340,7 @@ async fn read_initial_request(reader: &mut StreamReader) -> Result<SocketAddr> {
             let ip = std::net::Ipv4Addr::from(ip_buf);
             let port = u16::from_be_bytes(port_buf);
 
-            Ok(SocketAddr::from((ip, port)))
+            Ok(UdpRequest::Addr(SocketAddr::from((ip, port))))
         }
         0x04 => {
             // IPv6: 16 bytes IP + 2 bytes port
@@ -214,7 +359,7 @@ async fn read_initial_request(reader: &mut StreamReader) -> Result<SocketAddr> {
             let ip = std::net::Ipv6Addr::from(ip_buf);
             let port = u16::from_be_bytes(port_buf);
 
-            Ok(SocketAddr::from((ip, port)))
+            Ok(UdpRequest::Addr(SocketAddr::from((ip, port))))
         }
         0x03 => {
             // Domain: length (1 byte) + domain + 2 bytes port
@@ -246,10 +391,8 @@ async fn read_initial_request(reader: &mut StreamReader) -> Result<SocketAddr> {
 
             let port = u16::from_be_bytes(port_buf);
 
-            // Resolve domain name with caching and timeout
-            let addr = resolve_host_with_cache(&domain, port).await?;
-
-            Ok(addr)
+            // Resolved after the ACL has checked the name
+            Ok(UdpRequest::Domain(domain, port))
         }
         _ => Err(AnyTlsError::Protocol(format!(
             "Unknown address type: {}",
@@ -266,6 +409,7 @@ async fn stream_to_udp(
     stream: &Stream,
     udp: &UdpSocket,
     target_addr: &SocketAddr,
+    timer: &RelayTimer,
     packets_counter: Arc<AtomicU64>,
     bytes_counter: Arc<AtomicU64>,
 ) -> Result<()> {
@@ -308,6 +452,7 @@ async fn stream_to_udp(
         }
         packets_counter.fetch_add(1, Ordering::Relaxed);
         bytes_counter.fetch_add(sent as u64, Ordering::Relaxed);
+        timer.touch();
     }
 
     Ok(())
@@ -322,6 +467,7 @@ async fn udp_to_stream(
     stream: &Stream,
     udp: &UdpSocket,
     _target_addr: &SocketAddr,
+    timer: &RelayTimer,
     packets_counter: Arc<AtomicU64>,
     bytes_counter: Arc<AtomicU64>,
 ) -> Result<()> {
@@ -348,13 +494,14 @@ async fn udp_to_stream(
         // The source address info is not included in each packet (connection is established)
         let packet = encode_udp_packet_simple(&buf[..len])?;
 
-        // Send to Stream using the send_data method
-        if let Err(e) = stream.send_data(packet) {
+        // Send to Stream, waiting while the session's send queue is full
+        if let Err(e) = stream.write_data(packet).await {
             tracing::error!("[UDP] Failed to send to stream: {}", e);
             return Err(AnyTlsError::Protocol("Channel send failed".into()));
         }
         packets_counter.fetch_add(1, Ordering::Relaxed);
         bytes_counter.fetch_add(len as u64, Ordering::Relaxed);
+        timer.touch();
     }
 }
 
@@ -0,0 +1,140 @@
+//! Cover traffic for idle sessions
+//!
+//! Once a session has carried no stream traffic for
+//! [`CoverTrafficConfig::idle_after`], it writes Waste frames of random size
+//! at random intervals, within a byte rate cap, so an idle pooled session is
+//! not silent apart from heartbeats. Peers drop Waste frames, so no
+//! negotiation is needed.
+
+use crate::padding::SizeDistribution;
+use crate::protocol::{Command, HEADER_OVERHEAD_SIZE, MAX_FRAME_DATA_SIZE};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Cover traffic settings of a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverTrafficConfig {
+    /// Time without stream traffic before cover frames start
+    pub idle_after: Duration,
+    /// Shortest gap between cover frames
+    pub min_interval: Duration,
+    /// Longest gap between cover frames
+    pub max_interval: Duration,
+    /// Data size of each Waste frame
+    pub sizes: SizeDistribution,
+    /// Cover bytes per second, frame headers included; bursts are limited
+    /// to one second's worth
+    pub max_bytes_per_sec: u32,
+}
+
+impl Default for CoverTrafficConfig {
+    fn default() -> Self {
+        Self {
+            idle_after: Duration::from_secs(5),
+            min_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(8),
+            sizes: "64-1200"
+                .parse()
+                .expect("default cover sizes should be valid"),
+            max_bytes_per_sec: 4096,
+        }
+    }
+}
+
+impl CoverTrafficConfig {
+    /// Gap before the next cover frame
+    pub(crate) fn next_interval(&self) -> Duration {
+        if self.max_interval <= self.min_interval {
+            return self.min_interval;
+        }
+        let min = self.min_interval.as_millis() as u64;
+        let max = self.max_interval.as_millis() as u64;
+        Duration::from_millis(rand::random_range(min..=max))
+    }
+
+    /// Data size of the next cover frame
+    pub(crate) fn next_size(&self) -> usize {
+        (self.sizes.sample() as usize).min(MAX_FRAME_DATA_SIZE)
+    }
+}
+
+/// Frames that make a session busy; Waste and heartbeats do not
+pub(crate) fn is_stream_traffic(cmd: Command) -> bool {
+    matches!(
+        cmd,
+        Command::Push | Command::Syn | Command::SynAck | Command::Fin
+    )
+}
+
+/// Token bucket holding up to one second of cover bytes
+pub(crate) struct CoverBudget {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl CoverBudget {
+    pub(crate) fn new(max_bytes_per_sec: u32) -> Self {
+        Self {
+            rate: max_bytes_per_sec as f64,
+            tokens: max_bytes_per_sec as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Take the bytes of a frame with `data_len` bytes of data, if the rate
+    /// allows it now
+    pub(crate) fn try_take(&mut self, data_len: usize) -> bool {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated = now;
+
+        let cost = (HEADER_OVERHEAD_SIZE + data_len) as f64;
+        if cost > self.tokens {
+            return false;
+        }
+        self.tokens -= cost;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_budget_caps_bytes_per_second() {
+        let mut budget = CoverBudget::new(1000);
+        // One second's worth up front, headers included
+        assert!(budget.try_take(493));
+        assert!(budget.try_take(493));
+        assert!(!budget.try_take(1));
+
+        tokio::time::advance(Duration::from_millis(250)).await;
+        assert!(budget.try_take(243));
+        assert!(!budget.try_take(1));
+
+        // Idle time refills at most one second
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(budget.try_take(993));
+        assert!(!budget.try_take(1));
+    }
+
+    #[test]
+    fn test_intervals_and_sizes_stay_in_range() {
+        let config = CoverTrafficConfig {
+            min_interval: Duration::from_millis(100),
+            max_interval: Duration::from_millis(300),
+            sizes: "10-20".parse().unwrap(),
+            ..CoverTrafficConfig::default()
+        };
+        for _ in 0..100 {
+            let gap = config.next_interval();
+            assert!(gap >= Duration::from_millis(100) && gap <= Duration::from_millis(300));
+            assert!((10..=20).contains(&config.next_size()));
+        }
+        // Sizes above a frame's capacity are rejected when parsing
+        assert!("10-20|70000-70000".parse::<SizeDistribution>().is_err());
+    }
+}
@@ -0,0 +1,450 @@
+//! Byte budgets for session buffering
+//!
+//! AnyTLS has no per-stream flow control on the wire, so buffering is
+//! bounded locally:
+//!
+//! - **Receive**: every stream may hold up to
+//!   [`FlowControlConfig::stream_receive_buffer`] unread bytes on its own.
+//!   Bytes beyond that are charged to a budget shared by the whole session
+//!   ([`FlowControlConfig::session_receive_buffer`]). A slow reader therefore
+//!   never blocks `recv_loop` by itself. What happens once the shared budget
+//!   is used up is set by [`FlowControlConfig::overflow`]:
+//!   - [`ReceiveOverflow::Pause`] (default): the session stops reading from
+//!     the connection until readers catch up, which pushes back on the peer
+//!     through TCP. No data is lost, but every stream of the session waits
+//!     for the slowest one (head-of-line blocking); a larger shared budget
+//!     makes that rarer at the cost of memory.
+//!   - [`ReceiveOverflow::Reset`]: the stream whose data does not fit is
+//!     reset and the others keep flowing. Its reader fails once buffered
+//!     data is read, and the peer is told with an error SYNACK, so neither
+//!     side mistakes the truncated stream for a complete one. Peers that
+//!     cannot be told (protocol v1) are paused instead.
+//! - **Send**: data queued by streams for the connection is limited to
+//!   [`FlowControlConfig::session_send_buffer`] bytes. Writers wait (and
+//!   `poll_write` returns `Pending`) until earlier data has been written.
+
+use crate::util::{AnyTlsError, Result};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use tokio::sync::Notify;
+use tokio::sync::futures::OwnedNotified;
+
+/// Default unread bytes a single stream may buffer on its own
+pub const DEFAULT_STREAM_RECEIVE_BUFFER: usize = 256 * 1024;
+/// Default bytes shared by streams that exceed their own receive buffer
+pub const DEFAULT_SESSION_RECEIVE_BUFFER: usize = 4 * 1024 * 1024;
+/// Default bytes queued for the connection before writers wait
+pub const DEFAULT_SESSION_SEND_BUFFER: usize = 1024 * 1024;
+
+/// What a session does when the shared receive budget is used up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReceiveOverflow {
+    /// Stop reading from the connection until readers catch up; lossless,
+    /// but all streams of the session wait for the slowest one
+    #[default]
+    Pause,
+    /// Reset the stream whose data does not fit, telling the peer with an
+    /// error SYNACK; other streams are not held up
+    Reset,
+}
+
+/// Buffer limits of a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowControlConfig {
+    /// Unread bytes a single stream may buffer before it draws on the shared budget
+    pub stream_receive_buffer: usize,
+    /// Bytes shared by all streams above their own buffer
+    pub session_receive_buffer: usize,
+    /// Bytes queued for the connection before stream writers wait
+    pub session_send_buffer: usize,
+    /// What happens once `session_receive_buffer` is used up
+    pub overflow: ReceiveOverflow,
+}
+
+impl Default for FlowControlConfig {
+    fn default() -> Self {
+        Self {
+            stream_receive_buffer: DEFAULT_STREAM_RECEIVE_BUFFER,
+            session_receive_buffer: DEFAULT_SESSION_RECEIVE_BUFFER,
+            session_send_buffer: DEFAULT_SESSION_SEND_BUFFER,
+            overflow: ReceiveOverflow::Pause,
+        }
+    }
+}
+
+/// Session-wide receive accounting
+pub(crate) struct ReceiveBudget {
+    stream_limit: usize,
+    session_limit: usize,
+    policy: ReceiveOverflow,
+    /// Unread bytes over all streams
+    queued: AtomicUsize,
+    /// Unread bytes above the per-stream limits
+    overflow: AtomicUsize,
+    notify: Notify,
+}
+
+impl ReceiveBudget {
+    pub(crate) fn new(config: &FlowControlConfig) -> Arc<Self> {
+        Arc::new(Self {
+            stream_limit: config.stream_receive_buffer,
+            session_limit: config.session_receive_buffer,
+            policy: config.overflow,
+            queued: AtomicUsize::new(0),
+            overflow: AtomicUsize::new(0),
+            notify: Notify::new(),
+        })
+    }
+
+    /// What to do with data the shared budget cannot take
+    pub(crate) fn policy(&self) -> ReceiveOverflow {
+        self.policy
+    }
+
+    /// Create the window of a new stream
+    pub(crate) fn stream_window(self: &Arc<Self>) -> Arc<StreamWindow> {
+        Arc::new(StreamWindow {
+            budget: Arc::clone(self),
+            queued: AtomicUsize::new(0),
+            reset: AtomicBool::new(false),
+        })
+    }
+
+    /// Unread bytes over all streams
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Whether the connection may be read again
+    pub(crate) fn has_room(&self) -> bool {
+        self.overflow.load(Ordering::Acquire) < self.session_limit
+    }
+
+    /// Wait until readers have drained the shared budget below its limit
+    pub(crate) async fn wait_for_room(&self) {
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.has_room() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    fn over(&self, queued: usize) -> usize {
+        queued.saturating_sub(self.stream_limit)
+    }
+}
+
+/// Receive accounting of one stream, shared by the session and the stream's reader
+pub(crate) struct StreamWindow {
+    budget: Arc<ReceiveBudget>,
+    queued: AtomicUsize,
+    /// Set when the stream was reset
+    reset: AtomicBool,
+}
+
+impl StreamWindow {
+    /// Account `n` bytes handed to the stream's reader, even beyond the
+    /// shared budget (which then pauses `recv_loop`)
+    pub(crate) fn queued(&self, n: usize) {
+        let old = self.queued.fetch_add(n, Ordering::AcqRel);
+        self.budget.queued.fetch_add(n, Ordering::Relaxed);
+        let grown = self.budget.over(old + n) - self.budget.over(old);
+        if grown > 0 {
+            self.budget.overflow.fetch_add(grown, Ordering::AcqRel);
+        }
+    }
+
+    /// Account `n` bytes handed to the stream's reader
+    ///
+    /// Returns `false`, accounting nothing, if the bytes exceed the stream's
+    /// own buffer and the shared budget cannot take the excess.
+    pub(crate) fn try_queued(&self, n: usize) -> bool {
+        let budget = &self.budget;
+        // Readers only shrink the queue meanwhile, so this bounds the growth
+        let current = self.queued.load(Ordering::Acquire);
+        let reserved = budget.over(current + n) - budget.over(current);
+        if reserved > 0
+            && budget
+                .overflow
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |o| {
+                    (o + reserved <= budget.session_limit).then_some(o + reserved)
+                })
+                .is_err()
+        {
+            return false;
+        }
+        let old = self.queued.fetch_add(n, Ordering::AcqRel);
+        budget.queued.fetch_add(n, Ordering::Relaxed);
+        let grown = budget.over(old + n) - budget.over(old);
+        if reserved > grown {
+            budget
+                .overflow
+                .fetch_sub(reserved - grown, Ordering::AcqRel);
+        }
+        true
+    }
+
+    /// Mark the stream as reset; its reader fails instead of reporting EOF
+    pub(crate) fn mark_reset(&self) {
+        self.reset.store(true, Ordering::Release);
+    }
+
+    /// Whether the stream was reset, locally or by the peer
+    pub(crate) fn is_reset(&self) -> bool {
+        self.reset.load(Ordering::Acquire)
+    }
+
+    /// Account `n` bytes read (or dropped) by the stream's reader
+    pub(crate) fn consumed(&self, n: usize) {
+        let old = self
+            .queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |q| {
+                Some(q.saturating_sub(n))
+            })
+            .unwrap_or_default();
+        self.settle(old, old.saturating_sub(n));
+    }
+
+    /// Give back everything still buffered (the reader is gone)
+    pub(crate) fn release(&self) {
+        let old = self.queued.swap(0, Ordering::AcqRel);
+        self.settle(old, 0);
+    }
+
+    /// Unread bytes of this stream
+    #[cfg(test)]
+    pub(crate) fn buffered(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    fn settle(&self, old: usize, new: usize) {
+        let budget = &self.budget;
+        let _ = budget
+            .queued
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |q| {
+                Some(q.saturating_sub(old - new))
+            });
+        let shrunk = budget.over(old) - budget.over(new);
+        if shrunk > 0 {
+            budget.overflow.fetch_sub(shrunk, Ordering::AcqRel);
+            budget.notify.notify_waiters();
+        }
+    }
+}
+
+/// Session-wide send accounting
+///
+/// The limit is soft: a writer is admitted while the queue is below the
+/// limit, so the queue may exceed it by at most one write per writer.
+pub(crate) struct SendBudget {
+    limit: usize,
+    queued: AtomicUsize,
+    closed: AtomicBool,
+    notify: Arc<Notify>,
+}
+
+impl SendBudget {
+    pub(crate) fn new(config: &FlowControlConfig) -> Arc<Self> {
+        Arc::new(Self {
+            limit: config.session_send_buffer,
+            queued: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            notify: Arc::new(Notify::new()),
+        })
+    }
+
+    /// Bytes queued for the connection
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    fn try_reserve(&self, n: usize) -> bool {
+        self.queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |q| {
+                (q < self.limit).then_some(q + n)
+            })
+            .is_ok()
+    }
+
+    /// Charge `n` bytes without waiting
+    pub(crate) fn force_reserve(&self, n: usize) {
+        self.queued.fetch_add(n, Ordering::AcqRel);
+    }
+
+    /// Wait until `n` bytes may be queued
+    pub(crate) async fn reserve(&self, n: usize) -> Result<()> {
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.closed.load(Ordering::Acquire) {
+                return Err(AnyTlsError::SessionClosed);
+            }
+            if self.try_reserve(n) {
+                return Ok(());
+            }
+            notified.await;
+        }
+    }
+
+    /// Poll-based [`reserve`](Self::reserve); `wait` keeps the pending wakeup between polls
+    pub(crate) fn poll_reserve(
+        &self,
+        wait: &mut Option<Pin<Box<OwnedNotified>>>,
+        cx: &mut Context<'_>,
+        n: usize,
+    ) -> Poll<Result<()>> {
+        loop {
+            if self.closed.load(Ordering::Acquire) {
+                *wait = None;
+                return Poll::Ready(Err(AnyTlsError::SessionClosed));
+            }
+            if self.try_reserve(n) {
+                *wait = None;
+                return Poll::Ready(Ok(()));
+            }
+            match wait {
+                Some(notified) => match notified.as_mut().poll(cx) {
+                    Poll::Ready(()) => *wait = None,
+                    Poll::Pending => return Poll::Pending,
+                },
+                None => {
+                    let mut notified = Box::pin(Arc::clone(&self.notify).notified_owned());
+                    notified.as_mut().enable();
+                    *wait = Some(notified);
+                }
+            }
+        }
+    }
+
+    /// Return `n` bytes once they have been written to the connection
+    pub(crate) fn release(&self, n: usize) {
+        let _ = self
+            .queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |q| {
+                Some(q.saturating_sub(n))
+            });
+        self.notify.notify_waiters();
+    }
+
+    /// Fail current and future waiters
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.notify.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{Duration, timeout};
+
+    fn config(stream: usize, session: usize, send: usize) -> FlowControlConfig {
+        FlowControlConfig {
+            stream_receive_buffer: stream,
+            session_receive_buffer: session,
+            session_send_buffer: send,
+            ..FlowControlConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_receive_overflow_accounting() {
+        let budget = ReceiveBudget::new(&config(100, 150, 0));
+        let slow = budget.stream_window();
+        let fast = budget.stream_window();
+
+        // Within its own window a stream does not touch the shared budget
+        assert!(slow.try_queued(100));
+        assert!(fast.try_queued(50));
+        assert_eq!(budget.queued(), 150);
+        assert_eq!(budget.overflow.load(Ordering::Relaxed), 0);
+
+        // Overflow of one stream eats the shared budget
+        assert!(slow.try_queued(150));
+        assert_eq!(budget.overflow.load(Ordering::Relaxed), 150);
+        assert!(!budget.has_room());
+
+        // Bytes that do not fit are refused without being accounted, while
+        // the other stream may still use its own window
+        assert!(!slow.try_queued(1));
+        assert!(!fast.try_queued(51));
+        assert!(fast.try_queued(50));
+        assert_eq!(slow.buffered(), 250);
+
+        slow.consumed(60);
+        assert!(budget.has_room());
+        assert!(slow.try_queued(10));
+        assert_eq!(slow.buffered(), 200);
+
+        // Dropping a reader gives everything back
+        slow.release();
+        assert_eq!(budget.queued(), 100);
+        assert_eq!(budget.overflow.load(Ordering::Relaxed), 0);
+
+        // Late consumption after release does not underflow
+        slow.consumed(10);
+        assert_eq!(slow.buffered(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_room_wakes_on_consume() {
+        let budget = ReceiveBudget::new(&config(0, 10, 0));
+        let window = budget.stream_window();
+        // Pausing accounts everything handed over, even past the limit
+        window.queued(15);
+        assert_eq!(budget.overflow.load(Ordering::Relaxed), 15);
+
+        let waiter = {
+            let budget = Arc::clone(&budget);
+            tokio::spawn(async move { budget.wait_for_room().await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        window.consumed(5);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+        window.consumed(5);
+        timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_budget_blocks_until_release() {
+        let budget = SendBudget::new(&config(0, 0, 10));
+        budget.reserve(8).await.unwrap();
+        // Soft limit: admitted while below the limit
+        budget.reserve(8).await.unwrap();
+        assert_eq!(budget.queued(), 16);
+
+        assert!(
+            timeout(Duration::from_millis(50), budget.reserve(1))
+                .await
+                .is_err()
+        );
+
+        budget.release(8);
+        budget.release(8);
+        timeout(Duration::from_secs(1), budget.reserve(1))
+            .await
+            .unwrap()
+            .unwrap();
+
+        budget.close();
+        budget.release(1);
+        assert!(matches!(
+            budget.reserve(1).await,
+            Err(AnyTlsError::SessionClosed)
+        ));
+    }
+}
@@ -1,8 +1,14 @@
+pub mod cover;
+pub mod flow_control;
 #[allow(clippy::module_inception)]
 pub mod session;
 pub mod stream;
 pub mod stream_reader;
+pub mod writer;
 
-pub use session::{Session, SessionHeartbeatConfig};
+pub use cover::CoverTrafficConfig;
+pub use flow_control::{FlowControlConfig, ReceiveOverflow};
+pub use session::{DownstreamPadding, Session, SessionHeartbeatConfig, StreamLimits};
 pub use stream::Stream;
 pub use stream_reader::StreamReader;
+pub use writer::WriterConfig;
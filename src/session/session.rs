@@ -1,15 +1,18 @@
 //! Session implementation for AnyTLS protocol
 
 use crate::padding::PaddingFactory;
-use crate::protocol::{Command, Frame, FrameCodec};
-use crate::session::Stream;
-use crate::util::{AnyTlsError, Result, StringMap};
+use crate::protocol::{Command, Frame, FrameCodec, HEADER_OVERHEAD_SIZE, MAX_FRAME_DATA_SIZE};
+use crate::session::cover::{CoverBudget, is_stream_traffic};
+use crate::session::flow_control::{ReceiveBudget, ReceiveOverflow, SendBudget, StreamWindow};
+use crate::session::writer::{FrameWriter, WaitingWriter, WriteAck, WriteError, WriteRequest};
+use crate::session::{CoverTrafficConfig, FlowControlConfig, Stream, WriterConfig};
+use crate::util::{AnyTlsError, GaugeGuard, METRICS, Result, StringMap, UserIdentity};
 use bytes::{Bytes, BytesMut};
 use md5;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::sync::{Notify, RwLock, mpsc};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::sync::{Notify, RwLock, mpsc, oneshot};
 use tokio::time::{self, Duration, Instant, MissedTickBehavior};
 use tracing::{field, info_span};
 
@@ -26,6 +29,33 @@ pub struct SessionHeartbeatConfig {
     pub timeout: Duration,
 }
 
+/// Limits on the streams a peer may open (server side)
+///
+/// Syns over a limit are refused with a SYNACK error (protocol v2) or a Fin;
+/// `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamLimits {
+    /// Open streams at a time
+    pub max_streams: Option<usize>,
+    /// New streams per second
+    pub max_syn_per_sec: Option<u32>,
+}
+
+/// Padding of the server-to-client direction
+///
+/// Only used with clients that advertise `downstream-padding=1` in their
+/// `Settings`; other clients get unpadded writes as before.
+#[derive(Clone, Default)]
+pub enum DownstreamPadding {
+    /// Write without padding
+    #[default]
+    Disabled,
+    /// Pad with the session's scheme, following its updates
+    SessionScheme,
+    /// Pad with a scheme of its own
+    Scheme(Arc<PaddingFactory>),
+}
+
 struct HeartbeatState {
     interval: Duration,
     timeout: Duration,
@@ -35,11 +65,20 @@ struct HeartbeatState {
 /// Session manages multiple streams over a single TLS connection
 type StreamDataReceiver = mpsc::UnboundedReceiver<(u32, Bytes)>;
 
+/// Sender half of a stream's receive channel plus its receive accounting
+type StreamSender = (mpsc::UnboundedSender<Bytes>, Arc<StreamWindow>);
+
 pub struct Session {
     id: u64,
-    // Connection reader and writer (split TLS stream)
+    // Connection reader (split TLS stream)
     reader: Arc<tokio::sync::Mutex<Box<dyn AsyncRead + Send + Unpin>>>,
-    writer: Arc<tokio::sync::Mutex<Box<dyn AsyncWrite + Send + Unpin>>>,
+
+    // Queue to the writer task, which owns the write half of the connection
+    write_tx: mpsc::UnboundedSender<WriteRequest>,
+    // Writer task state until the first write starts it
+    frame_writer: std::sync::Mutex<Option<FrameWriter>>,
+    writer_config: WriterConfig,
+    write_waiting: Arc<std::sync::atomic::AtomicUsize>,
 
     // Stream management - using Arc for sharing
     streams: Arc<RwLock<HashMap<u32, Arc<Stream>>>>,
@@ -50,7 +89,11 @@ pub struct Session {
     stream_data_rx: Arc<tokio::sync::Mutex<Option<StreamDataReceiver>>>,
 
     // Channel for sending data to streams (stream_id -> sender)
-    stream_receive_tx: Arc<RwLock<HashMap<u32, mpsc::UnboundedSender<Bytes>>>>,
+    stream_receive_tx: Arc<RwLock<HashMap<u32, StreamSender>>>,
+
+    // Byte budgets bounding receive and send buffering
+    receive_budget: Arc<ReceiveBudget>,
+    send_budget: Arc<SendBudget>,
 
     // Session state
     is_closed: Arc<std::sync::atomic::AtomicBool>,
@@ -60,8 +103,6 @@ pub struct Session {
 
     // Client/Server specific
     is_client: bool,
-    send_padding: bool,
-    pkt_counter: Arc<std::sync::atomic::AtomicU32>,
 
     // Peer version
     #[allow(dead_code)]
@@ -70,9 +111,8 @@ pub struct Session {
     // Session sequence number (for pool ordering)
     seq: Arc<std::sync::atomic::AtomicU64>,
 
-    // Buffering state
+    // Buffering state (frames stay in the writer until buffering is disabled)
     buffering: Arc<std::sync::atomic::AtomicBool>,
-    buffer: Arc<tokio::sync::Mutex<Vec<u8>>>,
 
     // Server callback for new streams (optional)
     on_new_stream: Option<NewStreamCallback>,
@@ -80,9 +120,40 @@ pub struct Session {
     // Optional server settings to send to client
     server_settings: Option<StringMap>,
 
+    // Padding of server writes, applied once the client supports it
+    downstream_padding: DownstreamPadding,
+    // Server writes are padded (server: writer switch, client: confirmed)
+    downstream_padded: Arc<std::sync::atomic::AtomicBool>,
+
+    // padding-md5 the client advertised or was last sent (server side)
+    peer_padding_md5: std::sync::Mutex<Option<String>>,
+
+    // Authenticated user (server side)
+    user: Option<UserIdentity>,
+
     // Heartbeat configuration (client side)
     heartbeat: Option<Arc<HeartbeatState>>,
     close_notify: Arc<Notify>,
+
+    // Limits on streams opened by the peer, and the current one-second
+    // window of Syns (start, count)
+    stream_limits: StreamLimits,
+    syn_window: std::sync::Mutex<(Instant, u32)>,
+
+    // Deadline for the first frame after the session starts (server side)
+    first_frame_timeout: Option<Duration>,
+
+    // Cover traffic for idle periods (optional)
+    cover_traffic: Option<CoverTrafficConfig>,
+    // Last stream traffic, in milliseconds since `created`
+    created: Instant,
+    last_activity_ms: std::sync::atomic::AtomicU64,
+    // Stream payload bytes received from and sent to the peer
+    bytes_in: std::sync::atomic::AtomicU64,
+    bytes_out: std::sync::atomic::AtomicU64,
+
+    // Keeps the active sessions gauge up to date
+    _active: GaugeGuard,
 }
 
 impl Session {
@@ -117,6 +188,15 @@ impl Session {
     {
         let (stream_data_tx, stream_data_rx) = mpsc::unbounded_channel();
         let id = SESSION_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let padding = Arc::new(RwLock::new(padding));
+        let (write_tx, write_rx) = mpsc::unbounded_channel();
+        let frame_writer = FrameWriter::new(
+            Box::new(writer),
+            write_rx,
+            Arc::clone(&padding),
+            Arc::new(std::sync::atomic::AtomicU32::new(0)),
+            Arc::new(std::sync::atomic::AtomicBool::new(true)),
+        );
         let heartbeat_state = heartbeat.map(|cfg| {
             Arc::new(HeartbeatState {
                 interval: cfg.interval,
@@ -128,25 +208,40 @@ impl Session {
         Self {
             id,
             reader: Arc::new(tokio::sync::Mutex::new(Box::new(reader))),
-            writer: Arc::new(tokio::sync::Mutex::new(Box::new(writer))),
+            write_tx,
+            write_waiting: frame_writer.waiting(),
+            frame_writer: std::sync::Mutex::new(Some(frame_writer)),
+            writer_config: WriterConfig::default(),
             streams: Arc::new(RwLock::new(HashMap::new())),
             stream_id: Arc::new(std::sync::atomic::AtomicU32::new(1)),
             stream_data_tx,
             stream_data_rx: Arc::new(tokio::sync::Mutex::new(Some(stream_data_rx))),
             stream_receive_tx: Arc::new(RwLock::new(HashMap::new())),
+            receive_budget: ReceiveBudget::new(&FlowControlConfig::default()),
+            send_budget: SendBudget::new(&FlowControlConfig::default()),
             is_closed: Arc::new(std::sync::atomic::AtomicBool::new(false)),
-            padding: Arc::new(RwLock::new(padding)),
+            padding,
             is_client: true,
-            send_padding: true,
-            pkt_counter: Arc::new(std::sync::atomic::AtomicU32::new(0)),
             peer_version: Arc::new(std::sync::atomic::AtomicU8::new(0)),
             seq: Arc::new(std::sync::atomic::AtomicU64::new(0)),
             buffering: Arc::new(std::sync::atomic::AtomicBool::new(false)),
-            buffer: Arc::new(tokio::sync::Mutex::new(Vec::new())),
             on_new_stream: None,
             server_settings: None,
+            downstream_padding: DownstreamPadding::Disabled,
+            downstream_padded: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            peer_padding_md5: std::sync::Mutex::new(None),
+            user: None,
             heartbeat: heartbeat_state,
             close_notify: Arc::new(Notify::new()),
+            stream_limits: StreamLimits::default(),
+            syn_window: std::sync::Mutex::new((Instant::now(), 0)),
+            first_frame_timeout: None,
+            cover_traffic: None,
+            created: Instant::now(),
+            last_activity_ms: std::sync::atomic::AtomicU64::new(0),
+            bytes_in: std::sync::atomic::AtomicU64::new(0),
+            bytes_out: std::sync::atomic::AtomicU64::new(0),
+            _active: GaugeGuard::new(&METRICS.sessions_active),
         }
     }
 
@@ -158,29 +253,54 @@ impl Session {
     {
         let (stream_data_tx, stream_data_rx) = mpsc::unbounded_channel();
         let id = SESSION_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let padding = Arc::new(RwLock::new(padding));
+        let (write_tx, write_rx) = mpsc::unbounded_channel();
+        let downstream_padded = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let frame_writer = FrameWriter::new(
+            Box::new(writer),
+            write_rx,
+            Arc::clone(&padding),
+            Arc::new(std::sync::atomic::AtomicU32::new(0)),
+            Arc::clone(&downstream_padded),
+        );
 
         Self {
             id,
             reader: Arc::new(tokio::sync::Mutex::new(Box::new(reader))),
-            writer: Arc::new(tokio::sync::Mutex::new(Box::new(writer))),
+            write_tx,
+            write_waiting: frame_writer.waiting(),
+            frame_writer: std::sync::Mutex::new(Some(frame_writer)),
+            writer_config: WriterConfig::default(),
             streams: Arc::new(RwLock::new(HashMap::new())),
             stream_id: Arc::new(std::sync::atomic::AtomicU32::new(1)),
             stream_data_tx,
             stream_data_rx: Arc::new(tokio::sync::Mutex::new(Some(stream_data_rx))),
             stream_receive_tx: Arc::new(RwLock::new(HashMap::new())),
+            receive_budget: ReceiveBudget::new(&FlowControlConfig::default()),
+            send_budget: SendBudget::new(&FlowControlConfig::default()),
             is_closed: Arc::new(std::sync::atomic::AtomicBool::new(false)),
-            padding: Arc::new(RwLock::new(padding)),
+            padding,
             is_client: false,
-            send_padding: false,
-            pkt_counter: Arc::new(std::sync::atomic::AtomicU32::new(0)),
             peer_version: Arc::new(std::sync::atomic::AtomicU8::new(0)),
             seq: Arc::new(std::sync::atomic::AtomicU64::new(0)),
             buffering: Arc::new(std::sync::atomic::AtomicBool::new(false)),
-            buffer: Arc::new(tokio::sync::Mutex::new(Vec::new())),
             on_new_stream: None,
             server_settings: None,
+            downstream_padding: DownstreamPadding::Disabled,
+            downstream_padded,
+            peer_padding_md5: std::sync::Mutex::new(None),
+            user: None,
             heartbeat: None,
             close_notify: Arc::new(Notify::new()),
+            stream_limits: StreamLimits::default(),
+            syn_window: std::sync::Mutex::new((Instant::now(), 0)),
+            first_frame_timeout: None,
+            cover_traffic: None,
+            created: Instant::now(),
+            last_activity_ms: std::sync::atomic::AtomicU64::new(0),
+            bytes_in: std::sync::atomic::AtomicU64::new(0),
+            bytes_out: std::sync::atomic::AtomicU64::new(0),
+            _active: GaugeGuard::new(&METRICS.sessions_active),
         }
     }
 
@@ -204,11 +324,226 @@ impl Session {
         self.server_settings = settings;
     }
 
+    /// Set the buffer limits of this session (before any stream is opened)
+    pub fn set_flow_control(&mut self, config: FlowControlConfig) {
+        self.receive_budget = ReceiveBudget::new(&config);
+        self.send_budget = SendBudget::new(&config);
+    }
+
+    /// Set the batching limits of the writer task (before the first write)
+    pub fn set_writer_config(&mut self, config: WriterConfig) {
+        self.writer_config = config;
+        if let Some(writer) = self.frame_writer.get_mut().unwrap().as_mut() {
+            writer.set_config(config);
+        }
+    }
+
+    /// Share the padding scheme with other sessions (before the first write)
+    ///
+    /// A scheme received in `UpdatePaddingScheme` replaces the shared value,
+    /// so sessions created later with the same handle start with it and
+    /// advertise its `padding-md5`.
+    pub fn set_padding_ref(&mut self, padding: Arc<RwLock<Arc<PaddingFactory>>>) {
+        if let Some(writer) = self.frame_writer.get_mut().unwrap().as_mut() {
+            writer.set_padding(Arc::clone(&padding));
+        }
+        self.padding = padding;
+    }
+
+    /// Pad server writes for clients that support it (server side, before
+    /// the first write)
+    pub fn set_downstream_padding(&mut self, padding: DownstreamPadding) {
+        if self.is_client {
+            return;
+        }
+        if let DownstreamPadding::Scheme(scheme) = &padding
+            && let Some(writer) = self.frame_writer.get_mut().unwrap().as_mut()
+        {
+            writer.set_padding(Arc::new(RwLock::new(Arc::clone(scheme))));
+        }
+        self.downstream_padding = padding;
+    }
+
+    /// Whether server writes are padded
+    ///
+    /// On the server this is true once the client advertised support; on
+    /// the client once the server confirmed it in `ServerSettings`.
+    pub fn is_downstream_padded(&self) -> bool {
+        self.downstream_padded
+            .load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    /// Limit the streams the client may open (server side)
+    pub fn set_stream_limits(&mut self, limits: StreamLimits) {
+        self.stream_limits = limits;
+    }
+
+    /// Close the session if no complete frame arrives within `timeout` of
+    /// `recv_loop` starting
+    pub fn set_first_frame_timeout(&mut self, timeout: Option<Duration>) {
+        self.first_frame_timeout = timeout;
+    }
+
+    /// Send cover traffic while the session is idle (before it is started)
+    pub fn set_cover_traffic(&mut self, config: Option<CoverTrafficConfig>) {
+        self.cover_traffic = config;
+    }
+
+    /// Time since the session was created
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    /// Stream payload bytes received from the peer
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Stream payload bytes sent to the peer
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Time since the last stream frame was sent or received
+    pub fn idle_for(&self) -> Duration {
+        let last = self
+            .last_activity_ms
+            .load(std::sync::atomic::Ordering::Relaxed);
+        self.created
+            .elapsed()
+            .saturating_sub(Duration::from_millis(last))
+    }
+
+    fn mark_activity(&self) {
+        let now = self.created.elapsed().as_millis() as u64;
+        self.last_activity_ms
+            .fetch_max(now, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Start the cover traffic task, if cover traffic is configured
+    ///
+    /// The task writes a Waste frame at each random interval once the
+    /// session has been idle long enough, skipping frames the byte rate cap
+    /// does not allow, and ends when the session closes.
+    pub fn start_cover_traffic(self: &Arc<Self>) {
+        let Some(config) = self.cover_traffic.clone() else {
+            return;
+        };
+        let session = Arc::downgrade(self);
+        let close_notify = Arc::clone(&self.close_notify);
+        let session_id = self.id();
+        tokio::spawn(async move {
+            let mut budget = CoverBudget::new(config.max_bytes_per_sec);
+            loop {
+                tokio::select! {
+                    _ = close_notify.notified() => break,
+                    _ = time::sleep(config.next_interval()) => {}
+                }
+                let Some(session) = session.upgrade() else {
+                    break;
+                };
+                if session.is_closed() {
+                    break;
+                }
+                if session.buffering.load(std::sync::atomic::Ordering::Relaxed)
+                    || session.idle_for() < config.idle_after
+                {
+                    continue;
+                }
+                let size = config.next_size();
+                if !budget.try_take(size) {
+                    continue;
+                }
+                let mut data = vec![0u8; size];
+                session.padding.read().await.fill_padding(&mut data);
+                let frame = Frame::with_data(Command::Waste, 0, Bytes::from(data));
+                if let Err(e) = session.write_frame(frame).await {
+                    tracing::debug!(
+                        session_id = session_id,
+                        "[Session] Cover traffic stopped: {}",
+                        e
+                    );
+                    break;
+                }
+                METRICS
+                    .cover_bytes
+                    .add((HEADER_OVERHEAD_SIZE + size) as u64);
+                tracing::trace!(
+                    session_id = session_id,
+                    "[Session] Sent {} bytes of cover traffic",
+                    size
+                );
+            }
+        });
+    }
+
+    /// MD5 of the padding scheme in use
+    pub async fn padding_md5(&self) -> String {
+        self.padding.read().await.md5().to_string()
+    }
+
+    /// Switch to a new padding scheme (server side)
+    ///
+    /// A client that advertised a different `padding-md5` is sent
+    /// `UpdatePaddingScheme` so it switches as well.
+    pub async fn update_padding(&self, padding: Arc<PaddingFactory>) -> Result<()> {
+        *self.padding.write().await = Arc::clone(&padding);
+        self.push_padding_if_stale(&padding).await
+    }
+
+    /// Send `padding` to the client unless it already uses it
+    async fn push_padding_if_stale(&self, padding: &PaddingFactory) -> Result<()> {
+        if self.is_client {
+            return Ok(());
+        }
+        {
+            let mut peer_md5 = self.peer_padding_md5.lock().unwrap();
+            match peer_md5.as_deref() {
+                Some(md5) if md5 != padding.md5() => {
+                    *peer_md5 = Some(padding.md5().to_string());
+                }
+                _ => return Ok(()),
+            }
+        }
+        tracing::debug!(
+            session_id = self.id(),
+            "[Session] Client padding-md5 mismatch, sending update {}",
+            padding.md5()
+        );
+        let update_frame = Frame::with_data(
+            Command::UpdatePaddingScheme,
+            0,
+            Bytes::copy_from_slice(padding.raw_scheme()),
+        );
+        self.write_frame(update_frame).await
+    }
+
+    /// Attach the authenticated user to this session (server side)
+    pub fn set_user(&mut self, user: UserIdentity) {
+        self.user = Some(user);
+    }
+
+    /// Authenticated user of this session (server side)
+    pub fn user(&self) -> Option<&UserIdentity> {
+        self.user.as_ref()
+    }
+
     /// Check if session is closed
     pub fn is_closed(&self) -> bool {
         self.is_closed.load(std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// Wait until the session is closed
+    pub async fn closed(&self) {
+        let notified = self.close_notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if self.is_closed() {
+            return;
+        }
+        notified.await;
+    }
+
     /// Close the session
     pub async fn close(&self) -> Result<()> {
         let already_closed = self
@@ -218,6 +553,7 @@ impl Session {
             return Ok(());
         }
         self.close_notify.notify_waiters();
+        self.send_budget.close();
 
         // Close stream data receiver so process_stream_data exits
         // Close all streams and notify pending waiters
@@ -233,8 +569,20 @@ impl Session {
 
         // Attempt to shutdown writer gracefully
         {
-            let mut writer = self.writer.lock().await;
-            match time::timeout(Duration::from_secs(1), writer.shutdown()).await {
+            let pending_writer = self.frame_writer.lock().unwrap().take();
+            let shutdown = async {
+                match pending_writer {
+                    Some(writer) => writer.shutdown().await,
+                    None => {
+                        let (done_tx, done_rx) = oneshot::channel();
+                        if self.write_tx.send(WriteRequest::Shutdown(done_tx)).is_err() {
+                            return Ok(());
+                        }
+                        done_rx.await.unwrap_or(Ok(()))
+                    }
+                }
+            };
+            match time::timeout(Duration::from_secs(1), shutdown).await {
                 Ok(Ok(())) => {}
                 Ok(Err(e)) => {
                     tracing::debug!(
@@ -276,6 +624,7 @@ impl Session {
         let mut buffer = BytesMut::with_capacity(8192);
         let mut iteration = 0u64;
         let mut total_bytes_in: usize = 0;
+        let mut first_frame_deadline = self.first_frame_timeout.map(|t| Instant::now() + t);
 
         loop {
             iteration += 1;
@@ -288,6 +637,21 @@ impl Session {
                 break;
             }
 
+            // Stop reading while streams that fell behind hold the shared receive budget
+            if !self.receive_budget.has_room() {
+                tracing::debug!(
+                    session_id = session_id,
+                    buffered = self.receive_budget.queued(),
+                    "[Session] recv_loop: Receive buffer full, pausing reads until streams catch up"
+                );
+                METRICS.receive_pauses.inc();
+                tokio::select! {
+                    _ = self.receive_budget.wait_for_room() => {}
+                    _ = self.closed() => {}
+                }
+                continue;
+            }
+
             // Read data from connection
             tracing::trace!(
                 session_id = session_id,
@@ -300,7 +664,25 @@ impl Session {
                 "[Session] recv_loop: Reader lock acquired, calling read_buf (iteration {})",
                 iteration
             );
-            let n = match reader.read_buf(&mut buffer).await {
+            let read = reader.read_buf(&mut buffer);
+            let read_result = match first_frame_deadline {
+                Some(deadline) => match time::timeout_at(deadline, read).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        drop(reader);
+                        tracing::warn!(
+                            session_id = session_id,
+                            "[Session] recv_loop: No frame received within {:?}, closing session",
+                            self.first_frame_timeout.unwrap_or_default()
+                        );
+                        METRICS.first_frame_timeouts.inc();
+                        let _ = self.close().await;
+                        return Err(AnyTlsError::Timeout("first frame".into()));
+                    }
+                },
+                None => read.await,
+            };
+            let n = match read_result {
                 Ok(n) => {
                     tracing::trace!(
                         session_id = session_id,
@@ -380,6 +762,9 @@ impl Session {
                 );
                 self.handle_frame(frame).await?;
             }
+            if frame_count > 0 {
+                first_frame_deadline = None;
+            }
             if frame_count == 0 && n > 0 {
                 tracing::debug!(
                     session_id = session_id,
@@ -426,10 +811,16 @@ impl Session {
             frame.stream_id,
             frame.data.len()
         );
+        if is_stream_traffic(frame.cmd) {
+            self.mark_activity();
+        }
         match frame.cmd {
             Command::Push => {
                 // Data frame - forward to stream
                 let data_len = frame.data.len();
+                METRICS.bytes_in.add(data_len as u64);
+                self.bytes_in
+                    .fetch_add(data_len as u64, std::sync::atomic::Ordering::Relaxed);
                 tracing::debug!(
                     session_id = session_id,
                     "[Session] handle_frame: Received PSH frame for stream {}, length={}",
@@ -444,30 +835,48 @@ impl Session {
                     frame.stream_id
                 );
 
-                if let Some(tx) = receive_map.get(&frame.stream_id) {
+                let mut overflowed = false;
+                if let Some((tx, window)) = receive_map.get(&frame.stream_id) {
                     tracing::trace!(
                         session_id = session_id,
                         "[Session] handle_frame: Found receiver for stream {}, sending {} bytes",
                         frame.stream_id,
                         data_len
                     );
-                    match tx.send(frame.data.clone()) {
-                        Ok(_) => {
-                            tracing::debug!(
-                                session_id = session_id,
-                                "[Session] handle_frame: Successfully sent {} bytes to stream {} via channel",
-                                data_len,
-                                frame.stream_id
-                            );
+                    // Never waits here: a slow reader draws on the shared budget,
+                    // and once that is used up recv_loop pauses or the stream is
+                    // reset. Only v2 peers can be told about a reset.
+                    let accepted = match self.receive_budget.policy() {
+                        ReceiveOverflow::Reset if self.peer_version() >= 2 => {
+                            window.try_queued(data_len)
                         }
-                        Err(e) => {
-                            tracing::error!(
-                                session_id = session_id,
-                                "[Session] handle_frame: Failed to send {} bytes to stream {} via channel: {}",
-                                data_len,
-                                frame.stream_id,
-                                e
-                            );
+                        _ => {
+                            window.queued(data_len);
+                            true
+                        }
+                    };
+                    if !accepted {
+                        overflowed = true;
+                    } else {
+                        match tx.send(frame.data.clone()) {
+                            Ok(_) => {
+                                tracing::debug!(
+                                    session_id = session_id,
+                                    "[Session] handle_frame: Successfully sent {} bytes to stream {} via channel",
+                                    data_len,
+                                    frame.stream_id
+                                );
+                            }
+                            Err(e) => {
+                                window.consumed(data_len);
+                                tracing::error!(
+                                    session_id = session_id,
+                                    "[Session] handle_frame: Failed to send {} bytes to stream {} via channel: {}",
+                                    data_len,
+                                    frame.stream_id,
+                                    e
+                                );
+                            }
                         }
                     }
                 } else {
@@ -483,6 +892,9 @@ impl Session {
                     session_id = session_id,
                     "[Session] handle_frame: Released stream_receive_tx read lock"
                 );
+                if overflowed {
+                    self.reset_overflowed_stream(frame.stream_id).await;
+                }
             }
             Command::Syn => {
                 // Stream open (server side)
@@ -494,21 +906,23 @@ impl Session {
                         stream_id
                     );
 
-                    let (receive_tx, receive_rx) = mpsc::unbounded_channel();
-
-                    // 创建 StreamReader
-                    let reader = crate::session::StreamReader::new(stream_id, receive_rx);
+                    if let Some(reason) = self.syn_limit_reached().await {
+                        tracing::warn!(
+                            session_id = session_id,
+                            "[Session] Refusing stream {}: {}",
+                            stream_id,
+                            reason
+                        );
+                        return self.refuse_stream(stream_id, &reason).await;
+                    }
 
                     // Server side: create stream without waiting for SYNACK
                     // The receiver is discarded since server doesn't need it
-                    let (stream, _synack_rx) =
-                        Stream::new(stream_id, reader, self.stream_data_tx.clone());
-
-                    let stream = Arc::new(stream);
+                    let (stream, _synack_rx, sender) = self.create_stream(stream_id);
 
                     {
                         let mut receive_map = self.stream_receive_tx.write().await;
-                        receive_map.insert(stream_id, receive_tx);
+                        receive_map.insert(stream_id, sender);
                     }
 
                     {
@@ -554,16 +968,33 @@ impl Session {
                 }
             }
             Command::SynAck => {
-                // Server acknowledges stream open (client side)
-                if self.is_client {
+                // Server acknowledges stream open (client side); an error
+                // SYNACK for an established stream resets it (either side)
+                let stream = self.streams.read().await.get(&frame.stream_id).cloned();
+                if let Some(stream) = &stream
+                    && !frame.data.is_empty()
+                    && !(self.is_client && stream.awaits_synack().await)
+                {
+                    let reason = String::from_utf8_lossy(&frame.data).to_string();
+                    tracing::warn!(
+                        session_id = session_id,
+                        "[Session] Stream {} reset by peer: {}",
+                        frame.stream_id,
+                        reason
+                    );
+                    self.reset_stream(
+                        frame.stream_id,
+                        format!("stream {} reset by peer: {}", frame.stream_id, reason),
+                    )
+                    .await;
+                } else if self.is_client {
                     tracing::debug!(
                         session_id = session_id,
                         "[Session] Received SYNACK for stream {}",
                         frame.stream_id
                     );
 
-                    let streams = self.streams.read().await;
-                    if let Some(stream) = streams.get(&frame.stream_id) {
+                    if let Some(stream) = &stream {
                         // If data is present, it's an error message
                         if !frame.data.is_empty() {
                             let error_msg = String::from_utf8_lossy(&frame.data).to_string();
@@ -602,39 +1033,44 @@ impl Session {
                 }
             }
             Command::Fin => {
-                // Stream close
+                // The peer finished writing: the reader sees EOF once buffered
+                // data is consumed, while this side may keep writing
                 tracing::debug!(
                     session_id = session_id,
-                    "[Session] FIN received for stream {}, closing",
+                    "[Session] FIN received for stream {}, closing read side",
                     frame.stream_id
                 );
-                let mut streams = self.streams.write().await;
-                streams.remove(&frame.stream_id);
-                let mut receive_map = self.stream_receive_tx.write().await;
-                receive_map.remove(&frame.stream_id);
+                self.stream_receive_tx
+                    .write()
+                    .await
+                    .remove(&frame.stream_id);
+                let stream = self.streams.read().await.get(&frame.stream_id).cloned();
+                if let Some(stream) = stream
+                    && stream.finish_read()
+                {
+                    self.remove_stream(frame.stream_id).await;
+                }
             }
             Command::Settings => {
                 // Client settings (server side)
                 if !self.is_client && !frame.data.is_empty() {
                     let settings = StringMap::from_bytes(&frame.data);
 
+                    // Pad our writes from here on if the client can take them
+                    let downstream_padded =
+                        settings.get("downstream-padding").is_some_and(|v| v == "1")
+                            && !matches!(self.downstream_padding, DownstreamPadding::Disabled);
+                    if downstream_padded {
+                        tracing::debug!(session_id = self.id(), "[Session] Padding server writes");
+                        self.downstream_padded
+                            .store(true, std::sync::atomic::Ordering::Release);
+                    }
+
                     // Check padding-md5
                     if let Some(client_md5) = settings.get("padding-md5") {
-                        let padding_guard = self.padding.read().await;
-                        let server_md5 = padding_guard.md5();
-                        if client_md5 != server_md5 {
-                            // Send UpdatePaddingScheme
-                            tracing::debug!(
-                                "[Session] Client padding-md5 mismatch, sending update"
-                            );
-                            let raw_scheme = padding_guard.raw_scheme();
-                            let update_frame = Frame::with_data(
-                                Command::UpdatePaddingScheme,
-                                0,
-                                Bytes::copy_from_slice(raw_scheme),
-                            );
-                            self.write_frame(update_frame).await?;
-                        }
+                        *self.peer_padding_md5.lock().unwrap() = Some(client_md5.to_string());
+                        let padding = self.padding.read().await.clone();
+                        self.push_padding_if_stale(&padding).await?;
                     }
 
                     // Check client version
@@ -648,6 +1084,9 @@ impl Session {
                         // Send ServerSettings
                         let mut server_settings = StringMap::new();
                         server_settings.insert("v", "2");
+                        if downstream_padded {
+                            server_settings.insert("downstream-padding", "1");
+                        }
                         if let Some(extra) = &self.server_settings {
                             for (k, v) in extra.clone().into_vec() {
                                 server_settings.insert(k, v);
@@ -673,19 +1112,23 @@ impl Session {
                             .store(v, std::sync::atomic::Ordering::Relaxed);
                         tracing::debug!("[Session] Server version: {}", v);
                     }
+                    if settings.get("downstream-padding").is_some_and(|v| v == "1") {
+                        tracing::debug!("[Session] Server pads its writes");
+                        self.downstream_padded
+                            .store(true, std::sync::atomic::Ordering::Release);
+                    }
                 }
             }
             Command::UpdatePaddingScheme => {
                 // Server updates padding scheme (client side)
                 if self.is_client && !frame.data.is_empty() {
                     let raw_scheme = frame.data.as_ref();
-                    match PaddingFactory::update_default(raw_scheme) {
-                        Ok(_) => {
-                            let md5_hash = md5::compute(raw_scheme);
-                            tracing::info!("[Session] Padding scheme updated: {:x}", md5_hash);
-                            // Update the session's padding factory
+                    match PaddingFactory::new(raw_scheme) {
+                        Ok(factory) => {
+                            tracing::info!("[Session] Padding scheme updated: {}", factory.md5());
+                            // Shared with the client's other sessions (see set_padding_ref)
                             let mut padding_guard = self.padding.write().await;
-                            *padding_guard = PaddingFactory::default();
+                            *padding_guard = Arc::new(factory);
                         }
                         Err(e) => {
                             let md5_hash = md5::compute(raw_scheme);
@@ -720,6 +1163,8 @@ impl Session {
                 // Mark session as closed
                 self.is_closed
                     .store(true, std::sync::atomic::Ordering::Relaxed);
+                self.close_notify.notify_waiters();
+                self.send_budget.close();
                 return Err(AnyTlsError::Protocol(format!("Alert: {}", alert_msg)));
             }
             Command::HeartRequest => {
@@ -754,18 +1199,91 @@ impl Session {
                     *last = Instant::now();
                 }
             }
-            _ => {
-                // Unhandled command - log and ignore
-                tracing::debug!(
-                    "[Session] Unhandled command: {:?} (stream_id={})",
-                    frame.cmd,
-                    frame.stream_id
-                );
+            Command::Waste => {
+                // Padding or cover traffic - dropped
             }
         }
         Ok(())
     }
 
+    /// Limit a new stream would exceed, if any (counts the Syn otherwise)
+    async fn syn_limit_reached(&self) -> Option<String> {
+        if let Some(max) = self.stream_limits.max_streams
+            && self.streams.read().await.len() >= max
+        {
+            return Some(format!("stream limit of {} reached", max));
+        }
+        if let Some(max) = self.stream_limits.max_syn_per_sec {
+            let mut window = self.syn_window.lock().unwrap();
+            let now = Instant::now();
+            if now.saturating_duration_since(window.0) >= Duration::from_secs(1) {
+                *window = (now, 0);
+            }
+            if window.1 >= max {
+                return Some(format!("limit of {} new streams per second reached", max));
+            }
+            window.1 += 1;
+        }
+        None
+    }
+
+    /// Reset a stream whose unread data no longer fits the receive budget
+    /// ([`ReceiveOverflow::Reset`])
+    ///
+    /// The peer is told with an error SYNACK rather than Fin, so it does not
+    /// take the stream for complete.
+    async fn reset_overflowed_stream(&self, stream_id: u32) {
+        tracing::warn!(
+            session_id = self.id(),
+            buffered = self.receive_budget.queued(),
+            "[Session] Resetting stream {}: receive buffer overflowed",
+            stream_id
+        );
+        METRICS.stream_overflows.inc();
+        let reason = format!("stream {} reset: receive buffer overflowed", stream_id);
+        let frame = Frame::with_data(Command::SynAck, stream_id, Bytes::from(reason.clone()));
+        if let Err(e) = self.write_control_frame(frame).await {
+            tracing::debug!(
+                "[Session] Failed to send reset of stream {}: {}",
+                stream_id,
+                e
+            );
+        }
+        self.reset_stream(stream_id, reason).await;
+    }
+
+    /// Tear down a stream without Fin in either direction
+    ///
+    /// The reader gets what was buffered, then an error instead of EOF;
+    /// writes fail. Later frames for the stream are dropped.
+    async fn reset_stream(&self, stream_id: u32, reason: String) {
+        if let Some((_, window)) = self.stream_receive_tx.write().await.remove(&stream_id) {
+            window.mark_reset();
+        }
+        let stream = self.streams.read().await.get(&stream_id).cloned();
+        if let Some(stream) = stream {
+            stream.abandon_write();
+            stream.close_with_error(AnyTlsError::Protocol(reason)).await;
+            stream.finish_read();
+            stream.finish_write();
+            self.remove_stream(stream_id).await;
+        }
+    }
+
+    /// Refuse a stream the peer opened, without creating it
+    async fn refuse_stream(&self, stream_id: u32, reason: &str) -> Result<()> {
+        let frame = if self.peer_version() >= 2 {
+            Frame::with_data(
+                Command::SynAck,
+                stream_id,
+                Bytes::copy_from_slice(reason.as_bytes()),
+            )
+        } else {
+            Frame::control(Command::Fin, stream_id)
+        };
+        self.write_control_frame(frame).await
+    }
+
     /// Create a new stream (client side)
     /// Returns the stream and SYNACK receiver for timeout detection
     pub async fn open_stream(
@@ -785,20 +1303,12 @@ impl Session {
             self.is_client
         );
 
-        // Create channels for this stream
-        let (receive_tx, receive_rx) = mpsc::unbounded_channel();
-
-        // 创建 StreamReader
-        let reader = crate::session::StreamReader::new(stream_id, receive_rx);
-
-        let (stream, synack_rx) = Stream::new(stream_id, reader, self.stream_data_tx.clone());
-
-        let stream = Arc::new(stream);
+        let (stream, synack_rx, sender) = self.create_stream(stream_id);
 
         // Store the receive_tx for sending data to this stream
         {
             let mut receive_map = self.stream_receive_tx.write().await;
-            receive_map.insert(stream_id, receive_tx);
+            receive_map.insert(stream_id, sender);
         }
 
         // Store the stream
@@ -818,13 +1328,38 @@ impl Session {
         Ok((stream, synack_rx))
     }
 
+    /// Create a stream wired to this session's channels and budgets
+    fn create_stream(
+        &self,
+        stream_id: u32,
+    ) -> (
+        Arc<Stream>,
+        tokio::sync::oneshot::Receiver<Result<()>>,
+        StreamSender,
+    ) {
+        let (receive_tx, receive_rx) = mpsc::unbounded_channel();
+        let window = self.receive_budget.stream_window();
+
+        // 创建 StreamReader
+        let reader = crate::session::StreamReader::new(stream_id, receive_rx)
+            .with_window(Arc::clone(&window));
+
+        let (stream, synack_rx) = Stream::new(stream_id, reader, self.stream_data_tx.clone());
+        let stream = stream.with_send_budget(Arc::clone(&self.send_budget));
+
+        (Arc::new(stream), synack_rx, (receive_tx, window))
+    }
+
     /// Disable buffering (this will flush buffer on next write)
     pub fn disable_buffering(&self) {
         self.buffering
             .store(false, std::sync::atomic::Ordering::Relaxed);
     }
 
-    /// Write a data frame to connection
+    /// Write data of a stream to connection
+    ///
+    /// Data larger than [`WriterConfig::max_frame_payload`] is split into
+    /// several Push frames.
     pub async fn write_data_frame(&self, stream_id: u32, data: Bytes) -> Result<()> {
         tracing::trace!(
             session_id = self.id(),
@@ -834,8 +1369,10 @@ impl Session {
             stream_id,
             data.len()
         );
-        let frame = Frame::data(stream_id, data);
-        self.write_frame(frame).await
+        let _waiting = WaitingWriter::new(&self.write_waiting);
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.queue_data(stream_id, data, Some(ack_tx))?;
+        self.wait_written(ack_rx).await
     }
 
     /// Write a control frame to connection
@@ -844,240 +1381,125 @@ impl Session {
     }
 
     /// Write a frame to the connection
+    ///
+    /// The frame is queued for the writer task, which may coalesce it with
+    /// other queued frames; this returns once the batch has been written (or
+    /// the frame buffered, while buffering is enabled). Frames carrying more
+    /// than [`MAX_FRAME_DATA_SIZE`] bytes fail with
+    /// [`AnyTlsError::InvalidFrame`]; use [`write_data_frame`](Self::write_data_frame)
+    /// for stream data of any size.
     pub async fn write_frame(&self, frame: Frame) -> Result<()> {
-        use tokio_util::codec::Encoder;
-        let frame_cmd = frame.cmd;
-        let frame_stream_id = frame.stream_id;
-        let mut codec = FrameCodec;
-        let mut buffer = BytesMut::new();
-        codec.encode(frame, &mut buffer)?;
-        tracing::trace!(
-            session_id = self.id(),
-            "[Session] write_frame: encoded frame cmd={:?}, stream_id={}, buffer_len={}",
-            frame_cmd,
-            frame_stream_id,
-            buffer.len()
-        );
+        let _waiting = WaitingWriter::new(&self.write_waiting);
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.queue_frame(frame, Some(ack_tx))?;
+        self.wait_written(ack_rx).await
+    }
 
-        // Check if buffering
-        if self.buffering.load(std::sync::atomic::Ordering::Relaxed) {
-            tracing::trace!(
-                "[Session] write_frame: Buffering frame cmd={:?}, stream_id={}",
-                frame_cmd,
-                frame_stream_id
-            );
-            let mut buf = self.buffer.lock().await;
-            let old_len = buf.len();
-            buf.extend_from_slice(&buffer);
-            tracing::debug!(
-                "[Session] write_frame: Buffered frame (buffer size: {} -> {})",
-                old_len,
-                buf.len()
-            );
-            return Ok(());
+    /// Queue data taken from the stream channel; empty data is a stream's
+    /// write shutdown and becomes a Fin frame
+    fn queue_stream_data(
+        &self,
+        stream_id: u32,
+        data: Bytes,
+        ack: Option<WriteAck>,
+        fins: &mut Vec<u32>,
+    ) -> Result<()> {
+        if data.is_empty() {
+            fins.push(stream_id);
+            return self.queue_frame(Frame::control(Command::Fin, stream_id), ack);
         }
+        self.queue_data(stream_id, data, ack)
+    }
 
-        // Flush buffer if any
+    /// Record that Fin was sent for a stream, forgetting it if its read side
+    /// has finished too
+    async fn finish_stream_write(&self, stream_id: u32) {
+        let stream = self.streams.read().await.get(&stream_id).cloned();
+        if let Some(stream) = stream
+            && stream.finish_write()
         {
-            let mut buf = self.buffer.lock().await;
-            if !buf.is_empty() {
-                let buffered_len = buf.len();
-                tracing::debug!(
-                    "[Session] write_frame: Flushing {} buffered bytes along with new frame ({} bytes)",
-                    buffered_len,
-                    buffer.len()
-                );
-
-                // Log first frame's header for debugging
-                if buffered_len >= 7 {
-                    tracing::debug!(
-                        "[Session] First buffered frame header: cmd={}, stream_id={:?}, data_len={:?}",
-                        buf[0],
-                        u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]),
-                        u16::from_be_bytes([buf[5], buf[6]])
-                    );
-                }
-
-                let mut combined = BytesMut::from(&buf[..]);
-                combined.extend_from_slice(&buffer);
-                buffer = combined;
-                buf.clear();
-            }
+            self.remove_stream(stream_id).await;
         }
+    }
 
-        // Log what we're about to send
-        if buffer.len() >= 7 {
-            tracing::info!(
-                "[Session] About to send frame header: cmd={}, stream_id={:?}, data_len={:?}, total_buffer_len={}",
-                buffer[0],
-                u32::from_be_bytes([buffer[1], buffer[2], buffer[3], buffer[4]]),
-                u16::from_be_bytes([buffer[5], buffer[6]]),
-                buffer.len()
-            );
+    /// Forget a stream nobody will use again, such as one whose handler
+    /// failed or whose SYNACK carried an error
+    ///
+    /// Sends Fin if it has not been sent and frees the stream's slot right
+    /// away instead of waiting for the peer's Fin; later frames for it are
+    /// dropped.
+    pub async fn release_stream(&self, stream_id: u32) {
+        let stream = self.streams.read().await.get(&stream_id).cloned();
+        if let Some(stream) = stream {
+            let _ = stream.shutdown_write();
+            stream.finish_read();
+            stream.finish_write();
+            self.remove_stream(stream_id).await;
         }
-
-        // Write with padding if enabled
-        self.write_with_padding(buffer).await
     }
 
-    /// Write buffer to connection with padding applied
-    async fn write_with_padding(&self, mut buffer: BytesMut) -> Result<()> {
-        use crate::padding::CHECK_MARK;
-        use crate::protocol::{Command, HEADER_OVERHEAD_SIZE};
-        use bytes::BufMut;
-
-        if !self.send_padding {
-            // No padding, write directly
-            tracing::trace!(
-                "[Session] write_with_padding: Writing {} bytes without padding",
-                buffer.len()
-            );
-            let mut writer = self.writer.lock().await;
-            if let Err(e) = writer.write_all(&buffer).await {
-                return Err(self.handle_io_error("write_without_padding", e).await);
-            }
-            if let Err(e) = writer.flush().await {
-                return Err(self.handle_io_error("flush_without_padding", e).await);
-            }
-            tracing::info!(
-                "[Session] write_with_padding: Successfully wrote {} bytes to connection",
-                buffer.len()
-            );
-            return Ok(());
-        }
+    async fn remove_stream(&self, stream_id: u32) {
+        tracing::debug!(
+            session_id = self.id(),
+            "[Session] Both directions of stream {} finished, removing",
+            stream_id
+        );
+        self.streams.write().await.remove(&stream_id);
+        self.stream_receive_tx.write().await.remove(&stream_id);
+    }
 
-        // Increment packet counter
-        let pkt = self
-            .pkt_counter
-            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        let padding_factory = {
-            let padding_guard = self.padding.read().await;
-            padding_guard.clone()
-        };
-        let stop = padding_factory.stop();
-
-        if pkt >= stop {
-            // Stop padding after stop packets
-            // Note: We should probably disable send_padding, but that requires mutable access
-            // For now, just write directly
-            let mut writer = self.writer.lock().await;
-            if let Err(e) = writer.write_all(&buffer).await {
-                return Err(self.handle_io_error("write_no_padding_stop", e).await);
-            }
-            if let Err(e) = writer.flush().await {
-                return Err(self.handle_io_error("flush_no_padding_stop", e).await);
-            }
-            return Ok(());
+    /// Queue stream data as Push frames of at most `max_frame_payload` bytes;
+    /// `ack` goes with the last frame
+    fn queue_data(&self, stream_id: u32, mut data: Bytes, ack: Option<WriteAck>) -> Result<()> {
+        let max_payload = self.writer_config.frame_payload();
+        while data.len() > max_payload {
+            let chunk = data.split_to(max_payload);
+            self.queue_frame(Frame::data(stream_id, chunk), None)?;
         }
+        self.queue_frame(Frame::data(stream_id, data), ack)
+    }
 
-        // Get padding sizes for this packet
-        let pkt_sizes = padding_factory.generate_record_payload_sizes(pkt);
-
-        // If no sizes defined, write directly
-        if pkt_sizes.is_empty() {
-            let mut writer = self.writer.lock().await;
-            if let Err(e) = writer.write_all(&buffer).await {
-                return Err(self.handle_io_error("write_no_padding_sizes", e).await);
-            }
-            if let Err(e) = writer.flush().await {
-                return Err(self.handle_io_error("flush_no_padding_sizes", e).await);
-            }
-            return Ok(());
+    /// Queue a frame for the writer task without waiting for it
+    fn queue_frame(&self, frame: Frame, ack: Option<WriteAck>) -> Result<()> {
+        if frame.data.len() > MAX_FRAME_DATA_SIZE {
+            return Err(AnyTlsError::InvalidFrame(format!(
+                "{:?} frame for stream {} carries {} bytes (max {})",
+                frame.cmd,
+                frame.stream_id,
+                frame.data.len(),
+                MAX_FRAME_DATA_SIZE
+            )));
         }
-
-        let mut writer = self.writer.lock().await;
-
-        for size in pkt_sizes {
-            let remain_payload_len = buffer.len();
-
-            if size == CHECK_MARK {
-                // Check mark: if no remaining payload, return early
-                if remain_payload_len == 0 {
-                    break;
-                }
-                // Otherwise continue to next size
-                continue;
-            }
-
-            let size = size as usize;
-
-            tracing::trace!(
-                "[Session] write_with_padding: Processing size={}, remain_payload_len={}",
-                size,
-                remain_payload_len
-            );
-
-            if remain_payload_len > size {
-                // This packet is all payload - send exactly size bytes
-                // Note: This may split a frame in the middle, but that's okay for TLS records
-                // The receiver will reassemble frames from the stream
-                tracing::debug!(
-                    "[Session] write_with_padding: Splitting payload: sending {} bytes (remain={})",
-                    size,
-                    remain_payload_len
-                );
-                if size >= 7 {
-                    tracing::debug!(
-                        "[Session] write_with_padding: First 7 bytes being sent: {:?}",
-                        &buffer[..7]
-                    );
-                }
-                if let Err(e) = writer.write_all(&buffer[..size]).await {
-                    return Err(self.handle_io_error("write_padding_split_payload", e).await);
-                }
-                buffer = buffer.split_off(size);
-            } else if remain_payload_len > 0 {
-                // This packet contains payload + padding
-                let padding_len = size.saturating_sub(remain_payload_len + HEADER_OVERHEAD_SIZE);
-
-                if padding_len > 0 {
-                    // Create padding frame (cmdWaste)
-                    let mut padding_frame =
-                        BytesMut::with_capacity(HEADER_OVERHEAD_SIZE + padding_len);
-                    padding_frame.put_u8(Command::Waste as u8);
-                    padding_frame.put_u32(0); // stream_id = 0
-                    padding_frame.put_u16(padding_len as u16);
-                    padding_frame.put_slice(&vec![0u8; padding_len]); // padding data (zeros)
-
-                    // Combine payload and padding
-                    buffer.put_slice(&padding_frame);
-                }
-
-                if let Err(e) = writer.write_all(&buffer).await {
-                    return Err(self.handle_io_error("write_padding_payload_frame", e).await);
-                }
-                buffer.clear();
-            } else {
-                // This packet is all padding
-                let mut padding_frame = BytesMut::with_capacity(HEADER_OVERHEAD_SIZE + size);
-                padding_frame.put_u8(Command::Waste as u8);
-                padding_frame.put_u32(0); // stream_id = 0
-                padding_frame.put_u16(size as u16);
-                padding_frame.put_slice(&vec![0u8; size]); // padding data (zeros)
-
-                if let Err(e) = writer.write_all(&padding_frame).await {
-                    return Err(self.handle_io_error("write_padding_frame_only", e).await);
-                }
-            }
+        if let Some(writer) = self.frame_writer.lock().unwrap().take() {
+            tokio::spawn(writer.run());
         }
-
-        // Write any remaining payload
-        if !buffer.is_empty() {
-            tracing::trace!(
-                "[Session] write_with_padding: Writing {} remaining payload bytes",
-                buffer.len()
-            );
-            if let Err(e) = writer.write_all(&buffer).await {
-                return Err(self.handle_io_error("write_remaining_payload", e).await);
-            }
+        if is_stream_traffic(frame.cmd) {
+            self.mark_activity();
         }
+        let hold = self.buffering.load(std::sync::atomic::Ordering::Relaxed);
+        tracing::trace!(
+            session_id = self.id(),
+            "[Session] queue_frame: cmd={:?}, stream_id={}, data_len={}, hold={}",
+            frame.cmd,
+            frame.stream_id,
+            frame.data.len(),
+            hold
+        );
+        self.write_tx
+            .send(WriteRequest::Frame { frame, hold, ack })
+            .map_err(|_| AnyTlsError::SessionClosed)
+    }
 
-        tracing::trace!("[Session] write_with_padding: Flushing writer");
-        if let Err(e) = writer.flush().await {
-            return Err(self.handle_io_error("flush_with_padding", e).await);
+    /// Wait for the writer task to report on a queued frame
+    async fn wait_written(
+        &self,
+        ack: oneshot::Receiver<std::result::Result<(), WriteError>>,
+    ) -> Result<()> {
+        match ack.await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err((context, e))) => Err(self.handle_io_error(context, e).await),
+            Err(_) => Err(AnyTlsError::SessionClosed),
         }
-        tracing::debug!("[Session] write_with_padding: Successfully wrote and flushed data");
-        Ok(())
     }
 
     /// Start the client session (send settings and start recv loop)
@@ -1093,6 +1515,8 @@ impl Session {
             padding_guard.md5().to_string()
         };
         settings.insert("padding-md5", padding_md5);
+        // Waste frames from the server are skipped, so padding is welcome
+        settings.insert("downstream-padding", "1");
 
         let frame = Frame::with_data(Command::Settings, 0, Bytes::from(settings.to_bytes()));
 
@@ -1214,6 +1638,8 @@ impl Session {
             });
         }
 
+        self.start_cover_traffic();
+
         Ok(())
     }
 
@@ -1280,7 +1706,7 @@ impl Session {
                         );
                         break;
                     }
-                    let data_len = data.len();
+                    let mut data_len = data.len();
                     tracing::debug!(
                         session_id = session_id,
                         "[Session] process_stream_data: Received {} bytes from stream {} (iteration {})",
@@ -1288,11 +1714,45 @@ impl Session {
                         stream_id,
                         iteration
                     );
-                    // Send data frame
-                    let write_result = self.write_data_frame(stream_id, data).await;
+                    // Queue everything already waiting so the writer can coalesce it,
+                    // then wait once for the whole batch
+                    let _waiting = WaitingWriter::new(&self.write_waiting);
+                    let (ack_tx, ack_rx) = oneshot::channel();
+                    let mut next = Some((stream_id, data));
+                    let mut queue_result = Ok(());
+                    let mut fins = Vec::new();
+                    while let Some((stream_id, data)) = next.take() {
+                        if data_len < self.writer_config.max_batch_bytes
+                            && let Ok(item) = receiver.try_recv()
+                        {
+                            data_len += item.1.len();
+                            next = Some(item);
+                            queue_result = self.queue_stream_data(stream_id, data, None, &mut fins);
+                        } else {
+                            queue_result =
+                                self.queue_stream_data(stream_id, data, Some(ack_tx), &mut fins);
+                            break;
+                        }
+                        if queue_result.is_err() {
+                            break;
+                        }
+                    }
+                    let write_result = match queue_result {
+                        Ok(()) => self.wait_written(ack_rx).await,
+                        Err(e) => Err(e),
+                    };
+                    self.send_budget.release(data_len);
+                    if write_result.is_ok() {
+                        for stream_id in fins {
+                            self.finish_stream_write(stream_id).await;
+                        }
+                    }
                     match write_result {
                         Ok(_) => {
                             total_bytes_out += data_len;
+                            METRICS.bytes_out.add(data_len as u64);
+                            self.bytes_out
+                                .fetch_add(data_len as u64, std::sync::atomic::Ordering::Relaxed);
                             tracing::debug!(
                                 session_id = session_id,
                                 "[Session] process_stream_data: Successfully wrote data frame for stream {} (iteration {})",
@@ -1353,6 +1813,26 @@ impl Session {
     pub fn peer_version(&self) -> u8 {
         self.peer_version.load(std::sync::atomic::Ordering::Relaxed)
     }
+
+    /// Number of streams currently open in this session
+    pub async fn stream_count(&self) -> usize {
+        self.streams.read().await.len()
+    }
+
+    /// Streams currently open in this session
+    pub async fn streams(&self) -> Vec<Arc<Stream>> {
+        self.streams.read().await.values().cloned().collect()
+    }
+
+    /// Bytes received but not yet read, over all streams
+    pub fn receive_buffered(&self) -> usize {
+        self.receive_budget.queued()
+    }
+
+    /// Bytes written by streams but not yet sent on the connection
+    pub fn send_buffered(&self) -> usize {
+        self.send_budget.queued()
+    }
 }
 
 #[cfg(test)]
@@ -1563,4 +2043,605 @@ mod tests {
 
         tracing::debug!("Bidirectional heartbeat test passed");
     }
+
+    /// Client/server pair whose client uses `flow_control`, with the server
+    /// handing out the streams the client opens
+    async fn flow_control_pair(
+        flow_control: FlowControlConfig,
+    ) -> (Arc<Session>, mpsc::UnboundedReceiver<Arc<Stream>>) {
+        let (client_stream, server_stream) = create_connected_streams();
+        let (client_read, client_write) = tokio::io::split(client_stream);
+        let (server_read, server_write) = tokio::io::split(server_stream);
+        let padding = create_test_padding();
+
+        let mut client = Session::new_client(client_read, client_write, padding.clone(), None);
+        client.set_flow_control(flow_control);
+        // The server supports error SYNACKs
+        client
+            .peer_version
+            .store(2, std::sync::atomic::Ordering::Relaxed);
+        let client = Arc::new(client);
+        let mut server = Session::new_server(server_read, server_write, padding);
+        let (stream_tx, stream_rx) = mpsc::unbounded_channel();
+        server.set_stream_callback(stream_tx);
+        let server = Arc::new(server);
+        for session in [&client, &server] {
+            let recv = Arc::clone(session);
+            tokio::spawn(async move { recv.recv_loop().await });
+            let send = Arc::clone(session);
+            tokio::spawn(async move { send.process_stream_data().await });
+        }
+        (client, stream_rx)
+    }
+
+    async fn read_some(stream: Arc<Stream>, len: usize) -> bool {
+        let mut buf = vec![0u8; len];
+        let mut reader = stream.reader().lock().await;
+        time::timeout(Duration::from_secs(1), reader.read_exact(&mut buf))
+            .await
+            .is_ok()
+    }
+
+    const SMALL_RECEIVE_BUFFERS: FlowControlConfig = FlowControlConfig {
+        stream_receive_buffer: 1024,
+        session_receive_buffer: 4096,
+        session_send_buffer: 64 * 1024,
+        overflow: ReceiveOverflow::Pause,
+    };
+
+    #[tokio::test]
+    async fn test_slow_stream_pauses_session() {
+        let (client, mut stream_rx) = flow_control_pair(SMALL_RECEIVE_BUFFERS).await;
+        let (slow, _) = client.open_stream().await.unwrap();
+        let (fast, _) = client.open_stream().await.unwrap();
+        let slow_origin = stream_rx.recv().await.unwrap();
+        let fast_origin = stream_rx.recv().await.unwrap();
+
+        // The unread stream only draws on the shared budget; others keep flowing
+        slow_origin
+            .write_data(Bytes::from(vec![1u8; 2048]))
+            .await
+            .unwrap();
+        fast_origin
+            .write_data(Bytes::from(vec![2u8; 512]))
+            .await
+            .unwrap();
+        assert!(read_some(Arc::clone(&fast), 512).await);
+        assert_eq!(client.receive_buffered(), 2048);
+
+        // Once the shared budget is used up the connection is no longer read
+        slow_origin
+            .write_data(Bytes::from(vec![1u8; 8192]))
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        fast_origin
+            .write_data(Bytes::from(vec![2u8; 512]))
+            .await
+            .unwrap();
+        assert!(!read_some(Arc::clone(&fast), 512).await);
+        assert!(client.receive_buffered() <= 1024 + 4096 + 8192);
+
+        // Draining the slow stream resumes delivery, with nothing lost
+        assert!(read_some(slow, 2048 + 8192).await);
+        assert!(read_some(fast, 512).await);
+        assert_eq!(client.receive_buffered(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_slow_stream_reset_when_configured() {
+        let (client, mut stream_rx) = flow_control_pair(FlowControlConfig {
+            overflow: ReceiveOverflow::Reset,
+            ..SMALL_RECEIVE_BUFFERS
+        })
+        .await;
+        let (slow, _) = client.open_stream().await.unwrap();
+        let (fast, _) = client.open_stream().await.unwrap();
+        let slow_origin = stream_rx.recv().await.unwrap();
+        let fast_origin = stream_rx.recv().await.unwrap();
+
+        slow_origin
+            .write_data(Bytes::from(vec![1u8; 2048]))
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(client.receive_buffered(), 2048);
+
+        // Data the shared budget cannot take resets the slow stream; the
+        // connection is still read and the fast stream keeps flowing
+        slow_origin
+            .write_data(Bytes::from(vec![1u8; 8192]))
+            .await
+            .unwrap();
+        for _ in 0..4 {
+            fast_origin
+                .write_data(Bytes::from(vec![2u8; 512]))
+                .await
+                .unwrap();
+            assert!(read_some(Arc::clone(&fast), 512).await);
+        }
+        assert_eq!(client.receive_buffered(), 2048);
+
+        // The slow reader gets what was buffered, then an error instead of EOF
+        let mut reader = slow.reader().lock().await;
+        let mut buf = vec![0u8; 2048];
+        reader.read_exact(&mut buf).await.unwrap();
+        let err = reader.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+        drop(reader);
+        assert!(slow.is_closed());
+        assert!(slow.write_data(Bytes::from_static(b"x")).await.is_err());
+        assert_eq!(client.receive_buffered(), 0);
+
+        // The peer is told with an error, not a plain Fin
+        let mut origin_reader = slow_origin.reader().lock().await;
+        let read = time::timeout(Duration::from_secs(1), origin_reader.read(&mut buf)).await;
+        let err = read.unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+        drop(origin_reader);
+        assert!(slow_origin.is_closed());
+        assert_eq!(client.stream_count().await, 1);
+        assert!(!client.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_large_transfer_to_slow_reader_is_complete() {
+        const TOTAL: usize = 10 * 1024 * 1024 + 123;
+        let (client, mut stream_rx) = flow_control_pair(FlowControlConfig {
+            stream_receive_buffer: 64 * 1024,
+            session_receive_buffer: 256 * 1024,
+            ..FlowControlConfig::default()
+        })
+        .await;
+        let pauses_before = METRICS.receive_pauses.get();
+        let (stream, _) = client.open_stream().await.unwrap();
+        let origin = stream_rx.recv().await.unwrap();
+
+        let writer = tokio::spawn(async move {
+            let data: Vec<u8> = (0..TOTAL).map(|i| (i % 251) as u8).collect();
+            for chunk in data.chunks(32 * 1024) {
+                origin
+                    .write_data(Bytes::copy_from_slice(chunk))
+                    .await
+                    .unwrap();
+            }
+            origin.shutdown_write().unwrap();
+        });
+
+        // Much slower than the writer: a pause after every 64 KiB
+        let mut reader = stream.reader().lock().await;
+        let mut buf = vec![0u8; 8192];
+        let mut received = 0;
+        loop {
+            let n = time::timeout(Duration::from_secs(5), reader.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            if n == 0 {
+                break;
+            }
+            for (offset, byte) in buf[..n].iter().enumerate() {
+                assert_eq!(*byte, ((received + offset) % 251) as u8);
+            }
+            received += n;
+            if received % (64 * 1024) < n {
+                time::sleep(Duration::from_millis(2)).await;
+            }
+        }
+        assert_eq!(received, TOTAL);
+        writer.await.unwrap();
+        assert!(METRICS.receive_pauses.get() > pauses_before);
+    }
+
+    #[tokio::test]
+    async fn test_large_writes_are_split_into_frames() {
+        let (near, mut far) = create_connected_streams();
+        let (near_read, near_write) = tokio::io::split(near);
+        let mut session = Session::new_server(near_read, near_write, create_test_padding());
+        session.set_writer_config(WriterConfig {
+            max_frame_payload: 1000,
+            ..WriterConfig::default()
+        });
+        let session = Arc::new(session);
+
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        session
+            .write_data_frame(7, Bytes::from(data.clone()))
+            .await
+            .unwrap();
+
+        let mut wire = BytesMut::new();
+        let mut frames = Vec::new();
+        while frames.len() < 3 {
+            if let Some(frame) = FrameCodec.decode(&mut wire).unwrap() {
+                frames.push(frame);
+                continue;
+            }
+            let mut buf = [0u8; 4096];
+            let n = far.read(&mut buf).await.unwrap();
+            wire.extend_from_slice(&buf[..n]);
+        }
+        let sizes: Vec<usize> = frames.iter().map(|f| f.data.len()).collect();
+        assert_eq!(sizes, [1000, 1000, 500]);
+        assert!(
+            frames
+                .iter()
+                .all(|f| f.cmd == Command::Push && f.stream_id == 7)
+        );
+        let joined: Vec<u8> = frames.iter().flat_map(|f| f.data.to_vec()).collect();
+        assert_eq!(joined, data);
+
+        // A single frame over the wire limit is refused instead of truncated
+        let oversized = Frame::data(7, Bytes::from(vec![0u8; MAX_FRAME_DATA_SIZE + 1]));
+        assert!(matches!(
+            session.write_frame(oversized).await,
+            Err(AnyTlsError::InvalidFrame(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_half_close_keeps_other_direction_open() {
+        let (client_stream, server_stream) = create_connected_streams();
+        let (client_read, client_write) = tokio::io::split(client_stream);
+        let (server_read, server_write) = tokio::io::split(server_stream);
+        let padding = create_test_padding();
+
+        let client = Arc::new(Session::new_client(
+            client_read,
+            client_write,
+            padding.clone(),
+            None,
+        ));
+        let mut server = Session::new_server(server_read, server_write, padding);
+        let (stream_tx, mut stream_rx) = mpsc::unbounded_channel();
+        server.set_stream_callback(stream_tx);
+        let server = Arc::new(server);
+        for session in [&client, &server] {
+            let recv = Arc::clone(session);
+            tokio::spawn(async move { recv.recv_loop().await });
+            let send = Arc::clone(session);
+            tokio::spawn(async move { send.process_stream_data().await });
+        }
+
+        let read_to_end = |stream: Arc<Stream>| async move {
+            let mut reader = stream.reader().lock().await;
+            let mut received = Vec::new();
+            let mut buf = [0u8; 64];
+            loop {
+                let n = time::timeout(Duration::from_secs(1), reader.read(&mut buf))
+                    .await
+                    .expect("EOF expected")
+                    .unwrap();
+                if n == 0 {
+                    return received;
+                }
+                received.extend_from_slice(&buf[..n]);
+            }
+        };
+
+        let (local, _) = client.open_stream().await.unwrap();
+        local.write_data(Bytes::from("request")).await.unwrap();
+        local.shutdown_write().unwrap();
+        assert!(local.write_data(Bytes::from("late")).await.is_err());
+
+        // The server reads the request up to EOF and can still answer
+        let remote = stream_rx.recv().await.unwrap();
+        assert_eq!(read_to_end(Arc::clone(&remote)).await, b"request");
+        assert!(!remote.is_closed());
+        remote.write_data(Bytes::from("response")).await.unwrap();
+        assert_eq!(server.stream_count().await, 1);
+
+        remote.shutdown_write().unwrap();
+        assert_eq!(read_to_end(Arc::clone(&local)).await, b"response");
+
+        // Both directions finished: the streams are gone on both ends
+        for session in [&client, &server] {
+            let deadline = Instant::now() + Duration::from_secs(1);
+            while session.stream_count().await > 0 && Instant::now() < deadline {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(session.stream_count().await, 0);
+        }
+        assert!(local.is_closed() && remote.is_closed());
+    }
+
+    /// Server session padding its writes as configured, and the commands of
+    /// the frames it wrote, decoded from the bytes the client received
+    async fn downstream_padding_exchange(
+        padding: DownstreamPadding,
+        advertise: bool,
+    ) -> (Arc<Session>, Arc<Session>, Vec<Command>) {
+        use tokio::io::AsyncWriteExt;
+        use tokio_util::codec::Decoder;
+
+        // client <-> tap <-> server, recording what the server writes
+        let (client_stream, tap_client) = duplex(65536);
+        let (tap_server, server_stream) = duplex(65536);
+        let (mut from_client, mut to_client) = tokio::io::split(tap_client);
+        let (mut from_server, mut to_server) = tokio::io::split(tap_server);
+        tokio::spawn(async move { tokio::io::copy(&mut from_client, &mut to_server).await });
+        let wire = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&wire);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = from_server.read(&mut buf).await {
+                if n == 0 || to_client.write_all(&buf[..n]).await.is_err() {
+                    break;
+                }
+                recorded.lock().unwrap().extend_from_slice(&buf[..n]);
+            }
+        });
+
+        let (client_read, client_write) = tokio::io::split(client_stream);
+        let (server_read, server_write) = tokio::io::split(server_stream);
+        let client = Arc::new(Session::new_client(
+            client_read,
+            client_write,
+            create_test_padding(),
+            None,
+        ));
+        let mut server = Session::new_server(server_read, server_write, create_test_padding());
+        server.set_downstream_padding(padding);
+        let (stream_tx, mut stream_rx) = mpsc::unbounded_channel();
+        server.set_stream_callback(stream_tx);
+        let server = Arc::new(server);
+        for session in [&client, &server] {
+            let recv = Arc::clone(session);
+            tokio::spawn(async move { recv.recv_loop().await });
+            let send = Arc::clone(session);
+            tokio::spawn(async move { send.process_stream_data().await });
+        }
+
+        // Settings as sent by start_client, or by a client predating the key
+        let mut settings = StringMap::new();
+        settings.insert("v", "2");
+        settings.insert("padding-md5", create_test_padding().md5());
+        if advertise {
+            settings.insert("downstream-padding", "1");
+        }
+        client
+            .write_frame(Frame::with_data(
+                Command::Settings,
+                0,
+                Bytes::from(settings.to_bytes()),
+            ))
+            .await
+            .unwrap();
+
+        // Echo a few writes, larger than the padded record sizes
+        let (local, _) = client.open_stream().await.unwrap();
+        let remote = stream_rx.recv().await.unwrap();
+        for round in 0..4u8 {
+            let payload = Bytes::from(vec![round; 1500]);
+            local.write_data(payload.clone()).await.unwrap();
+            let mut received = vec![0u8; payload.len()];
+            let mut remote_reader = remote.reader().lock().await;
+            remote_reader.read_exact(&mut received).await.unwrap();
+            drop(remote_reader);
+            remote.write_data(Bytes::from(received)).await.unwrap();
+
+            let mut echoed = vec![0u8; payload.len()];
+            let mut local_reader = local.reader().lock().await;
+            time::timeout(Duration::from_secs(1), local_reader.read_exact(&mut echoed))
+                .await
+                .expect("echo expected")
+                .unwrap();
+            assert_eq!(echoed, payload, "client decodes round {round}");
+        }
+
+        let mut wire = BytesMut::from(&wire.lock().unwrap()[..]);
+        let mut commands = Vec::new();
+        while let Some(frame) = FrameCodec.decode(&mut wire).unwrap() {
+            commands.push(frame.cmd);
+        }
+        assert!(wire.is_empty(), "server wrote whole frames");
+        (client, server, commands)
+    }
+
+    #[tokio::test]
+    async fn test_downstream_padding_negotiated() {
+        let scheme = Arc::new(
+            PaddingFactory::new(b"stop=3\n0=700-700\n1=300-300,300-300\n2=900-1000").unwrap(),
+        );
+        let (client, server, commands) =
+            downstream_padding_exchange(DownstreamPadding::Scheme(scheme), true).await;
+
+        assert!(server.is_downstream_padded());
+        assert!(client.is_downstream_padded());
+        assert!(commands.contains(&Command::Waste));
+        assert!(commands.contains(&Command::ServerSettings));
+        assert!(commands.iter().filter(|&&cmd| cmd == Command::Push).count() >= 4);
+    }
+
+    #[tokio::test]
+    async fn test_downstream_padding_skips_old_clients() {
+        let (client, server, commands) =
+            downstream_padding_exchange(DownstreamPadding::SessionScheme, false).await;
+        assert!(!server.is_downstream_padded());
+        assert!(!client.is_downstream_padded());
+        assert!(!commands.contains(&Command::Waste));
+
+        // Not configured on the server: plain writes even for new clients
+        let (client, server, commands) =
+            downstream_padding_exchange(DownstreamPadding::Disabled, true).await;
+        assert!(!server.is_downstream_padded());
+        assert!(!client.is_downstream_padded());
+        assert!(!commands.contains(&Command::Waste));
+    }
+
+    /// 服务端会话按 `config` 发送掩护流量 `wait` 时长后，对端收到的帧
+    async fn cover_traffic_frames(config: CoverTrafficConfig, wait: Duration) -> Vec<Frame> {
+        let (session_stream, mut peer) = duplex(65536);
+        let (read, write) = tokio::io::split(session_stream);
+        let mut session = Session::new_server(read, write, create_test_padding());
+        session.set_cover_traffic(Some(config));
+        let session = Arc::new(session);
+        session.start_cover_traffic();
+        time::sleep(wait).await;
+        session.close().await.unwrap();
+
+        let mut wire = Vec::new();
+        time::timeout(Duration::from_secs(1), peer.read_to_end(&mut wire))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut wire = BytesMut::from(&wire[..]);
+        let mut frames = Vec::new();
+        while let Some(frame) = FrameCodec.decode(&mut wire).unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    fn fast_cover_traffic() -> CoverTrafficConfig {
+        CoverTrafficConfig {
+            idle_after: Duration::from_millis(100),
+            min_interval: Duration::from_millis(10),
+            max_interval: Duration::from_millis(20),
+            sizes: "50-80".parse().unwrap(),
+            max_bytes_per_sec: 1000,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cover_traffic_on_idle_session() {
+        let frames = cover_traffic_frames(fast_cover_traffic(), Duration::from_millis(600)).await;
+        assert!(!frames.is_empty());
+        assert!(
+            frames
+                .iter()
+                .all(|f| f.cmd == Command::Waste && (50..=80).contains(&f.data.len()))
+        );
+        // One second's worth up front plus 0.6s of refill
+        let bytes: usize = frames
+            .iter()
+            .map(|f| HEADER_OVERHEAD_SIZE + f.data.len())
+            .sum();
+        assert!(bytes <= 1600, "{} cover bytes exceed the cap", bytes);
+
+        // Not idle for long enough yet
+        let config = CoverTrafficConfig {
+            idle_after: Duration::from_secs(10),
+            ..fast_cover_traffic()
+        };
+        let frames = cover_traffic_frames(config, Duration::from_millis(300)).await;
+        assert!(frames.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cover_traffic_dropped_by_peer() {
+        let (client_stream, server_stream) = create_connected_streams();
+        let (client_read, client_write) = tokio::io::split(client_stream);
+        let (server_read, server_write) = tokio::io::split(server_stream);
+        let mut client =
+            Session::new_client(client_read, client_write, create_test_padding(), None);
+        client.set_cover_traffic(Some(fast_cover_traffic()));
+        let mut server = Session::new_server(server_read, server_write, create_test_padding());
+        server.set_cover_traffic(Some(fast_cover_traffic()));
+        let (stream_tx, mut stream_rx) = mpsc::unbounded_channel();
+        server.set_stream_callback(stream_tx);
+        let (client, server) = (Arc::new(client), Arc::new(server));
+        for session in [&client, &server] {
+            let recv = Arc::clone(session);
+            tokio::spawn(async move { recv.recv_loop().await });
+            let send = Arc::clone(session);
+            tokio::spawn(async move { send.process_stream_data().await });
+            session.start_cover_traffic();
+        }
+
+        let sent_before = METRICS.cover_bytes.get();
+        time::sleep(Duration::from_millis(400)).await;
+        assert!(METRICS.cover_bytes.get() > sent_before);
+        assert!(server.idle_for() >= Duration::from_millis(100));
+
+        // Both ends kept going and stream traffic still flows
+        let (local, _) = client.open_stream().await.unwrap();
+        let remote = stream_rx.recv().await.unwrap();
+        local.write_data(Bytes::from_static(b"ping")).await.unwrap();
+        let mut received = [0u8; 4];
+        remote
+            .reader()
+            .lock()
+            .await
+            .read_exact(&mut received)
+            .await
+            .unwrap();
+        assert_eq!(&received, b"ping");
+        assert!(!client.is_closed() && !server.is_closed());
+        assert!(server.idle_for() < Duration::from_millis(100));
+    }
+
+    /// 按 `limits` 限制服务端会话，返回已发送 v2 Settings 的客户端会话
+    async fn limited_session_pair(limits: StreamLimits) -> (Arc<Session>, Arc<Session>) {
+        let (client_stream, server_stream) = create_connected_streams();
+        let (client_read, client_write) = tokio::io::split(client_stream);
+        let (server_read, server_write) = tokio::io::split(server_stream);
+        let client = Arc::new(Session::new_client(
+            client_read,
+            client_write,
+            create_test_padding(),
+            None,
+        ));
+        let mut server = Session::new_server(server_read, server_write, create_test_padding());
+        server.set_stream_limits(limits);
+        let (stream_tx, _) = mpsc::unbounded_channel();
+        server.set_stream_callback(stream_tx);
+        let server = Arc::new(server);
+        for session in [&client, &server] {
+            let recv = Arc::clone(session);
+            tokio::spawn(async move { recv.recv_loop().await });
+            let send = Arc::clone(session);
+            tokio::spawn(async move { send.process_stream_data().await });
+        }
+
+        let mut settings = StringMap::new();
+        settings.insert("v", "2");
+        settings.insert("padding-md5", create_test_padding().md5());
+        client
+            .write_frame(Frame::with_data(
+                Command::Settings,
+                0,
+                Bytes::from(settings.to_bytes()),
+            ))
+            .await
+            .unwrap();
+        (client, server)
+    }
+
+    /// 连续打开 `count` 个流，返回服务端是否拒绝了每个流
+    async fn open_streams(client: &Session, count: usize) -> Vec<bool> {
+        let mut synacks = Vec::new();
+        for _ in 0..count {
+            synacks.push(client.open_stream().await.unwrap());
+        }
+        time::sleep(Duration::from_millis(200)).await;
+        synacks
+            .into_iter()
+            .map(|(_stream, mut synack)| matches!(synack.try_recv(), Ok(Err(_))))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_stream_limits() {
+        let (client, server) = limited_session_pair(StreamLimits {
+            max_streams: Some(2),
+            max_syn_per_sec: None,
+        })
+        .await;
+        assert_eq!(open_streams(&client, 3).await, [false, false, true]);
+        assert_eq!(server.stream_count().await, 2);
+        assert!(!server.is_closed());
+
+        let (client, server) = limited_session_pair(StreamLimits {
+            max_streams: None,
+            max_syn_per_sec: Some(3),
+        })
+        .await;
+        assert_eq!(open_streams(&client, 4).await, [false, false, false, true]);
+        assert_eq!(server.stream_count().await, 3);
+
+        // The next second allows new streams again
+        time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(open_streams(&client, 1).await, [false]);
+    }
 }
@@ -3,18 +3,29 @@
 //! Stream provides a duplex communication channel that implements AsyncRead and AsyncWrite
 
 use crate::session::StreamReader;
-use crate::util::{AnyTlsError, Result};
+use crate::session::flow_control::SendBudget;
+use crate::util::{AnyTlsError, GaugeGuard, METRICS, Result};
 use bytes::Bytes;
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::futures::OwnedNotified;
 use tokio::sync::{mpsc, oneshot};
 
+/// 读方向已结束（收到对端的 Fin）
+const READ_FINISHED: u8 = 0b01;
+/// 写方向已结束（Fin 已发送给对端）
+const WRITE_FINISHED: u8 = 0b10;
+
 /// Stream represents a single data stream within a Session
 /// It implements AsyncRead and AsyncWrite to be used as a connection
+///
+/// 两个方向可以分别关闭（half-close）：[`shutdown_write`](Self::shutdown_write)
+/// 在已写入的数据之后向对端发送 Fin，对端的 Fin 在 reader 上表现为 EOF。
+/// 两个方向都结束后 Session 才会移除该流。
 pub struct Stream {
     id: u32,
 
@@ -25,12 +36,24 @@ pub struct Stream {
     // ===== 写入部分：直接使用 channel，无需锁 =====
     writer_tx: mpsc::UnboundedSender<(u32, Bytes)>,
 
+    // ===== 写入预算：Session 发送队列满时写入方等待 =====
+    send_budget: Option<Arc<SendBudget>>,
+    send_wait: Option<Pin<Box<OwnedNotified>>>,
+
     // ===== SYNACK 通知 (用于超时检测) =====
     synack_tx: Arc<tokio::sync::Mutex<Option<oneshot::Sender<Result<()>>>>>,
 
     // ===== 状态管理 =====
     is_closed: Arc<AtomicBool>,
+    write_shutdown: AtomicBool,
+    finished: AtomicU8,
     close_error: Arc<tokio::sync::Mutex<Option<AnyTlsError>>>,
+
+    // Destination requested by the peer, once known
+    destination: OnceLock<String>,
+
+    // Keeps the active streams gauge up to date
+    _active: GaugeGuard,
 }
 
 impl Stream {
@@ -39,7 +62,7 @@ impl Stream {
     /// # Arguments
     /// * `id` - Stream ID
     /// * `reader` - StreamReader 用于读取数据
-    /// * `writer_tx` - 发送数据到 Session 的 channel
+    /// * `writer_tx` - 发送数据到 Session 的 channel（空数据表示 Fin）
     ///
     /// # Returns
     /// (Stream, Receiver) - The receiver can be used to wait for SYNACK
@@ -54,14 +77,26 @@ impl Stream {
             id,
             reader: Arc::new(tokio::sync::Mutex::new(reader)),
             writer_tx,
+            send_budget: None,
+            send_wait: None,
             synack_tx: Arc::new(tokio::sync::Mutex::new(Some(synack_tx))),
             is_closed: Arc::new(AtomicBool::new(false)),
+            write_shutdown: AtomicBool::new(false),
+            finished: AtomicU8::new(0),
             close_error: Arc::new(tokio::sync::Mutex::new(None)),
+            destination: OnceLock::new(),
+            _active: GaugeGuard::new(&METRICS.streams_active),
         };
 
         (stream, synack_rx)
     }
 
+    /// Charge writes against the session's send budget
+    pub(crate) fn with_send_budget(mut self, budget: Arc<SendBudget>) -> Self {
+        self.send_budget = Some(budget);
+        self
+    }
+
     /// Notify that SYNACK has been received
     ///
     /// # Arguments
@@ -87,11 +122,27 @@ impl Stream {
         }
     }
 
+    /// Whether the stream still waits for its SYNACK
+    pub(crate) async fn awaits_synack(&self) -> bool {
+        self.synack_tx.lock().await.is_some()
+    }
+
     /// Get stream ID
     pub fn id(&self) -> u32 {
         self.id
     }
 
+    /// Record the destination the stream is relayed to; only the first call
+    /// has an effect
+    pub fn set_destination(&self, destination: String) {
+        let _ = self.destination.set(destination);
+    }
+
+    /// Destination the stream is relayed to, if known
+    pub fn destination(&self) -> Option<&str> {
+        self.destination.get().map(String::as_str)
+    }
+
     /// Close the stream with error (can be called with `Arc<Stream>`)
     pub async fn close_with_error(&self, err: AnyTlsError) {
         if self
@@ -113,6 +164,60 @@ impl Stream {
         self.is_closed.load(Ordering::Relaxed)
     }
 
+    /// Finish the write direction (half-close)
+    ///
+    /// 对端在收到此前写入的全部数据后收到 Fin；读方向不受影响。
+    /// 重复调用无副作用。
+    pub fn shutdown_write(&self) -> Result<()> {
+        if self.write_shutdown.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        tracing::debug!("[Stream] Shutting down write side of stream {}", self.id);
+        // 空数据是 Fin 标记，与数据走同一个 channel 以保证顺序
+        self.writer_tx
+            .send((self.id, Bytes::new()))
+            .map_err(|_| AnyTlsError::SessionClosed)
+    }
+
+    /// Finish the write direction without sending Fin (the stream was reset)
+    pub(crate) fn abandon_write(&self) {
+        self.write_shutdown.store(true, Ordering::Release);
+    }
+
+    /// Check if the write direction has been shut down
+    pub fn is_write_shutdown(&self) -> bool {
+        self.write_shutdown.load(Ordering::Acquire)
+    }
+
+    /// 标记读方向结束；两个方向都结束时返回 true
+    pub(crate) fn finish_read(&self) -> bool {
+        self.finish(READ_FINISHED)
+    }
+
+    /// 标记写方向结束（Fin 已发送）；两个方向都结束时返回 true
+    pub(crate) fn finish_write(&self) -> bool {
+        self.finish(WRITE_FINISHED)
+    }
+
+    fn finish(&self, half: u8) -> bool {
+        let both = READ_FINISHED | WRITE_FINISHED;
+        let previous = self.finished.fetch_or(half, Ordering::AcqRel);
+        let done = previous != both && previous | half == both;
+        if done {
+            self.is_closed.store(true, Ordering::Relaxed);
+        }
+        done
+    }
+
+    fn write_closed_error(&self) -> std::io::Error {
+        let reason = if self.is_write_shutdown() {
+            "stream write side shut down"
+        } else {
+            "stream closed"
+        };
+        std::io::Error::new(std::io::ErrorKind::BrokenPipe, reason)
+    }
+
     /// Get a reference to the reader (for direct access in handlers)
     pub fn reader(&self) -> &Arc<tokio::sync::Mutex<StreamReader>> {
         &self.reader
@@ -120,13 +225,37 @@ impl Stream {
 
     /// Send data through the writer channel (无锁方式)
     ///
-    /// 这个方法可以被多个任务并发调用，无需任何锁
+    /// 这个方法可以被多个任务并发调用，无需任何锁。
+    /// 不等待 Session 的发送预算（数据仍计入预算）；生产速度可能超过连接的
+    /// 写入方应使用 [`write_data`](Self::write_data)
     pub fn send_data(
         &self,
         data: Bytes,
     ) -> std::result::Result<(), mpsc::error::SendError<(u32, Bytes)>> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if let Some(budget) = &self.send_budget {
+            budget.force_reserve(data.len());
+        }
         self.writer_tx.send((self.id, data))
     }
+
+    /// Send data through the writer channel, waiting while the session's send budget is exhausted
+    pub async fn write_data(&self, data: Bytes) -> Result<()> {
+        if self.is_closed() || self.is_write_shutdown() {
+            return Err(AnyTlsError::Io(self.write_closed_error()));
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        if let Some(budget) = &self.send_budget {
+            budget.reserve(data.len()).await?;
+        }
+        self.writer_tx
+            .send((self.id, data))
+            .map_err(|_| AnyTlsError::SessionClosed)
+    }
 }
 
 // Stream is not meant to be cloned - use Arc<Stream> instead
@@ -182,7 +311,7 @@ impl AsyncRead for Stream {
 impl AsyncWrite for Stream {
     fn poll_write(
         self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
+        cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
         let stream_id = self.id;
@@ -193,12 +322,33 @@ impl AsyncWrite for Stream {
             buf_len
         );
 
-        if self.is_closed.load(Ordering::Relaxed) {
+        if self.is_closed.load(Ordering::Relaxed) || self.is_write_shutdown() {
             tracing::warn!("[Stream] poll_write: Stream {} is closed", stream_id);
-            return Poll::Ready(Err(std::io::Error::new(
-                std::io::ErrorKind::BrokenPipe,
-                "stream closed",
-            )));
+            return Poll::Ready(Err(self.write_closed_error()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        // Wait for room in the session's send queue
+        let this = self.get_mut();
+        if let Some(budget) = &this.send_budget {
+            match budget.poll_reserve(&mut this.send_wait, cx, buf_len) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => {
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::BrokenPipe,
+                        e.to_string(),
+                    )));
+                }
+                Poll::Pending => {
+                    tracing::trace!(
+                        "[Stream] poll_write: Send budget exhausted, waiting (stream_id={})",
+                        stream_id
+                    );
+                    return Poll::Pending;
+                }
+            }
         }
 
         // Send data to session via channel
@@ -208,7 +358,7 @@ impl AsyncWrite for Stream {
             buf_len,
             stream_id
         );
-        match self.writer_tx.send((self.id, data)) {
+        match this.writer_tx.send((this.id, data)) {
             Ok(_) => {
                 tracing::debug!(
                     "[Stream] poll_write: Successfully sent {} bytes to channel for stream {}",
@@ -237,9 +387,11 @@ impl AsyncWrite for Stream {
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        // Mark as closed
-        self.is_closed.store(true, Ordering::Relaxed);
-        Poll::Ready(Ok(()))
+        // 只关闭写方向：对端收到 Fin，读方向继续可用
+        Poll::Ready(
+            self.shutdown_write()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::BrokenPipe, e.to_string())),
+        )
     }
 }
 
@@ -308,4 +460,34 @@ mod tests {
         assert_eq!(stream_id, 1);
         assert_eq!(data.as_ref(), b"output");
     }
+
+    #[tokio::test]
+    async fn test_stream_write_backpressure() {
+        use crate::session::FlowControlConfig;
+        use std::time::Duration;
+
+        let budget = SendBudget::new(&FlowControlConfig {
+            session_send_buffer: 8,
+            ..FlowControlConfig::default()
+        });
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (_reader_tx, reader_rx) = mpsc::unbounded_channel();
+
+        let reader = StreamReader::new(1, reader_rx);
+        let (stream, _synack_rx) = Stream::new(1, reader, tx);
+        let mut stream = stream.with_send_budget(Arc::clone(&budget));
+
+        stream.write_all(b"0123456789").await.unwrap();
+
+        // 预算耗尽：poll_write 返回 Pending
+        let blocked = tokio::time::timeout(Duration::from_millis(50), stream.write_all(b"more"));
+        assert!(blocked.await.is_err());
+
+        // Session 写出数据后归还预算
+        let (_, data) = rx.recv().await.unwrap();
+        budget.release(data.len());
+        stream.write_all(b"more").await.unwrap();
+        let (_, data) = rx.recv().await.unwrap();
+        assert_eq!(data.as_ref(), b"more");
+    }
 }
@@ -3,8 +3,10 @@
 //! 负责从 Session 接收数据并提供给上层读取
 //! 与 Stream 的写入操作完全分离
 
+use crate::session::flow_control::StreamWindow;
 use bytes::Bytes;
 use std::io;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 
 /// StreamReader 管理单个流的读取状态
@@ -27,6 +29,9 @@ pub struct StreamReader {
 
     /// EOF 标志
     eof: bool,
+
+    /// Session 的接收预算（读取后归还）
+    window: Option<Arc<StreamWindow>>,
 }
 
 impl StreamReader {
@@ -37,9 +42,36 @@ impl StreamReader {
             reader_rx,
             reader_buffer: Vec::new(),
             eof: false,
+            window: None,
+        }
+    }
+
+    /// 关联 Session 的接收预算，读取的字节会归还给 Session
+    pub(crate) fn with_window(mut self, window: Arc<StreamWindow>) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    fn consumed(&self, n: usize) {
+        if let Some(window) = &self.window {
+            window.consumed(n);
         }
     }
 
+    /// Result of a read once the buffer is drained and the channel closed
+    ///
+    /// A reset stream fails rather than reporting EOF, so truncated data is
+    /// not taken for a complete stream.
+    fn finished(&self) -> io::Result<usize> {
+        if self.window.as_ref().is_some_and(|window| window.is_reset()) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                format!("stream {} was reset", self.id),
+            ));
+        }
+        Ok(0)
+    }
+
     /// 读取数据到 buffer
     ///
     /// 实现逻辑：
@@ -49,7 +81,7 @@ impl StreamReader {
     pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         // 1. 先检查 EOF
         if self.eof && self.reader_buffer.is_empty() {
-            return Ok(0);
+            return self.finished();
         }
 
         // 2. 从 buffer 读取（如果有数据）
@@ -57,6 +89,7 @@ impl StreamReader {
             let n = std::cmp::min(self.reader_buffer.len(), buf.len());
             buf[..n].copy_from_slice(&self.reader_buffer[..n]);
             self.reader_buffer.drain(..n);
+            self.consumed(n);
 
             tracing::trace!(
                 "[StreamReader] Read {} bytes from buffer (stream_id={}, buffer_remaining={})",
@@ -92,6 +125,7 @@ impl StreamReader {
                     );
                 }
 
+                self.consumed(n);
                 Ok(n)
             }
             None => {
@@ -101,7 +135,7 @@ impl StreamReader {
                     self.id
                 );
                 self.eof = true;
-                Ok(0)
+                self.finished()
             }
         }
     }
@@ -143,6 +177,15 @@ impl StreamReader {
     }
 }
 
+impl Drop for StreamReader {
+    fn drop(&mut self) {
+        // 未读取的数据随 reader 一起丢弃，归还全部预算
+        if let Some(window) = &self.window {
+            window.release();
+        }
+    }
+}
+
 // StreamReader 不需要实现 Clone
 // 因为它包含 UnboundedReceiver（不可 Clone）
 
@@ -232,4 +275,29 @@ mod tests {
 
         assert_eq!(total, b"chunk1chunk2chunk3");
     }
+
+    #[tokio::test]
+    async fn test_stream_reader_returns_window() {
+        use crate::session::FlowControlConfig;
+        use crate::session::flow_control::ReceiveBudget;
+
+        let budget = ReceiveBudget::new(&FlowControlConfig::default());
+        let window = budget.stream_window();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut reader = StreamReader::new(1, rx).with_window(Arc::clone(&window));
+
+        assert!(window.try_queued(11));
+        tx.send(Bytes::from("hello world")).unwrap();
+
+        // 部分读取只归还已读取的字节
+        let mut buf = vec![0u8; 5];
+        reader.read(&mut buf).await.unwrap();
+        assert_eq!(window.buffered(), 6);
+        reader.read(&mut buf).await.unwrap();
+        assert_eq!(window.buffered(), 1);
+
+        // 丢弃 reader 归还剩余部分
+        drop(reader);
+        assert_eq!(budget.queued(), 0);
+    }
 }
@@ -0,0 +1,464 @@
+//! Connection writer task
+//!
+//! Every frame written by a [`Session`](crate::session::Session) goes through
+//! a queue to a single writer task that owns the write half of the
+//! connection. The task drains whatever is ready into one reused buffer (up
+//! to [`WriterConfig::max_batch_bytes`], optionally waiting
+//! [`WriterConfig::max_delay`] for more), applies the padding scheme and
+//! writes the batch with a single flush.
+
+use crate::padding::PaddingFactory;
+use crate::protocol::{Frame, FrameCodec, MAX_FRAME_DATA_SIZE};
+use bytes::{Buf, BytesMut};
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{RwLock, mpsc, oneshot};
+use tokio::time::{self, Duration, Instant};
+use tokio_util::codec::Encoder;
+
+/// Default upper bound of one coalesced write
+pub const DEFAULT_MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/// Batching limits of the writer task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriterConfig {
+    /// Stop adding frames to a batch once it holds this many bytes
+    pub max_batch_bytes: usize,
+    /// How long to wait for more frames before writing a batch that is not
+    /// full; zero writes as soon as the queue is empty
+    pub max_delay: Duration,
+    /// Larger stream writes are split into Push frames of at most this many
+    /// bytes (clamped to `1..=MAX_FRAME_DATA_SIZE`)
+    pub max_frame_payload: usize,
+}
+
+impl WriterConfig {
+    /// Payload size used to split stream writes
+    pub(crate) fn frame_payload(&self) -> usize {
+        self.max_frame_payload.clamp(1, MAX_FRAME_DATA_SIZE)
+    }
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
+            max_delay: Duration::ZERO,
+            max_frame_payload: MAX_FRAME_DATA_SIZE,
+        }
+    }
+}
+
+/// Write failure with the step it happened in (for logging)
+pub(crate) type WriteError = (&'static str, io::Error);
+pub(crate) type WriteAck = oneshot::Sender<std::result::Result<(), WriteError>>;
+
+pub(crate) enum WriteRequest {
+    Frame {
+        frame: Frame,
+        /// Keep the frame in the buffer until a later frame is written
+        hold: bool,
+        ack: Option<WriteAck>,
+    },
+    Shutdown(oneshot::Sender<io::Result<()>>),
+}
+
+/// Counts a caller waiting for its frame to be written while alive
+pub(crate) struct WaitingWriter(Arc<AtomicUsize>);
+
+impl WaitingWriter {
+    pub(crate) fn new(waiting: &Arc<AtomicUsize>) -> Self {
+        waiting.fetch_add(1, Ordering::AcqRel);
+        Self(Arc::clone(waiting))
+    }
+}
+
+impl Drop for WaitingWriter {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Owns the write half of a connection until the writer task is started
+pub(crate) struct FrameWriter {
+    writer: Box<dyn AsyncWrite + Send + Unpin>,
+    requests: mpsc::UnboundedReceiver<WriteRequest>,
+    padding: Arc<RwLock<Arc<PaddingFactory>>>,
+    pkt_counter: Arc<AtomicU32>,
+    /// Padding is applied while set; the server turns it on once negotiated
+    send_padding: Arc<AtomicBool>,
+    config: WriterConfig,
+    /// Callers between queueing a frame and getting its result
+    waiting: Arc<AtomicUsize>,
+    buffer: BytesMut,
+    acks: Vec<WriteAck>,
+    /// The last frame asked to stay buffered
+    holding: bool,
+    failed: Option<(&'static str, io::ErrorKind, String)>,
+}
+
+impl FrameWriter {
+    pub(crate) fn new(
+        writer: Box<dyn AsyncWrite + Send + Unpin>,
+        requests: mpsc::UnboundedReceiver<WriteRequest>,
+        padding: Arc<RwLock<Arc<PaddingFactory>>>,
+        pkt_counter: Arc<AtomicU32>,
+        send_padding: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            writer,
+            requests,
+            padding,
+            pkt_counter,
+            send_padding,
+            config: WriterConfig::default(),
+            waiting: Arc::new(AtomicUsize::new(0)),
+            buffer: BytesMut::new(),
+            acks: Vec::new(),
+            holding: false,
+            failed: None,
+        }
+    }
+
+    pub(crate) fn set_config(&mut self, config: WriterConfig) {
+        self.config = config;
+    }
+
+    pub(crate) fn set_padding(&mut self, padding: Arc<RwLock<Arc<PaddingFactory>>>) {
+        self.padding = padding;
+    }
+
+    /// Counter for [`WaitingWriter`] guards of this writer's callers
+    pub(crate) fn waiting(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.waiting)
+    }
+
+    /// Shut the connection down without starting the task
+    pub(crate) async fn shutdown(mut self) -> io::Result<()> {
+        self.writer.shutdown().await
+    }
+
+    /// Run until the session is dropped or shut down
+    pub(crate) async fn run(mut self) {
+        while let Some(request) = self.requests.recv().await {
+            let mut pending = Some(request);
+            let mut yielded = false;
+            let deadline = Instant::now() + self.config.max_delay;
+
+            // Drain ready requests into one batch
+            loop {
+                let request = match pending.take() {
+                    Some(request) => request,
+                    None if self.buffer.len() >= self.config.max_batch_bytes => break,
+                    None => match self.requests.try_recv() {
+                        Ok(request) => request,
+                        Err(_)
+                            if !yielded
+                                && self.waiting.load(Ordering::Acquire) > self.acks.len() =>
+                        {
+                            // Other callers are about to queue a frame: give them one turn
+                            yielded = true;
+                            tokio::task::yield_now().await;
+                            continue;
+                        }
+                        Err(_) if self.config.max_delay.is_zero() => break,
+                        Err(_) => match time::timeout_at(deadline, self.requests.recv()).await {
+                            Ok(Some(request)) => request,
+                            _ => break,
+                        },
+                    },
+                };
+                match request {
+                    WriteRequest::Frame { frame, hold, ack } => {
+                        if let Err(e) = FrameCodec.encode(frame, &mut self.buffer) {
+                            let e = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+                            self.fail("encode_frame", e);
+                        }
+                        if let Some(ack) = ack {
+                            self.acks.push(ack);
+                        }
+                        self.holding = hold;
+                    }
+                    WriteRequest::Shutdown(done) => {
+                        self.flush_batch().await;
+                        let result = self.writer.shutdown().await;
+                        let _ = done.send(result);
+                        return;
+                    }
+                }
+            }
+
+            self.flush_batch().await;
+        }
+    }
+
+    async fn flush_batch(&mut self) {
+        if self.holding {
+            // Buffered frames go out together with the next frame
+            self.ack_all();
+            return;
+        }
+        if self.acks.is_empty() && self.buffer.is_empty() {
+            return;
+        }
+        if self.failed.is_none() && !self.buffer.is_empty() {
+            tracing::trace!(
+                "[Session] writer: Writing batch of {} bytes ({} waiter(s))",
+                self.buffer.len(),
+                self.acks.len()
+            );
+            if let Err((context, e)) = self.write_with_padding().await {
+                self.fail(context, e);
+            }
+        }
+        self.buffer.clear();
+        self.ack_all();
+    }
+
+    fn fail(&mut self, context: &'static str, e: io::Error) {
+        if self.failed.is_none() {
+            self.failed = Some((context, e.kind(), e.to_string()));
+        }
+    }
+
+    fn ack_all(&mut self) {
+        for ack in self.acks.drain(..) {
+            let result = match &self.failed {
+                None => Ok(()),
+                Some((context, kind, message)) => {
+                    Err((*context, io::Error::new(*kind, message.clone())))
+                }
+            };
+            let _ = ack.send(result);
+        }
+    }
+
+    /// Write the batch to the connection with padding applied
+    async fn write_with_padding(&mut self) -> std::result::Result<(), WriteError> {
+        use crate::protocol::{Command, HEADER_OVERHEAD_SIZE};
+        use bytes::BufMut;
+
+        let writer = &mut self.writer;
+        let buffer = &mut self.buffer;
+
+        if !self.send_padding.load(Ordering::Acquire) {
+            // No padding, write directly
+            tracing::trace!(
+                "[Session] write_with_padding: Writing {} bytes without padding",
+                buffer.len()
+            );
+            writer
+                .write_all(buffer)
+                .await
+                .map_err(|e| ("write_without_padding", e))?;
+            writer
+                .flush()
+                .await
+                .map_err(|e| ("flush_without_padding", e))?;
+            tracing::debug!(
+                "[Session] write_with_padding: Successfully wrote {} bytes to connection",
+                buffer.len()
+            );
+            return Ok(());
+        }
+
+        // Increment packet counter
+        let pkt = self.pkt_counter.fetch_add(1, Ordering::SeqCst);
+        let padding_factory = {
+            let padding_guard = self.padding.read().await;
+            padding_guard.clone()
+        };
+
+        for record in padding_factory.plan_records(pkt, buffer.len()) {
+            tracing::trace!(
+                "[Session] write_with_padding: Writing record payload={}, padding={}, delay={:?}",
+                record.payload,
+                record.padding,
+                record.delay
+            );
+            if !record.delay.is_zero() {
+                // Flush what was written so the delay shows on the wire
+                writer
+                    .flush()
+                    .await
+                    .map_err(|e| ("flush_padding_delay", e))?;
+                time::sleep(record.delay).await;
+            }
+            if record.padding == 0 {
+                // Payload only; the receiver reassembles frames split across records
+                writer
+                    .write_all(&buffer[..record.payload])
+                    .await
+                    .map_err(|e| ("write_padding_payload", e))?;
+                buffer.advance(record.payload);
+                continue;
+            }
+
+            // The rest of the payload (if any) followed by a Waste frame
+            let padding_len = record.padding - HEADER_OVERHEAD_SIZE;
+            buffer.reserve(record.padding);
+            buffer.put_u8(Command::Waste as u8);
+            buffer.put_u32(0); // stream_id = 0
+            buffer.put_u16(padding_len as u16);
+            let start = buffer.len();
+            buffer.resize(start + padding_len, 0);
+            padding_factory.fill_padding(&mut buffer[start..]);
+            writer
+                .write_all(buffer)
+                .await
+                .map_err(|e| ("write_padding_frame", e))?;
+            buffer.clear();
+        }
+
+        tracing::trace!("[Session] write_with_padding: Flushing writer");
+        writer
+            .flush()
+            .await
+            .map_err(|e| ("flush_with_padding", e))?;
+        tracing::debug!("[Session] write_with_padding: Successfully wrote and flushed data");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Command;
+    use bytes::Bytes;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use std::task::{Context, Poll};
+
+    /// Records the size of every flushed write
+    #[derive(Clone, Default)]
+    struct RecordingWriter {
+        pending: Arc<Mutex<usize>>,
+        flushed: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl AsyncWrite for RecordingWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            *self.pending.lock().unwrap() += buf.len();
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let written = std::mem::take(&mut *self.pending.lock().unwrap());
+            if written > 0 {
+                self.flushed.lock().unwrap().push(written);
+            }
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn frame_writer(
+        config: WriterConfig,
+    ) -> (
+        mpsc::UnboundedSender<WriteRequest>,
+        FrameWriter,
+        RecordingWriter,
+    ) {
+        let recorder = RecordingWriter::default();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut writer = FrameWriter::new(
+            Box::new(recorder.clone()),
+            rx,
+            Arc::new(RwLock::new(PaddingFactory::default())),
+            Arc::new(AtomicU32::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        );
+        writer.set_config(config);
+        (tx, writer, recorder)
+    }
+
+    fn data_request(len: usize, ack: Option<WriteAck>) -> WriteRequest {
+        WriteRequest::Frame {
+            frame: Frame::data(1, Bytes::from(vec![0u8; len])),
+            hold: false,
+            ack,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queued_frames_are_coalesced() {
+        let (tx, writer, recorder) = frame_writer(WriterConfig::default());
+        let (ack_tx, ack_rx) = oneshot::channel();
+        for _ in 0..9 {
+            tx.send(data_request(100, None)).unwrap();
+        }
+        tx.send(data_request(100, Some(ack_tx))).unwrap();
+
+        tokio::spawn(writer.run());
+        ack_rx.await.unwrap().unwrap();
+        assert_eq!(*recorder.flushed.lock().unwrap(), [10 * 107]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_size_limit() {
+        let config = WriterConfig {
+            max_batch_bytes: 250,
+            ..WriterConfig::default()
+        };
+        let (tx, writer, recorder) = frame_writer(config);
+        let (ack_tx, ack_rx) = oneshot::channel();
+        for _ in 0..4 {
+            tx.send(data_request(100, None)).unwrap();
+        }
+        tx.send(data_request(100, Some(ack_tx))).unwrap();
+
+        tokio::spawn(writer.run());
+        ack_rx.await.unwrap().unwrap();
+        assert_eq!(*recorder.flushed.lock().unwrap(), [321, 214]);
+    }
+
+    #[tokio::test]
+    async fn test_held_frames_wait_for_next_write() {
+        let (tx, writer, recorder) = frame_writer(WriterConfig::default());
+        tokio::spawn(writer.run());
+
+        let (ack_tx, ack_rx) = oneshot::channel();
+        tx.send(WriteRequest::Frame {
+            frame: Frame::control(Command::Syn, 1),
+            hold: true,
+            ack: Some(ack_tx),
+        })
+        .unwrap();
+        ack_rx.await.unwrap().unwrap();
+        assert!(recorder.flushed.lock().unwrap().is_empty());
+
+        let (ack_tx, ack_rx) = oneshot::channel();
+        tx.send(data_request(10, Some(ack_tx))).unwrap();
+        ack_rx.await.unwrap().unwrap();
+        assert_eq!(*recorder.flushed.lock().unwrap(), [7 + 17]);
+    }
+
+    #[tokio::test]
+    async fn test_max_delay_collects_late_frames() {
+        let config = WriterConfig {
+            max_delay: Duration::from_millis(50),
+            ..WriterConfig::default()
+        };
+        let (tx, writer, recorder) = frame_writer(config);
+        tokio::spawn(writer.run());
+
+        let (first_tx, first_rx) = oneshot::channel();
+        tx.send(data_request(10, Some(first_tx))).unwrap();
+        time::sleep(Duration::from_millis(10)).await;
+        let (second_tx, second_rx) = oneshot::channel();
+        tx.send(data_request(10, Some(second_tx))).unwrap();
+
+        first_rx.await.unwrap().unwrap();
+        second_rx.await.unwrap().unwrap();
+        assert_eq!(*recorder.flushed.lock().unwrap(), [34]);
+    }
+}
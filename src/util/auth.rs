@@ -1,10 +1,11 @@
 //! Authentication utilities for AnyTLS protocol
 
 use crate::padding::PaddingFactory;
-use crate::util::{AnyTlsError, Result};
+use crate::util::{AnyTlsError, Result, UserIdentity, UserTable};
 use sha2::{Digest, Sha256};
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{Duration, timeout};
 
 /// Compute SHA256 hash of password
 pub fn hash_password(password: &str) -> [u8; 32] {
@@ -13,26 +14,94 @@ pub fn hash_password(password: &str) -> [u8; 32] {
     hasher.finalize().into()
 }
 
+/// Parse a SHA256 digest written as 64 hex characters
+pub(crate) fn parse_sha256_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// How long to wait for the rest of a partially received password hash.
+///
+/// Real clients send the whole hash in the first TLS record, so a short
+/// first read usually means a probe sending a small request and waiting.
+/// Only prefixes of a known hash are waited for; anything else is rejected
+/// at once. The wait does tell a client that its bytes so far prefix a
+/// valid hash, so every rejection counts towards an auth ban.
+const PARTIAL_HASH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Result of reading the authentication request
+#[derive(Debug)]
+pub enum AuthResult {
+    /// The password hash matched a known user
+    Accepted(UserIdentity),
+    /// No user matched; holds every byte consumed from the connection so
+    /// far so they can be replayed to a fallback backend
+    Rejected(Vec<u8>),
+}
+
 /// Authenticate a client connection (server side)
 ///
 /// Reads authentication data from the connection:
 /// - SHA256(password) (32 bytes)
 /// - padding0_length (2 bytes, big-endian)
 /// - padding0 (variable length)
+///
+/// Returns the identity of the user whose password hash matched.
 pub async fn authenticate_client<R: AsyncReadExt + Unpin>(
     reader: &mut R,
-    expected_password_hash: &[u8; 32],
+    users: &UserTable,
     _padding_factory: &Arc<PaddingFactory>,
-) -> Result<()> {
-    // Read SHA256(password)
-    let mut password_hash = [0u8; 32];
-    reader.read_exact(&mut password_hash).await?;
+) -> Result<UserIdentity> {
+    match read_authentication(reader, users).await? {
+        AuthResult::Accepted(user) => Ok(user),
+        AuthResult::Rejected(_) => Err(AnyTlsError::AuthenticationFailed),
+    }
+}
 
-    // Verify password
-    if password_hash != *expected_password_hash {
-        return Err(AnyTlsError::AuthenticationFailed);
+/// Read the authentication request, keeping the consumed bytes on rejection
+///
+/// Unlike [`authenticate_client`], a connection that closes or stalls before
+/// sending a full password hash is reported as [`AuthResult::Rejected`]
+/// rather than an IO error, since it cannot be a valid client. A partial hash
+/// that no user's hash starts with is rejected without waiting for more.
+pub async fn read_authentication<R: AsyncReadExt + Unpin>(
+    reader: &mut R,
+    users: &UserTable,
+) -> Result<AuthResult> {
+    // Read SHA256(password), possibly over several reads. Never read past
+    // the hash so that no protocol data is consumed from a valid client.
+    let mut password_hash = [0u8; 32];
+    let mut filled = 0;
+    while filled < password_hash.len() {
+        let read = reader.read(&mut password_hash[filled..]);
+        let n = if filled == 0 {
+            read.await?
+        } else {
+            match timeout(PARTIAL_HASH_TIMEOUT, read).await {
+                Ok(result) => result?,
+                Err(_) => return Ok(AuthResult::Rejected(password_hash[..filled].to_vec())),
+            }
+        };
+        if n == 0 {
+            return Ok(AuthResult::Rejected(password_hash[..filled].to_vec()));
+        }
+        filled += n;
+        if filled < password_hash.len() && !users.matches_hash_prefix(&password_hash[..filled]) {
+            return Ok(AuthResult::Rejected(password_hash[..filled].to_vec()));
+        }
     }
 
+    // Look up the user owning this password
+    let Some(user) = users.lookup(&password_hash).cloned() else {
+        return Ok(AuthResult::Rejected(password_hash.to_vec()));
+    };
+
     // Read padding0_length
     let mut padding_len_bytes = [0u8; 2];
     reader.read_exact(&mut padding_len_bytes).await?;
@@ -45,7 +114,7 @@ pub async fn authenticate_client<R: AsyncReadExt + Unpin>(
         // Padding is discarded
     }
 
-    Ok(())
+    Ok(AuthResult::Accepted(user))
 }
 
 /// Send authentication data (client side)
@@ -78,7 +147,8 @@ pub async fn send_authentication<W: AsyncWriteExt + Unpin>(
 
     // Write padding0
     if padding_len > 0 {
-        let padding = vec![0u8; padding_len as usize];
+        let mut padding = vec![0u8; padding_len as usize];
+        padding_factory.fill_padding(&mut padding);
         writer.write_all(&padding).await?;
     }
 
@@ -107,16 +177,35 @@ mod tests {
             .unwrap();
 
         // Server authenticates
-        authenticate_client(&mut server, &password_hash, &padding)
+        let users = UserTable::single("tester", password);
+        let user = authenticate_client(&mut server, &users, &padding)
             .await
             .unwrap();
+        assert_eq!(user.name(), "tester");
+    }
+
+    #[tokio::test]
+    async fn test_authentication_multi_user() {
+        let users = UserTable::parse("alice=alice_pw\nbob=bob_pw").unwrap();
+        let padding = PaddingFactory::default();
+
+        for (name, password) in [("alice", "alice_pw"), ("bob", "bob_pw")] {
+            let (mut client, mut server) = duplex(1024);
+            send_authentication(&mut client, &hash_password(password), &padding)
+                .await
+                .unwrap();
+            let user = authenticate_client(&mut server, &users, &padding)
+                .await
+                .unwrap();
+            assert_eq!(user.name(), name);
+        }
     }
 
     #[tokio::test]
     async fn test_authentication_failure() {
         let password = "test_password";
         let password_hash = hash_password(password);
-        let wrong_hash = hash_password("wrong_password");
+        let users = UserTable::single("tester", "wrong_password");
 
         let (mut client, mut server) = duplex(1024);
         let padding = PaddingFactory::default();
@@ -127,7 +216,7 @@ mod tests {
             .unwrap();
 
         // Server authenticates with wrong password - should fail
-        let result = authenticate_client(&mut server, &wrong_hash, &padding).await;
+        let result = authenticate_client(&mut server, &users, &padding).await;
         assert!(result.is_err());
         assert!(matches!(
             result.unwrap_err(),
@@ -135,6 +224,57 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_read_authentication_keeps_rejected_bytes() {
+        let users = UserTable::single("tester", "test_password");
+
+        // A probe longer than the hash: exactly the first 32 bytes are consumed
+        let request = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(request).await.unwrap();
+        match read_authentication(&mut server, &users).await.unwrap() {
+            AuthResult::Rejected(consumed) => assert_eq!(consumed, &request[..32]),
+            AuthResult::Accepted(_) => panic!("probe must not authenticate"),
+        }
+
+        // A short probe that closes its side is rejected with what it sent
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(b"HEAD / HTTP/1.0\r\n\r\n").await.unwrap();
+        drop(client);
+        match read_authentication(&mut server, &users).await.unwrap() {
+            AuthResult::Rejected(consumed) => assert_eq!(consumed, b"HEAD / HTTP/1.0\r\n\r\n"),
+            AuthResult::Accepted(_) => panic!("probe must not authenticate"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_read_authentication_waits_only_for_hash_prefixes() {
+        let users = UserTable::single("tester", "test_password");
+
+        // A short probe that keeps its side open is rejected without waiting
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(b"HEAD / HTTP/1.0\r\n\r\n").await.unwrap();
+        let started = tokio::time::Instant::now();
+        match read_authentication(&mut server, &users).await.unwrap() {
+            AuthResult::Rejected(consumed) => assert_eq!(consumed, b"HEAD / HTTP/1.0\r\n\r\n"),
+            AuthResult::Accepted(_) => panic!("probe must not authenticate"),
+        }
+        assert_eq!(started.elapsed(), Duration::ZERO);
+
+        // A client whose hash arrives in pieces is still accepted
+        let hash = hash_password("test_password");
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(&hash[..10]).await.unwrap();
+        let reader = tokio::spawn(async move { read_authentication(&mut server, &users).await });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        client.write_all(&hash[10..]).await.unwrap();
+        client.write_all(&0u16.to_be_bytes()).await.unwrap();
+        match reader.await.unwrap().unwrap() {
+            AuthResult::Accepted(user) => assert_eq!(user.name(), "tester"),
+            AuthResult::Rejected(_) => panic!("split hash must authenticate"),
+        }
+    }
+
     #[tokio::test]
     async fn test_hash_password() {
         let hash1 = hash_password("test");
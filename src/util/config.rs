@@ -0,0 +1,1138 @@
+//! Configuration files for the anytls-server and anytls-client binaries
+//!
+//! Both binaries accept `--config FILE` in TOML (`.toml`) or YAML
+//! (`.yaml`/`.yml`) format. Every field is optional and mirrors a command
+//! line flag; flags given on the command line override values from the file
+//! (see [`ServerFileConfig::merge`] and [`ClientFileConfig::merge`]).
+//!
+//! ```toml
+//! listen = "0.0.0.0:8443"
+//! password = "secret"
+//! cert = "/etc/anytls/cert.pem"
+//! key = "/etc/anytls/key.pem"
+//! dns = ["1.1.1.1", "8.8.8.8"]
+//!
+//! [users]
+//! alice = "alice-password"
+//! bob = "sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//! ```
+
+use crate::client::LoadBalanceStrategy;
+use crate::padding::SizeDistribution;
+use crate::protocol::HEADER_OVERHEAD_SIZE;
+use crate::server::{AuthBans, BanConfig, HandshakeTimeouts, ServerLimits};
+use crate::session::CoverTrafficConfig;
+use crate::util::{AnyTlsError, CertPin, CertVerification, Result, TimeoutPolicy, UserTable};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+/// Settings for `anytls-server`, as read from a configuration file
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerFileConfig {
+    /// Listen address
+    pub listen: Option<String>,
+    /// Password of the implicit `default` user
+    pub password: Option<String>,
+    /// User table file (see [`UserTable`])
+    pub users_file: Option<PathBuf>,
+    /// Inline users, `name = "password"` or `name = "sha256:<hex>"`
+    pub users: Option<BTreeMap<String, String>>,
+    /// PEM encoded TLS certificate
+    pub cert: Option<PathBuf>,
+    /// PEM encoded TLS private key
+    pub key: Option<PathBuf>,
+    /// Watch certificate files and reload on change
+    pub watch_cert: Option<bool>,
+    /// Display certificate information at startup
+    pub show_cert_info: Option<bool>,
+    /// Certificate expiry warning threshold in days
+    pub expiry_warning_days: Option<u64>,
+    /// Padding scheme file
+    pub padding_scheme: Option<PathBuf>,
+    /// Pad writes to clients that support it, using `padding_scheme`
+    pub downstream_padding: Option<bool>,
+    /// Separate padding scheme file for writes to clients (implies
+    /// `downstream_padding`)
+    pub downstream_padding_scheme: Option<PathBuf>,
+    /// Send Waste frames to clients while their sessions are idle
+    pub cover_traffic: Option<bool>,
+    /// Seconds without stream traffic before cover frames start
+    pub cover_traffic_idle_after: Option<u64>,
+    /// Gap between cover frames in milliseconds, `MIN-MAX` or a fixed value
+    pub cover_traffic_interval_ms: Option<String>,
+    /// Data size of cover frames, e.g. `64-1200` (see [`SizeDistribution`])
+    pub cover_traffic_sizes: Option<String>,
+    /// Cover bytes per second, frame headers included
+    pub cover_traffic_max_rate: Option<u32>,
+    /// Concurrent sessions, counting connections still in the handshake
+    pub max_sessions: Option<usize>,
+    /// Concurrent connections from one source IP
+    pub max_connections_per_ip: Option<usize>,
+    /// Open streams of one session
+    pub max_streams_per_session: Option<usize>,
+    /// Streams one session may open per second
+    pub max_syn_per_sec: Option<u32>,
+    /// Seconds a connection may take for the TLS handshake
+    pub tls_handshake_timeout: Option<u64>,
+    /// Seconds a connection may take to authenticate after TLS
+    pub auth_timeout: Option<u64>,
+    /// Seconds a session may take to send its first frame
+    pub first_frame_timeout: Option<u64>,
+    /// Seconds to connect to a destination
+    pub connect_timeout: Option<u64>,
+    /// Seconds to resolve a destination
+    pub dns_timeout: Option<u64>,
+    /// Seconds without traffic before a relayed stream is closed
+    pub stream_idle_timeout: Option<u64>,
+    /// Seconds a relayed stream may stay open
+    pub max_stream_lifetime: Option<u64>,
+    /// Failed authentications from one source that trigger a ban
+    pub auth_ban_failures: Option<u32>,
+    /// Seconds the failures have to fall within
+    pub auth_ban_window: Option<u64>,
+    /// Seconds a ban lasts
+    pub auth_ban_duration: Option<u64>,
+    /// Prefix length grouping IPv4 clients into one source
+    pub auth_ban_ipv4_prefix: Option<u8>,
+    /// Prefix length grouping IPv6 clients into one source
+    pub auth_ban_ipv6_prefix: Option<u8>,
+    /// File the ban list is kept in across restarts
+    pub auth_ban_file: Option<PathBuf>,
+    /// Idle session check interval hint for clients (seconds)
+    pub idle_session_check_interval: Option<u64>,
+    /// Idle session timeout hint for clients (seconds)
+    pub idle_session_timeout: Option<u64>,
+    /// Minimum idle sessions hint for clients
+    pub min_idle_session: Option<usize>,
+    /// Custom DNS resolvers
+    pub dns: Option<Vec<String>>,
+    /// Fallback backend for unauthenticated connections
+    pub fallback: Option<String>,
+    /// Outbound access control rules (see [`OutboundAcl`](crate::server::OutboundAcl))
+    pub acl_file: Option<PathBuf>,
+    /// Listen address for the Prometheus metrics endpoint
+    pub metrics_listen: Option<String>,
+    /// Listen address for the admin API, a loopback `host:port` or
+    /// `unix:/path/to/socket`
+    pub admin_listen: Option<String>,
+    /// Bearer token required by the admin API
+    pub admin_token: Option<String>,
+    /// File holding the admin API token
+    pub admin_token_file: Option<PathBuf>,
+    /// Seconds active streams may keep running after a shutdown signal
+    pub drain_timeout: Option<u64>,
+    /// Log level
+    pub log_level: Option<String>,
+}
+
+impl ServerFileConfig {
+    /// Load and validate a configuration file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config: Self = load_config_file(path.as_ref())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Overlay `overrides` (typically from the command line) on top of `self`
+    pub fn merge(self, overrides: Self) -> Self {
+        Self {
+            listen: overrides.listen.or(self.listen),
+            password: overrides.password.or(self.password),
+            users_file: overrides.users_file.or(self.users_file),
+            users: overrides.users.or(self.users),
+            cert: overrides.cert.or(self.cert),
+            key: overrides.key.or(self.key),
+            watch_cert: overrides.watch_cert.or(self.watch_cert),
+            show_cert_info: overrides.show_cert_info.or(self.show_cert_info),
+            expiry_warning_days: overrides.expiry_warning_days.or(self.expiry_warning_days),
+            padding_scheme: overrides.padding_scheme.or(self.padding_scheme),
+            downstream_padding: overrides.downstream_padding.or(self.downstream_padding),
+            downstream_padding_scheme: overrides
+                .downstream_padding_scheme
+                .or(self.downstream_padding_scheme),
+            cover_traffic: overrides.cover_traffic.or(self.cover_traffic),
+            cover_traffic_idle_after: overrides
+                .cover_traffic_idle_after
+                .or(self.cover_traffic_idle_after),
+            cover_traffic_interval_ms: overrides
+                .cover_traffic_interval_ms
+                .or(self.cover_traffic_interval_ms),
+            cover_traffic_sizes: overrides.cover_traffic_sizes.or(self.cover_traffic_sizes),
+            cover_traffic_max_rate: overrides
+                .cover_traffic_max_rate
+                .or(self.cover_traffic_max_rate),
+            max_sessions: overrides.max_sessions.or(self.max_sessions),
+            max_connections_per_ip: overrides
+                .max_connections_per_ip
+                .or(self.max_connections_per_ip),
+            max_streams_per_session: overrides
+                .max_streams_per_session
+                .or(self.max_streams_per_session),
+            max_syn_per_sec: overrides.max_syn_per_sec.or(self.max_syn_per_sec),
+            tls_handshake_timeout: overrides
+                .tls_handshake_timeout
+                .or(self.tls_handshake_timeout),
+            auth_timeout: overrides.auth_timeout.or(self.auth_timeout),
+            first_frame_timeout: overrides.first_frame_timeout.or(self.first_frame_timeout),
+            connect_timeout: overrides.connect_timeout.or(self.connect_timeout),
+            dns_timeout: overrides.dns_timeout.or(self.dns_timeout),
+            stream_idle_timeout: overrides.stream_idle_timeout.or(self.stream_idle_timeout),
+            max_stream_lifetime: overrides.max_stream_lifetime.or(self.max_stream_lifetime),
+            auth_ban_failures: overrides.auth_ban_failures.or(self.auth_ban_failures),
+            auth_ban_window: overrides.auth_ban_window.or(self.auth_ban_window),
+            auth_ban_duration: overrides.auth_ban_duration.or(self.auth_ban_duration),
+            auth_ban_ipv4_prefix: overrides.auth_ban_ipv4_prefix.or(self.auth_ban_ipv4_prefix),
+            auth_ban_ipv6_prefix: overrides.auth_ban_ipv6_prefix.or(self.auth_ban_ipv6_prefix),
+            auth_ban_file: overrides.auth_ban_file.or(self.auth_ban_file),
+            idle_session_check_interval: overrides
+                .idle_session_check_interval
+                .or(self.idle_session_check_interval),
+            idle_session_timeout: overrides.idle_session_timeout.or(self.idle_session_timeout),
+            min_idle_session: overrides.min_idle_session.or(self.min_idle_session),
+            dns: overrides.dns.or(self.dns),
+            fallback: overrides.fallback.or(self.fallback),
+            acl_file: overrides.acl_file.or(self.acl_file),
+            metrics_listen: overrides.metrics_listen.or(self.metrics_listen),
+            admin_listen: overrides.admin_listen.or(self.admin_listen),
+            admin_token: overrides.admin_token.or(self.admin_token),
+            admin_token_file: overrides.admin_token_file.or(self.admin_token_file),
+            drain_timeout: overrides.drain_timeout.or(self.drain_timeout),
+            log_level: overrides.log_level.or(self.log_level),
+        }
+    }
+
+    /// Check values that can be verified without touching the filesystem
+    pub fn validate(&self) -> Result<()> {
+        if let Some(listen) = &self.listen {
+            validate_address("listen", listen)?;
+        }
+        if let Some(fallback) = &self.fallback {
+            validate_address("fallback", fallback)?;
+        }
+        if let Some(metrics_listen) = &self.metrics_listen {
+            validate_address("metrics_listen", metrics_listen)?;
+        }
+        if let Some(admin_listen) = &self.admin_listen {
+            match admin_listen.strip_prefix("unix:") {
+                Some("") => {
+                    return Err(AnyTlsError::Config(
+                        "admin_listen: unix socket path cannot be empty".into(),
+                    ));
+                }
+                Some(_) => {}
+                None => validate_address("admin_listen", admin_listen)?,
+            }
+            if self.admin_token.is_none() && self.admin_token_file.is_none() {
+                return Err(AnyTlsError::Config(
+                    "admin_listen requires admin_token or admin_token_file".into(),
+                ));
+            }
+        }
+        if self.admin_token.is_some() && self.admin_token_file.is_some() {
+            return Err(AnyTlsError::Config(
+                "admin_token and admin_token_file are mutually exclusive".into(),
+            ));
+        }
+        if self.password.as_deref().is_some_and(str::is_empty) {
+            return Err(AnyTlsError::Config("password cannot be empty".into()));
+        }
+        if self.admin_token.as_deref().is_some_and(str::is_empty) {
+            return Err(AnyTlsError::Config("admin_token cannot be empty".into()));
+        }
+        if self.cert.is_some() != self.key.is_some() {
+            return Err(AnyTlsError::Config(
+                "cert and key must be provided together".into(),
+            ));
+        }
+        if let Some(users) = &self.users {
+            validate_inline_users(users)?;
+        }
+        validate_positive(
+            "idle_session_check_interval",
+            self.idle_session_check_interval,
+        )?;
+        validate_positive("idle_session_timeout", self.idle_session_timeout)?;
+        validate_positive("drain_timeout", self.drain_timeout)?;
+        validate_positive("expiry_warning_days", self.expiry_warning_days)?;
+        validate_positive("max_sessions", self.max_sessions.map(|n| n as u64))?;
+        validate_positive(
+            "max_connections_per_ip",
+            self.max_connections_per_ip.map(|n| n as u64),
+        )?;
+        validate_positive(
+            "max_streams_per_session",
+            self.max_streams_per_session.map(|n| n as u64),
+        )?;
+        validate_positive("max_syn_per_sec", self.max_syn_per_sec.map(u64::from))?;
+        validate_positive("tls_handshake_timeout", self.tls_handshake_timeout)?;
+        validate_positive("auth_timeout", self.auth_timeout)?;
+        validate_positive("first_frame_timeout", self.first_frame_timeout)?;
+        validate_stream_timeouts(
+            self.connect_timeout,
+            self.dns_timeout,
+            self.stream_idle_timeout,
+            self.max_stream_lifetime,
+        )?;
+        self.cover_traffic_config()?;
+        validate_positive("auth_ban_failures", self.auth_ban_failures.map(u64::from))?;
+        validate_positive("auth_ban_window", self.auth_ban_window)?;
+        validate_positive("auth_ban_duration", self.auth_ban_duration)?;
+        for (field, prefix, max) in [
+            ("auth_ban_ipv4_prefix", self.auth_ban_ipv4_prefix, 32),
+            ("auth_ban_ipv6_prefix", self.auth_ban_ipv6_prefix, 128),
+        ] {
+            if prefix.is_some_and(|prefix| prefix > max) {
+                return Err(AnyTlsError::Config(format!(
+                    "{} must be at most {}",
+                    field, max
+                )));
+            }
+        }
+        validate_log_level(self.log_level.as_deref())?;
+        Ok(())
+    }
+
+    /// Resource limits set by `max_sessions`, `max_connections_per_ip`,
+    /// `max_streams_per_session` and `max_syn_per_sec`
+    pub fn limits(&self) -> ServerLimits {
+        ServerLimits {
+            max_sessions: self.max_sessions,
+            max_connections_per_ip: self.max_connections_per_ip,
+            max_streams_per_session: self.max_streams_per_session,
+            max_syn_per_sec: self.max_syn_per_sec,
+        }
+    }
+
+    /// Handshake deadlines, with the defaults of [`HandshakeTimeouts`] for
+    /// unset values
+    pub fn handshake_timeouts(&self) -> HandshakeTimeouts {
+        let defaults = HandshakeTimeouts::default();
+        let secs = |value: Option<u64>, default| value.map_or(default, Duration::from_secs);
+        HandshakeTimeouts {
+            tls_handshake: secs(self.tls_handshake_timeout, defaults.tls_handshake),
+            authentication: secs(self.auth_timeout, defaults.authentication),
+            first_frame: secs(self.first_frame_timeout, defaults.first_frame),
+        }
+    }
+
+    /// Outbound and relay timeouts, with the defaults of [`TimeoutPolicy`]
+    /// for unset values
+    pub fn timeouts(&self) -> TimeoutPolicy {
+        timeout_policy(
+            None,
+            self.connect_timeout,
+            self.dns_timeout,
+            self.stream_idle_timeout,
+            self.max_stream_lifetime,
+        )
+    }
+
+    /// Cover traffic settings, if `cover_traffic` or any `cover_traffic_*`
+    /// value is set (and `cover_traffic` is not `false`)
+    ///
+    /// Unset values take the defaults of [`CoverTrafficConfig`].
+    pub fn cover_traffic_config(&self) -> Result<Option<CoverTrafficConfig>> {
+        cover_traffic_config(
+            self.cover_traffic,
+            self.cover_traffic_idle_after,
+            self.cover_traffic_interval_ms.as_deref(),
+            self.cover_traffic_sizes.as_deref(),
+            self.cover_traffic_max_rate,
+        )
+    }
+
+    /// Authentication failure tracker, if any `auth_ban_*` value is set
+    ///
+    /// Unset values take the defaults of [`BanConfig`]; bans in
+    /// `auth_ban_file` are restored.
+    pub fn auth_bans(&self) -> Result<Option<AuthBans>> {
+        let enabled = self.auth_ban_failures.is_some()
+            || self.auth_ban_window.is_some()
+            || self.auth_ban_duration.is_some()
+            || self.auth_ban_ipv4_prefix.is_some()
+            || self.auth_ban_ipv6_prefix.is_some()
+            || self.auth_ban_file.is_some();
+        if !enabled {
+            return Ok(None);
+        }
+        let defaults = BanConfig::default();
+        let secs = |value: Option<u64>, default| value.map_or(default, Duration::from_secs);
+        let config = BanConfig {
+            max_failures: self.auth_ban_failures.unwrap_or(defaults.max_failures),
+            window: secs(self.auth_ban_window, defaults.window),
+            ban_duration: secs(self.auth_ban_duration, defaults.ban_duration),
+            ipv4_prefix: self.auth_ban_ipv4_prefix.unwrap_or(defaults.ipv4_prefix),
+            ipv6_prefix: self.auth_ban_ipv6_prefix.unwrap_or(defaults.ipv6_prefix),
+        };
+        let bans = match &self.auth_ban_file {
+            Some(path) => AuthBans::with_file(config, path)?,
+            None => AuthBans::new(config)?,
+        };
+        Ok(Some(bans))
+    }
+
+    /// Admin API token from `admin_token` or the first line of
+    /// `admin_token_file`
+    pub fn admin_token(&self) -> Result<Option<String>> {
+        let Some(path) = &self.admin_token_file else {
+            return Ok(self.admin_token.clone());
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            AnyTlsError::Config(format!("admin_token_file {}: {}", path.display(), e))
+        })?;
+        let token = contents.lines().next().unwrap_or_default().trim();
+        if token.is_empty() {
+            return Err(AnyTlsError::Config(format!(
+                "admin_token_file {} is empty",
+                path.display()
+            )));
+        }
+        Ok(Some(token.to_string()))
+    }
+
+    /// Build the user table from `users_file`, inline `users` and `password`
+    ///
+    /// The password, if any, is added as the user
+    /// [`DEFAULT_USER_NAME`](crate::util::DEFAULT_USER_NAME), which must not
+    /// also be listed in `users_file` or `users`.
+    pub fn user_table(&self) -> Result<UserTable> {
+        let mut table = match &self.users_file {
+            Some(path) => UserTable::from_file(path)?,
+            None => UserTable::new(),
+        };
+        if let Some(users) = &self.users {
+            for (name, secret) in users {
+                table.add_user_secret(name, secret)?;
+            }
+        }
+        if let Some(password) = &self.password {
+            if table.contains(crate::util::DEFAULT_USER_NAME) {
+                return Err(AnyTlsError::Config(format!(
+                    "password defines the user '{}', which is also listed in users or users_file",
+                    crate::util::DEFAULT_USER_NAME
+                )));
+            }
+            table.add_user(crate::util::DEFAULT_USER_NAME, password)?;
+        }
+        if table.is_empty() {
+            return Err(AnyTlsError::Config(
+                "no users configured (set password, users or users_file)".into(),
+            ));
+        }
+        Ok(table)
+    }
+}
+
+/// Settings for `anytls-client`, as read from a configuration file
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientFileConfig {
+    /// SOCKS5 listen address
+    pub listen: Option<String>,
+    /// HTTP proxy listen address
+    pub http_listen: Option<String>,
+    /// Server address
+    pub server: Option<String>,
+    /// TLS SNI
+    pub sni: Option<String>,
+    /// Server password
+    pub password: Option<String>,
+    /// Padding scheme file
+    pub padding_scheme: Option<PathBuf>,
+    /// Send Waste frames on sessions while they are idle
+    pub cover_traffic: Option<bool>,
+    /// Seconds without stream traffic before cover frames start
+    pub cover_traffic_idle_after: Option<u64>,
+    /// Gap between cover frames in milliseconds, `MIN-MAX` or a fixed value
+    pub cover_traffic_interval_ms: Option<String>,
+    /// Data size of cover frames, e.g. `64-1200` (see [`SizeDistribution`])
+    pub cover_traffic_sizes: Option<String>,
+    /// Cover bytes per second, frame headers included
+    pub cover_traffic_max_rate: Option<u32>,
+    /// Several upstream servers instead of `server`; `sni`, `password` and
+    /// `padding_scheme` above are the defaults for each entry
+    pub upstreams: Option<Vec<UpstreamConfig>>,
+    /// Upstream selection: `failover`, `round-robin` or `least-active`
+    pub strategy: Option<String>,
+    /// CA bundle used to verify the server
+    pub ca: Option<PathBuf>,
+    /// SHA256 pin of the server certificate
+    pub pin: Option<String>,
+    /// SHA256 pin of the server public key (SPKI)
+    pub pin_spki: Option<String>,
+    /// Skip certificate verification
+    pub insecure: Option<bool>,
+    /// Idle session check interval (seconds)
+    pub idle_session_check_interval: Option<u64>,
+    /// Idle session timeout (seconds)
+    pub idle_session_timeout: Option<u64>,
+    /// Minimum idle sessions retained
+    pub min_idle_session: Option<usize>,
+    /// Seconds to wait for the server to connect a stream (SYNACK)
+    pub synack_timeout: Option<u64>,
+    /// Seconds to connect to a destination routed `direct`
+    pub connect_timeout: Option<u64>,
+    /// Seconds to resolve a destination routed `direct`
+    pub dns_timeout: Option<u64>,
+    /// Seconds without traffic before a relayed stream is closed
+    pub stream_idle_timeout: Option<u64>,
+    /// Seconds a relayed stream may stay open
+    pub max_stream_lifetime: Option<u64>,
+    /// Routing rules deciding direct/proxy/block (see [`Router`](crate::client::Router))
+    pub rules_file: Option<PathBuf>,
+    /// Listen address for the Prometheus metrics endpoint
+    pub metrics_listen: Option<String>,
+    /// Seconds active streams may keep running after a shutdown signal
+    pub drain_timeout: Option<u64>,
+    /// Log level
+    pub log_level: Option<String>,
+}
+
+impl ClientFileConfig {
+    /// Load and validate a configuration file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config: Self = load_config_file(path.as_ref())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Overlay `overrides` (typically from the command line) on top of `self`
+    ///
+    /// The certificate verification settings are treated as one group: if
+    /// `overrides` sets any of them, all of them are taken from `overrides`.
+    /// Likewise a `server` in `overrides` replaces the configured `upstreams`.
+    pub fn merge(self, overrides: Self) -> Self {
+        let verification_overridden = overrides.ca.is_some()
+            || overrides.pin.is_some()
+            || overrides.pin_spki.is_some()
+            || overrides.insecure.is_some();
+        // A server given on the command line replaces the file's upstreams
+        let upstreams = if overrides.server.is_some() {
+            overrides.upstreams
+        } else {
+            overrides.upstreams.or(self.upstreams)
+        };
+        let (ca, pin, pin_spki, insecure) = if verification_overridden {
+            (
+                overrides.ca,
+                overrides.pin,
+                overrides.pin_spki,
+                overrides.insecure,
+            )
+        } else {
+            (self.ca, self.pin, self.pin_spki, self.insecure)
+        };
+
+        Self {
+            listen: overrides.listen.or(self.listen),
+            http_listen: overrides.http_listen.or(self.http_listen),
+            server: overrides.server.or(self.server),
+            sni: overrides.sni.or(self.sni),
+            password: overrides.password.or(self.password),
+            padding_scheme: overrides.padding_scheme.or(self.padding_scheme),
+            cover_traffic: overrides.cover_traffic.or(self.cover_traffic),
+            cover_traffic_idle_after: overrides
+                .cover_traffic_idle_after
+                .or(self.cover_traffic_idle_after),
+            cover_traffic_interval_ms: overrides
+                .cover_traffic_interval_ms
+                .or(self.cover_traffic_interval_ms),
+            cover_traffic_sizes: overrides.cover_traffic_sizes.or(self.cover_traffic_sizes),
+            cover_traffic_max_rate: overrides
+                .cover_traffic_max_rate
+                .or(self.cover_traffic_max_rate),
+            upstreams,
+            strategy: overrides.strategy.or(self.strategy),
+            ca,
+            pin,
+            pin_spki,
+            insecure,
+            idle_session_check_interval: overrides
+                .idle_session_check_interval
+                .or(self.idle_session_check_interval),
+            idle_session_timeout: overrides.idle_session_timeout.or(self.idle_session_timeout),
+            min_idle_session: overrides.min_idle_session.or(self.min_idle_session),
+            synack_timeout: overrides.synack_timeout.or(self.synack_timeout),
+            connect_timeout: overrides.connect_timeout.or(self.connect_timeout),
+            dns_timeout: overrides.dns_timeout.or(self.dns_timeout),
+            stream_idle_timeout: overrides.stream_idle_timeout.or(self.stream_idle_timeout),
+            max_stream_lifetime: overrides.max_stream_lifetime.or(self.max_stream_lifetime),
+            rules_file: overrides.rules_file.or(self.rules_file),
+            metrics_listen: overrides.metrics_listen.or(self.metrics_listen),
+            drain_timeout: overrides.drain_timeout.or(self.drain_timeout),
+            log_level: overrides.log_level.or(self.log_level),
+        }
+    }
+
+    /// Certificate verification mode selected by `ca`, `pin`, `pin_spki`
+    /// and `insecure`, defaulting to the webpki roots
+    pub fn cert_verification(&self) -> Result<CertVerification> {
+        self.validate()?;
+        if let Some(ca) = &self.ca {
+            return Ok(CertVerification::CaFile(ca.clone()));
+        }
+        if let Some(pin) = &self.pin {
+            return Ok(CertVerification::Pinned(CertPin::certificate(pin)?));
+        }
+        if let Some(pin) = &self.pin_spki {
+            return Ok(CertVerification::Pinned(CertPin::spki(pin)?));
+        }
+        if self.insecure == Some(true) {
+            return Ok(CertVerification::Insecure);
+        }
+        Ok(CertVerification::WebPki)
+    }
+
+    /// Check values that can be verified without touching the filesystem
+    pub fn validate(&self) -> Result<()> {
+        if let Some(listen) = &self.listen {
+            validate_address("listen", listen)?;
+        }
+        if let Some(http_listen) = &self.http_listen {
+            validate_address("http_listen", http_listen)?;
+        }
+        if let Some(server) = &self.server {
+            validate_address("server", server)?;
+        }
+        if let Some(upstreams) = &self.upstreams {
+            if self.server.is_some() {
+                return Err(AnyTlsError::Config(
+                    "server and upstreams are mutually exclusive".into(),
+                ));
+            }
+            if upstreams.is_empty() {
+                return Err(AnyTlsError::Config("upstreams cannot be empty".into()));
+            }
+            for upstream in upstreams {
+                validate_address("upstreams.server", &upstream.server)?;
+                if upstream.password.as_deref().is_some_and(str::is_empty) {
+                    return Err(AnyTlsError::Config(format!(
+                        "upstreams: password for {} cannot be empty",
+                        upstream.server
+                    )));
+                }
+            }
+        }
+        if let Some(strategy) = &self.strategy {
+            strategy
+                .parse::<LoadBalanceStrategy>()
+                .map_err(|e| AnyTlsError::Config(format!("strategy: {}", config_message(e))))?;
+        }
+        if let Some(metrics_listen) = &self.metrics_listen {
+            validate_address("metrics_listen", metrics_listen)?;
+        }
+        if self.password.as_deref().is_some_and(str::is_empty) {
+            return Err(AnyTlsError::Config("password cannot be empty".into()));
+        }
+        if let Some(pin) = &self.pin {
+            CertPin::certificate(pin)
+                .map_err(|e| AnyTlsError::Config(format!("pin: {}", config_message(e))))?;
+        }
+        if let Some(pin) = &self.pin_spki {
+            CertPin::spki(pin)
+                .map_err(|e| AnyTlsError::Config(format!("pin_spki: {}", config_message(e))))?;
+        }
+        let verification_modes = [
+            self.ca.is_some(),
+            self.pin.is_some(),
+            self.pin_spki.is_some(),
+            self.insecure == Some(true),
+        ];
+        if verification_modes.iter().filter(|set| **set).count() > 1 {
+            return Err(AnyTlsError::Config(
+                "ca, pin, pin_spki and insecure are mutually exclusive".into(),
+            ));
+        }
+        validate_positive(
+            "idle_session_check_interval",
+            self.idle_session_check_interval,
+        )?;
+        validate_positive("idle_session_timeout", self.idle_session_timeout)?;
+        validate_positive("drain_timeout", self.drain_timeout)?;
+        validate_positive("synack_timeout", self.synack_timeout)?;
+        self.cover_traffic_config()?;
+        validate_stream_timeouts(
+            self.connect_timeout,
+            self.dns_timeout,
+            self.stream_idle_timeout,
+            self.max_stream_lifetime,
+        )?;
+        validate_log_level(self.log_level.as_deref())?;
+        Ok(())
+    }
+
+    /// SYNACK, direct connect and relay timeouts, with the defaults of
+    /// [`TimeoutPolicy`] for unset values
+    pub fn timeouts(&self) -> TimeoutPolicy {
+        timeout_policy(
+            self.synack_timeout,
+            self.connect_timeout,
+            self.dns_timeout,
+            self.stream_idle_timeout,
+            self.max_stream_lifetime,
+        )
+    }
+
+    /// Cover traffic settings, if `cover_traffic` or any `cover_traffic_*`
+    /// value is set (and `cover_traffic` is not `false`)
+    ///
+    /// Unset values take the defaults of [`CoverTrafficConfig`].
+    pub fn cover_traffic_config(&self) -> Result<Option<CoverTrafficConfig>> {
+        cover_traffic_config(
+            self.cover_traffic,
+            self.cover_traffic_idle_after,
+            self.cover_traffic_interval_ms.as_deref(),
+            self.cover_traffic_sizes.as_deref(),
+            self.cover_traffic_max_rate,
+        )
+    }
+}
+
+/// One entry of [`ClientFileConfig::upstreams`]
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamConfig {
+    /// Server address
+    pub server: String,
+    /// TLS SNI (defaults to the top-level `sni`, then the server host)
+    pub sni: Option<String>,
+    /// Server password (defaults to the top-level `password`)
+    pub password: Option<String>,
+    /// Padding scheme file (defaults to the top-level `padding_scheme`)
+    pub padding_scheme: Option<PathBuf>,
+}
+
+/// Read a TOML or YAML file, choosing the format by extension
+pub fn load_config_file<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        AnyTlsError::Config(format!("Failed to read config file {:?}: {}", path, e))
+    })?;
+
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("toml") => toml::from_str(&text)
+            .map_err(|e| AnyTlsError::Config(format!("Invalid config file {:?}: {}", path, e))),
+        Some("yaml") | Some("yml") => serde_yaml_ng::from_str(&text)
+            .map_err(|e| AnyTlsError::Config(format!("Invalid config file {:?}: {}", path, e))),
+        _ => Err(AnyTlsError::Config(format!(
+            "Unsupported config file {:?}: expected a .toml, .yaml or .yml extension",
+            path
+        ))),
+    }
+}
+
+fn validate_address(field: &str, value: &str) -> Result<()> {
+    let valid = value
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+    if valid {
+        Ok(())
+    } else {
+        Err(AnyTlsError::Config(format!(
+            "{}: '{}' is not a valid host:port address",
+            field, value
+        )))
+    }
+}
+
+fn validate_inline_users(users: &BTreeMap<String, String>) -> Result<()> {
+    let mut table = UserTable::new();
+    for (name, secret) in users {
+        table
+            .add_user_secret(name, secret)
+            .map_err(|e| AnyTlsError::Config(format!("users: {}", config_message(e))))?;
+    }
+    Ok(())
+}
+
+fn validate_positive(field: &str, value: Option<u64>) -> Result<()> {
+    if value == Some(0) {
+        return Err(AnyTlsError::Config(format!(
+            "{} must be greater than 0",
+            field
+        )));
+    }
+    Ok(())
+}
+
+fn validate_stream_timeouts(
+    connect: Option<u64>,
+    dns: Option<u64>,
+    stream_idle: Option<u64>,
+    stream_lifetime: Option<u64>,
+) -> Result<()> {
+    validate_positive("connect_timeout", connect)?;
+    validate_positive("dns_timeout", dns)?;
+    validate_positive("stream_idle_timeout", stream_idle)?;
+    validate_positive("max_stream_lifetime", stream_lifetime)
+}
+
+fn timeout_policy(
+    synack: Option<u64>,
+    connect: Option<u64>,
+    dns: Option<u64>,
+    stream_idle: Option<u64>,
+    stream_lifetime: Option<u64>,
+) -> TimeoutPolicy {
+    let defaults = TimeoutPolicy::default();
+    let secs = |value: Option<u64>, default| value.map_or(default, Duration::from_secs);
+    TimeoutPolicy {
+        synack: secs(synack, defaults.synack),
+        connect: secs(connect, defaults.connect),
+        dns: secs(dns, defaults.dns),
+        stream_idle: stream_idle.map(Duration::from_secs),
+        stream_lifetime: stream_lifetime.map(Duration::from_secs),
+    }
+}
+
+fn cover_traffic_config(
+    enabled: Option<bool>,
+    idle_after: Option<u64>,
+    interval_ms: Option<&str>,
+    sizes: Option<&str>,
+    max_rate: Option<u32>,
+) -> Result<Option<CoverTrafficConfig>> {
+    let tuned =
+        idle_after.is_some() || interval_ms.is_some() || sizes.is_some() || max_rate.is_some();
+    if !enabled.unwrap_or(tuned) {
+        return Ok(None);
+    }
+    let mut config = CoverTrafficConfig::default();
+    if let Some(secs) = idle_after {
+        config.idle_after = Duration::from_secs(secs);
+    }
+    if let Some(interval) = interval_ms {
+        let invalid = || {
+            AnyTlsError::Config(format!(
+                "cover_traffic_interval_ms: '{}' is not MIN-MAX milliseconds",
+                interval
+            ))
+        };
+        let (min, max) = interval.split_once('-').unwrap_or((interval, interval));
+        let min: u64 = min.trim().parse().map_err(|_| invalid())?;
+        let max: u64 = max.trim().parse().map_err(|_| invalid())?;
+        if min == 0 || min > max {
+            return Err(invalid());
+        }
+        config.min_interval = Duration::from_millis(min);
+        config.max_interval = Duration::from_millis(max);
+    }
+    if let Some(sizes) = sizes {
+        config.sizes = sizes
+            .parse::<SizeDistribution>()
+            .map_err(|e| AnyTlsError::Config(format!("cover_traffic_sizes: {}", e)))?;
+    }
+    if let Some(rate) = max_rate {
+        config.max_bytes_per_sec = rate;
+    }
+    // A frame larger than one second's budget would never be sent
+    let largest = config.sizes.bounds().1 as usize + HEADER_OVERHEAD_SIZE;
+    if (config.max_bytes_per_sec as usize) < largest {
+        return Err(AnyTlsError::Config(format!(
+            "cover_traffic_max_rate must be at least {} bytes per second for the largest cover frame",
+            largest
+        )));
+    }
+    Ok(Some(config))
+}
+
+fn validate_log_level(level: Option<&str>) -> Result<()> {
+    match level {
+        Some(level) if !LOG_LEVELS.contains(&level) => Err(AnyTlsError::Config(format!(
+            "log_level: '{}' is not one of {}",
+            level,
+            LOG_LEVELS.join("|")
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Strip the "Configuration error: " prefix when nesting config errors
+fn config_message(err: AnyTlsError) -> String {
+    match err {
+        AnyTlsError::Config(msg) => msg,
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::hash_password;
+    use std::io::Write;
+
+    fn write_temp(extension: &str, contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new()
+            .suffix(extension)
+            .tempfile()
+            .unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_load_server_config_toml_and_yaml() {
+        let toml = write_temp(
+            ".toml",
+            r#"
+listen = "0.0.0.0:9443"
+password = "secret"
+dns = ["1.1.1.1"]
+idle_session_timeout = 120
+
+[users]
+alice = "alice-password"
+"#,
+        );
+        let yaml = write_temp(
+            ".yaml",
+            r#"
+listen: "0.0.0.0:9443"
+password: secret
+dns: ["1.1.1.1"]
+idle_session_timeout: 120
+users:
+  alice: alice-password
+"#,
+        );
+
+        let from_toml = ServerFileConfig::load(toml.path()).unwrap();
+        let from_yaml = ServerFileConfig::load(yaml.path()).unwrap();
+        assert_eq!(from_toml, from_yaml);
+        assert_eq!(from_toml.listen.as_deref(), Some("0.0.0.0:9443"));
+
+        let table = from_toml.user_table().unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(
+            table
+                .lookup(&hash_password("alice-password"))
+                .unwrap()
+                .name(),
+            "alice"
+        );
+        assert_eq!(
+            table.lookup(&hash_password("secret")).unwrap().name(),
+            crate::util::DEFAULT_USER_NAME
+        );
+    }
+
+    #[test]
+    fn test_invalid_config_errors() {
+        let cases = [
+            (".toml", "listen = \"no-port\""),
+            (".toml", "cert = \"cert.pem\""),
+            (".toml", "idle_session_timeout = 0"),
+            (".toml", "auth_ban_ipv4_prefix = 33"),
+            (".toml", "stream_idle_timeout = 0"),
+            (".toml", "admin_listen = \"127.0.0.1:9102\""),
+            (".toml", "admin_listen = \"unix:\"\nadmin_token = \"t\""),
+            (".toml", "admin_token = \"\""),
+            (".toml", "log_level = \"verbose\""),
+            (".toml", "cover_traffic_interval_ms = \"900-100\""),
+            (".toml", "cover_traffic_sizes = \"big\""),
+            (".toml", "cover_traffic_max_rate = 100"),
+            (".toml", "unknown_field = 1"),
+            (".toml", "listen = ["),
+            (".yaml", "users:\n  alice: \"\""),
+            (".json", "{}"),
+        ];
+        for (extension, contents) in cases {
+            let file = write_temp(extension, contents);
+            let err = ServerFileConfig::load(file.path()).unwrap_err();
+            assert!(
+                matches!(err, AnyTlsError::Config(_)),
+                "{contents}: unexpected error {err}"
+            );
+        }
+
+        let file = write_temp(".toml", "ca = \"ca.pem\"\ninsecure = true");
+        assert!(matches!(
+            ClientFileConfig::load(file.path()),
+            Err(AnyTlsError::Config(_))
+        ));
+        let file = write_temp(".toml", "strategy = \"random\"");
+        assert!(matches!(
+            ClientFileConfig::load(file.path()),
+            Err(AnyTlsError::Config(_))
+        ));
+        let file = write_temp(
+            ".yaml",
+            "server: a.example.com:443\nupstreams:\n  - server: b.example.com:443",
+        );
+        assert!(matches!(
+            ClientFileConfig::load(file.path()),
+            Err(AnyTlsError::Config(_))
+        ));
+        let file = write_temp(".yml", "pin: nothex");
+        assert!(matches!(
+            ClientFileConfig::load(file.path()),
+            Err(AnyTlsError::Config(_))
+        ));
+        let file = write_temp(".toml", "synack_timeout = 0");
+        assert!(matches!(
+            ClientFileConfig::load(file.path()),
+            Err(AnyTlsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_cover_traffic_config() {
+        let off = write_temp(".toml", "");
+        let config = ServerFileConfig::load(off.path()).unwrap();
+        assert_eq!(config.cover_traffic_config().unwrap(), None);
+
+        let tuned = write_temp(
+            ".yaml",
+            "cover_traffic_idle_after: 30\ncover_traffic_interval_ms: 200-500\ncover_traffic_sizes: 100-300\ncover_traffic_max_rate: 2048",
+        );
+        let config = ClientFileConfig::load(tuned.path()).unwrap();
+        let cover = config.cover_traffic_config().unwrap().unwrap();
+        assert_eq!(cover.idle_after, Duration::from_secs(30));
+        assert_eq!(cover.min_interval, Duration::from_millis(200));
+        assert_eq!(cover.max_interval, Duration::from_millis(500));
+        assert_eq!(cover.sizes.bounds(), (100, 300));
+        assert_eq!(cover.max_bytes_per_sec, 2048);
+
+        // Explicitly disabled wins over tuning values; unset values keep defaults
+        let disabled = ClientFileConfig {
+            cover_traffic: Some(false),
+            ..config.clone()
+        };
+        assert_eq!(disabled.cover_traffic_config().unwrap(), None);
+        let fixed = ServerFileConfig {
+            cover_traffic_interval_ms: Some("750".into()),
+            ..ServerFileConfig::default()
+        };
+        let cover = fixed.cover_traffic_config().unwrap().unwrap();
+        assert_eq!(cover.min_interval, cover.max_interval);
+        assert_eq!(
+            cover.max_bytes_per_sec,
+            CoverTrafficConfig::default().max_bytes_per_sec
+        );
+    }
+
+    #[test]
+    fn test_password_conflicts_with_default_user() {
+        let file = write_temp(".txt", "default=file-password\nalice=alice-password\n");
+        let config = ServerFileConfig {
+            users_file: Some(file.path().to_path_buf()),
+            password: Some("cli-password".into()),
+            ..Default::default()
+        };
+        let err = config.user_table().unwrap_err();
+        assert!(
+            matches!(&err, AnyTlsError::Config(msg) if msg.contains("also listed")),
+            "{err}"
+        );
+
+        let config = ServerFileConfig {
+            users: Some(BTreeMap::from([("default".into(), "inline".into())])),
+            password: Some("cli-password".into()),
+            ..Default::default()
+        };
+        assert!(config.user_table().is_err());
+
+        // Without a `default` entry the password is added alongside the table
+        let file = write_temp(".txt", "alice=alice-password\n");
+        let config = ServerFileConfig {
+            users_file: Some(file.path().to_path_buf()),
+            password: Some("cli-password".into()),
+            ..Default::default()
+        };
+        assert_eq!(config.user_table().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_admin_token_file() {
+        let file = write_temp(".txt", "  s3cret  \nignored\n");
+        let config = ServerFileConfig {
+            admin_listen: Some("unix:/run/anytls/admin.sock".into()),
+            admin_token_file: Some(file.path().to_path_buf()),
+            ..Default::default()
+        };
+        config.validate().unwrap();
+        assert_eq!(config.admin_token().unwrap().as_deref(), Some("s3cret"));
+
+        let empty = write_temp(".txt", "\n");
+        let config = ServerFileConfig {
+            admin_token_file: Some(empty.path().to_path_buf()),
+            ..Default::default()
+        };
+        assert!(matches!(config.admin_token(), Err(AnyTlsError::Config(_))));
+    }
+
+    #[test]
+    fn test_load_client_upstreams() {
+        let yaml = write_temp(
+            ".yaml",
+            r#"
+password: shared
+strategy: round-robin
+upstreams:
+  - server: a.example.com:443
+  - server: 203.0.113.7:8443
+    sni: b.example.com
+    password: other
+"#,
+        );
+        let config = ClientFileConfig::load(yaml.path()).unwrap();
+        let upstreams = config.upstreams.as_deref().unwrap();
+        assert_eq!(upstreams.len(), 2);
+        assert_eq!(upstreams[0].password, None);
+        assert_eq!(upstreams[1].sni.as_deref(), Some("b.example.com"));
+        assert_eq!(config.strategy.as_deref(), Some("round-robin"));
+
+        // A server on the command line replaces the configured upstreams
+        let cli = ClientFileConfig {
+            server: Some("c.example.com:443".into()),
+            ..Default::default()
+        };
+        let merged = config.merge(cli);
+        assert_eq!(merged.upstreams, None);
+        assert_eq!(merged.strategy.as_deref(), Some("round-robin"));
+        merged.validate().unwrap();
+    }
+
+    #[test]
+    fn test_cli_overrides_file() {
+        let file = ClientFileConfig {
+            server: Some("example.com:443".into()),
+            password: Some("file-password".into()),
+            insecure: Some(true),
+            min_idle_session: Some(2),
+            stream_idle_timeout: Some(300),
+            ..Default::default()
+        };
+        let cli = ClientFileConfig {
+            password: Some("cli-password".into()),
+            ca: Some(PathBuf::from("ca.pem")),
+            synack_timeout: Some(5),
+            ..Default::default()
+        };
+
+        let merged = file.merge(cli);
+        assert_eq!(merged.server.as_deref(), Some("example.com:443"));
+        assert_eq!(merged.password.as_deref(), Some("cli-password"));
+        assert_eq!(merged.min_idle_session, Some(2));
+        let timeouts = merged.timeouts();
+        assert_eq!(timeouts.synack, Duration::from_secs(5));
+        assert_eq!(timeouts.stream_idle, Some(Duration::from_secs(300)));
+        assert_eq!(timeouts.connect, TimeoutPolicy::default().connect);
+        // Choosing a verification mode on the command line replaces the file's
+        assert_eq!(merged.ca, Some(PathBuf::from("ca.pem")));
+        assert_eq!(merged.insecure, None);
+        assert!(matches!(
+            merged.cert_verification().unwrap(),
+            CertVerification::CaFile(_)
+        ));
+    }
+}
@@ -1,6 +1,6 @@
 //! Simple async DNS cache to reduce repeated lookups for popular domains.
 
-use crate::util::{AnyTlsError, Result};
+use crate::util::{AnyTlsError, DEFAULT_DNS_TIMEOUT, METRICS, Result};
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
@@ -15,8 +15,6 @@ use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, Res
 
 /// TTL for cached DNS entries.
 const DEFAULT_TTL: Duration = Duration::from_secs(60);
-/// Timeout for DNS lookup operations.
-const DNS_TIMEOUT: Duration = Duration::from_secs(10);
 
 static DNS_CACHE: Lazy<DnsCache> = Lazy::new(DnsCache::new);
 static DNS_RESOLVER: Lazy<RwLock<Option<Arc<TokioAsyncResolver>>>> =
@@ -80,24 +78,34 @@ impl DnsCache {
 
 /// Resolve a hostname with caching and timeout.
 pub async fn resolve_host_with_cache(host: &str, port: u16) -> Result<SocketAddr> {
+    resolve_host_with_timeout(host, port, DEFAULT_DNS_TIMEOUT).await
+}
+
+/// Resolve a hostname with caching, giving up after `dns_timeout`.
+pub async fn resolve_host_with_timeout(
+    host: &str,
+    port: u16,
+    dns_timeout: Duration,
+) -> Result<SocketAddr> {
     if let Ok(ip) = host.parse::<IpAddr>() {
         return Ok(SocketAddr::new(ip, port));
     }
 
     if let Some(addr) = DNS_CACHE.get(host).await {
+        METRICS.dns_cache_hits.inc();
         DNS_CACHE.advance(host).await;
         return Ok(addr);
     }
+    METRICS.dns_cache_misses.inc();
 
     let resolver_opt = DNS_RESOLVER.read().await.clone();
     let mut addresses: Vec<SocketAddr> = if let Some(resolver) = resolver_opt {
-        let lookup = tokio::time::timeout(DNS_TIMEOUT, resolver.lookup_ip(host))
+        let lookup = tokio::time::timeout(dns_timeout, resolver.lookup_ip(host))
             .await
             .map_err(|_| {
                 AnyTlsError::Protocol(format!(
-                    "DNS resolution timeout ({}s) for {}",
-                    DNS_TIMEOUT.as_secs(),
-                    host
+                    "DNS resolution timeout ({:?}) for {}",
+                    dns_timeout, host
                 ))
             })?
             .map_err(|err| {
@@ -113,13 +121,12 @@ pub async fn resolve_host_with_cache(host: &str, port: u16) -> Result<SocketAddr
         addrs
     } else {
         let lookup_future = lookup_host((host, port));
-        tokio::time::timeout(DNS_TIMEOUT, lookup_future)
+        tokio::time::timeout(dns_timeout, lookup_future)
             .await
             .map_err(|_| {
                 AnyTlsError::Protocol(format!(
-                    "DNS resolution timeout ({}s) for {}",
-                    DNS_TIMEOUT.as_secs(),
-                    host
+                    "DNS resolution timeout ({:?}) for {}",
+                    dns_timeout, host
                 ))
             })?
             .map_err(|err| {
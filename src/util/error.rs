@@ -38,6 +38,14 @@ pub enum AnyTlsError {
     /// Configuration error
     #[error("Configuration error: {0}")]
     Config(String),
+
+    /// Destination rejected by the outbound access control list
+    #[error("Access denied: {0}")]
+    AccessDenied(String),
+
+    /// Peer did not complete a step in time
+    #[error("Timed out: {0}")]
+    Timeout(String),
 }
 
 /// Result type alias
@@ -0,0 +1,191 @@
+//! Destination matchers shared by the server ACL and the client router
+//!
+//! Both rule engines match destinations by IP network, port set and domain
+//! suffix; the primitives live here so the two parsers accept the same
+//! syntax.
+
+use crate::util::{AnyTlsError, Result};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// An IP network in CIDR notation, e.g. `10.0.0.0/8` or `fc00::/7`
+///
+/// A bare address parses as a single-host network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpCidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    /// Create a network, masking off host bits of `addr`
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return Err(AnyTlsError::Config(format!(
+                "Invalid prefix length /{} for {}",
+                prefix, addr
+            )));
+        }
+        let network = match addr {
+            IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from(u32::from(v4) & v4_mask(prefix))),
+            IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & v6_mask(prefix))),
+        };
+        Ok(Self { network, prefix })
+    }
+
+    /// Whether `ip` lies inside this network
+    ///
+    /// IPv4-mapped IPv6 addresses are compared as IPv4.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                u32::from(ip) & v4_mask(self.prefix) == u32::from(net)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                u128::from(ip) & v6_mask(self.prefix) == u128::from(net)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = AnyTlsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| AnyTlsError::Config(format!("Invalid IP address: {}", s)))?;
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .map_err(|_| AnyTlsError::Config(format!("Invalid prefix length: {}", s)))?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Self::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+fn v4_mask(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+}
+
+fn v6_mask(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0)
+}
+
+/// Set of ports written as `443`, `8000-8080` or a comma separated list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortSet(Vec<RangeInclusive<u16>>);
+
+impl PortSet {
+    /// Whether `port` is in the set
+    pub fn contains(&self, port: u16) -> bool {
+        self.0.iter().any(|range| range.contains(&port))
+    }
+}
+
+impl FromStr for PortSet {
+    type Err = AnyTlsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || AnyTlsError::Config(format!("Invalid port range '{}'", s));
+        s.split(',')
+            .map(|part| {
+                let (low, high) = part.split_once('-').unwrap_or((part, part));
+                let low: u16 = low.trim().parse().map_err(|_| invalid())?;
+                let high: u16 = high.trim().parse().map_err(|_| invalid())?;
+                if low > high {
+                    return Err(invalid());
+                }
+                Ok(low..=high)
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+/// Lower-case `domain` and strip surrounding whitespace and the root dot
+pub fn normalize_domain(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Parse a domain suffix pattern (`example.org`, `.example.org` or
+/// `*.example.org`) into its normalized form
+pub fn parse_domain_suffix(pattern: &str) -> Result<String> {
+    let suffix = normalize_domain(pattern.trim_start_matches("*.").trim_start_matches('.'));
+    if suffix.is_empty()
+        || !suffix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_')
+    {
+        return Err(AnyTlsError::Config(format!(
+            "Invalid domain pattern '{}'",
+            pattern
+        )));
+    }
+    Ok(suffix)
+}
+
+/// Whether `domain` equals `suffix` or is a subdomain of it
+///
+/// `suffix` must already be normalized (see [`parse_domain_suffix`]).
+pub fn domain_matches_suffix(domain: &str, suffix: &str) -> bool {
+    let domain = normalize_domain(domain);
+    domain == suffix
+        || domain
+            .strip_suffix(suffix)
+            .is_some_and(|head| head.ends_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        let cidr: IpCidr = "10.1.2.3/16".parse().unwrap();
+        assert_eq!(cidr.to_string(), "10.1.0.0/16");
+        assert!(cidr.contains(ip("10.1.255.1")));
+        assert!(cidr.contains(ip("::ffff:10.1.0.9")));
+        assert!(!cidr.contains(ip("10.2.0.1")));
+        assert!(!cidr.contains(ip("::1")));
+
+        let all: IpCidr = "::/0".parse().unwrap();
+        assert!(all.contains(ip("2001:db8::1")));
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+    }
+
+    #[test]
+    fn test_port_set_and_domain_suffix() {
+        let ports: PortSet = "22,8000-8080".parse().unwrap();
+        assert!(ports.contains(22) && ports.contains(8080));
+        assert!(!ports.contains(80));
+        assert!("80-20".parse::<PortSet>().is_err());
+        assert!("http".parse::<PortSet>().is_err());
+
+        let suffix = parse_domain_suffix("*.Example.org").unwrap();
+        assert!(domain_matches_suffix("www.example.ORG.", &suffix));
+        assert!(domain_matches_suffix("example.org", &suffix));
+        assert!(!domain_matches_suffix("notexample.org", &suffix));
+        assert!(parse_domain_suffix("bad_host!").is_err());
+    }
+}
@@ -0,0 +1,429 @@
+//! Process-wide metrics registry with a Prometheus text endpoint
+//!
+//! Counters and gauges are plain atomics in a static registry, so recording a
+//! value costs one atomic operation and needs no handle threading. Both
+//! binaries can expose the registry on a local HTTP listener in the
+//! Prometheus text exposition format (`GET /metrics`).
+
+use crate::util::{AnyTlsError, Result};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, timeout};
+
+/// Global metrics registry
+pub static METRICS: Metrics = Metrics::new();
+
+/// Maximum size of an HTTP request head accepted by the local endpoints
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+/// Timeout for reading a request from a local endpoint client
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Monotonically increasing counter
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    /// Increment the counter by one
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    /// Increment the counter by `n`
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Current value
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Gauge that can go up and down
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicI64,
+}
+
+impl Gauge {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicI64::new(0),
+        }
+    }
+
+    /// Increment the gauge by one
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Decrement the gauge by one
+    pub fn dec(&self) {
+        self.value.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Current value
+    pub fn get(&self) -> i64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Keeps a gauge incremented for as long as the guard is alive
+///
+/// Used to track live objects such as sessions and streams.
+pub struct GaugeGuard(&'static Gauge);
+
+impl GaugeGuard {
+    pub fn new(gauge: &'static Gauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+type ValueSource = Box<dyn Fn() -> u64 + Send + Sync>;
+
+/// All metrics exported by the server and client
+pub struct Metrics {
+    /// Sessions currently alive
+    pub sessions_active: Gauge,
+    /// Streams currently alive
+    pub streams_active: Gauge,
+    /// Stream payload bytes received from the peer
+    pub bytes_in: Counter,
+    /// Stream payload bytes sent to the peer
+    pub bytes_out: Counter,
+    /// Connections that failed password authentication (server)
+    pub auth_failures: Counter,
+    /// Connections that did not finish the TLS handshake in time (server)
+    pub tls_handshake_timeouts: Counter,
+    /// Connections that did not finish authentication in time (server)
+    pub auth_timeouts: Counter,
+    /// Sessions that sent no frame in time after authentication (server)
+    pub first_frame_timeouts: Counter,
+    /// Connections closed because their source is banned (server)
+    pub banned_connections: Counter,
+    /// Streams whose SYNACK reported a connection error
+    pub synack_errors: Counter,
+    /// Streams whose SYNACK did not arrive or connect in time
+    pub synack_timeouts: Counter,
+    /// Relayed streams closed by the idle timeout or maximum lifetime
+    pub stream_timeouts: Counter,
+    /// Streams reset because their unread data exceeded the receive budget
+    pub stream_overflows: Counter,
+    /// Times a session stopped reading because its receive budget was full
+    pub receive_pauses: Counter,
+    /// Idle sessions kept in the session pool (client)
+    pub pool_idle_sessions: Gauge,
+    /// DNS lookups answered from the cache
+    pub dns_cache_hits: Counter,
+    /// DNS lookups that had to query the resolver
+    pub dns_cache_misses: Counter,
+    /// Cover traffic bytes sent on idle sessions, frame headers included
+    pub cover_bytes: Counter,
+    cert_reloads: RwLock<Option<ValueSource>>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            sessions_active: Gauge::new("anytls_sessions_active", "Number of active sessions"),
+            streams_active: Gauge::new("anytls_streams_active", "Number of active streams"),
+            bytes_in: Counter::new(
+                "anytls_bytes_in_total",
+                "Stream payload bytes received from the peer",
+            ),
+            bytes_out: Counter::new(
+                "anytls_bytes_out_total",
+                "Stream payload bytes sent to the peer",
+            ),
+            auth_failures: Counter::new(
+                "anytls_auth_failures_total",
+                "Connections that failed authentication",
+            ),
+            tls_handshake_timeouts: Counter::new(
+                "anytls_tls_handshake_timeouts_total",
+                "Connections that timed out in the TLS handshake",
+            ),
+            auth_timeouts: Counter::new(
+                "anytls_auth_timeouts_total",
+                "Connections that timed out in authentication",
+            ),
+            first_frame_timeouts: Counter::new(
+                "anytls_first_frame_timeouts_total",
+                "Sessions that sent no frame in time after authentication",
+            ),
+            banned_connections: Counter::new(
+                "anytls_banned_connections_total",
+                "Connections closed because their source is banned",
+            ),
+            synack_errors: Counter::new(
+                "anytls_synack_errors_total",
+                "Streams whose SYNACK reported an error",
+            ),
+            synack_timeouts: Counter::new(
+                "anytls_synack_timeouts_total",
+                "Streams whose SYNACK timed out",
+            ),
+            stream_timeouts: Counter::new(
+                "anytls_stream_timeouts_total",
+                "Relayed streams closed by the idle timeout or lifetime limit",
+            ),
+            stream_overflows: Counter::new(
+                "anytls_stream_overflows_total",
+                "Streams reset for exceeding the receive buffer",
+            ),
+            receive_pauses: Counter::new(
+                "anytls_receive_pauses_total",
+                "Times a session paused reading until slow streams caught up",
+            ),
+            pool_idle_sessions: Gauge::new(
+                "anytls_pool_idle_sessions",
+                "Idle sessions in the client session pool",
+            ),
+            dns_cache_hits: Counter::new("anytls_dns_cache_hits_total", "DNS cache hits"),
+            dns_cache_misses: Counter::new("anytls_dns_cache_misses_total", "DNS cache misses"),
+            cover_bytes: Counter::new(
+                "anytls_cover_bytes_total",
+                "Cover traffic bytes sent on idle sessions",
+            ),
+            cert_reloads: RwLock::new(None),
+        }
+    }
+
+    /// Report the certificate reload count from `source`
+    ///
+    /// The server binary registers `CertReloader::get_reload_count` here; the
+    /// metric is omitted until a source is set.
+    pub fn set_cert_reload_source<F>(&self, source: F)
+    where
+        F: Fn() -> u64 + Send + Sync + 'static,
+    {
+        *self.cert_reloads.write().unwrap() = Some(Box::new(source));
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for gauge in [&self.sessions_active, &self.streams_active] {
+            write_metric(&mut out, gauge.name, gauge.help, "gauge", gauge.get());
+        }
+        for counter in [
+            &self.bytes_in,
+            &self.bytes_out,
+            &self.auth_failures,
+            &self.tls_handshake_timeouts,
+            &self.auth_timeouts,
+            &self.first_frame_timeouts,
+            &self.banned_connections,
+            &self.synack_errors,
+            &self.synack_timeouts,
+            &self.stream_timeouts,
+            &self.stream_overflows,
+            &self.receive_pauses,
+        ] {
+            write_metric(
+                &mut out,
+                counter.name,
+                counter.help,
+                "counter",
+                counter.get(),
+            );
+        }
+        let pool = &self.pool_idle_sessions;
+        write_metric(&mut out, pool.name, pool.help, "gauge", pool.get());
+        for counter in [
+            &self.dns_cache_hits,
+            &self.dns_cache_misses,
+            &self.cover_bytes,
+        ] {
+            write_metric(
+                &mut out,
+                counter.name,
+                counter.help,
+                "counter",
+                counter.get(),
+            );
+        }
+        if let Some(source) = self.cert_reloads.read().unwrap().as_ref() {
+            write_metric(
+                &mut out,
+                "anytls_cert_reloads_total",
+                "Successful certificate reloads",
+                "counter",
+                source(),
+            );
+        }
+        out
+    }
+}
+
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    kind: &str,
+    value: impl std::fmt::Display,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Bind `addr` and serve `GET /metrics` in a background task
+///
+/// Returns the bound address (useful with port 0) and the task handle.
+pub async fn spawn_metrics_server(addr: &str) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(addr).await.map_err(|e| {
+        AnyTlsError::Config(format!("Failed to bind metrics listener {}: {}", addr, e))
+    })?;
+    let local_addr = listener.local_addr()?;
+    tracing::info!("[Metrics] Serving metrics on http://{}/metrics", local_addr);
+
+    let handle = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, peer)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_metrics_request(socket).await {
+                            tracing::debug!("[Metrics] Request from {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!("[Metrics] Failed to accept connection: {}", e);
+                }
+            }
+        }
+    });
+
+    Ok((local_addr, handle))
+}
+
+async fn handle_metrics_request(mut socket: TcpStream) -> Result<()> {
+    let Some(head) = read_request_head(&mut socket).await? else {
+        return Ok(());
+    };
+    let request_line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+
+    let (status, body) = match (method, path) {
+        (b"GET", b"/metrics") => ("200 OK", METRICS.render()),
+        (b"GET", _) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+    write_response(
+        &mut socket,
+        status,
+        "text/plain; version=0.0.4; charset=utf-8",
+        &body,
+    )
+    .await
+}
+
+/// Read an HTTP request head, up to and including the blank line
+///
+/// Returns `None` if the client closes the connection first; a request body
+/// is not read.
+pub(crate) async fn read_request_head<S>(socket: &mut S) -> Result<Option<Vec<u8>>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = timeout(REQUEST_TIMEOUT, socket.read(&mut buf))
+            .await
+            .map_err(|_| AnyTlsError::Protocol("HTTP request timed out".into()))??;
+        if n == 0 {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(AnyTlsError::Protocol("HTTP request too large".into()));
+        }
+    }
+    Ok(Some(head))
+}
+
+/// Write a complete HTTP/1.1 response and close the write side
+pub(crate) async fn write_response<S>(
+    socket: &mut S,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_exposition_format() {
+        let metrics = Metrics::new();
+        metrics.bytes_in.add(42);
+        metrics.sessions_active.inc();
+        metrics.sessions_active.inc();
+        metrics.sessions_active.dec();
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE anytls_bytes_in_total counter\nanytls_bytes_in_total 42\n"));
+        assert!(text.contains("# TYPE anytls_sessions_active gauge\nanytls_sessions_active 1\n"));
+        assert!(!text.contains("anytls_cert_reloads_total"));
+
+        metrics.set_cert_reload_source(|| 3);
+        assert!(metrics.render().contains("anytls_cert_reloads_total 3\n"));
+    }
+
+    #[test]
+    fn test_gauge_guard_tracks_lifetime() {
+        static GAUGE: Gauge = Gauge::new("test_gauge", "test");
+        let first = GaugeGuard::new(&GAUGE);
+        let second = GaugeGuard::new(&GAUGE);
+        assert_eq!(GAUGE.get(), 2);
+        drop(first);
+        assert_eq!(GAUGE.get(), 1);
+        drop(second);
+        assert_eq!(GAUGE.get(), 0);
+    }
+}
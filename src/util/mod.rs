@@ -3,19 +3,36 @@ pub mod auth;
 pub mod cert_analyzer;
 /// Certificate reloader with hot reload support
 pub mod cert_reloader;
+/// Configuration files for the server and client binaries
+pub mod config;
 pub mod dns_cache;
 /// Error types and Result alias
 pub mod error;
+/// Destination matchers (CIDR, ports, domain suffix) for rule engines
+pub mod matcher;
+/// Metrics registry and Prometheus text endpoint
+pub mod metrics;
 pub mod net;
+/// Graceful shutdown handle and drain helpers
+pub mod shutdown;
 /// String-based key-value map implementation
 pub mod string_map;
+pub mod timeouts;
 pub mod tls;
+/// User table for multi-user authentication
+pub mod users;
 
 pub use auth::*;
 pub use cert_analyzer::*;
 pub use cert_reloader::*;
+pub use config::*;
 pub use dns_cache::*;
 pub use error::*;
+pub use matcher::*;
+pub use metrics::*;
 pub use net::*;
+pub use shutdown::*;
 pub use string_map::*;
+pub use timeouts::*;
 pub use tls::*;
+pub use users::*;
@@ -0,0 +1,211 @@
+//! Graceful shutdown support for the server and client listeners
+//!
+//! A [`ShutdownHandle`] is shared between the code that decides to stop
+//! (typically a signal handler) and the listeners. Once triggered, listeners
+//! stop accepting, give in-flight work up to the drain timeout to finish and
+//! then force-close whatever is left.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+use tokio_util::sync::CancellationToken;
+
+/// Default time in-flight streams get to finish after shutdown is triggered
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Extra time granted to tasks that must close themselves at the deadline
+const FORCE_CLOSE_GRACE: Duration = Duration::from_secs(5);
+
+/// Cloneable handle used to trigger and observe a graceful shutdown
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    token: CancellationToken,
+    drain_timeout: Duration,
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownHandle {
+    /// Create a handle with the default drain timeout
+    pub fn new() -> Self {
+        Self {
+            token: CancellationToken::new(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+        }
+    }
+
+    /// Set how long active streams may keep running after shutdown
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Drain deadline, measured from the moment shutdown is triggered
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    /// Trigger shutdown (idempotent)
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    /// Whether shutdown has been triggered
+    pub fn is_shutdown(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Wait until shutdown is triggered
+    pub async fn triggered(&self) {
+        self.token.cancelled().await;
+    }
+
+    /// Wait for `tasks` to finish within the drain timeout, then abort the rest
+    pub(crate) async fn drain_tasks(&self, tasks: &mut JoinSet<()>, tag: &str) {
+        let remaining = tasks.len();
+        if remaining == 0 {
+            return;
+        }
+        tracing::info!(
+            "[{}] Draining {} connection(s) (timeout {}s)",
+            tag,
+            remaining,
+            self.drain_timeout.as_secs()
+        );
+        let drained = timeout(self.drain_timeout, async {
+            while tasks.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            tracing::warn!(
+                "[{}] Drain timeout reached, closing {} connection(s)",
+                tag,
+                tasks.len()
+            );
+            tasks.abort_all();
+            while tasks.join_next().await.is_some() {}
+        }
+    }
+
+    /// Like [`drain_tasks`](Self::drain_tasks), for tasks that close
+    /// themselves at the drain deadline and only need a short grace period
+    pub(crate) async fn join_tasks(&self, tasks: &mut JoinSet<()>, tag: &str) {
+        let limit = self.drain_timeout + FORCE_CLOSE_GRACE;
+        if timeout(limit, async { while tasks.join_next().await.is_some() {} })
+            .await
+            .is_err()
+        {
+            tracing::warn!("[{}] Aborting {} stuck connection(s)", tag, tasks.len());
+            tasks.abort_all();
+            while tasks.join_next().await.is_some() {}
+        }
+    }
+}
+
+/// Counts in-flight work (e.g. streams of a session) so it can be drained
+#[derive(Clone, Default)]
+pub struct ActivityTracker {
+    inner: Arc<TrackerInner>,
+}
+
+#[derive(Default)]
+struct TrackerInner {
+    active: AtomicUsize,
+    idle: Notify,
+}
+
+impl ActivityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register one unit of work; it ends when the guard is dropped
+    pub fn track(&self) -> ActivityGuard {
+        self.inner.active.fetch_add(1, Ordering::AcqRel);
+        ActivityGuard {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    /// Number of units currently in flight
+    pub fn active(&self) -> usize {
+        self.inner.active.load(Ordering::Acquire)
+    }
+
+    /// Wait until no work is in flight
+    pub async fn wait_idle(&self) {
+        loop {
+            let notified = self.inner.idle.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.active() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Guard returned by [`ActivityTracker::track`]
+pub struct ActivityGuard {
+    inner: Arc<TrackerInner>,
+}
+
+impl Drop for ActivityGuard {
+    fn drop(&mut self) {
+        if self.inner.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.inner.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_activity_tracker_wait_idle() {
+        let tracker = ActivityTracker::new();
+        tracker.wait_idle().await;
+
+        let guard = tracker.track();
+        assert_eq!(tracker.active(), 1);
+        assert!(
+            timeout(Duration::from_millis(50), tracker.wait_idle())
+                .await
+                .is_err()
+        );
+
+        let waiter = {
+            let tracker = tracker.clone();
+            tokio::spawn(async move { tracker.wait_idle().await })
+        };
+        drop(guard);
+        timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("tracker should become idle")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drain_tasks_aborts_after_timeout() {
+        let shutdown = ShutdownHandle::new().with_drain_timeout(Duration::from_millis(50));
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async {});
+        tasks.spawn(async { tokio::time::sleep(Duration::from_secs(60)).await });
+
+        timeout(
+            Duration::from_secs(1),
+            shutdown.drain_tasks(&mut tasks, "Test"),
+        )
+        .await
+        .expect("drain must respect its timeout");
+        assert!(tasks.is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+//! Timeouts shared by the client and server
+//!
+//! A [`TimeoutPolicy`] gathers the deadlines for opening a stream (SYNACK,
+//! outbound connect, DNS) and the limits that reclaim relayed streams: an
+//! idle timeout, reset by traffic in either direction, and a maximum
+//! lifetime. Both relay limits are off by default.
+
+use crate::util::{AnyTlsError, Result};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{Duration, Instant, sleep_until};
+
+/// Deadlines applied by a [`Client`](crate::client::Client) or
+/// [`Server`](crate::server::Server)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutPolicy {
+    /// Time the client waits for the server to report the outbound
+    /// connection (SYNACK)
+    pub synack: Duration,
+    /// TCP connect to a destination: by the server for proxied streams, by
+    /// the client for `direct` routes
+    pub connect: Duration,
+    /// DNS lookup of a destination
+    pub dns: Duration,
+    /// Close a relayed stream after this long without traffic
+    pub stream_idle: Option<Duration>,
+    /// Close a relayed stream this long after it was opened
+    pub stream_lifetime: Option<Duration>,
+}
+
+impl Default for TimeoutPolicy {
+    fn default() -> Self {
+        Self {
+            synack: Duration::from_secs(30),
+            connect: Duration::from_secs(15),
+            dns: DEFAULT_DNS_TIMEOUT,
+            stream_idle: None,
+            stream_lifetime: None,
+        }
+    }
+}
+
+/// DNS lookup timeout when no [`TimeoutPolicy`] applies
+pub const DEFAULT_DNS_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl fmt::Display for TimeoutPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn show(limit: Option<Duration>) -> String {
+            limit.map_or_else(|| "none".to_string(), |d| format!("{:?}", d))
+        }
+        write!(
+            f,
+            "SYNACK {:?}, connect {:?}, DNS {:?}, stream idle {}, stream lifetime {}",
+            self.synack,
+            self.connect,
+            self.dns,
+            show(self.stream_idle),
+            show(self.stream_lifetime)
+        )
+    }
+}
+
+/// Why a relayed stream was closed by its [`RelayTimer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RelayExpiry {
+    /// No traffic for the idle timeout
+    Idle(Duration),
+    /// Maximum lifetime reached
+    Lifetime(Duration),
+}
+
+impl fmt::Display for RelayExpiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Idle(limit) => write!(f, "idle for {:?}", limit),
+            Self::Lifetime(limit) => write!(f, "lifetime of {:?} reached", limit),
+        }
+    }
+}
+
+/// Idle and lifetime deadlines of one relayed stream
+///
+/// Both relay directions share a timer and [`touch`](Self::touch) it for
+/// every transfer; the relay stops once [`expired`](Self::expired) resolves.
+#[derive(Clone)]
+pub(crate) struct RelayTimer {
+    started: Instant,
+    /// Milliseconds from `started` to the last transfer
+    last_activity_ms: Arc<AtomicU64>,
+    idle: Option<Duration>,
+    lifetime: Option<Duration>,
+}
+
+impl RelayTimer {
+    pub(crate) fn new(policy: &TimeoutPolicy) -> Self {
+        Self {
+            started: Instant::now(),
+            last_activity_ms: Arc::new(AtomicU64::new(0)),
+            idle: policy.stream_idle,
+            lifetime: policy.stream_lifetime,
+        }
+    }
+
+    /// Record traffic, restarting the idle timeout
+    pub(crate) fn touch(&self) {
+        if self.idle.is_some() {
+            let elapsed = self.started.elapsed().as_millis() as u64;
+            self.last_activity_ms.fetch_max(elapsed, Ordering::Relaxed);
+        }
+    }
+
+    /// Resolve once the stream was idle too long or reached its lifetime;
+    /// never resolves if neither limit is set
+    pub(crate) async fn expired(&self) -> RelayExpiry {
+        loop {
+            let idle_deadline = self.idle.map(|idle| {
+                let last = self.last_activity_ms.load(Ordering::Relaxed);
+                self.started + Duration::from_millis(last) + idle
+            });
+            let lifetime_deadline = self.lifetime.map(|lifetime| self.started + lifetime);
+            let deadline = match (idle_deadline, lifetime_deadline) {
+                (Some(idle), Some(lifetime)) => idle.min(lifetime),
+                (Some(deadline), None) | (None, Some(deadline)) => deadline,
+                (None, None) => return std::future::pending().await,
+            };
+            sleep_until(deadline).await;
+
+            let now = Instant::now();
+            if let (Some(lifetime), Some(deadline)) = (self.lifetime, lifetime_deadline)
+                && now >= deadline
+            {
+                return RelayExpiry::Lifetime(lifetime);
+            }
+            if let Some(idle) = self.idle {
+                let last = self.last_activity_ms.load(Ordering::Relaxed);
+                if now >= self.started + Duration::from_millis(last) + idle {
+                    return RelayExpiry::Idle(idle);
+                }
+            }
+            // Traffic arrived while sleeping; wait for the new deadline
+        }
+    }
+
+    /// Relay between two plain connections until both directions finish,
+    /// like [`tokio::io::copy_bidirectional`] but stopped by this timer
+    ///
+    /// Returns the bytes sent from `a` to `b` and from `b` to `a`, or
+    /// [`AnyTlsError::Timeout`] once the timer expires.
+    pub(crate) async fn copy_bidirectional<A, B>(&self, a: &mut A, b: &mut B) -> Result<(u64, u64)>
+    where
+        A: AsyncRead + AsyncWrite + Unpin,
+        B: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (mut a_read, mut a_write) = tokio::io::split(a);
+        let (mut b_read, mut b_write) = tokio::io::split(b);
+        let relay = async {
+            tokio::try_join!(
+                self.copy(&mut a_read, &mut b_write),
+                self.copy(&mut b_read, &mut a_write)
+            )
+        };
+        tokio::select! {
+            result = relay => Ok(result?),
+            expiry = self.expired() => Err(AnyTlsError::Timeout(format!("stream {}", expiry))),
+        }
+    }
+
+    /// Copy `reader` to `writer` until EOF, which is passed on as a shutdown
+    async fn copy<R, W>(&self, reader: &mut R, writer: &mut W) -> std::io::Result<u64>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = vec![0u8; 8192];
+        let mut total = 0;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                writer.shutdown().await?;
+                return Ok(total);
+            }
+            writer.write_all(&buf[..n]).await?;
+            total += n as u64;
+            self.touch();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_relay_timer_expiry() {
+        let policy = TimeoutPolicy {
+            stream_idle: Some(Duration::from_secs(10)),
+            stream_lifetime: Some(Duration::from_secs(25)),
+            ..TimeoutPolicy::default()
+        };
+
+        // Traffic keeps pushing the idle deadline back until the lifetime ends
+        let timer = RelayTimer::new(&policy);
+        let toucher = timer.clone();
+        let traffic = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                toucher.touch();
+            }
+        });
+        let started = Instant::now();
+        assert_eq!(
+            timer.expired().await,
+            RelayExpiry::Lifetime(Duration::from_secs(25))
+        );
+        assert_eq!(started.elapsed(), Duration::from_secs(25));
+        traffic.abort();
+
+        // Without traffic the idle timeout fires first
+        let timer = RelayTimer::new(&policy);
+        let started = Instant::now();
+        assert_eq!(
+            timer.expired().await,
+            RelayExpiry::Idle(Duration::from_secs(10))
+        );
+        assert_eq!(started.elapsed(), Duration::from_secs(10));
+
+        // No limits: never expires
+        let timer = RelayTimer::new(&TimeoutPolicy::default());
+        assert!(
+            tokio::time::timeout(Duration::from_secs(3600), timer.expired())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_copy_bidirectional_stops_when_idle() {
+        let policy = TimeoutPolicy {
+            stream_idle: Some(Duration::from_secs(10)),
+            ..TimeoutPolicy::default()
+        };
+        let (mut client, mut a) = tokio::io::duplex(1024);
+        let (mut b, mut server) = tokio::io::duplex(1024);
+        let timer = RelayTimer::new(&policy);
+        let relay = tokio::spawn(async move { timer.copy_bidirectional(&mut a, &mut b).await });
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // Neither side sends or closes: the relay gives up after the idle timeout
+        let started = Instant::now();
+        let result = relay.await.unwrap();
+        assert!(matches!(result, Err(AnyTlsError::Timeout(_))));
+        assert_eq!(started.elapsed(), Duration::from_secs(10));
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    }
+}
@@ -1,9 +1,12 @@
 //! TLS utilities for certificate generation and configuration
 
-use crate::util::{AnyTlsError, Result};
+use crate::util::{AnyTlsError, Result, parse_sha256_hex};
+use rustls::crypto::CryptoProvider;
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use rustls::server::ServerConfig;
 use rustls::{ClientConfig, RootCertStore};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::{fs::File, io::BufReader, path::Path};
 
@@ -59,7 +62,14 @@ pub fn generate_key_pair_with_name(
 /// Create a server TLS config with a generated certificate
 pub fn create_server_config() -> Result<Arc<ServerConfig>> {
     let (cert, key) = generate_key_pair()?;
+    create_server_config_with_cert(cert, key)
+}
 
+/// Create a server TLS config from an in-memory certificate and private key
+pub fn create_server_config_with_cert(
+    cert: CertificateDer<'static>,
+    key: PrivateKeyDer<'static>,
+) -> Result<Arc<ServerConfig>> {
     let config = ServerConfig::builder()
         .with_no_client_auth()
         .with_single_cert(vec![cert], key)?;
@@ -154,22 +164,207 @@ impl rustls::client::danger::ServerCertVerifier for NoCertificateVerification {
     }
 }
 
-/// Create a client TLS config with insecure verification (for testing)
-/// This accepts self-signed certificates, similar to Go's InsecureSkipVerify: true
-pub fn create_client_config() -> Result<Arc<ClientConfig>> {
-    let root_store = RootCertStore::empty();
+/// SHA256 pin of the server certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertPin {
+    /// SHA256 of the whole DER encoded leaf certificate
+    Certificate([u8; 32]),
+    /// SHA256 of the leaf certificate's DER encoded SubjectPublicKeyInfo,
+    /// which survives certificate renewal with the same key
+    Spki([u8; 32]),
+}
+
+impl CertPin {
+    /// Parse a certificate pin from 64 hex characters (colons allowed)
+    pub fn certificate(fingerprint: &str) -> Result<Self> {
+        parse_fingerprint(fingerprint).map(CertPin::Certificate)
+    }
+
+    /// Parse an SPKI pin from 64 hex characters (colons allowed)
+    pub fn spki(fingerprint: &str) -> Result<Self> {
+        parse_fingerprint(fingerprint).map(CertPin::Spki)
+    }
+
+    /// Check whether the pin matches a DER encoded certificate
+    pub fn matches(&self, cert: &CertificateDer<'_>) -> bool {
+        match self {
+            CertPin::Certificate(expected) => certificate_fingerprint(cert) == *expected,
+            CertPin::Spki(expected) => {
+                spki_fingerprint(cert).is_ok_and(|actual| actual == *expected)
+            }
+        }
+    }
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32]> {
+    let hex: String = fingerprint.trim().chars().filter(|c| *c != ':').collect();
+    parse_sha256_hex(&hex).ok_or_else(|| {
+        AnyTlsError::Config(format!(
+            "invalid SHA256 fingerprint '{}': expected 64 hex characters",
+            fingerprint
+        ))
+    })
+}
+
+/// SHA256 fingerprint of a DER encoded certificate
+pub fn certificate_fingerprint(cert: &CertificateDer<'_>) -> [u8; 32] {
+    Sha256::digest(cert.as_ref()).into()
+}
+
+/// SHA256 fingerprint of a certificate's SubjectPublicKeyInfo
+pub fn spki_fingerprint(cert: &CertificateDer<'_>) -> Result<[u8; 32]> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref())
+        .map_err(|e| AnyTlsError::Tls(format!("failed to parse certificate: {e}")))?;
+    Ok(Sha256::digest(parsed.tbs_certificate.subject_pki.raw).into())
+}
 
-    // For testing, we'll accept any certificate
-    // In production, you should load proper root certificates
+/// Format a fingerprint as lowercase hex, as accepted by [`CertPin`]
+pub fn format_fingerprint(fingerprint: &[u8; 32]) -> String {
+    fingerprint.iter().map(|b| format!("{:02x}", b)).collect()
+}
 
-    let mut config = ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
+/// How the client verifies the server certificate
+#[derive(Debug, Clone, Default)]
+pub enum CertVerification {
+    /// Verify against the bundled Mozilla root certificates (webpki-roots)
+    #[default]
+    WebPki,
+    /// Verify against the CA certificates in a PEM bundle
+    CaFile(PathBuf),
+    /// Accept only a leaf certificate matching the pin; the chain, name and
+    /// validity period are not checked, so self-signed certificates work
+    Pinned(CertPin),
+    /// Accept any certificate. Vulnerable to MITM, for testing only
+    Insecure,
+}
 
-    // Set custom certificate verifier to accept self-signed certificates
-    config
-        .dangerous()
-        .set_certificate_verifier(Arc::new(NoCertificateVerification));
+/// Certificate verifier that accepts only a pinned leaf certificate
+#[derive(Debug)]
+struct PinnedCertVerification {
+    pin: CertPin,
+    provider: Arc<CryptoProvider>,
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedCertVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> std::result::Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        if self.pin.matches(end_entity) {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "server certificate does not match pinned fingerprint".into(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Load CA certificates from a PEM bundle into a root store
+fn load_root_store<P: AsRef<Path>>(ca_path: P) -> Result<RootCertStore> {
+    let ca_file = File::open(&ca_path).map_err(|e| {
+        AnyTlsError::Tls(format!(
+            "failed to open CA file {:?}: {e}",
+            ca_path.as_ref()
+        ))
+    })?;
+    let mut ca_reader = BufReader::new(ca_file);
+    let certs = rustls_pemfile::certs(&mut ca_reader)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| AnyTlsError::Tls(format!("failed to parse CA certificate: {e}")))?;
+
+    let mut root_store = RootCertStore::empty();
+    let (added, _ignored) = root_store.add_parsable_certificates(certs);
+    if added == 0 {
+        return Err(AnyTlsError::Tls(format!(
+            "no usable CA certificates found in {:?}",
+            ca_path.as_ref()
+        )));
+    }
+    Ok(root_store)
+}
+
+/// Create a client TLS config verifying the server against the webpki roots
+pub fn create_client_config() -> Result<Arc<ClientConfig>> {
+    create_client_config_with_verification(&CertVerification::WebPki)
+}
+
+/// Create a client TLS config with insecure verification (for testing)
+/// This accepts self-signed certificates, similar to Go's InsecureSkipVerify: true
+pub fn create_insecure_client_config() -> Result<Arc<ClientConfig>> {
+    create_client_config_with_verification(&CertVerification::Insecure)
+}
+
+/// Create a client TLS config with the given certificate verification mode
+pub fn create_client_config_with_verification(
+    verification: &CertVerification,
+) -> Result<Arc<ClientConfig>> {
+    let builder = ClientConfig::builder();
+    let config = match verification {
+        CertVerification::WebPki => {
+            let root_store = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            builder
+                .with_root_certificates(root_store)
+                .with_no_client_auth()
+        }
+        CertVerification::CaFile(path) => builder
+            .with_root_certificates(load_root_store(path)?)
+            .with_no_client_auth(),
+        CertVerification::Pinned(pin) => {
+            let verifier = PinnedCertVerification {
+                pin: *pin,
+                provider: Arc::clone(builder.crypto_provider()),
+            };
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+                .with_no_client_auth()
+        }
+        CertVerification::Insecure => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoCertificateVerification))
+            .with_no_client_auth(),
+    };
 
     Ok(Arc::new(config))
 }
@@ -183,6 +378,36 @@ mod tests {
         let config = create_client_config().unwrap();
         // Config should be created successfully
         assert!(Arc::strong_count(&config) >= 1);
+
+        let config = create_insecure_client_config().unwrap();
+        assert!(Arc::strong_count(&config) >= 1);
+    }
+
+    #[test]
+    fn test_cert_pin_parse_and_match() {
+        let (cert, _key) = generate_key_pair().unwrap();
+        let (other, _key) = generate_key_pair().unwrap();
+
+        let hex = format_fingerprint(&certificate_fingerprint(&cert));
+        let pin = CertPin::certificate(&hex).unwrap();
+        assert!(pin.matches(&cert));
+        assert!(!pin.matches(&other));
+
+        // openssl style colon separated upper-case fingerprints are accepted
+        let colon_hex = hex
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap().to_uppercase())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(CertPin::certificate(&colon_hex).unwrap(), pin);
+
+        let spki = CertPin::spki(&format_fingerprint(&spki_fingerprint(&cert).unwrap())).unwrap();
+        assert!(spki.matches(&cert));
+        assert!(!spki.matches(&other));
+
+        assert!(CertPin::certificate("abcd").is_err());
+        assert!(CertPin::spki(&"zz".repeat(32)).is_err());
     }
 
     #[test]
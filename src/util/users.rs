@@ -0,0 +1,253 @@
+//! User table for multi-user authentication
+//!
+//! The server keeps a table of `SHA256(password) -> user` entries so that
+//! each teammate can have an individual password. Revoking one user only
+//! requires removing that user's line from the table.
+//!
+//! # File format
+//!
+//! One user per line, `name=password`. Lines starting with `#` and blank
+//! lines are ignored. To avoid storing plaintext passwords, the value may
+//! also be a pre-computed hash in the form `sha256:<64 hex chars>`:
+//!
+//! ```text
+//! # name=password
+//! alice=correct-horse-battery-staple
+//! bob=sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//! ```
+
+use crate::util::{AnyTlsError, Result, hash_password, parse_sha256_hex};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Name used for the implicit user when the server is configured with a single password
+pub const DEFAULT_USER_NAME: &str = "default";
+
+/// Identity of an authenticated user
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UserIdentity {
+    name: Arc<str>,
+}
+
+impl UserIdentity {
+    /// Create a new user identity
+    pub fn new(name: impl Into<Arc<str>>) -> Self {
+        Self { name: name.into() }
+    }
+
+    /// User name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for UserIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// UserTable maps password hashes to user identities
+#[derive(Debug, Clone, Default)]
+pub struct UserTable {
+    users: HashMap<[u8; 32], UserIdentity>,
+}
+
+impl UserTable {
+    /// Create an empty user table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a table containing a single user with the given password
+    pub fn single(name: &str, password: &str) -> Self {
+        let mut table = Self::new();
+        table
+            .users
+            .insert(hash_password(password), UserIdentity::new(name));
+        table
+    }
+
+    /// Add a user with a plaintext password
+    pub fn add_user(&mut self, name: &str, password: &str) -> Result<()> {
+        self.add_user_hash(name, hash_password(password))
+    }
+
+    /// Add a user with a pre-computed SHA256(password)
+    pub fn add_user_hash(&mut self, name: &str, password_hash: [u8; 32]) -> Result<()> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(AnyTlsError::Config("user name cannot be empty".into()));
+        }
+        if self.contains(name) {
+            return Err(AnyTlsError::Config(format!("duplicate user '{}'", name)));
+        }
+        if let Some(existing) = self.users.get(&password_hash) {
+            return Err(AnyTlsError::Config(format!(
+                "users '{}' and '{}' share the same password",
+                existing, name
+            )));
+        }
+        self.users.insert(password_hash, UserIdentity::new(name));
+        Ok(())
+    }
+
+    /// Add a user from a table value: a plaintext password or `sha256:<hex>`
+    pub fn add_user_secret(&mut self, name: &str, secret: &str) -> Result<()> {
+        let secret = secret.trim();
+        if secret.is_empty() {
+            return Err(AnyTlsError::Config(format!(
+                "empty password for '{}'",
+                name.trim()
+            )));
+        }
+        let password_hash = match secret.strip_prefix("sha256:") {
+            Some(hex) => parse_sha256_hex(hex.trim()).ok_or_else(|| {
+                AnyTlsError::Config(format!("invalid sha256 hash for '{}'", name.trim()))
+            })?,
+            None => hash_password(secret),
+        };
+        self.add_user_hash(name, password_hash)
+    }
+
+    /// Remove a user by name, returns true if the user existed
+    pub fn remove_user(&mut self, name: &str) -> bool {
+        let before = self.users.len();
+        self.users.retain(|_, user| user.name() != name);
+        self.users.len() != before
+    }
+
+    /// Whether a user named `name` exists
+    pub fn contains(&self, name: &str) -> bool {
+        self.users.values().any(|user| user.name() == name)
+    }
+
+    /// Look up the user owning the given password hash
+    pub fn lookup(&self, password_hash: &[u8; 32]) -> Option<&UserIdentity> {
+        self.users.get(password_hash)
+    }
+
+    /// Whether some user's password hash starts with `prefix`
+    pub fn matches_hash_prefix(&self, prefix: &[u8]) -> bool {
+        self.users.keys().any(|hash| hash.starts_with(prefix))
+    }
+
+    /// Number of users in the table
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    /// Check if the table is empty
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// User names, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .users
+            .values()
+            .map(|user| user.name().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Parse a user table from text (see module docs for the format)
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut table = Self::new();
+
+        for (index, raw_line) in text.lines().enumerate() {
+            let line_no = index + 1;
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, secret) = line.split_once('=').ok_or_else(|| {
+                AnyTlsError::Config(format!(
+                    "user table line {}: expected 'name=password'",
+                    line_no
+                ))
+            })?;
+
+            table.add_user_secret(name, secret).map_err(|e| match e {
+                AnyTlsError::Config(msg) => {
+                    AnyTlsError::Config(format!("user table line {}: {}", line_no, msg))
+                }
+                other => other,
+            })?;
+        }
+
+        if table.is_empty() {
+            return Err(AnyTlsError::Config("user table contains no users".into()));
+        }
+
+        Ok(table)
+    }
+
+    /// Load a user table from a file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text = std::fs::read_to_string(&path).map_err(|e| {
+            AnyTlsError::Config(format!(
+                "Failed to read user table {:?}: {}",
+                path.as_ref(),
+                e
+            ))
+        })?;
+        Self::parse(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_user_table() {
+        let text = r#"
+# team members
+alice=alice-password
+bob = sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+"#;
+        let table = UserTable::parse(text).unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(
+            table
+                .lookup(&hash_password("alice-password"))
+                .unwrap()
+                .name(),
+            "alice"
+        );
+        // sha256("test")
+        assert_eq!(table.lookup(&hash_password("test")).unwrap().name(), "bob");
+        assert!(table.lookup(&hash_password("other")).is_none());
+
+        let hash = hash_password("alice-password");
+        assert!(table.matches_hash_prefix(&hash[..5]));
+        assert!(table.matches_hash_prefix(b""));
+        assert!(!table.matches_hash_prefix(b"GET / HTTP/1.0\r\n"));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_lines() {
+        assert!(UserTable::parse("alice").is_err());
+        assert!(UserTable::parse("alice=").is_err());
+        assert!(UserTable::parse("alice=sha256:1234").is_err());
+        assert!(UserTable::parse("# only comments\n").is_err());
+        // Same password for two users cannot be told apart
+        assert!(UserTable::parse("alice=same\nbob=same").is_err());
+        assert!(UserTable::parse("alice=one\nalice=two").is_err());
+    }
+
+    #[test]
+    fn test_remove_user() {
+        let mut table = UserTable::parse("alice=a\nbob=b").unwrap();
+        assert!(table.remove_user("alice"));
+        assert!(!table.remove_user("alice"));
+        assert!(table.lookup(&hash_password("a")).is_none());
+        assert_eq!(table.names(), vec!["bob".to_string()]);
+    }
+}
@@ -59,7 +59,7 @@ echo "  服务器运行正常"
 
 # 启动客户端
 echo "[4/8] 启动客户端..."
-./anytls-client -l "127.0.0.1:${CLIENT_PORT}" -s "${SERVER_ADDR}" -p "${PASSWORD}" > client.log 2>&1 &
+./anytls-client -l "127.0.0.1:${CLIENT_PORT}" -s "${SERVER_ADDR}" -p "${PASSWORD}" --insecure > client.log 2>&1 &
 CLIENT_PID=$!
 echo "  客户端PID: ${CLIENT_PID}"
 
@@ -38,7 +38,7 @@ echo "[3/6] 启动客户端..."
 cargo run --release --bin anytls-client -- \
   -l "127.0.0.1:$CLIENT_PORT" \
   -s "127.0.0.1:$SERVER_PORT" \
-  -p "$PASSWORD" > client.log 2>&1 &
+  -p "$PASSWORD" --insecure > client.log 2>&1 &
 CLIENT_PID=$!
 sleep 3
 
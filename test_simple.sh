@@ -11,7 +11,7 @@ sleep 3
 
 echo ""
 echo "=== 步骤2: 启动客户端（后台）==="
-cargo run --release --bin anytls-client -- -l 127.0.0.1:1080 -s 127.0.0.1:8443 -p "$PASSWORD" &
+cargo run --release --bin anytls-client -- -l 127.0.0.1:1080 -s 127.0.0.1:8443 -p "$PASSWORD" --insecure &
 CLIENT_PID=$!
 echo "客户端PID: $CLIENT_PID"
 sleep 5
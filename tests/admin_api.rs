@@ -0,0 +1,249 @@
+//! Admin API tests
+//!
+//! The admin listener should list live sessions with their streams, close a
+//! session on request, show and reload the certificate, list banned sources,
+//! and refuse requests without the token.
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::padding::PaddingFactory;
+use anytls_rs::server::{AdminApi, AuthBans, BanConfig, Server, spawn_admin_server};
+use anytls_rs::util::{CertReloader, CertReloaderConfig, tls};
+use bytes::Bytes;
+use common::*;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, sleep, timeout};
+
+const TOKEN: &str = "admin-token";
+
+/// Start a server and its admin API; returns the admin address
+async fn start_server(
+    config: &TestConfig,
+    reloader: Option<Arc<CertReloader>>,
+) -> Result<SocketAddr> {
+    let server = spawn_test_server(config, |server| server).await?;
+    let mut api = AdminApi::new(&server, TOKEN)?;
+    if let Some(reloader) = reloader {
+        api = api.with_cert_reloader(reloader);
+    }
+    let (admin_addr, _) = spawn_admin_server("127.0.0.1:0", api).await?;
+    Ok(admin_addr)
+}
+
+/// Send one request and return the status code and JSON body
+async fn request<S>(
+    mut socket: S,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+) -> Result<(u16, serde_json::Value)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let auth = token.map_or_else(String::new, |token| {
+        format!("Authorization: Bearer {}\r\n", token)
+    });
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+        method, path, auth
+    );
+    socket.write_all(head.as_bytes()).await?;
+    let mut response = String::new();
+    timeout(Duration::from_secs(5), socket.read_to_string(&mut response)).await??;
+    let status = response
+        .split(' ')
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .unwrap_or_default();
+    let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+    Ok((status, serde_json::from_str(body)?))
+}
+
+async fn admin_request(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+) -> Result<(u16, serde_json::Value)> {
+    request(TcpStream::connect(addr).await?, method, path, Some(TOKEN)).await
+}
+
+#[tokio::test]
+async fn test_admin_lists_and_closes_sessions() -> Result<()> {
+    let config = new_test_config()?;
+    let admin_addr = start_server(&config, None).await?;
+    let (echo_addr, _echo) = spawn_tcp_echo_server().await?;
+    let client = create_test_client(&config).await?;
+    let (stream, _session) = client
+        .create_proxy_stream((echo_addr.ip().to_string(), echo_addr.port()))
+        .await?;
+    stream.write_data(Bytes::from_static(b"ping")).await?;
+    let mut echoed = [0u8; 4];
+    timeout(
+        Duration::from_secs(2),
+        stream.reader().lock().await.read_exact(&mut echoed),
+    )
+    .await??;
+
+    // Requests without the token are refused
+    let (status, body) = request(
+        TcpStream::connect(admin_addr).await?,
+        "GET",
+        "/sessions",
+        None,
+    )
+    .await?;
+    assert_eq!(status, 401);
+    assert!(body["error"].is_string());
+    let (status, _) = request(
+        TcpStream::connect(admin_addr).await?,
+        "GET",
+        "/sessions",
+        Some("wrong"),
+    )
+    .await?;
+    assert_eq!(status, 401);
+
+    let (status, body) = admin_request(admin_addr, "GET", "/sessions").await?;
+    assert_eq!(status, 200);
+    let sessions = body["sessions"].as_array().unwrap();
+    assert_eq!(sessions.len(), 1, "{body}");
+    let session = &sessions[0];
+    assert_eq!(session["user"], "default");
+    assert_eq!(session["peer_version"], 2);
+    assert_eq!(session["stream_count"], 1);
+    assert!(session["peer"].as_str().unwrap().starts_with("127.0.0.1:"));
+    assert!(session["bytes_in"].as_u64().unwrap() >= 4);
+    assert!(session["bytes_out"].as_u64().unwrap() >= 4);
+    assert_eq!(
+        session["streams"][0]["destination"],
+        echo_addr.to_string().as_str()
+    );
+
+    // Closing the session closes the client's stream
+    let id = session["id"].as_u64().unwrap();
+    let (status, body) =
+        admin_request(admin_addr, "POST", &format!("/sessions/{}/close", id)).await?;
+    assert_eq!(status, 200);
+    assert_eq!(body["closed"], id);
+    let mut buf = [0u8; 16];
+    let read = timeout(
+        Duration::from_secs(3),
+        stream.reader().lock().await.read(&mut buf),
+    )
+    .await?;
+    assert!(matches!(read, Ok(0) | Err(_)));
+    let mut remaining = usize::MAX;
+    for _ in 0..30 {
+        let (_, body) = admin_request(admin_addr, "GET", "/sessions").await?;
+        remaining = body["sessions"].as_array().map_or(0, Vec::len);
+        if remaining == 0 {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(remaining, 0, "closed session still listed");
+
+    let (status, _) = admin_request(admin_addr, "POST", &format!("/sessions/{}/close", id)).await?;
+    assert_eq!(status, 404);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_admin_cert_info_and_reload() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let (_, cert_path, key_path) = write_pem_pair(&dir, "server");
+    let reloader = Arc::new(CertReloader::new(CertReloaderConfig {
+        cert_path,
+        key_path,
+        watch_enabled: false,
+        debounce_ms: 500,
+        check_expiry: true,
+        expiry_warning_days: 30,
+    })?);
+    let config = new_test_config()?;
+    let admin_addr = start_server(&config, Some(Arc::clone(&reloader))).await?;
+
+    let (status, body) = admin_request(admin_addr, "GET", "/cert").await?;
+    assert_eq!(status, 200);
+    let summary = reloader.get_cert_info().unwrap().summary();
+    assert_eq!(body["certificate"]["summary"], summary.as_str());
+    assert_eq!(body["reload_count"], 0);
+
+    write_pem_pair(&dir, "server");
+    let (status, body) = admin_request(admin_addr, "POST", "/cert/reload").await?;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["reload_count"], 1);
+    assert_eq!(reloader.get_reload_count(), 1);
+
+    // A broken certificate is reported and the current one kept
+    std::fs::write(dir.path().join("server_cert.pem"), "not a certificate")?;
+    let (status, body) = admin_request(admin_addr, "POST", "/cert/reload").await?;
+    assert_eq!(status, 500);
+    assert!(body["error"].is_string());
+    assert_eq!(reloader.get_reload_count(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_admin_lists_bans() -> Result<()> {
+    let config = new_test_config()?;
+    let bans = Arc::new(AuthBans::new(BanConfig {
+        max_failures: 1,
+        ipv4_prefix: 24,
+        ..BanConfig::default()
+    })?);
+    let server = Server::new(
+        &config.password,
+        Arc::new(tokio_rustls::TlsAcceptor::from(tls::create_server_config()?)),
+        PaddingFactory::default(),
+        None,
+    )
+    .with_auth_bans(Arc::clone(&bans));
+    let (admin_addr, _) = spawn_admin_server("127.0.0.1:0", AdminApi::new(&server, TOKEN)?).await?;
+
+    let (status, body) = admin_request(admin_addr, "GET", "/bans").await?;
+    assert_eq!(status, 200);
+    assert_eq!(body, serde_json::json!({ "bans": [] }));
+
+    let ban = bans
+        .record_failure("198.51.100.23".parse()?)
+        .expect("first failure bans");
+    let (status, body) = admin_request(admin_addr, "GET", "/bans").await?;
+    assert_eq!(status, 200);
+    let listed = body["bans"].as_array().expect("bans array");
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["source"], "198.51.100.0/24");
+    let until = ban.until.duration_since(std::time::UNIX_EPOCH)?.as_secs();
+    assert_eq!(listed[0]["until"], until);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_admin_listener_restrictions() -> Result<()> {
+    let config = new_test_config()?;
+    let server = Server::new(
+        &config.password,
+        Arc::new(tokio_rustls::TlsAcceptor::from(tls::create_server_config()?)),
+        PaddingFactory::default(),
+        None,
+    );
+    assert!(AdminApi::new(&server, "").is_err());
+    let api = AdminApi::new(&server, TOKEN)?;
+    assert!(spawn_admin_server("0.0.0.0:0", api.clone()).await.is_err());
+
+    #[cfg(unix)]
+    {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("admin.sock");
+        anytls_rs::server::spawn_admin_unix_server(&path, api).await?;
+        let socket = tokio::net::UnixStream::connect(&path).await?;
+        let (status, body) = request(socket, "GET", "/sessions", Some(TOKEN)).await?;
+        assert_eq!(status, 200);
+        assert_eq!(body, serde_json::json!({ "sessions": [] }));
+    }
+    Ok(())
+}
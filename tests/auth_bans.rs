@@ -0,0 +1,114 @@
+//! Authentication failure ban tests
+//!
+//! After repeated wrong passwords, connections from the same source should
+//! be closed before the TLS handshake, or handed straight to the fallback
+//! backend when one is configured, and the ban should be listed.
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::padding::PaddingFactory;
+use anytls_rs::server::{AuthBans, BanConfig, Server};
+use anytls_rs::util::{METRICS, hash_password, send_authentication};
+use common::*;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, timeout};
+
+/// Ban a source after two failed authentications
+fn with_bans(server: Server) -> Server {
+    let bans = AuthBans::new(BanConfig {
+        max_failures: 2,
+        ..BanConfig::default()
+    })
+    .expect("valid ban config");
+    server.with_auth_bans(Arc::new(bans))
+}
+
+/// Authenticate with a wrong password and wait for the server to react
+async fn fail_authentication(config: &TestConfig) -> Result<()> {
+    let mut tls_stream = connect_tls(config).await?;
+    send_authentication(
+        &mut tls_stream,
+        &hash_password("wrong password"),
+        &PaddingFactory::default(),
+    )
+    .await?;
+    tls_stream.flush().await?;
+    let mut buf = [0u8; 64];
+    let _ = timeout(Duration::from_secs(2), tls_stream.read(&mut buf)).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_banned_source_closed_before_tls() -> Result<()> {
+    let config = new_test_config()?;
+    let bans = spawn_test_server(&config, with_bans)
+        .await?
+        .auth_bans()
+        .unwrap();
+    let before = METRICS.banned_connections.get();
+
+    fail_authentication(&config).await?;
+    assert!(bans.bans().is_empty());
+    fail_authentication(&config).await?;
+    assert!(wait_for(|| bans.bans().len() == 1, Duration::from_secs(2)).await);
+    assert_eq!(bans.bans()[0].source.to_string(), "127.0.0.1/32");
+
+    // Even the right password is not checked any more
+    let mut tcp = TcpStream::connect(&config.server_addr).await?;
+    let mut buf = [0u8; 16];
+    let read = timeout(Duration::from_secs(2), tcp.read(&mut buf)).await?;
+    assert!(
+        matches!(read, Ok(0) | Err(_)),
+        "banned connection stayed open"
+    );
+    assert!(connect_tls(&config).await.is_err());
+    assert!(METRICS.banned_connections.get() > before);
+
+    // Lifting the ban lets clients through again
+    assert!(bans.unban(&bans.bans()[0].source));
+    let (echo_addr, _echo) = spawn_tcp_echo_server().await?;
+    let client = create_test_client(&config).await?;
+    let (stream, _session) = client
+        .create_proxy_stream((echo_addr.ip().to_string(), echo_addr.port()))
+        .await?;
+    stream
+        .write_data(bytes::Bytes::from_static(b"unbanned"))
+        .await?;
+    let mut echoed = [0u8; 8];
+    timeout(
+        Duration::from_secs(2),
+        stream.reader().lock().await.read_exact(&mut echoed),
+    )
+    .await??;
+    assert_eq!(&echoed, b"unbanned");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_banned_source_sent_to_fallback() -> Result<()> {
+    let config = new_test_config()?;
+    let (fallback_addr, _fallback) = spawn_tcp_echo_server().await?;
+    let bans = spawn_test_server(&config, |server| {
+        with_bans(server).with_fallback(fallback_addr.to_string())
+    })
+    .await?
+    .auth_bans()
+    .unwrap();
+
+    fail_authentication(&config).await?;
+    fail_authentication(&config).await?;
+    assert!(wait_for(|| bans.bans().len() == 1, Duration::from_secs(2)).await);
+
+    // Fewer bytes than a password hash reach the fallback right away, as no
+    // authentication is read from a banned source
+    let mut tls_stream = connect_tls(&config).await?;
+    tls_stream.write_all(b"GET /").await?;
+    tls_stream.flush().await?;
+    let mut echoed = [0u8; 5];
+    timeout(Duration::from_secs(2), tls_stream.read_exact(&mut echoed)).await??;
+    assert_eq!(&echoed, b"GET /");
+    Ok(())
+}
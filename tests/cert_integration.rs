@@ -1,11 +1,21 @@
 //! Integration tests for certificate loading and analysis
 
-use anytls_rs::util::{CertReloader, CertReloaderConfig, CertificateInfo, generate_key_pair};
+mod common;
+
+use anytls_rs::util::{
+    CertPin, CertReloader, CertReloaderConfig, CertVerification, CertificateInfo,
+    certificate_fingerprint, create_client_config_with_verification,
+    create_server_config_from_files, generate_key_pair, spki_fingerprint,
+};
+use common::*;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tempfile::TempDir;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
 
 #[test]
 fn test_generate_and_analyze_certificate() {
@@ -241,3 +251,106 @@ async fn test_manual_certificate_reload() {
         }
     }
 }
+
+/// Run a TLS handshake against a local server using the given certificate
+async fn handshake(
+    server_cert: &PathBuf,
+    server_key: &PathBuf,
+    verification: &CertVerification,
+) -> std::io::Result<()> {
+    let server_config =
+        create_server_config_from_files(server_cert, server_key).expect("server config");
+    let acceptor = TlsAcceptor::from(server_config);
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let server_task = tokio::spawn(async move {
+        if let Ok((tcp, _)) = listener.accept().await {
+            let _ = acceptor.accept(tcp).await;
+        }
+    });
+
+    let client_config =
+        create_client_config_with_verification(verification).expect("client config");
+    let connector = TlsConnector::from(client_config);
+    let tcp = TcpStream::connect(addr).await?;
+    let server_name = ServerName::try_from("localhost").expect("server name");
+    let result = connector.connect(server_name, tcp).await.map(|_| ());
+
+    server_task.abort();
+    let _ = server_task.await;
+    result
+}
+
+#[tokio::test]
+async fn test_client_verification_with_ca_file() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let (_, cert_path, key_path) = write_pem_pair(&temp_dir, "server");
+    let (_, other_cert_path, _) = write_pem_pair(&temp_dir, "other");
+
+    // The self-signed certificate is its own CA
+    let trusted = CertVerification::CaFile(cert_path.clone());
+    assert!(handshake(&cert_path, &key_path, &trusted).await.is_ok());
+
+    // A bundle without the server's issuer is rejected
+    let untrusted = CertVerification::CaFile(other_cert_path);
+    assert!(handshake(&cert_path, &key_path, &untrusted).await.is_err());
+
+    // A missing CA file is a configuration error
+    let missing = CertVerification::CaFile(temp_dir.path().join("missing.pem"));
+    assert!(create_client_config_with_verification(&missing).is_err());
+}
+
+#[tokio::test]
+async fn test_client_verification_with_pins() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let (cert_der, cert_path, key_path) = write_pem_pair(&temp_dir, "server");
+    let (other_der, _, _) = write_pem_pair(&temp_dir, "other");
+
+    let pin = CertPin::Certificate(certificate_fingerprint(&cert_der));
+    assert!(
+        handshake(&cert_path, &key_path, &CertVerification::Pinned(pin))
+            .await
+            .is_ok()
+    );
+
+    let spki_pin = CertPin::Spki(spki_fingerprint(&cert_der).expect("spki"));
+    assert!(
+        handshake(&cert_path, &key_path, &CertVerification::Pinned(spki_pin))
+            .await
+            .is_ok()
+    );
+
+    let wrong_pin = CertPin::Certificate(certificate_fingerprint(&other_der));
+    assert!(
+        handshake(&cert_path, &key_path, &CertVerification::Pinned(wrong_pin))
+            .await
+            .is_err()
+    );
+
+    let wrong_spki = CertPin::Spki(spki_fingerprint(&other_der).expect("spki"));
+    assert!(
+        handshake(&cert_path, &key_path, &CertVerification::Pinned(wrong_spki))
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_client_verification_default_and_insecure() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let (_, cert_path, key_path) = write_pem_pair(&temp_dir, "server");
+
+    // Self-signed certificates are rejected by the default webpki roots
+    assert!(
+        handshake(&cert_path, &key_path, &CertVerification::default())
+            .await
+            .is_err()
+    );
+
+    // ...and only accepted when verification is explicitly disabled
+    assert!(
+        handshake(&cert_path, &key_path, &CertVerification::Insecure)
+            .await
+            .is_ok()
+    );
+}
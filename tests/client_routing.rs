@@ -0,0 +1,88 @@
+//! Client routing tests
+//!
+//! No AnyTLS server runs here, so any request that is tunneled fails;
+//! `direct` routes must still reach the local echo server and `block` routes
+//! must be refused by the local proxies.
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::client::{Router, start_http_proxy_server, start_socks5_server};
+use common::*;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, sleep, timeout};
+
+async fn assert_echo(conn: &mut TcpStream, payload: &[u8]) -> Result<()> {
+    conn.write_all(payload).await?;
+    let mut buf = vec![0u8; payload.len()];
+    timeout(Duration::from_secs(5), conn.read_exact(&mut buf)).await??;
+    assert_eq!(buf, payload);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_socks5_and_http_follow_routing_rules() -> Result<()> {
+    let config = new_test_config()?;
+    let client = create_test_client(&config).await?;
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let blocked_port = 9;
+
+    let rules = format!("block port {}\ndirect ip-cidr 127.0.0.0/8\n", blocked_port);
+    *client.get_router_ref().write().unwrap() = Arc::new(Router::parse(&rules)?);
+
+    let socks_addr = config.client_listen.clone();
+    let socks_client = Arc::clone(&client);
+    let socks_handle =
+        tokio::spawn(async move { start_socks5_server(&socks_addr, socks_client).await });
+    let http_addr: SocketAddr = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        listener.local_addr()?
+    };
+    let http_client = Arc::clone(&client);
+    let http_handle =
+        tokio::spawn(
+            async move { start_http_proxy_server(&http_addr.to_string(), http_client).await },
+        );
+    sleep(Duration::from_millis(300)).await;
+
+    // Direct route reaches the echo server although no AnyTLS server exists
+    let (mut conn, reply) = socks5_connect(&config.client_listen, echo_addr).await?;
+    assert_eq!(reply, 0x00);
+    assert_echo(&mut conn, b"direct").await?;
+
+    // Blocked port is refused with "connection not allowed by ruleset"
+    let (_conn, reply) =
+        socks5_connect(&config.client_listen, ([127, 0, 0, 1], blocked_port).into()).await?;
+    assert_eq!(reply, 0x02);
+
+    // HTTP CONNECT uses the same router
+    let mut http = TcpStream::connect(http_addr).await?;
+    let connect = format!(
+        "CONNECT 127.0.0.1:{0} HTTP/1.1\r\nHost: 127.0.0.1:{0}\r\n\r\n",
+        echo_addr.port()
+    );
+    http.write_all(connect.as_bytes()).await?;
+    let mut status = [0u8; 39];
+    timeout(Duration::from_secs(5), http.read_exact(&mut status)).await??;
+    assert!(status.starts_with(b"HTTP/1.1 200"));
+    assert_echo(&mut http, b"tunnel").await?;
+
+    // Swapped rules apply to new requests
+    *client.get_router_ref().write().unwrap() = Arc::new(Router::parse("block ip-cidr 127.0.0.1")?);
+    let mut http = TcpStream::connect(http_addr).await?;
+    http.write_all(connect.as_bytes()).await?;
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(5), http.read_to_end(&mut response)).await??;
+    assert!(response.starts_with(b"HTTP/1.1 403"), "{:?}", response);
+
+    client.stop_session_pool_cleanup().await;
+    socks_handle.abort();
+    http_handle.abort();
+    echo_handle.abort();
+    let _ = echo_handle.await;
+
+    Ok(())
+}
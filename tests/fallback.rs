@@ -0,0 +1,171 @@
+//! Fallback forwarding tests
+//!
+//! Connections that fail authentication should be spliced to a fallback
+//! backend so that probes see an ordinary HTTPS website.
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::util::tls;
+use bytes::Bytes;
+use common::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant, timeout};
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+const DECOY_BODY: &str = "Welcome to nginx!";
+
+/// Local HTTP stand-in that records each request and answers with a fixed page
+async fn spawn_decoy_http_server()
+-> Result<(String, mpsc::UnboundedReceiver<Vec<u8>>, JoinHandle<()>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?.to_string();
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let handle = tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let _ = tx.send(request);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nServer: nginx\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    DECOY_BODY.len(),
+                    DECOY_BODY
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            });
+        }
+    });
+
+    Ok((addr, rx, handle))
+}
+
+/// Connect like a browser or active probe: plain TLS, then raw bytes
+async fn probe(server_addr: &str, request: &[u8]) -> Result<String> {
+    let connector = TlsConnector::from(tls::create_insecure_client_config()?);
+    let tcp = TcpStream::connect(server_addr).await?;
+    let mut tls_stream = connector
+        .connect(ServerName::try_from("localhost")?, tcp)
+        .await?;
+    tls_stream.write_all(request).await?;
+    tls_stream.flush().await?;
+
+    let mut response = Vec::new();
+    timeout(
+        Duration::from_secs(5),
+        tls_stream.read_to_end(&mut response),
+    )
+    .await?
+    .ok();
+    Ok(String::from_utf8_lossy(&response).into_owned())
+}
+
+#[tokio::test]
+async fn test_probe_is_forwarded_to_fallback() -> Result<()> {
+    let config = new_test_config()?;
+    let (decoy_addr, mut requests, decoy_handle) = spawn_decoy_http_server().await?;
+    let _server =
+        spawn_test_server(&config, |server| server.with_fallback(decoy_addr.as_str())).await?;
+
+    let request = b"GET /index.html HTTP/1.1\r\nHost: localhost\r\nUser-Agent: probe\r\n\r\n";
+    let response = probe(&config.server_addr, request).await?;
+    assert!(
+        response.starts_with("HTTP/1.1 200 OK"),
+        "unexpected response: {}",
+        response
+    );
+    assert!(response.contains(DECOY_BODY));
+
+    // The backend must see the request unchanged, including the bytes that
+    // were consumed as a password hash
+    let received = timeout(Duration::from_secs(5), requests.recv())
+        .await?
+        .expect("fallback received no request");
+    assert_eq!(received, request);
+
+    decoy_handle.abort();
+    let _ = decoy_handle.await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_short_probe_is_forwarded_to_fallback() -> Result<()> {
+    let config = new_test_config()?;
+    let (decoy_addr, mut requests, decoy_handle) = spawn_decoy_http_server().await?;
+    let _server =
+        spawn_test_server(&config, |server| server.with_fallback(decoy_addr.as_str())).await?;
+
+    // Shorter than a password hash; the probe then waits for an answer,
+    // which must not be held back by the wait for the rest of a hash
+    let request = b"GET / HTTP/1.0\r\n\r\n";
+    let started = Instant::now();
+    let response = probe(&config.server_addr, request).await?;
+    assert!(
+        response.contains(DECOY_BODY),
+        "unexpected response: {}",
+        response
+    );
+    assert!(
+        started.elapsed() < Duration::from_secs(1),
+        "fallback answered after {:?}",
+        started.elapsed()
+    );
+
+    let received = timeout(Duration::from_secs(5), requests.recv())
+        .await?
+        .expect("fallback received no request");
+    assert_eq!(received, request);
+
+    decoy_handle.abort();
+    let _ = decoy_handle.await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_valid_client_unaffected_by_fallback() -> Result<()> {
+    let config = new_test_config()?;
+    let (decoy_addr, mut requests, decoy_handle) = spawn_decoy_http_server().await?;
+    let _server =
+        spawn_test_server(&config, |server| server.with_fallback(decoy_addr.as_str())).await?;
+
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let client = create_test_client(&config).await?;
+    let (stream, _session) = timeout(
+        Duration::from_secs(5),
+        client.create_proxy_stream((echo_addr.ip().to_string(), echo_addr.port())),
+    )
+    .await??;
+
+    stream.send_data(Bytes::from_static(b"ping"))?;
+    let mut buf = [0u8; 4];
+    timeout(
+        Duration::from_secs(5),
+        stream.reader().lock().await.read_exact(&mut buf),
+    )
+    .await??;
+    assert_eq!(&buf, b"ping");
+    assert!(requests.try_recv().is_err(), "fallback must not be used");
+
+    client.stop_session_pool_cleanup().await;
+    echo_handle.abort();
+    let _ = echo_handle.await;
+    decoy_handle.abort();
+    let _ = decoy_handle.await;
+
+    Ok(())
+}
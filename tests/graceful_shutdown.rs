@@ -0,0 +1,123 @@
+//! Graceful shutdown tests
+//!
+//! Triggering a `ShutdownHandle` must stop the listeners, let active streams
+//! run until the drain timeout and then close what is left.
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::server::{OutboundAcl, Server};
+use anytls_rs::util::{ShutdownHandle, tls};
+use bytes::Bytes;
+use common::*;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::time::{Duration, Instant, sleep, timeout};
+
+#[tokio::test]
+async fn test_server_drains_active_streams_then_closes() -> Result<()> {
+    let config = new_test_config()?;
+    let shutdown = ShutdownHandle::new().with_drain_timeout(Duration::from_secs(2));
+    let server_config = tls::create_server_config()?;
+    let server = Arc::new(
+        Server::new(
+            &config.password,
+            Arc::new(tokio_rustls::TlsAcceptor::from(server_config)),
+            anytls_rs::padding::PaddingFactory::default(),
+            None,
+        )
+        .with_shutdown(shutdown.clone())
+        .with_outbound_acl(OutboundAcl::allow_all()),
+    );
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move { server.listen(&server_addr).await });
+    sleep(Duration::from_millis(300)).await;
+
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let client = create_test_client(&config).await?;
+    let (stream, session) = timeout(
+        Duration::from_secs(5),
+        client.create_proxy_stream((echo_addr.ip().to_string(), echo_addr.port())),
+    )
+    .await??;
+
+    let triggered_at = Instant::now();
+    shutdown.shutdown();
+    sleep(Duration::from_millis(200)).await;
+
+    // The listener is gone, but the open stream keeps working while draining
+    assert!(TcpStream::connect(&config.server_addr).await.is_err());
+    stream.send_data(Bytes::from_static(b"drain"))?;
+    let mut buf = [0u8; 5];
+    timeout(
+        Duration::from_secs(1),
+        stream.reader().lock().await.read_exact(&mut buf),
+    )
+    .await??;
+    assert_eq!(&buf, b"drain");
+
+    // At the deadline the session is closed and listen() returns
+    let result = timeout(Duration::from_secs(10), server_handle).await??;
+    assert!(result.is_ok());
+    assert!(triggered_at.elapsed() >= Duration::from_secs(2));
+    assert!(
+        wait_for(|| session.is_closed(), Duration::from_secs(5)).await,
+        "client session should be closed by the server"
+    );
+
+    client.stop_session_pool_cleanup().await;
+    echo_handle.abort();
+    let _ = echo_handle.await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_without_sessions_stops_immediately() -> Result<()> {
+    let config = new_test_config()?;
+    let server = create_test_server(&config).await?;
+    let shutdown = server.shutdown_handle();
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move { server.listen(&server_addr).await });
+    sleep(Duration::from_millis(300)).await;
+
+    shutdown.shutdown();
+    let result = timeout(Duration::from_secs(2), server_handle).await??;
+    assert!(result.is_ok());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_socks5_server_stops_on_shutdown() -> Result<()> {
+    let config = new_test_config()?;
+    let client = create_test_client(&config).await?;
+    let shutdown = ShutdownHandle::new().with_drain_timeout(Duration::from_millis(500));
+    let listen_addr = config.client_listen.clone();
+    let socks_shutdown = shutdown.clone();
+    let socks_client = Arc::clone(&client);
+    let socks_handle = tokio::spawn(async move {
+        anytls_rs::client::start_socks5_server_with_shutdown(
+            &listen_addr,
+            socks_client,
+            socks_shutdown,
+        )
+        .await
+    });
+    sleep(Duration::from_millis(300)).await;
+
+    // An idle SOCKS5 connection is closed once the drain timeout expires
+    let mut idle_conn = TcpStream::connect(&config.client_listen).await?;
+    shutdown.shutdown();
+    let result = timeout(Duration::from_secs(3), socks_handle).await??;
+    assert!(result.is_ok());
+    assert!(TcpStream::connect(&config.client_listen).await.is_err());
+
+    let mut buf = [0u8; 1];
+    let read = timeout(Duration::from_secs(1), idle_conn.read(&mut buf)).await?;
+    assert!(matches!(read, Ok(0) | Err(_)));
+
+    client.stop_session_pool_cleanup().await;
+    Ok(())
+}
@@ -0,0 +1,86 @@
+//! TCP half-close through the SOCKS5 proxy and the server relay
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::client::start_socks5_server;
+use common::*;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, sleep, timeout};
+
+/// Upstream that reads a request up to EOF, then answers with its length
+async fn spawn_read_to_eof_server() -> Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let handle = tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                if socket.read_to_end(&mut request).await.is_ok() {
+                    let reply = format!("received {} bytes", request.len());
+                    let _ = socket.write_all(reply.as_bytes()).await;
+                }
+                let _ = socket.shutdown().await;
+            });
+        }
+    });
+    Ok((addr, handle))
+}
+
+#[tokio::test]
+async fn test_half_close_through_socks5() -> Result<()> {
+    let config = new_test_config()?;
+    let server = create_test_server(&config).await?;
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = server.listen(&server_addr).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+
+    let (upstream, upstream_handle) = spawn_read_to_eof_server().await?;
+
+    let client = create_test_client(&config).await?;
+    let socks_addr = config.client_listen.clone();
+    let socks_client = Arc::clone(&client);
+    let socks_handle =
+        tokio::spawn(async move { start_socks5_server(&socks_addr, socks_client).await });
+    sleep(Duration::from_millis(300)).await;
+
+    // Like `nc -N`: send the request, shut down writing, then read the answer
+    for _ in 0..2 {
+        let (mut conn, reply) = socks5_connect(&config.client_listen, upstream).await?;
+        assert_eq!(reply, 0x00, "SOCKS5 connect failed");
+        conn.write_all(&[7u8; 100_000]).await?;
+        conn.shutdown().await?;
+
+        let mut reply = String::new();
+        timeout(Duration::from_secs(5), conn.read_to_string(&mut reply)).await??;
+        assert_eq!(reply, "received 100000 bytes");
+    }
+
+    // Both directions finished, so no stream is left open on the client
+    let mut open_streams = usize::MAX;
+    for _ in 0..20 {
+        let status = client.upstream_status().await;
+        open_streams = status.iter().map(|s| s.active_streams).sum();
+        if open_streams == 0 {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(
+        open_streams, 0,
+        "streams should be removed after both directions close"
+    );
+
+    client.stop_session_pool_cleanup().await;
+    socks_handle.abort();
+    server_handle.abort();
+    upstream_handle.abort();
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Handshake timeout tests
+//!
+//! Peers that open a socket and then stall, or trickle bytes, at each step
+//! of the accept path should be disconnected once that step's deadline
+//! passes. The metrics registry is shared by the tests in this binary, so
+//! each test checks a different counter and only that it went up.
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::padding::PaddingFactory;
+use anytls_rs::server::{HandshakeTimeouts, Server};
+use anytls_rs::util::{METRICS, hash_password, send_authentication};
+use common::*;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, Instant, sleep, timeout};
+
+const DEADLINE: Duration = Duration::from_millis(300);
+
+/// Give every step of the accept path [`DEADLINE`]
+fn short_deadlines(server: Server) -> Server {
+    server.with_handshake_timeouts(HandshakeTimeouts {
+        tls_handshake: DEADLINE,
+        authentication: DEADLINE,
+        first_frame: DEADLINE,
+    })
+}
+
+/// Wait until the server closes the connection, returning how long it took
+async fn wait_closed<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Duration> {
+    let started = Instant::now();
+    let mut buf = [0u8; 64];
+    loop {
+        match timeout(Duration::from_secs(3), reader.read(&mut buf)).await? {
+            Ok(0) | Err(_) => return Ok(started.elapsed()),
+            Ok(_) => continue,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_stalled_tcp_client_times_out_in_tls_handshake() -> Result<()> {
+    let config = new_test_config()?;
+    spawn_test_server(&config, short_deadlines).await?;
+    let before = METRICS.tls_handshake_timeouts.get();
+
+    // Connect and never send a ClientHello
+    let mut stalled = TcpStream::connect(&config.server_addr).await?;
+    wait_closed(&mut stalled).await?;
+    assert!(METRICS.tls_handshake_timeouts.get() > before);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_trickled_authentication_times_out() -> Result<()> {
+    let config = new_test_config()?;
+    spawn_test_server(&config, short_deadlines).await?;
+    let before = METRICS.auth_timeouts.get();
+
+    // Send the password hash one byte at a time, each well within the
+    // per-read allowance, so only the overall deadline can stop it
+    let tls_stream = connect_tls(&config).await?;
+    let (mut reader, mut writer) = tokio::io::split(tls_stream);
+    let hash = hash_password(&config.password);
+    let trickle = tokio::spawn(async move {
+        for byte in hash {
+            if writer.write_all(&[byte]).await.is_err() || writer.flush().await.is_err() {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    });
+
+    let elapsed = wait_closed(&mut reader).await?;
+    assert!(
+        elapsed < Duration::from_secs(2),
+        "closed after {:?}",
+        elapsed
+    );
+    assert!(METRICS.auth_timeouts.get() > before);
+    trickle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_silent_session_times_out_before_first_frame() -> Result<()> {
+    let config = new_test_config()?;
+    spawn_test_server(&config, short_deadlines).await?;
+    let before = METRICS.first_frame_timeouts.get();
+
+    // Authenticate, then send no frame
+    let mut tls_stream = connect_tls(&config).await?;
+    send_authentication(
+        &mut tls_stream,
+        &hash_password(&config.password),
+        &PaddingFactory::default(),
+    )
+    .await?;
+    tls_stream.flush().await?;
+
+    wait_closed(&mut tls_stream).await?;
+    assert!(METRICS.first_frame_timeouts.get() > before);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! Metrics endpoint tests
+//!
+//! Server and client share one registry inside the test process, and other
+//! tests in this binary may run concurrently, so assertions only check that
+//! values moved in the expected direction.
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::util::{METRICS, spawn_metrics_server};
+use bytes::Bytes;
+use common::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, sleep, timeout};
+
+async fn http_get(addr: std::net::SocketAddr, path: &str) -> Result<String> {
+    let mut socket = TcpStream::connect(addr).await?;
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    socket.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(5), socket.read_to_end(&mut response)).await??;
+    Ok(String::from_utf8_lossy(&response).into_owned())
+}
+
+fn metric_value(body: &str, name: &str) -> Option<f64> {
+    body.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .and_then(|value| value.parse().ok())
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint_reports_traffic() -> Result<()> {
+    let config = new_test_config()?;
+    let server = create_test_server(&config).await?;
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = server.listen(&server_addr).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+    sleep(Duration::from_millis(300)).await;
+
+    let (metrics_addr, metrics_handle) = spawn_metrics_server("127.0.0.1:0").await?;
+    let bytes_out_before = METRICS.bytes_out.get();
+    let bytes_in_before = METRICS.bytes_in.get();
+
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let client = create_test_client(&config).await?;
+    let (stream, _session) = timeout(
+        Duration::from_secs(5),
+        client.create_proxy_stream((echo_addr.ip().to_string(), echo_addr.port())),
+    )
+    .await??;
+    stream.send_data(Bytes::from_static(b"metrics"))?;
+    let mut buf = [0u8; 7];
+    timeout(
+        Duration::from_secs(5),
+        stream.reader().lock().await.read_exact(&mut buf),
+    )
+    .await??;
+
+    let response = http_get(metrics_addr, "/metrics").await?;
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("text/plain; version=0.0.4"));
+    assert!(response.contains("# TYPE anytls_sessions_active gauge"));
+    assert!(metric_value(&response, "anytls_sessions_active").unwrap() >= 2.0);
+    assert!(metric_value(&response, "anytls_streams_active").unwrap() >= 2.0);
+    assert!(METRICS.bytes_out.get() >= bytes_out_before + 14);
+    assert!(METRICS.bytes_in.get() >= bytes_in_before + 14);
+    assert!(metric_value(&response, "anytls_dns_cache_misses_total").is_some());
+
+    let not_found = http_get(metrics_addr, "/").await?;
+    assert!(not_found.starts_with("HTTP/1.1 404"), "{}", not_found);
+
+    client.stop_session_pool_cleanup().await;
+    echo_handle.abort();
+    let _ = echo_handle.await;
+    metrics_handle.abort();
+    let _ = metrics_handle.await;
+    server_handle.abort();
+    let _ = server_handle.await;
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+//! Multi-user authentication tests
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::server::{OutboundAcl, Server};
+use anytls_rs::util::{UserTable, tls};
+use common::*;
+use std::sync::{Arc, RwLock};
+use tokio::time::{Duration, sleep, timeout};
+
+fn client_config_for(config: &TestConfig, password: &str) -> TestConfig {
+    TestConfig {
+        server_addr: config.server_addr.clone(),
+        client_listen: config.client_listen.clone(),
+        password: password.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_multi_user_login_and_revoke() -> Result<()> {
+    let config = new_test_config()?;
+
+    let users = UserTable::parse("alice=alice_password\nbob=bob_password")?;
+    let users_ref = Arc::new(RwLock::new(Arc::new(users)));
+    let server_config = tls::create_server_config()?;
+    let tls_acceptor = Arc::new(tokio_rustls::TlsAcceptor::from(server_config));
+    let server = Arc::new(
+        Server::new_with_users(
+            Arc::clone(&users_ref),
+            Arc::new(RwLock::new(tls_acceptor)),
+            anytls_rs::padding::PaddingFactory::default(),
+            None,
+        )
+        .with_outbound_acl(OutboundAcl::allow_all()),
+    );
+    let server_clone = server.clone();
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = server_clone.listen(&server_addr).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let destination = (echo_addr.ip().to_string(), echo_addr.port());
+
+    // Both users can open streams with their own passwords
+    for password in ["alice_password", "bob_password"] {
+        let client = create_test_client(&client_config_for(&config, password)).await?;
+        let result = timeout(
+            Duration::from_secs(5),
+            client.create_proxy_stream(destination.clone()),
+        )
+        .await?;
+        assert!(result.is_ok(), "user with {} should be accepted", password);
+        client.stop_session_pool_cleanup().await;
+    }
+
+    // Revoke bob without touching alice
+    {
+        let mut guard = users_ref.write().unwrap();
+        let mut table = (**guard).clone();
+        assert!(table.remove_user("bob"));
+        *guard = Arc::new(table);
+    }
+
+    let bob = create_test_client(&client_config_for(&config, "bob_password")).await?;
+    let result = timeout(
+        Duration::from_secs(5),
+        bob.create_proxy_stream(destination.clone()),
+    )
+    .await?;
+    assert!(result.is_err(), "revoked user should be rejected");
+    bob.stop_session_pool_cleanup().await;
+
+    let alice = create_test_client(&client_config_for(&config, "alice_password")).await?;
+    let result = timeout(
+        Duration::from_secs(5),
+        alice.create_proxy_stream(destination),
+    )
+    .await?;
+    assert!(result.is_ok(), "remaining user should still be accepted");
+    alice.stop_session_pool_cleanup().await;
+
+    echo_handle.abort();
+    let _ = echo_handle.await;
+    server_handle.abort();
+    let _ = server_handle.await;
+
+    Ok(())
+}
@@ -0,0 +1,149 @@
+//! Outbound ACL tests
+//!
+//! The server must check destinations before dialing, report rejections in
+//! the SYNACK (or reset the stream if it was already acknowledged) and pick
+//! up a swapped rule set for new streams.
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::server::{OutboundAcl, Server};
+use anytls_rs::util::tls;
+use bytes::Bytes;
+use common::*;
+use std::sync::Arc;
+use tokio::time::{Duration, sleep, timeout};
+
+#[tokio::test]
+async fn test_outbound_acl_blocks_and_reloads() -> Result<()> {
+    let config = new_test_config()?;
+    let server_config = tls::create_server_config()?;
+    // No rules: loopback destinations are blocked by default
+    let server = Arc::new(Server::new(
+        &config.password,
+        Arc::new(tokio_rustls::TlsAcceptor::from(server_config)),
+        anytls_rs::padding::PaddingFactory::default(),
+        None,
+    ));
+    let acl_ref = server.get_acl_ref();
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move { server.listen(&server_addr).await });
+    sleep(Duration::from_millis(300)).await;
+
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let destination = (echo_addr.ip().to_string(), echo_addr.port());
+    let client = create_test_client(&config).await?;
+
+    let err = timeout(
+        Duration::from_secs(5),
+        client.create_proxy_stream(destination.clone()),
+    )
+    .await?
+    .err()
+    .expect("loopback destination should be rejected");
+    assert!(
+        err.to_string()
+            .contains("private destinations are not allowed"),
+        "{}",
+        err
+    );
+
+    // Allow the echo server's port only
+    let rules = format!("allow 127.0.0.0/8 {}\n", echo_addr.port());
+    *acl_ref.write().unwrap() = Arc::new(OutboundAcl::parse(&rules)?);
+
+    let (stream, _session) = timeout(
+        Duration::from_secs(5),
+        client.create_proxy_stream(destination.clone()),
+    )
+    .await??;
+    stream.send_data(Bytes::from_static(b"acl"))?;
+    let mut buf = [0u8; 3];
+    timeout(
+        Duration::from_secs(5),
+        stream.reader().lock().await.read_exact(&mut buf),
+    )
+    .await??;
+    assert_eq!(&buf, b"acl");
+
+    // An explicit deny rule names itself in the error
+    *acl_ref.write().unwrap() = Arc::new(OutboundAcl::parse("deny 127.0.0.1")?);
+    let err = timeout(
+        Duration::from_secs(5),
+        client.create_proxy_stream(destination),
+    )
+    .await?
+    .err()
+    .expect("denied destination should be rejected");
+    assert!(err.to_string().contains("deny 127.0.0.1"), "{}", err);
+
+    client.stop_session_pool_cleanup().await;
+    echo_handle.abort();
+    let _ = echo_handle.await;
+    server_handle.abort();
+    let _ = server_handle.await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_outbound_acl_checks_udp_domain_before_synack() -> Result<()> {
+    let config = new_test_config()?;
+    let server_config = tls::create_server_config()?;
+    let server = Arc::new(
+        Server::new(
+            &config.password,
+            Arc::new(tokio_rustls::TlsAcceptor::from(server_config)),
+            anytls_rs::padding::PaddingFactory::default(),
+            None,
+        )
+        .with_outbound_acl(OutboundAcl::parse("deny localhost\nallow 127.0.0.1")?),
+    );
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move { server.listen(&server_addr).await });
+    sleep(Duration::from_millis(300)).await;
+    let client = create_test_client(&config).await?;
+
+    // The domain rule applies even though the name resolves to an allowed IP
+    let err = timeout(
+        Duration::from_secs(5),
+        client.create_udp_stream("localhost", 53),
+    )
+    .await?
+    .err()
+    .expect("denied UDP domain should be rejected in the SYNACK");
+    assert!(err.to_string().contains("deny localhost"), "{}", err);
+
+    let stream = timeout(
+        Duration::from_secs(5),
+        client.create_udp_stream("127.0.0.1", 53),
+    )
+    .await??;
+    assert!(!stream.is_closed());
+
+    // A client that waits for the SYNACK before sending the request gets it
+    // for the stream, and a rejected target resets the stream afterwards
+    let (stream, _session) = timeout(
+        Duration::from_secs(5),
+        client.create_proxy_stream(("sp.v2.udp-over-tcp.arpa".to_string(), 0)),
+    )
+    .await??;
+    let mut request = vec![1, 0x03, 9];
+    request.extend_from_slice(b"localhost");
+    request.extend_from_slice(&53u16.to_be_bytes());
+    stream.send_data(Bytes::from(request))?;
+    let mut buf = [0u8; 1];
+    let err = timeout(
+        Duration::from_secs(5),
+        stream.reader().lock().await.read(&mut buf),
+    )
+    .await?
+    .expect_err("rejected target should reset the stream");
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset, "{}", err);
+
+    client.stop_session_pool_cleanup().await;
+    server_handle.abort();
+    let _ = server_handle.await;
+
+    Ok(())
+}
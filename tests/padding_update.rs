@@ -0,0 +1,221 @@
+//! Padding scheme pushed by the server with UpdatePaddingScheme
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::padding::PaddingFactory;
+use anytls_rs::server::{OutboundAcl, Server};
+use anytls_rs::session::DownstreamPadding;
+use anytls_rs::util::tls;
+use common::*;
+use std::sync::Arc;
+use tokio::time::{Duration, sleep, timeout};
+
+const SERVER_SCHEME: &str = "stop=4\n0=30-30\n1=200-300\n2=300-400,c,300-400\n3=100-200";
+
+async fn echo_once(stream: &anytls_rs::session::Stream, payload: &[u8]) -> Result<Vec<u8>> {
+    stream
+        .write_data(bytes::Bytes::copy_from_slice(payload))
+        .await?;
+    let mut buf = vec![0u8; payload.len()];
+    let mut reader = stream.reader().lock().await;
+    timeout(Duration::from_secs(5), reader.read_exact(&mut buf)).await??;
+    Ok(buf)
+}
+
+#[tokio::test]
+async fn test_client_adopts_server_padding_scheme() -> Result<()> {
+    let config = new_test_config()?;
+    let server_padding =
+        Arc::new(PaddingFactory::new(SERVER_SCHEME.as_bytes()).map_err(|e| anyhow::anyhow!(e))?);
+    let server_md5 = server_padding.md5().to_string();
+    let tls_acceptor = Arc::new(tokio_rustls::TlsAcceptor::from(tls::create_server_config()?));
+    let server = Arc::new(
+        Server::new(&config.password, tls_acceptor, server_padding, None)
+            .with_outbound_acl(OutboundAcl::allow_all()),
+    );
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = server.listen(&server_addr).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+    sleep(Duration::from_millis(300)).await;
+
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let target = (echo_addr.ip().to_string(), echo_addr.port());
+
+    // The client starts out with the default scheme
+    let client = create_test_client(&config).await?;
+    assert_ne!(client.upstream_status().await[0].padding_md5, server_md5);
+
+    let (first, first_session) = client.create_proxy_stream(target.clone()).await?;
+    assert_eq!(echo_once(&first, b"before update").await?, b"before update");
+
+    // The server answers the mismatching padding-md5 with its scheme
+    let mut updated = false;
+    for _ in 0..50 {
+        if first_session.padding_md5().await == server_md5 {
+            updated = true;
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    assert!(updated, "session should switch to the server's scheme");
+    assert_eq!(client.upstream_status().await[0].padding_md5, server_md5);
+    assert_eq!(echo_once(&first, b"after update").await?, b"after update");
+
+    // A later session starts with, and advertises, the updated scheme
+    drop(first);
+    first_session.close().await?;
+    let (second, second_session) = client.create_proxy_stream(target).await?;
+    assert!(!Arc::ptr_eq(&first_session, &second_session));
+    assert_eq!(second_session.padding_md5().await, server_md5);
+    assert_eq!(echo_once(&second, b"new session").await?, b"new session");
+
+    client.stop_session_pool_cleanup().await;
+    server_handle.abort();
+    echo_handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_padding_reload_reaches_live_sessions() -> Result<()> {
+    let config = new_test_config()?;
+    let server = create_test_server(&config).await?;
+    let padding = server.padding_handle();
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = server.listen(&server_addr).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+    sleep(Duration::from_millis(300)).await;
+
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let target = (echo_addr.ip().to_string(), echo_addr.port());
+    let client = create_test_client(&config).await?;
+    let (stream, session) = client.create_proxy_stream(target).await?;
+    assert_eq!(echo_once(&stream, b"default").await?, b"default");
+    let default_md5 = session.padding_md5().await;
+    assert_eq!(default_md5, padding.current().md5());
+
+    // Rotating the scheme reaches the connected client without a reconnect
+    let rotated =
+        Arc::new(PaddingFactory::new(SERVER_SCHEME.as_bytes()).map_err(|e| anyhow::anyhow!(e))?);
+    let rotated_md5 = rotated.md5().to_string();
+    padding.update(rotated);
+
+    let mut updated = false;
+    for _ in 0..50 {
+        if session.padding_md5().await == rotated_md5 {
+            updated = true;
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    assert!(updated, "live session should receive the new scheme");
+    assert!(!session.is_closed());
+    assert_eq!(echo_once(&stream, b"rotated").await?, b"rotated");
+    assert_eq!(client.upstream_status().await[0].padding_md5, rotated_md5);
+
+    client.stop_session_pool_cleanup().await;
+    server_handle.abort();
+    echo_handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_pads_downstream_for_new_clients() -> Result<()> {
+    let config = new_test_config()?;
+    let downstream =
+        Arc::new(PaddingFactory::new(SERVER_SCHEME.as_bytes()).map_err(|e| anyhow::anyhow!(e))?);
+    let downstream_md5 = downstream.md5().to_string();
+    let tls_acceptor = Arc::new(tokio_rustls::TlsAcceptor::from(tls::create_server_config()?));
+    let server = Arc::new(
+        Server::new(
+            &config.password,
+            tls_acceptor,
+            PaddingFactory::default(),
+            None,
+        )
+        .with_outbound_acl(OutboundAcl::allow_all())
+        .with_downstream_padding(DownstreamPadding::Scheme(downstream)),
+    );
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = server.listen(&server_addr).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+    sleep(Duration::from_millis(300)).await;
+
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let target = (echo_addr.ip().to_string(), echo_addr.port());
+    let client = create_test_client(&config).await?;
+    let (stream, session) = client.create_proxy_stream(target).await?;
+
+    // The first replies are split and padded, and still decode on the client
+    let payload: Vec<u8> = (0..900u32).map(|i| i as u8).collect();
+    for _ in 0..5 {
+        assert_eq!(echo_once(&stream, &payload).await?, payload);
+    }
+    assert!(session.is_downstream_padded());
+    // The downstream scheme is not pushed to the client
+    assert_ne!(session.padding_md5().await, downstream_md5);
+
+    client.stop_session_pool_cleanup().await;
+    server_handle.abort();
+    echo_handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_extended_scheme_is_pushed_and_applied() -> Result<()> {
+    const EXTENDED_SCHEME: &str = "stop=4\nfill=random\n0=30-30\n1=d1-5,200-400~\n2=100-200*3|600-700,c,300-400\n3=d2-2,500-600";
+    let config = new_test_config()?;
+    let server = create_test_server(&config).await?;
+    let padding = server.padding_handle();
+    let extended =
+        Arc::new(PaddingFactory::new(EXTENDED_SCHEME.as_bytes()).map_err(|e| anyhow::anyhow!(e))?);
+    let extended_md5 = extended.md5().to_string();
+    padding.update(extended);
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = server.listen(&server_addr).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+    sleep(Duration::from_millis(300)).await;
+
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let target = (echo_addr.ip().to_string(), echo_addr.port());
+    let client = create_test_client(&config).await?;
+    let (stream, first_session) = client.create_proxy_stream(target.clone()).await?;
+    assert_eq!(echo_once(&stream, b"first").await?, b"first");
+
+    let mut updated = false;
+    for _ in 0..50 {
+        if first_session.padding_md5().await == extended_md5 {
+            updated = true;
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    assert!(updated, "client should accept the extended scheme");
+
+    // A new session pads its first packets with the extended scheme
+    drop(stream);
+    first_session.close().await?;
+    let (stream, session) = client.create_proxy_stream(target).await?;
+    assert_eq!(session.padding_md5().await, extended_md5);
+    let payload: Vec<u8> = (0..700u32).map(|i| (i * 7) as u8).collect();
+    for _ in 0..5 {
+        assert_eq!(echo_once(&stream, &payload).await?, payload);
+    }
+
+    client.stop_session_pool_cleanup().await;
+    server_handle.abort();
+    echo_handle.abort();
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! Server resource limit tests
+//!
+//! Connections over the per-IP limit should be closed before the TLS
+//! handshake, and streams over the per-session limit refused, without
+//! affecting the connections and streams already admitted.
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::server::ServerLimits;
+use bytes::Bytes;
+use common::*;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::time::{Duration, timeout};
+
+#[tokio::test]
+async fn test_connections_per_ip_limit() -> Result<()> {
+    let config = new_test_config()?;
+    let server = spawn_test_server(&config, |server| {
+        server.with_limits(ServerLimits {
+            max_connections_per_ip: Some(1),
+            ..ServerLimits::default()
+        })
+    })
+    .await?;
+
+    // A stalled connection holds the only slot of 127.0.0.1
+    let held = TcpStream::connect(&config.server_addr).await?;
+    assert!(wait_for(|| server.connection_count() == 1, Duration::from_secs(2)).await);
+
+    let mut refused = TcpStream::connect(&config.server_addr).await?;
+    let mut buf = [0u8; 16];
+    let read = timeout(Duration::from_secs(2), refused.read(&mut buf)).await?;
+    assert!(
+        matches!(read, Ok(0) | Err(_)),
+        "connection over limit stayed open"
+    );
+    assert_eq!(server.connection_count(), 1);
+
+    // Once the slot is free, a client gets through
+    drop(held);
+    assert!(wait_for(|| server.connection_count() == 0, Duration::from_secs(2)).await);
+    let (echo_addr, _echo) = spawn_tcp_echo_server().await?;
+    let client = create_test_client(&config).await?;
+    let (stream, _session) = client
+        .create_proxy_stream((echo_addr.ip().to_string(), echo_addr.port()))
+        .await?;
+    stream.write_data(Bytes::from_static(b"limited")).await?;
+    let mut echoed = [0u8; 7];
+    timeout(
+        Duration::from_secs(2),
+        stream.reader().lock().await.read_exact(&mut echoed),
+    )
+    .await??;
+    assert_eq!(&echoed, b"limited");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_streams_per_session_limit() -> Result<()> {
+    let config = new_test_config()?;
+    let _server = spawn_test_server(&config, |server| {
+        server.with_limits(ServerLimits {
+            max_streams_per_session: Some(1),
+            ..ServerLimits::default()
+        })
+    })
+    .await?;
+    let (echo_addr, _echo) = spawn_tcp_echo_server().await?;
+    let client = create_test_client(&config).await?;
+    let destination = (echo_addr.ip().to_string(), echo_addr.port());
+
+    let (first, session) = client.create_proxy_stream(destination).await?;
+    let (_second, synack) = session.open_stream().await?;
+    let refused = timeout(Duration::from_secs(2), synack).await?;
+    assert!(matches!(refused, Ok(Err(_))), "second stream was accepted");
+
+    // The admitted stream keeps working
+    first.write_data(Bytes::from_static(b"first")).await?;
+    let mut echoed = [0u8; 5];
+    timeout(
+        Duration::from_secs(2),
+        first.reader().lock().await.read_exact(&mut echoed),
+    )
+    .await??;
+    assert_eq!(&echoed, b"first");
+    assert!(!session.is_closed());
+    Ok(())
+}
+
+/// SOCKS5 address of an IPv4 destination, as sent at the start of a stream
+fn ipv4_destination(addr: std::net::SocketAddr) -> Bytes {
+    let std::net::IpAddr::V4(ip) = addr.ip() else {
+        panic!("expected an IPv4 address");
+    };
+    let mut bytes = vec![0x01];
+    bytes.extend_from_slice(&ip.octets());
+    bytes.extend_from_slice(&addr.port().to_be_bytes());
+    Bytes::from(bytes)
+}
+
+#[tokio::test]
+async fn test_failed_streams_free_their_slots() -> Result<()> {
+    let config = new_test_config()?;
+    let _server = spawn_test_server(&config, |server| {
+        server.with_limits(ServerLimits {
+            max_streams_per_session: Some(2),
+            ..ServerLimits::default()
+        })
+    })
+    .await?;
+    let (echo_addr, _echo) = spawn_tcp_echo_server().await?;
+    let closed_addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await?
+        .local_addr()?;
+    let client = create_test_client(&config).await?;
+
+    let (first, session) = client
+        .create_proxy_stream((echo_addr.ip().to_string(), echo_addr.port()))
+        .await?;
+
+    // More failing streams than the limit, one after another on one session
+    for _ in 0..5 {
+        let (stream, synack) = session.open_stream().await?;
+        session
+            .write_data_frame(stream.id(), ipv4_destination(closed_addr))
+            .await?;
+        let failed = timeout(Duration::from_secs(5), synack).await?;
+        assert!(
+            matches!(failed, Ok(Err(_))),
+            "stream to a closed port succeeded"
+        );
+        session.release_stream(stream.id()).await;
+    }
+
+    // A new stream is still admitted next to the open one
+    let (stream, synack) = session.open_stream().await?;
+    session
+        .write_data_frame(stream.id(), ipv4_destination(echo_addr))
+        .await?;
+    let accepted = timeout(Duration::from_secs(5), synack).await?;
+    assert!(matches!(accepted, Ok(Ok(()))), "stream was refused");
+    stream.write_data(Bytes::from_static(b"after")).await?;
+    let mut echoed = [0u8; 5];
+    timeout(
+        Duration::from_secs(2),
+        stream.reader().lock().await.read_exact(&mut echoed),
+    )
+    .await??;
+    assert_eq!(&echoed, b"after");
+    drop(first);
+    Ok(())
+}
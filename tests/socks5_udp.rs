@@ -0,0 +1,199 @@
+//! SOCKS5 UDP ASSOCIATE integration tests
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::client::{Client, Router, start_socks5_server};
+use anytls_rs::padding::PaddingFactory;
+use anytls_rs::util::{TimeoutPolicy, tls};
+use common::*;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, sleep, timeout};
+use tokio_rustls::rustls::pki_types::ServerName;
+
+async fn spawn_udp_echo_server() -> Result<(SocketAddr, JoinHandle<()>)> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let addr = socket.local_addr()?;
+    let handle = tokio::spawn(async move {
+        let mut buf = vec![0u8; 2048];
+        while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(&buf[..len], peer).await;
+        }
+    });
+    Ok((addr, handle))
+}
+
+/// Open a UDP association and return the control connection and relay address
+async fn udp_associate(proxy: &str) -> Result<(TcpStream, SocketAddr)> {
+    let mut control = TcpStream::connect(proxy).await?;
+    control.write_all(&[0x05, 0x01, 0x00]).await?;
+    let mut method = [0u8; 2];
+    control.read_exact(&mut method).await?;
+    assert_eq!(method, [0x05, 0x00]);
+
+    control
+        .write_all(&[0x05, 0x03, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+        .await?;
+    let mut reply = [0u8; 10];
+    timeout(Duration::from_secs(5), control.read_exact(&mut reply)).await??;
+    assert_eq!(&reply[..4], &[0x05, 0x00, 0x00, 0x01]);
+    let ip = std::net::Ipv4Addr::new(reply[4], reply[5], reply[6], reply[7]);
+    let port = u16::from_be_bytes([reply[8], reply[9]]);
+    Ok((control, SocketAddr::from((ip, port))))
+}
+
+fn udp_datagram(target: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let SocketAddr::V4(target) = target else {
+        panic!("IPv4 target expected");
+    };
+    let mut datagram = vec![0x00, 0x00, 0x00, 0x01];
+    datagram.extend_from_slice(&target.ip().octets());
+    datagram.extend_from_slice(&target.port().to_be_bytes());
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+async fn round_trip(
+    app: &UdpSocket,
+    relay: SocketAddr,
+    target: SocketAddr,
+    payload: &[u8],
+) -> Result<Vec<u8>> {
+    app.send_to(&udp_datagram(target, payload), relay).await?;
+    let mut buf = vec![0u8; 2048];
+    let (len, from) = timeout(Duration::from_secs(5), app.recv_from(&mut buf)).await??;
+    assert_eq!(from, relay);
+    Ok(buf[..len].to_vec())
+}
+
+#[tokio::test]
+async fn test_socks5_udp_associate_proxy_and_direct() -> Result<()> {
+    let config = new_test_config()?;
+    let server = create_test_server(&config).await?;
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = server.listen(&server_addr).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+
+    let (proxied_echo, proxied_handle) = spawn_udp_echo_server().await?;
+    let (direct_echo, direct_handle) = spawn_udp_echo_server().await?;
+
+    let client = create_test_client(&config).await?;
+    let rules = format!("direct port {}\n", direct_echo.port());
+    *client.get_router_ref().write().unwrap() = Arc::new(Router::parse(&rules)?);
+    let socks_addr = config.client_listen.clone();
+    let socks_client = Arc::clone(&client);
+    let socks_handle =
+        tokio::spawn(async move { start_socks5_server(&socks_addr, socks_client).await });
+    sleep(Duration::from_millis(300)).await;
+
+    let (control, relay) = udp_associate(&config.client_listen).await?;
+    let app = UdpSocket::bind("127.0.0.1:0").await?;
+
+    // Tunneled over UDP-over-TCP; the reply carries the destination header
+    for payload in [&b"first"[..], b"second"] {
+        let reply = round_trip(&app, relay, proxied_echo, payload).await?;
+        assert_eq!(reply, udp_datagram(proxied_echo, payload));
+    }
+
+    // Routed direct by the client rules
+    let reply = round_trip(&app, relay, direct_echo, b"direct").await?;
+    assert_eq!(reply, udp_datagram(direct_echo, b"direct"));
+
+    // Closing the control connection ends the association
+    drop(control);
+    sleep(Duration::from_millis(200)).await;
+    app.send_to(&udp_datagram(direct_echo, b"late"), relay)
+        .await?;
+    let mut buf = vec![0u8; 2048];
+    assert!(
+        timeout(Duration::from_millis(500), app.recv_from(&mut buf))
+            .await
+            .is_err(),
+        "relay should stop after the control connection closes"
+    );
+
+    client.stop_session_pool_cleanup().await;
+    socks_handle.abort();
+    server_handle.abort();
+    proxied_handle.abort();
+    direct_handle.abort();
+    Ok(())
+}
+
+/// UDP server that answers every datagram with the sender's address
+async fn spawn_udp_peer_server() -> Result<(SocketAddr, JoinHandle<()>)> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let addr = socket.local_addr()?;
+    let handle = tokio::spawn(async move {
+        let mut buf = vec![0u8; 2048];
+        while let Ok((_, peer)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(peer.to_string().as_bytes(), peer).await;
+        }
+    });
+    Ok((addr, handle))
+}
+
+#[tokio::test]
+async fn test_socks5_udp_flow_expires_when_idle() -> Result<()> {
+    let config = new_test_config()?;
+    let server = create_test_server(&config).await?;
+    let server_addr = config.server_addr.clone();
+    let server_handle = tokio::spawn(async move {
+        if let Err(e) = server.listen(&server_addr).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+    let (peer_server, peer_handle) = spawn_udp_peer_server().await?;
+
+    let client = Arc::new(
+        Client::new(
+            &config.password,
+            config.server_addr.clone(),
+            ServerName::try_from("localhost")?,
+            Arc::new(tokio_rustls::TlsConnector::from(
+                tls::create_insecure_client_config()?,
+            )),
+            PaddingFactory::default(),
+        )
+        .with_timeouts(TimeoutPolicy {
+            stream_idle: Some(Duration::from_millis(500)),
+            ..TimeoutPolicy::default()
+        }),
+    );
+    let socks_addr = config.client_listen.clone();
+    let socks_client = Arc::clone(&client);
+    let socks_handle =
+        tokio::spawn(async move { start_socks5_server(&socks_addr, socks_client).await });
+    sleep(Duration::from_millis(300)).await;
+
+    let (_control, relay) = udp_associate(&config.client_listen).await?;
+    let app = UdpSocket::bind("127.0.0.1:0").await?;
+    let header_len = udp_datagram(peer_server, b"").len();
+
+    // The server relays a flow from one socket while it is in use
+    let first = round_trip(&app, relay, peer_server, b"one").await?;
+    let second = round_trip(&app, relay, peer_server, b"two").await?;
+    assert_eq!(first, second);
+
+    // An idle flow is closed; the next datagram opens a new one
+    sleep(Duration::from_millis(1500)).await;
+    let third = round_trip(&app, relay, peer_server, b"three").await?;
+    assert_ne!(
+        first[header_len..],
+        third[header_len..],
+        "idle flow should have been replaced"
+    );
+
+    client.stop_session_pool_cleanup().await;
+    socks_handle.abort();
+    server_handle.abort();
+    peer_handle.abort();
+    Ok(())
+}
@@ -0,0 +1,175 @@
+//! Stream timeout tests
+//!
+//! Relayed streams, UDP over TCP included, should be closed once they go
+//! idle or reach their maximum lifetime, and a client should give up on a
+//! stream whose SYNACK does not arrive within its SYNACK timeout.
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::client::Client;
+use anytls_rs::padding::PaddingFactory;
+use anytls_rs::util::{METRICS, TimeoutPolicy, tls};
+use bytes::Bytes;
+use common::*;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant, sleep, timeout};
+use tokio_rustls::rustls::pki_types::ServerName;
+
+/// Read from the stream until the server closes it, returning how long it
+/// stayed open
+async fn wait_stream_closed(stream: &anytls_rs::session::Stream) -> Result<Duration> {
+    let started = Instant::now();
+    let mut buf = [0u8; 64];
+    let mut reader = stream.reader().lock().await;
+    loop {
+        match timeout(Duration::from_secs(3), reader.read(&mut buf)).await? {
+            Ok(0) | Err(_) => return Ok(started.elapsed()),
+            Ok(_) => continue,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_idle_stream_closed() -> Result<()> {
+    let config = new_test_config()?;
+    spawn_test_server(&config, |server| {
+        server.with_timeouts(TimeoutPolicy {
+            stream_idle: Some(Duration::from_millis(400)),
+            ..TimeoutPolicy::default()
+        })
+    })
+    .await?;
+    let before = METRICS.stream_timeouts.get();
+    let (echo_addr, _echo) = spawn_tcp_echo_server().await?;
+    let client = create_test_client(&config).await?;
+    let (stream, _session) = client
+        .create_proxy_stream((echo_addr.ip().to_string(), echo_addr.port()))
+        .await?;
+
+    // Traffic keeps the stream open past the idle timeout
+    for _ in 0..4 {
+        stream.write_data(Bytes::from_static(b"ping")).await?;
+        let mut echoed = [0u8; 4];
+        timeout(
+            Duration::from_secs(2),
+            stream.reader().lock().await.read_exact(&mut echoed),
+        )
+        .await??;
+        assert_eq!(&echoed, b"ping");
+        sleep(Duration::from_millis(200)).await;
+    }
+
+    let elapsed = wait_stream_closed(&stream).await?;
+    assert!(
+        elapsed < Duration::from_secs(1),
+        "closed after {:?}",
+        elapsed
+    );
+    assert!(METRICS.stream_timeouts.get() > before);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_idle_udp_over_tcp_stream_closed() -> Result<()> {
+    let config = new_test_config()?;
+    spawn_test_server(&config, |server| {
+        server.with_timeouts(TimeoutPolicy {
+            stream_idle: Some(Duration::from_millis(400)),
+            ..TimeoutPolicy::default()
+        })
+    })
+    .await?;
+    let before = METRICS.stream_timeouts.get();
+    let target = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let target_addr = target.local_addr()?;
+    let client = create_test_client(&config).await?;
+    let (stream, _session) = client
+        .create_proxy_stream(("sp.v2.udp-over-tcp.arpa".to_string(), 0))
+        .await?;
+
+    // Connect request, then a single datagram
+    let mut request = vec![0x01, 0x01];
+    request.extend_from_slice(&[127, 0, 0, 1]);
+    request.extend_from_slice(&target_addr.port().to_be_bytes());
+    request.extend_from_slice(&4u16.to_be_bytes());
+    request.extend_from_slice(b"ping");
+    stream.write_data(Bytes::from(request)).await?;
+    let mut buf = [0u8; 16];
+    let (len, _) = timeout(Duration::from_secs(2), target.recv_from(&mut buf)).await??;
+    assert_eq!(&buf[..len], b"ping");
+
+    let elapsed = wait_stream_closed(&stream).await?;
+    assert!(
+        elapsed < Duration::from_secs(1),
+        "closed after {:?}",
+        elapsed
+    );
+    assert!(METRICS.stream_timeouts.get() > before);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_lifetime_limit() -> Result<()> {
+    let config = new_test_config()?;
+    spawn_test_server(&config, |server| {
+        server.with_timeouts(TimeoutPolicy {
+            stream_idle: Some(Duration::from_secs(10)),
+            stream_lifetime: Some(Duration::from_millis(600)),
+            ..TimeoutPolicy::default()
+        })
+    })
+    .await?;
+    let (echo_addr, _echo) = spawn_tcp_echo_server().await?;
+    let client = create_test_client(&config).await?;
+    let started = Instant::now();
+    let (stream, _session) = client
+        .create_proxy_stream((echo_addr.ip().to_string(), echo_addr.port()))
+        .await?;
+
+    // A busy stream is still closed at the end of its lifetime
+    let writer = Arc::clone(&stream);
+    let traffic = tokio::spawn(async move {
+        while writer.write_data(Bytes::from_static(b"busy")).await.is_ok() {
+            sleep(Duration::from_millis(50)).await;
+        }
+    });
+    wait_stream_closed(&stream).await?;
+    let elapsed = started.elapsed();
+    assert!(
+        elapsed >= Duration::from_millis(600) && elapsed < Duration::from_secs(2),
+        "closed after {:?}",
+        elapsed
+    );
+    traffic.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_synack_timeout() -> Result<()> {
+    let config = new_test_config()?;
+    // Accept streams but never connect them
+    spawn_test_server(&config, |server| server.with_stream_handler(|_stream| {})).await?;
+
+    let client = Client::new(
+        &config.password,
+        config.server_addr.clone(),
+        ServerName::try_from("localhost")?,
+        Arc::new(tokio_rustls::TlsConnector::from(
+            tls::create_insecure_client_config()?,
+        )),
+        PaddingFactory::default(),
+    )
+    .with_timeouts(TimeoutPolicy {
+        synack: Duration::from_millis(300),
+        ..TimeoutPolicy::default()
+    });
+
+    let started = Instant::now();
+    let result = client
+        .create_proxy_stream(("example.com".to_string(), 80))
+        .await;
+    assert!(result.is_err(), "stream opened without SYNACK");
+    assert!(started.elapsed() < Duration::from_secs(2));
+    Ok(())
+}
@@ -6,7 +6,10 @@
 mod common;
 
 use anyhow::Result;
-use common::{TestConfig, create_test_client, create_test_server};
+use bytes::Bytes;
+use common::{
+    TestConfig, create_test_client, create_test_server, new_test_config, spawn_tcp_echo_server,
+};
 use std::net::{Ipv4Addr, TcpListener};
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -153,3 +156,64 @@ async fn test_tcp_roundtrip_via_socks5() -> Result<()> {
 
     Ok(())
 }
+
+fn pattern(len: usize, seed: usize) -> Vec<u8> {
+    (0..len).map(|i| ((i + seed) % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn test_tcp_roundtrip_multi_megabyte_writes() -> Result<()> {
+    let config = new_test_config()?;
+    let server = create_test_server(&config).await?;
+    let server_clone = Arc::clone(&server);
+    let server_addr = config.server_addr.clone();
+    let server_task = tokio::spawn(async move {
+        if let Err(e) = server_clone.listen(&server_addr).await {
+            tracing::error!("[Test] Server error: {}", e);
+        }
+    });
+    sleep(Duration::from_millis(300)).await;
+
+    let client = create_test_client(&config).await?;
+    let (echo_addr, echo_task) = spawn_tcp_echo_server().await?;
+    let (stream, session) = client
+        .create_proxy_stream((echo_addr.ip().to_string(), echo_addr.port()))
+        .await?;
+
+    // Single writes far above the 65535-byte frame limit, plus one just past a
+    // frame boundary
+    let writes = [
+        pattern(4 * 1024 * 1024, 0),
+        pattern(2 * 1024 * 1024 + 17, 1),
+        pattern(2 * 65535 + 1, 2),
+    ];
+    let expected: Vec<u8> = writes.concat();
+
+    let reader_stream = Arc::clone(&stream);
+    let total = expected.len();
+    let reader = tokio::spawn(async move {
+        let mut received = vec![0u8; total];
+        let mut reader = reader_stream.reader().lock().await;
+        reader.read_exact(&mut received).await.map(|_| received)
+    });
+
+    for data in writes {
+        stream.write_data(Bytes::from(data)).await?;
+    }
+
+    let received = timeout(Duration::from_secs(30), reader).await???;
+    assert!(
+        received == expected,
+        "echoed data differs from what was sent"
+    );
+
+    drop(stream);
+    session.close().await?;
+    client.stop_session_pool_cleanup().await;
+    server_task.abort();
+    let _ = server_task.await;
+    echo_task.abort();
+    let _ = echo_task.await;
+
+    Ok(())
+}
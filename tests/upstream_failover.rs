@@ -0,0 +1,126 @@
+//! Multiple upstream servers: failover and load balancing
+
+mod common;
+
+use anyhow::Result;
+use anytls_rs::client::{Client, LoadBalanceStrategy, SessionPoolConfig, UpstreamEndpoint};
+use anytls_rs::padding::PaddingFactory;
+use anytls_rs::util::tls;
+use common::*;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, sleep, timeout};
+use tokio_rustls::rustls::pki_types::ServerName;
+
+async fn spawn_server(config: &TestConfig) -> Result<JoinHandle<()>> {
+    let server = create_test_server(config).await?;
+    let addr = config.server_addr.clone();
+    let handle = tokio::spawn(async move {
+        if let Err(e) = server.listen(&addr).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+    Ok(handle)
+}
+
+fn endpoint(config: &TestConfig) -> Result<UpstreamEndpoint> {
+    let tls_connector = Arc::new(tokio_rustls::TlsConnector::from(
+        tls::create_insecure_client_config()?,
+    ));
+    Ok(UpstreamEndpoint::new(
+        &config.password,
+        config.server_addr.clone(),
+        ServerName::try_from("localhost")?,
+        tls_connector,
+        PaddingFactory::default(),
+    ))
+}
+
+#[tokio::test]
+async fn test_failover_to_second_upstream() -> Result<()> {
+    // First upstream has nothing listening, second one is up
+    let down = new_test_config()?;
+    let up = new_test_config()?;
+    let server_handle = spawn_server(&up).await?;
+    sleep(Duration::from_millis(300)).await;
+
+    let client = Client::with_endpoints(
+        vec![endpoint(&down)?, endpoint(&up)?],
+        SessionPoolConfig::default(),
+    )?;
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let destination = (echo_addr.ip().to_string(), echo_addr.port());
+
+    for _ in 0..2 {
+        let result = timeout(
+            Duration::from_secs(5),
+            client.create_proxy_stream(destination.clone()),
+        )
+        .await?;
+        assert!(
+            result.is_ok(),
+            "request should fail over to the live server"
+        );
+    }
+
+    // The dead upstream was tried once and then skipped while marked down
+    let status = client.upstream_status().await;
+    assert!(!status[0].healthy);
+    assert_eq!(status[0].consecutive_failures, 1);
+    assert!(status[1].healthy);
+    assert_eq!(status[1].active_streams, 2);
+
+    client.stop_session_pool_cleanup().await;
+    server_handle.abort();
+    echo_handle.abort();
+    let _ = echo_handle.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_round_robin_and_least_active_spread_streams() -> Result<()> {
+    let first = new_test_config()?;
+    let second = new_test_config()?;
+    let first_handle = spawn_server(&first).await?;
+    let second_handle = spawn_server(&second).await?;
+    sleep(Duration::from_millis(300)).await;
+    let (echo_addr, echo_handle) = spawn_tcp_echo_server().await?;
+    let destination = (echo_addr.ip().to_string(), echo_addr.port());
+
+    for strategy in [
+        LoadBalanceStrategy::RoundRobin,
+        LoadBalanceStrategy::LeastActive,
+    ] {
+        let client = Client::with_endpoints(
+            vec![endpoint(&first)?, endpoint(&second)?],
+            SessionPoolConfig::default(),
+        )?
+        .with_strategy(strategy);
+
+        let mut streams = Vec::new();
+        for _ in 0..4 {
+            let result = timeout(
+                Duration::from_secs(5),
+                client.create_proxy_stream(destination.clone()),
+            )
+            .await?;
+            streams.push(result.map_err(|e| anyhow::anyhow!("{}: {}", strategy, e))?);
+        }
+
+        let active: Vec<usize> = client
+            .upstream_status()
+            .await
+            .iter()
+            .map(|status| status.active_streams)
+            .collect();
+        assert_eq!(active, [2, 2], "{} should spread streams evenly", strategy);
+
+        client.stop_session_pool_cleanup().await;
+    }
+
+    first_handle.abort();
+    second_handle.abort();
+    echo_handle.abort();
+    let _ = echo_handle.await;
+    Ok(())
+}